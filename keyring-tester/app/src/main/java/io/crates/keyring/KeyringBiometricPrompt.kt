@@ -0,0 +1,42 @@
+package io.crates.keyring
+
+import androidx.biometric.BiometricPrompt
+import androidx.core.content.ContextCompat
+import androidx.fragment.app.FragmentActivity
+import javax.crypto.Cipher
+
+class KeyringBiometricPrompt(private val handle: Long) :
+    BiometricPrompt.AuthenticationCallback() {
+    fun authenticate(
+        activity: FragmentActivity,
+        cipher: Cipher?,
+        title: String,
+        subtitle: String?,
+        negativeButton: String,
+    ) {
+        activity.runOnUiThread {
+            val executor = ContextCompat.getMainExecutor(activity)
+            val prompt = BiometricPrompt(activity, executor, this)
+            val info = BiometricPrompt.PromptInfo.Builder()
+                .setTitle(title)
+                .setSubtitle(subtitle)
+                .setNegativeButtonText(negativeButton)
+                .build()
+            if (cipher == null) {
+                prompt.authenticate(info)
+            } else {
+                prompt.authenticate(info, BiometricPrompt.CryptoObject(cipher))
+            }
+        }
+    }
+
+    override fun onAuthenticationSucceeded(result: BiometricPrompt.AuthenticationResult) {
+        onResult(handle, result.cryptoObject?.cipher, 0, null)
+    }
+
+    override fun onAuthenticationError(errorCode: Int, errString: CharSequence) {
+        onResult(handle, null, errorCode, errString.toString())
+    }
+
+    private external fun onResult(handle: Long, cipher: Cipher?, errorCode: Int, message: String?)
+}
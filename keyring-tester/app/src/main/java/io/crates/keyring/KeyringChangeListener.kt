@@ -0,0 +1,12 @@
+package io.crates.keyring
+
+import android.content.SharedPreferences
+
+class KeyringChangeListener(private val handle: Long) :
+    SharedPreferences.OnSharedPreferenceChangeListener {
+    override fun onSharedPreferenceChanged(sharedPreferences: SharedPreferences?, key: String?) {
+        onChanged(handle, key)
+    }
+
+    private external fun onChanged(handle: Long, key: String?)
+}
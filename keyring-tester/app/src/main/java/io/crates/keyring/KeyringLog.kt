@@ -5,5 +5,7 @@ import android.content.Context
 class KeyringLog {
     companion object {
         external fun setLog(filter: String)
+        external fun setLogWithTag(filter: String, tag: String)
+        external fun reloadLog(filter: String)
     }
 }
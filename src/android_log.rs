@@ -1,45 +1,167 @@
+/*!
+Logging to logcat
+
+This crate reports what it does through `tracing` events and `log` records.
+This module sends them to logcat, where each message is tagged with the target
+(module path) of the code that logged it, or with a fixed tag chosen by the
+application. Secrets and byte data are redacted, in the messages of `log`
+records as well as in the fields of events.
+
+Logging can be started from Rust, with [init] or [init_with_tag], or from
+Kotlin, by declaring this companion object and calling `setLog` or
+`setLogWithTag`:
+
+```kotlin
+package io.crates.keyring
+
+class KeyringLog {
+    companion object {
+        external fun setLog(filter: String)
+        external fun setLogWithTag(filter: String, tag: String)
+        external fun reloadLog(filter: String)
+    }
+}
+```
+
+The filter uses the syntax of `tracing_subscriber`'s `EnvFilter`, for
+example `android_native_keyring_store=debug`. Once logging has started,
+later calls change its filter but not its tag.
+ */
+
 use android_log_sys::{__android_log_write, LogPriority};
 use jni::{
     JNIEnv,
     objects::{JObject, JString},
 };
-use std::{ffi::CString, fmt::Write};
+use std::{ffi::CString, sync::OnceLock};
 use tracing::{Level, Subscriber, span};
 use tracing_subscriber::{
-    EnvFilter, Layer, Registry, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt,
+    EnvFilter, Layer, Registry, layer::SubscriberExt, registry::LookupSpan, reload,
+    util::SubscriberInitExt,
 };
 
-// package io.crates.keyring
-// class KeyringLog {
-//     companion object {
-//         external fun setLog(filter: String);
-//     }
-// }
+use crate::log_format::{SpanPrefix, level_and_target};
+
+/// Start logging to logcat with the given filter, or once logging has
+/// started, change its filter. See [init].
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_io_crates_keyring_KeyringLog_00024Companion_setLog(
     mut env: JNIEnv,
     _class: JObject,
     filter: JString,
 ) {
-    let filter = {
-        let filter = env.get_string(&filter).unwrap();
-        filter.to_string_lossy().into_owned()
-    };
-
-    match Registry::default()
-        .with(EnvFilter::from(&filter))
-        .with(AndroidLogCat)
-        .try_init()
-    {
-        Ok(()) => tracing::debug!(?filter, "Logger initialized"),
-        Err(e) => {
-            tracing::warn!(%e, "Trying to initialize logger twice");
-            tracing::debug!(?e);
+    let filter = get_string(&mut env, &filter);
+    if let Err(e) = init(&filter) {
+        tracing::warn!(%e, "Failed to set up logging");
+    }
+}
+
+/// Like `setLog`, but every message is logged with the given tag.
+/// See [init_with_tag].
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_crates_keyring_KeyringLog_00024Companion_setLogWithTag(
+    mut env: JNIEnv,
+    _class: JObject,
+    filter: JString,
+    tag: JString,
+) {
+    let filter = get_string(&mut env, &filter);
+    let tag = get_string(&mut env, &tag);
+    if let Err(e) = init_with_tag(&filter, &tag) {
+        tracing::warn!(%e, "Failed to set up logging");
+    }
+}
+
+/// The same as `setLog`, for callers that only mean to change the filter.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_crates_keyring_KeyringLog_00024Companion_reloadLog(
+    env: JNIEnv,
+    class: JObject,
+    filter: JString,
+) {
+    Java_io_crates_keyring_KeyringLog_00024Companion_setLog(env, class, filter)
+}
+
+fn get_string(env: &mut JNIEnv, s: &JString) -> String {
+    let s = env.get_string(s).unwrap();
+    s.to_string_lossy().into_owned()
+}
+
+/// The result of starting logging, or of changing its filter.
+pub type LogResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// Start logging to logcat with the given filter, or once logging has
+/// started, change its filter.
+///
+/// Messages are tagged with the target (module path) of the code that
+/// logged them. `log` records are forwarded to the same subscriber, so
+/// this fails if the process has a `tracing` subscriber or a `log` logger
+/// that wasn't set up by this module.
+pub fn init(filter: &str) -> LogResult {
+    set_log(filter, AndroidLogCat::default())
+}
+
+/// Like [init], but every message is logged with the given tag, rather than
+/// with the target of the code that logged it. The target is still at the
+/// start of each message. Once logging has started, its tag can't be
+/// changed, so later calls only change the filter.
+pub fn init_with_tag(filter: &str, tag: &str) -> LogResult {
+    set_log(filter, AndroidLogCat::with_tag(tag))
+}
+
+/// Changes the filter of the logger, once it has been initialized.
+pub(crate) static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Initialize the logger with the given filter and layer or,
+/// if it's already initialized, replace its filter.
+fn set_log(filter: &str, layer: AndroidLogCat) -> LogResult {
+    if let Some(handle) = FILTER.get() {
+        handle.reload(EnvFilter::from(filter))?;
+        tracing::debug!(?filter, "Logger filter changed");
+        return Ok(());
+    }
+    let (filter_layer, handle) = reload::Layer::new(EnvFilter::from(filter));
+    Registry::default()
+        .with(filter_layer)
+        .with(layer)
+        .try_init()?;
+    _ = FILTER.set(handle);
+    tracing::debug!(?filter, "Logger initialized");
+    Ok(())
+}
+
+/// A `tracing_subscriber` layer that writes events to logcat.
+///
+/// [init] sets up a subscriber with this layer, but applications with
+/// subscribers of their own can add it to them instead. This crate's `log`
+/// records only reach the layer if they're forwarded to the subscriber,
+/// as `tracing_log::LogTracer` does.
+#[derive(Default)]
+pub struct AndroidLogCat {
+    // the tag for every message, if not the message's target
+    tag: Option<CString>,
+}
+
+impl AndroidLogCat {
+    /// A layer that logs every message with the given tag.
+    ///
+    /// A tag that can't be passed to logcat (because it contains a NUL)
+    /// is ignored, and messages are tagged with their targets.
+    pub fn with_tag(tag: &str) -> Self {
+        Self {
+            tag: CString::new(tag).ok(),
+        }
+    }
+
+    /// The logcat tag for a message with the given target.
+    pub(crate) fn tag(&self, target: &str) -> CString {
+        match &self.tag {
+            Some(tag) => tag.clone(),
+            None => CString::new(target).unwrap(),
         }
     }
 }
 
-pub struct AndroidLogCat;
 impl<S> Layer<S> for AndroidLogCat
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
@@ -63,7 +185,8 @@ where
     }
 
     fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
-        let priority = match *event.metadata().level() {
+        let (level, target) = level_and_target(event);
+        let priority = match level {
             Level::ERROR => LogPriority::ERROR,
             Level::WARN => LogPriority::WARN,
             Level::INFO => LogPriority::INFO,
@@ -71,132 +194,41 @@ where
             Level::TRACE => LogPriority::VERBOSE,
         };
 
-        let tag = CString::new(event.metadata().target()).unwrap();
+        let tag = self.tag(&target);
 
         let message = SpanPrefix::on_event(event, ctx);
-        let message = CString::new(message).unwrap_or_default();
-
-        unsafe {
-            __android_log_write(priority as i32, tag.as_ptr(), message.as_ptr());
-        }
-    }
-}
-
-pub struct SpanPrefix {
-    name: String,
-    values: String,
-}
-impl SpanPrefix {
-    pub fn on_new_span<S>(
-        attrs: &span::Attributes<'_>,
-        id: &span::Id,
-        ctx: tracing_subscriber::layer::Context<'_, S>,
-    ) where
-        S: Subscriber + for<'a> LookupSpan<'a>,
-    {
-        let span = ctx.span(id).expect("Span not found, this is a bug");
-        let mut extensions = span.extensions_mut();
-        match extensions.get_mut::<SpanPrefix>() {
-            Some(prefix) => {
-                prefix.name = attrs.metadata().name().to_string();
-                attrs.record(&mut prefix.visit());
-            }
-            None => {
-                let mut prefix = SpanPrefix::new(span.name().to_string());
-                attrs.record(&mut prefix.visit());
-                extensions.insert(prefix)
+        for line in log_lines(&message) {
+            unsafe {
+                __android_log_write(priority as i32, tag.as_ptr(), line.as_ptr());
             }
         }
     }
-
-    pub fn on_record<S>(
-        id: &span::Id,
-        values: &span::Record<'_>,
-        ctx: tracing_subscriber::layer::Context<'_, S>,
-    ) where
-        S: Subscriber + for<'a> LookupSpan<'a>,
-    {
-        let span = ctx.span(id).expect("Span not found, this is a bug");
-        let mut extensions = span.extensions_mut();
-        match extensions.get_mut::<SpanPrefix>() {
-            Some(prefix) => {
-                values.record(&mut prefix.visit());
-            }
-            None => {
-                let mut prefix = SpanPrefix::new(span.name().to_string());
-                values.record(&mut prefix.visit());
-                extensions.insert(prefix)
-            }
-        }
-    }
-
-    pub fn on_event<S>(
-        event: &tracing::Event<'_>,
-        ctx: tracing_subscriber::layer::Context<'_, S>,
-    ) -> String
-    where
-        S: Subscriber + for<'a> LookupSpan<'a>,
-    {
-        let mut message = String::new();
-        if let Some(scope) = ctx.event_scope(event) {
-            for span in scope.from_root() {
-                if let Some(prefix) = span.extensions().get::<SpanPrefix>() {
-                    write!(message, "{prefix}").unwrap();
-                }
-            }
-        };
-        if !message.is_empty() {
-            write!(message, " ").unwrap();
-        }
-        write!(message, "{}:", event.metadata().target()).unwrap();
-
-        event.record(&mut Visitor(&mut message));
-        message
-    }
-
-    fn new(name: String) -> Self {
-        SpanPrefix {
-            name,
-            values: Default::default(),
-        }
-    }
-
-    fn visit(&mut self) -> impl tracing::field::Visit + '_ {
-        Visitor(&mut self.values)
-    }
 }
-impl std::fmt::Display for SpanPrefix {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = &self.name;
-        let values = &self.values;
-
-        match values.is_empty() {
-            true => write!(f, "{name}:"),
-            false => write!(f, "{name}{{{values}}}:"),
-        }
-    }
-}
-
-struct Visitor<'a>(&'a mut String);
-impl Visitor<'_> {
-    fn record_field(&mut self, field: &tracing::field::Field) {
-        if !self.0.is_empty() {
-            write!(self.0, " ").unwrap();
-        }
 
-        if field.as_ref() != "message" {
-            write!(self.0, "{field}=").unwrap();
+/// The most bytes logcat shows of a single message; it truncates longer ones.
+pub(crate) const MAX_LINE_LEN: usize = 4000;
+/// The end of every line of a message that continues on the next line.
+pub(crate) const CONTINUATION_MARKER: &str = " \u{2026}";
+
+/// Split a message into lines that logcat shows in full.
+///
+/// Lines are split at character boundaries, and every line but the last ends
+/// with [CONTINUATION_MARKER]. NULs (which would end a line early) are
+/// replaced with `\0`.
+pub(crate) fn log_lines(message: &str) -> Vec<CString> {
+    let message = message.replace('\0', "\\0");
+    let max_chunk_len = MAX_LINE_LEN - CONTINUATION_MARKER.len();
+    let mut lines = Vec::new();
+    let mut rest = message.as_str();
+    while rest.len() > MAX_LINE_LEN {
+        let mut end = max_chunk_len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
         }
+        let (chunk, remainder) = rest.split_at(end);
+        lines.push(CString::new(format!("{chunk}{CONTINUATION_MARKER}")).unwrap());
+        rest = remainder;
     }
-}
-impl tracing::field::Visit for Visitor<'_> {
-    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
-        self.record_field(field);
-        write!(self.0, "{value}").unwrap();
-    }
-
-    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
-        self.record_field(field);
-        write!(self.0, "{value:?}").unwrap();
-    }
+    lines.push(CString::new(rest).unwrap());
+    lines
 }
@@ -0,0 +1,126 @@
+use std::{thread::sleep, time::Duration};
+
+use jni::JNIEnv;
+
+use crate::{
+    error::pending_exception_is,
+    keystore::{PROVIDER_EXCEPTION, STRONGBOX_UNAVAILABLE_EXCEPTION},
+};
+
+/// The outcome of a failed attempt at a retriable operation.
+pub enum Failure<E> {
+    /// The operation might succeed if tried again.
+    Transient(E),
+    /// The operation will never succeed, so there's no point retrying it.
+    Permanent(E),
+}
+
+/// A bounded retry policy with exponential backoff.
+///
+/// The first retry waits for the base delay, and each
+/// subsequent retry waits for twice as long as the prior one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+impl Backoff {
+    /// Run an operation until it succeeds, fails permanently,
+    /// or has been attempted the maximum number of times.
+    ///
+    /// The error from the last attempt is returned on failure.
+    pub fn retry<T, E, F>(&self, mut op: F) -> Result<T, E>
+    where
+        F: FnMut() -> Result<T, Failure<E>>,
+    {
+        let mut delay = self.base_delay;
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Ok(result) => return Ok(result),
+                Err(Failure::Permanent(err)) => return Err(err),
+                Err(Failure::Transient(err)) if attempt >= self.max_attempts => return Err(err),
+                Err(Failure::Transient(_)) => {
+                    log::warn!("Transient failure on attempt {attempt}, retrying in {delay:?}");
+                    sleep(delay);
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Classify a failed keystore call by the Java exception it threw.
+///
+/// Keystore daemon hiccups surface as a `ProviderException`, and those are
+/// worth retrying. In that case the pending exception is cleared so the
+/// next attempt can proceed. Anything else (such as a bad key spec, or the
+/// `StrongBoxUnavailableException` subclass) is permanent, and the exception
+/// is left pending for the caller to handle.
+pub fn classify_keystore_failure<E>(env: &mut JNIEnv, err: E) -> Failure<E> {
+    if pending_exception_is(env, PROVIDER_EXCEPTION)
+        && !pending_exception_is(env, STRONGBOX_UNAVAILABLE_EXCEPTION)
+        && env.exception_clear().is_ok()
+    {
+        Failure::Transient(err)
+    } else {
+        Failure::Permanent(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff() -> Backoff {
+        Backoff {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        let mut calls = 0;
+        let result: Result<u32, u32> = backoff().retry(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(Failure::Transient(calls))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result, Ok(3));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut calls = 0;
+        let result: Result<u32, u32> = backoff().retry(|| {
+            calls += 1;
+            Err(Failure::Transient(calls))
+        });
+        assert_eq!(result, Err(4));
+    }
+
+    #[test]
+    fn does_not_retry_permanent_failures() {
+        let mut calls = 0;
+        let result: Result<u32, u32> = backoff().retry(|| {
+            calls += 1;
+            Err(Failure::Permanent(calls))
+        });
+        assert_eq!(result, Err(1));
+    }
+}
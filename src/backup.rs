@@ -0,0 +1,203 @@
+/*!
+# Passphrase-Protected Backups
+
+This module defines the backup blobs written by
+[Store::export_service](crate::Store::export_service), which hold all the
+credentials of a service encrypted under a key derived from a passphrase.
+Unlike credentials at rest, which are encrypted with a key that never leaves
+the device's KeyStore, a backup is encrypted in Rust, so it can be opened with
+[open] on any device (or any host) that knows the passphrase.
+
+## Blob Format
+
+A blob starts with the four bytes `AKBK` and a version byte (currently 1).
+Then come the Argon2id parameters used to derive the key from the passphrase,
+each a four-byte big-endian number: the memory cost in KiB, the number of
+passes, and the degree of parallelism. Then come the 16-byte Argon2id salt,
+a 16-byte passphrase check, and the 12-byte AES-GCM nonce. Argon2id derives
+48 bytes from the passphrase: the first 32 are the key, and the last 16 are
+the check, which tells a wrong passphrase apart from a corrupted blob.
+The rest of the blob is the encryption, with
+AES-256-GCM under the derived key, of a JSON object holding the service name
+and an array of the service's credentials, each with its user name and its
+base64-encoded secret. Everything before the ciphertext is authenticated as
+additional data, so none of it can be altered without detection.
+ */
+use aes_gcm::{
+    Aes256Gcm,
+    aead::{Aead, AeadCore, KeyInit, Nonce, OsRng, Payload, rand_core::RngCore},
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use keyring_core::{Error, Result};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+const BLOB_MAGIC: [u8; 4] = *b"AKBK";
+const BLOB_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const CHECK_LEN: usize = 16;
+/// The length of everything before the ciphertext.
+const HEADER_LEN: usize = BLOB_MAGIC.len() + 1 + 3 * 4 + SALT_LEN + CHECK_LEN + NONCE_LEN;
+/// The Argon2id parameters for new backups: 19 MiB of memory,
+/// two passes, and one lane, as OWASP recommends.
+const MEMORY_COST_KIB: u32 = 19 * 1024;
+const PASSES: u32 = 2;
+const PARALLELISM: u32 = 1;
+/// The most memory a blob can ask for, so that a corrupted or
+/// malicious blob can't make opening it exhaust the device.
+const MAX_MEMORY_COST_KIB: u32 = 256 * 1024;
+const MAX_PASSES: u32 = 16;
+const MAX_PARALLELISM: u32 = 16;
+
+/// The credentials of a service, as read from a backup blob by [open].
+#[derive(Debug)]
+pub struct Backup {
+    /// The service whose credentials were backed up.
+    pub service: String,
+    /// The user name and secret of each credential.
+    pub credentials: Vec<(String, Zeroizing<Vec<u8>>)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Contents {
+    service: String,
+    credentials: Vec<BackupCredential>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupCredential {
+    user: String,
+    secret: String,
+}
+
+/// Encrypt the given credentials of a service into a backup blob,
+/// under a key derived from the given passphrase.
+pub(crate) fn seal(
+    service: &str,
+    credentials: &[(String, Zeroizing<Vec<u8>>)],
+    passphrase: &str,
+) -> Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        let msg = "cannot be empty".to_string();
+        return Err(Error::Invalid("passphrase".to_string(), msg));
+    }
+    let contents = Contents {
+        service: service.to_string(),
+        credentials: credentials
+            .iter()
+            .map(|(user, secret)| BackupCredential {
+                user: user.clone(),
+                secret: STANDARD.encode(secret),
+            })
+            .collect(),
+    };
+    let plaintext = Zeroizing::new(
+        serde_json::to_vec(&contents).map_err(|e| Error::PlatformFailure(Box::new(e)))?,
+    );
+    // wipe the encoded secrets along with the plaintext
+    for credential in contents.credentials {
+        _ = Zeroizing::new(credential.secret);
+    }
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let mut blob = BLOB_MAGIC.to_vec();
+    blob.push(BLOB_VERSION);
+    for param in [MEMORY_COST_KIB, PASSES, PARALLELISM] {
+        blob.extend_from_slice(&param.to_be_bytes());
+    }
+    let derived = derive_key(passphrase, &salt, MEMORY_COST_KIB, PASSES, PARALLELISM)?;
+    let (key, check) = derived.split_at(KEY_LEN);
+    blob.extend_from_slice(&salt);
+    blob.extend_from_slice(check);
+    blob.extend_from_slice(&nonce);
+    let cipher = Aes256Gcm::new_from_slice(key).expect("AES-256 key has the wrong length");
+    let payload = Payload {
+        msg: &plaintext,
+        aad: &blob,
+    };
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| Error::PlatformFailure("Failed to encrypt backup".into()))?;
+    blob.extend_from_slice(&ciphertext);
+    Ok(blob)
+}
+
+/// Decrypt a backup blob written by [Store::export_service](crate::Store::export_service)
+/// with the passphrase it was written with.
+///
+/// A wrong passphrase fails with an [Invalid](Error::Invalid) error about the
+/// `passphrase`, while a blob that's malformed, truncated, altered, or of an
+/// unsupported version fails with an [Invalid](Error::Invalid) error about the `blob`.
+pub fn open(blob: &[u8], passphrase: &str) -> Result<Backup> {
+    let malformed = || Error::Invalid("blob".to_string(), "malformed backup blob".to_string());
+    if blob.len() < HEADER_LEN || blob[..BLOB_MAGIC.len()] != BLOB_MAGIC {
+        return Err(malformed());
+    }
+    let version = blob[BLOB_MAGIC.len()];
+    if version != BLOB_VERSION {
+        let msg = format!("unsupported backup blob version {version}");
+        return Err(Error::Invalid("blob".to_string(), msg));
+    }
+    let (header, ciphertext) = blob.split_at(HEADER_LEN);
+    let params = &header[BLOB_MAGIC.len() + 1..];
+    let param = |i: usize| u32::from_be_bytes(params[4 * i..4 * i + 4].try_into().unwrap());
+    let (memory_cost, passes, parallelism) = (param(0), param(1), param(2));
+    if memory_cost > MAX_MEMORY_COST_KIB || passes > MAX_PASSES || parallelism > MAX_PARALLELISM {
+        let msg = "backup blob asks for too much work to open".to_string();
+        return Err(Error::Invalid("blob".to_string(), msg));
+    }
+    let (salt, rest) = params[12..].split_at(SALT_LEN);
+    let (expected_check, nonce) = rest.split_at(CHECK_LEN);
+    let nonce = Nonce::<Aes256Gcm>::from_slice(nonce);
+    let derived = derive_key(passphrase, salt, memory_cost, passes, parallelism)?;
+    let (key, check) = derived.split_at(KEY_LEN);
+    if check != expected_check {
+        let msg = "doesn't match the one the backup was made with".to_string();
+        return Err(Error::Invalid("passphrase".to_string(), msg));
+    }
+    let cipher = Aes256Gcm::new_from_slice(key).expect("AES-256 key has the wrong length");
+    let payload = Payload {
+        msg: ciphertext,
+        aad: header,
+    };
+    let plaintext = Zeroizing::new(cipher.decrypt(nonce, payload).map_err(|_| {
+        let msg = "backup blob is truncated or corrupted".to_string();
+        Error::Invalid("blob".to_string(), msg)
+    })?);
+    let contents: Contents = serde_json::from_slice(&plaintext).map_err(|_| malformed())?;
+    let mut credentials = Vec::with_capacity(contents.credentials.len());
+    for credential in contents.credentials {
+        let secret = Zeroizing::new(credential.secret);
+        let secret = STANDARD
+            .decode(secret.as_bytes())
+            .map_err(|_| malformed())?;
+        credentials.push((credential.user, Zeroizing::new(secret)));
+    }
+    Ok(Backup {
+        service: contents.service,
+        credentials,
+    })
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    memory_cost: u32,
+    passes: u32,
+    parallelism: u32,
+) -> Result<Zeroizing<[u8; KEY_LEN + CHECK_LEN]>> {
+    let output_len = KEY_LEN + CHECK_LEN;
+    let params = Params::new(memory_cost, passes, parallelism, Some(output_len)).map_err(|e| {
+        let msg = format!("invalid key derivation parameters: {e}");
+        Error::Invalid("blob".to_string(), msg)
+    })?;
+    let mut derived = Zeroizing::new([0u8; KEY_LEN + CHECK_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, derived.as_mut_slice())
+        .map_err(|e| Error::PlatformFailure(e.to_string().into()))?;
+    Ok(derived)
+}
@@ -0,0 +1,455 @@
+/*!
+# Authenticating with BiometricPrompt
+
+Keys that require user authentication (such as the legacy store's protected
+keys) can only be used after the user authenticates. The functions here show
+an AndroidX `BiometricPrompt` in a given `FragmentActivity` and use the key
+once the user has authenticated.
+
+A key that must be authenticated for each use is used through the cipher
+the prompt authenticated (its `CryptoObject`). A key that can be used for a
+while after the user authenticates can't be put into a cipher until then,
+so the prompt is shown first and the key is used afterwards.
+
+Applications that show their own prompt can instead get a [CryptoObject]
+for a credential (see the legacy store's `Cred::crypto_object`), show the
+prompt with it, and then decrypt with its authenticated cipher. That only
+works for keys that must be authenticated for each use.
+
+The prompt's callback must implement a Java class, which native code can't
+do, so applications that want prompts must depend on `androidx.biometric`
+and include this Kotlin shim, which shows the prompt on the UI thread and
+forwards its result to the Rust code waiting for it:
+
+```kotlin
+package io.crates.keyring
+
+import androidx.biometric.BiometricPrompt
+import androidx.core.content.ContextCompat
+import androidx.fragment.app.FragmentActivity
+import javax.crypto.Cipher
+
+class KeyringBiometricPrompt(private val handle: Long) :
+    BiometricPrompt.AuthenticationCallback() {
+    fun authenticate(
+        activity: FragmentActivity,
+        cipher: Cipher?,
+        title: String,
+        subtitle: String?,
+        negativeButton: String,
+    ) {
+        activity.runOnUiThread {
+            val executor = ContextCompat.getMainExecutor(activity)
+            val prompt = BiometricPrompt(activity, executor, this)
+            val info = BiometricPrompt.PromptInfo.Builder()
+                .setTitle(title)
+                .setSubtitle(subtitle)
+                .setNegativeButtonText(negativeButton)
+                .build()
+            if (cipher == null) {
+                prompt.authenticate(info)
+            } else {
+                prompt.authenticate(info, BiometricPrompt.CryptoObject(cipher))
+            }
+        }
+    }
+
+    override fun onAuthenticationSucceeded(result: BiometricPrompt.AuthenticationResult) {
+        onResult(handle, result.cryptoObject?.cipher, 0, null)
+    }
+
+    override fun onAuthenticationError(errorCode: Int, errString: CharSequence) {
+        onResult(handle, null, errorCode, errString.toString())
+    }
+
+    private external fun onResult(handle: Long, cipher: Cipher?, errorCode: Int, message: String?)
+}
+```
+
+## Checking for authenticators
+
+Before offering protected credentials, an application can check whether
+the device can authenticate the user at all with
+[Store::can_authenticate](crate::Store::can_authenticate), passing some
+combination of [BIOMETRIC_STRONG], [BIOMETRIC_WEAK] and [DEVICE_CREDENTIAL].
+
+## Threading
+
+The prompt runs on the application's main (UI) thread, and the functions
+here block until the user has dealt with it. So they must be called on
+some other thread: called on the main thread, they would wait forever for
+a prompt that can't be shown, so they fail with an
+[Invalid](keyring_core::Error::Invalid) error instead.
+ */
+
+use std::collections::HashMap;
+use std::sync::{Mutex, mpsc::Sender};
+#[cfg(feature = "legacy")]
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    mpsc::{Receiver, channel},
+};
+
+use jni::{
+    JNIEnv,
+    objects::{GlobalRef, JObject, JString},
+    sys::{jint, jlong},
+};
+
+#[cfg(feature = "legacy")]
+use crate::{
+    cipher::Cipher,
+    crypto::{decrypt, decrypt_authorized},
+    error::{AndroidKeyringError, AndroidKeyringResult},
+    keystore::Key,
+    methods::{
+        ClassDecl, Constructible, FromValue, Method, NoParam, SignatureComp, StaticMethod, ToValue,
+    },
+};
+use crate::{keyguard::BiometricManager, methods::JResult, shared_preferences::Context};
+#[cfg(feature = "legacy")]
+use jni::objects::JValueGen;
+
+pub use crate::cipher::CryptoObject;
+
+#[cfg(feature = "legacy")]
+const PROMPT_CLASS: &str = "io.crates.keyring.KeyringBiometricPrompt";
+
+// The BiometricPrompt error codes for a prompt the user dismissed
+// or that was canceled, rather than one that failed.
+#[cfg(feature = "legacy")]
+const ERROR_CANCELED: i32 = 5;
+#[cfg(feature = "legacy")]
+const ERROR_USER_CANCELED: i32 = 10;
+#[cfg(feature = "legacy")]
+const ERROR_NEGATIVE_BUTTON: i32 = 13;
+
+/// Authentication with a Class 3 (strong) biometric.
+pub const BIOMETRIC_STRONG: i32 = 0x000F;
+/// Authentication with a Class 2 (weak) or stronger biometric.
+pub const BIOMETRIC_WEAK: i32 = 0x00FF;
+/// Authentication with the device's screen lock (PIN, pattern or password).
+pub const DEVICE_CREDENTIAL: i32 = 0x8000;
+
+/// Whether the user can authenticate with the requested authenticators,
+/// decoded from the result codes of `BiometricManager.canAuthenticate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthenticationStatus {
+    /// The user can authenticate.
+    Success,
+    /// The device can't tell (typically on older Android versions).
+    Unknown,
+    /// The requested authenticators aren't supported on this Android version.
+    Unsupported,
+    /// The hardware is present but can't be used right now.
+    HardwareUnavailable,
+    /// The user hasn't enrolled any biometric or set a screen lock.
+    NoneEnrolled,
+    /// The device has no such hardware.
+    NoHardware,
+    /// A security vulnerability has been found in the hardware,
+    /// which can't be used until it's updated.
+    SecurityUpdateRequired,
+    /// A result code this crate doesn't know about.
+    Other(i32),
+}
+
+impl From<i32> for AuthenticationStatus {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => Self::Success,
+            -1 => Self::Unknown,
+            -2 => Self::Unsupported,
+            1 => Self::HardwareUnavailable,
+            11 => Self::NoneEnrolled,
+            12 => Self::NoHardware,
+            15 => Self::SecurityUpdateRequired,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Check whether the user can authenticate with the given authenticators.
+pub(crate) fn can_authenticate(
+    env: &mut JNIEnv,
+    context: &Context,
+    authenticators: i32,
+) -> JResult<AuthenticationStatus> {
+    let manager = BiometricManager::from_androidx(env, context)?;
+    let status = manager.can_authenticate(env, authenticators)?;
+    Ok(status.into())
+}
+
+/// The text shown by the prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptInfo {
+    pub title: String,
+    pub subtitle: Option<String>,
+    /// The label of the button that dismisses the prompt.
+    pub negative_button: String,
+}
+
+// The authenticated cipher (if the prompt was given one),
+// or the prompt's error code and message.
+pub(crate) type PromptResult = Result<Option<GlobalRef>, (i32, String)>;
+
+// The Java callback only holds a handle, so a result that arrives
+// after its caller has stopped waiting finds nothing to send to.
+static WAITING: Mutex<Option<HashMap<jlong, Sender<PromptResult>>>> = Mutex::new(None);
+#[cfg(feature = "legacy")]
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
+
+/// Decrypt data encrypted by [encrypt](crate::crypto::encrypt) with a key
+/// that requires user authentication, prompting the user to authenticate.
+///
+/// See the [module documentation](self) for the shim and the threading rules.
+#[cfg(feature = "legacy")]
+pub(crate) fn decrypt_with_prompt(
+    env: &mut JNIEnv,
+    context: &Context,
+    activity: &JObject,
+    key: Key,
+    aad: Option<&[u8]>,
+    data: Vec<u8>,
+    info: &PromptInfo,
+) -> AndroidKeyringResult<Vec<u8>> {
+    if is_main_thread(env)? {
+        return Err(keyring_core::Error::Invalid(
+            "thread".to_string(),
+            "can't wait for a prompt on the main thread".to_string(),
+        )
+        .into());
+    }
+    let key_info = key.get_key_info(env)?;
+    if !key_info.is_user_authentication_required(env)? {
+        return decrypt(env, key, aad, data);
+    }
+    if key_info.get_user_authentication_validity_duration_seconds(env)? <= 0 {
+        // the user authenticates this use of the key, through the cipher
+        return decrypt_authorized(env, key, aad, data, |env, cipher| {
+            Ok(prompt(env, context, activity, Some(&cipher), info)?.unwrap_or(cipher))
+        });
+    }
+    // the key can't be put into a cipher until the user has authenticated
+    match decrypt(env, key.clone(), aad, data.clone()) {
+        Err(AndroidKeyringError::AuthenticationRequired(_)) => {
+            prompt(env, context, activity, None, info)?;
+            decrypt(env, key, aad, data)
+        }
+        result => result,
+    }
+}
+
+/// Show the prompt, and wait for the user to authenticate.
+///
+/// If the prompt is given a cipher, the authenticated cipher is returned.
+#[cfg(feature = "legacy")]
+fn prompt(
+    env: &mut JNIEnv,
+    context: &Context,
+    activity: &JObject,
+    cipher: Option<&Cipher>,
+    info: &PromptInfo,
+) -> AndroidKeyringResult<Option<Cipher>> {
+    struct New;
+    impl Constructible for New {
+        type Param = jlong;
+        type Return = Prompt;
+    }
+    struct Authenticate<'a>(std::marker::PhantomData<&'a ()>);
+    impl<'a> Method for Authenticate<'a> {
+        type Param = (
+            Activity,
+            Option<&'a Cipher>,
+            &'a str,
+            Option<&'a str>,
+            &'a str,
+        );
+        type Return = ();
+
+        const NAME: &'static str = "authenticate";
+    }
+
+    let class = context
+        .get_class_loader(env)?
+        .load_class(env, PROMPT_CLASS)?;
+    let (handle, receiver) = wait_for_result();
+    let shown = New::call_new_loaded(&class, env, handle).and_then(|prompt| {
+        let params = (
+            Activity(env.new_global_ref(activity)?),
+            cipher,
+            info.title.as_str(),
+            info.subtitle.as_deref(),
+            info.negative_button.as_str(),
+        );
+        Authenticate::call(prompt.0.as_obj(), env, params)
+    });
+    if let Err(e) = shown {
+        forget(handle);
+        return Err(e.into());
+    }
+    receive_result(env, handle, &receiver)
+}
+
+/// Register for the result of a prompt, returning the handle
+/// the prompt is given and the receiver the result is sent to.
+#[cfg(feature = "legacy")]
+pub(crate) fn wait_for_result() -> (jlong, Receiver<PromptResult>) {
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
+    let (sender, receiver) = channel();
+    WAITING
+        .lock()
+        .expect("Prompt lock poisoned: report a bug!")
+        .get_or_insert_with(HashMap::new)
+        .insert(handle, sender);
+    (handle, receiver)
+}
+
+/// Wait for the result of a prompt, returning the authenticated cipher
+/// (if the prompt was given one).
+///
+/// A prompt the user dismissed fails with [AuthCanceled](AndroidKeyringError::AuthCanceled),
+/// and one that failed otherwise with
+/// [AuthenticationRequired](AndroidKeyringError::AuthenticationRequired).
+#[cfg(feature = "legacy")]
+pub(crate) fn receive_result(
+    env: &mut JNIEnv,
+    handle: jlong,
+    receiver: &Receiver<PromptResult>,
+) -> AndroidKeyringResult<Option<Cipher>> {
+    let result = receiver.recv().ok();
+    forget(handle);
+    match result {
+        Some(Ok(cipher)) => Ok(cipher
+            .map(|self_| Cipher::from_object(self_, env))
+            .transpose()?),
+        Some(Err((ERROR_CANCELED | ERROR_USER_CANCELED | ERROR_NEGATIVE_BUTTON, _))) => {
+            Err(AndroidKeyringError::AuthCanceled)
+        }
+        Some(Err((code, message))) => Err(AndroidKeyringError::AuthenticationRequired(Some(
+            format!("{message} (error {code})"),
+        ))),
+        None => Err(AndroidKeyringError::AuthenticationRequired(None)),
+    }
+}
+
+#[cfg(feature = "legacy")]
+fn forget(handle: jlong) {
+    if let Some(waiting) = WAITING
+        .lock()
+        .expect("Prompt lock poisoned: report a bug!")
+        .as_mut()
+    {
+        waiting.remove(&handle);
+    }
+}
+
+/// Whether this is the application's main (UI) thread.
+#[cfg(feature = "legacy")]
+fn is_main_thread(env: &mut JNIEnv) -> JResult<bool> {
+    struct MyLooper;
+    impl StaticMethod for MyLooper {
+        type Param = NoParam;
+        type Return = Option<Looper>;
+
+        const NAME: &str = "myLooper";
+    }
+    struct GetMainLooper;
+    impl StaticMethod for GetMainLooper {
+        type Param = NoParam;
+        type Return = Option<Looper>;
+
+        const NAME: &str = "getMainLooper";
+    }
+
+    let mine = MyLooper::call(Looper::class(), env, NoParam)?;
+    let main = GetMainLooper::call(Looper::class(), env, NoParam)?;
+    match (mine, main) {
+        (Some(mine), Some(main)) => env.is_same_object(mine.0.as_obj(), main.0.as_obj()),
+        _ => Ok(false),
+    }
+}
+
+#[cfg(feature = "legacy")]
+struct Looper(GlobalRef);
+#[cfg(feature = "legacy")]
+impl Looper {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/os/Looper;")
+    }
+}
+#[cfg(feature = "legacy")]
+impl FromValue for Looper {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self(self_))
+    }
+}
+
+#[cfg(feature = "legacy")]
+struct Prompt(GlobalRef);
+#[cfg(feature = "legacy")]
+impl FromValue for Prompt {
+    fn signature() -> SignatureComp {
+        ClassDecl("Lio/crates/keyring/KeyringBiometricPrompt;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self(self_))
+    }
+}
+
+#[cfg(feature = "legacy")]
+struct Activity(GlobalRef);
+#[cfg(feature = "legacy")]
+impl ToValue for Activity {
+    fn signature() -> SignatureComp {
+        ClassDecl("Landroidx/fragment/app/FragmentActivity;").into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.0)?.into())
+    }
+}
+
+/// Forward the result of a prompt from the Kotlin shim to the code waiting for it.
+///
+/// See the [module documentation](self) for the shim.
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_crates_keyring_KeyringBiometricPrompt_onResult(
+    mut env: JNIEnv,
+    _this: JObject,
+    handle: jlong,
+    cipher: JObject,
+    error_code: jint,
+    message: JString,
+) {
+    let result = if message.is_null() {
+        match cipher.is_null() {
+            true => Ok(None),
+            false => env
+                .new_global_ref(&cipher)
+                .map(Some)
+                .map_err(|e| (0, e.to_string())),
+        }
+    } else {
+        let message = env
+            .get_string(&message)
+            .map(String::from)
+            .unwrap_or_else(|e| e.to_string());
+        Err((error_code, message))
+    };
+    let sender = WAITING
+        .lock()
+        .expect("Prompt lock poisoned: report a bug!")
+        .as_ref()
+        .and_then(|waiting| waiting.get(&handle).cloned());
+    match sender {
+        Some(sender) => _ = sender.send(result),
+        None => tracing::warn!(handle, "No one is waiting for this prompt's result"),
+    }
+}
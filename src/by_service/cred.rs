@@ -1,26 +1,453 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use jni::{JNIEnv, JavaVM};
 use keyring_core::{Credential, api::CredentialApi};
+use zeroize::Zeroizing;
 
 use crate::{
-    crypto::{decrypt, encrypt},
-    error::AndroidKeyringResult,
+    backoff::{Backoff, classify_keystore_failure},
+    by_store::cred::aad,
+    crypto::{Transformation, decrypt, encrypt, encrypt_legacy, is_legacy, legacy_binding},
+    error::{AndroidKeyringError, AndroidKeyringResult, CorruptedData, pending_exception_class},
+    keyguard::{BIOMETRIC_SUCCESS, BiometricManager},
     keystore::{
-        BLOCK_MODE_GCM, ENCRYPTION_PADDING_NONE, KEY_ALGORITHM_AES, Key,
-        KeyGenParameterSpecBuilder, KeyGenerator, KeyStore, PROVIDER, PURPOSE_DECRYPT,
-        PURPOSE_ENCRYPT,
+        BLOCK_MODE_GCM, ENCRYPTION_PADDING_NONE, INVALID_KEY_EXCEPTION, KEY_ALGORITHM_AES, Key,
+        KeyGenParameterSpec, KeyGenParameterSpecBuilder, KeyGenerator, KeyStore, PROVIDER,
+        PURPOSE_DECRYPT, PURPOSE_ENCRYPT, key_auth_type,
+    },
+    methods::{JResult, sdk_int},
+    shared_preferences::{
+        Context, MODE_PRIVATE, SharedPreferences, SharedPreferencesEditor, StringSet,
+        current_time_millis,
     },
-    shared_preferences::{Context, MODE_PRIVATE, SharedPreferences},
 };
 
-use super::HasJavaVm;
+use super::{DEFAULT_READ_RETRIES, HasJavaVm};
+#[cfg(feature = "biometric")]
+use crate::crypto::{decrypt_with_cipher, decryption_cipher};
+
+/// The suffix of the key that holds a credential's attributes,
+/// which is kept in the service's file next to the credential's secret.
+const ATTRIBUTES_SUFFIX: &str = ".attrs";
+
+/// The suffix of the key that holds the time a credential's secret
+/// was first set, which is kept next to the secret like its attributes.
+const CTIME_SUFFIX: &str = ".ctime";
+
+/// The suffix of the key that holds the time a credential's secret was last set.
+const MTIME_SUFFIX: &str = ".mtime";
+
+/// The suffixes of the keys kept next to each credential's secret.
+const SIDECAR_SUFFIXES: [&str; 3] = [ATTRIBUTES_SUFFIX, CTIME_SUFFIX, MTIME_SUFFIX];
+
+/// The key that holds the set of users with a secret in a service's file,
+/// so that the service's credentials can be listed without reading the
+/// whole file. Files last written by earlier versions don't have one.
+pub(crate) const USERS_KEY: &str = ".users";
+
+/// The key that holds the set of shared files that a service's credentials
+/// have been kept in, so that the service-wide operations can reach them.
+const SHARED_FILES_KEY: &str = ".files";
+
+/// Whether the given key of a service's file holds a credential's
+/// attributes or timestamps, or one of the file's indexes, rather than a secret.
+/// Users can't have such names, or their secrets would overwrite those keys.
+fn is_sidecar_key(key: &str) -> bool {
+    key == USERS_KEY
+        || key == SHARED_FILES_KEY
+        || SIDECAR_SUFFIXES.iter().any(|suffix| key.ends_with(suffix))
+}
+
+/// Reject a user name that [is_sidecar_key].
+pub(crate) fn check_user(user: &str) -> keyring_core::Result<()> {
+    if is_sidecar_key(user) {
+        return Err(keyring_core::Error::Invalid(
+            "user".to_string(),
+            format!(
+                "cannot be {USERS_KEY} or {SHARED_FILES_KEY} or end with {}",
+                SIDECAR_SUFFIXES.join(", ")
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The users with a secret in a service's file, read from its index,
+/// or from its keys if it has no index yet.
+pub(crate) fn indexed_users(env: &mut JNIEnv, file: &SharedPreferences) -> JResult<StringSet> {
+    if let Some(users) = file.get_string_set(env, USERS_KEY)? {
+        return Ok(users);
+    }
+    let keys = file.get_all_keys(env)?;
+    Ok(StringSet(
+        keys.into_iter()
+            .filter(|key| !is_sidecar_key(key))
+            .collect(),
+    ))
+}
+
+/// The files that hold a service's credentials, given the service's own
+/// file: that file (as `None`), then each shared file recorded in it.
+pub(crate) fn service_files(
+    env: &mut JNIEnv,
+    file: &SharedPreferences,
+) -> JResult<Vec<Option<String>>> {
+    let mut files = vec![None];
+    if let Some(shared) = file.get_string_set(env, SHARED_FILES_KEY)? {
+        files.extend(shared.0.into_iter().map(Some));
+    }
+    Ok(files)
+}
+
+/// The users of the given service that have a secret in the given file,
+/// each with the key of its secret, which is prefixed by the service
+/// if the file is a shared one.
+fn service_users(
+    env: &mut JNIEnv,
+    file: &SharedPreferences,
+    service: &str,
+    shared: bool,
+) -> JResult<Vec<(String, String)>> {
+    let users = indexed_users(env, file)?.0.into_iter();
+    if !shared {
+        return Ok(users.map(|user| (user.clone(), user)).collect());
+    }
+    let prefix = format!("{service}{SERVICE_SEPARATOR}");
+    Ok(users
+        .filter_map(|key| Some((key.strip_prefix(&prefix)?.to_string(), key.clone())))
+        .collect())
+}
+
+/// Remove the secrets of the given service from a file it shares with
+/// other services, along with their attributes, timestamps, and index
+/// entries, returning whether there were any.
+pub(crate) fn remove_from_shared_file(
+    env: &mut JNIEnv,
+    context: &Context,
+    shared_file: &str,
+    service: &str,
+) -> AndroidKeyringResult<bool> {
+    let name = file_name(shared_file);
+    let file = context.get_shared_preferences(env, &name, MODE_PRIVATE)?;
+    let lock = file_lock(&name);
+    let _lock = lock.lock().unwrap();
+    let keys: Vec<String> = service_users(env, &file, service, true)?
+        .into_iter()
+        .map(|(_, key)| key)
+        .collect();
+    if keys.is_empty() {
+        return Ok(false);
+    }
+    let edit = file.edit(env)?;
+    put_index(env, &file, &edit, &keys, false)?;
+    for key in &keys {
+        edit.remove(env, key)?;
+        for suffix in SIDECAR_SUFFIXES {
+            edit.remove(env, &format!("{key}{suffix}"))?;
+        }
+    }
+    if !edit.commit(env)? {
+        let err = format!("Failed to remove the secrets of service {service:?}");
+        return Err(keyring_core::Error::PlatformFailure(err.into()).into());
+    }
+    Ok(true)
+}
+
+/// If the secret under the given key of a file should keep the legacy layout
+/// when it's rewritten (because `upgrade_envelopes` is false and it's in that
+/// layout), whether it's bound to additional authenticated data.
+fn previous_binding(
+    env: &mut JNIEnv,
+    file: &SharedPreferences,
+    key: &str,
+    upgrade_envelopes: bool,
+) -> AndroidKeyringResult<Option<bool>> {
+    if upgrade_envelopes {
+        return Ok(None);
+    }
+    let previous = match file.get_binary(env, key) {
+        Ok(previous) => previous,
+        // a corrupted value is replaced by one in the current layout
+        Err(AndroidKeyringError::CorruptedData(_, _)) => None,
+        Err(err) => return Err(err),
+    };
+    Ok(previous.as_deref().and_then(legacy_binding))
+}
+
+/// Encrypt a secret in the current layout, or, if `legacy_bound` is given,
+/// in the legacy layout, bound to the additional authenticated data if it's true.
+fn encrypt_secret(
+    env: &mut JNIEnv,
+    key: Key,
+    aad: &[u8],
+    legacy_bound: Option<bool>,
+    secret: &[u8],
+) -> AndroidKeyringResult<Vec<u8>> {
+    match legacy_bound {
+        Some(bound) => {
+            let aad = Some(aad).filter(|_| bound);
+            encrypt_legacy(env, key, Transformation::default(), aad, secret)
+        }
+        None => encrypt(env, key, Transformation::default(), Some(aad), secret),
+    }
+}
+
+/// A lock for each file that has been written.
+static FILE_LOCKS: Mutex<BTreeMap<String, Arc<Mutex<()>>>> = Mutex::new(BTreeMap::new());
+
+/// The lock that serializes the edits of the file with the given name.
+///
+/// Each edit reads the file (for example, its index of users) before
+/// changing it, so concurrent edits of the same file, even for different
+/// users, could otherwise lose each other's changes when committed.
+/// The lock is held from the read until the editor is committed.
+/// Edits of different files don't wait for each other.
+pub(crate) fn file_lock(name: &str) -> Arc<Mutex<()>> {
+    FILE_LOCKS
+        .lock()
+        .expect("File locks poisoned: report a bug!")
+        .entry(name.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Record in the editor that the given users now have secrets in the file
+/// (or, if `present` is false, no longer do). The caller must hold
+/// the [file_lock] until the editor is committed.
+fn put_index<U: AsRef<str>>(
+    env: &mut JNIEnv,
+    file: &SharedPreferences,
+    edit: &SharedPreferencesEditor,
+    users: &[U],
+    present: bool,
+) -> JResult<()> {
+    let mut index = indexed_users(env, file)?;
+    for user in users {
+        if present {
+            index.0.insert(user.as_ref().to_string());
+        } else {
+            index.0.remove(user.as_ref());
+        }
+    }
+    edit.put_string_set(env, USERS_KEY, &index)?;
+    Ok(())
+}
+
+/// The suffix of the alias of the key that holds
+/// a service's secrets while its key is rotated.
+const ROTATION_SUFFIX: &str = "/rotation";
+
+/// The suffix of the key alias for a service's protected credentials.
+const PROTECTED_SUFFIX: &str = "/auth";
+
+/// The alias of the key that holds a service's secrets while its key is rotated.
+pub(crate) fn rotation_alias(service: &str) -> String {
+    format!("{service}{ROTATION_SUFFIX}")
+}
+
+/// Whether the given alias is one that a service's key can't have,
+/// because it ends with the suffix of a rotation or protected key.
+///
+/// Services with such names are rejected, so those keys
+/// can't collide with the key of another service.
+pub(crate) fn is_reserved_alias(alias: &str) -> bool {
+    alias.ends_with(ROTATION_SUFFIX) || alias.ends_with(PROTECTED_SUFFIX)
+}
+
+/// The name of the file that holds a service's credentials.
+///
+/// File names can't contain a path separator or a null character, so those
+/// are percent-encoded, along with the percent sign itself so that no two
+/// services share a file. Other characters (including `:`, which Android's
+/// file systems allow) are kept, so a service's file is usually named
+/// by the service itself, as it was in earlier versions of this crate.
+pub(crate) fn file_name(service: &str) -> String {
+    let mut name = String::with_capacity(service.len());
+    for c in service.chars() {
+        match c {
+            '%' | '/' | '\0' => name.push_str(&format!("%{:02X}", c as u32)),
+            c => name.push(c),
+        }
+    }
+    name
+}
+
+/// The character between the service and the user in the keys
+/// of a file shared by several services.
+pub(crate) const SERVICE_SEPARATOR: char = '\0';
+
+/// The key of a credential's secret in a file shared by several services.
+fn shared_key(service: &str, user: &str) -> String {
+    format!("{service}{SERVICE_SEPARATOR}{user}")
+}
+
+/// How long, by default, a protected key can be used after the user
+/// last authenticated (for example, by unlocking the device).
+const AUTH_VALIDITY_SECONDS: u32 = 30;
+
+/// The API level that introduced `setUserAuthenticationParameters`.
+const AUTH_PARAMETERS_SDK: i32 = 30;
+
+/// How the user can authenticate to use a protected key.
+///
+/// The platform can only bind keys to Class 3 (strong) biometrics,
+/// so there is no way to accept a weak one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthType {
+    /// A Class 3 (strong) biometric.
+    Biometric,
+    /// The device's screen lock (PIN, pattern or password).
+    DeviceCredential,
+    /// Either of them.
+    #[default]
+    Any,
+}
+
+impl AuthType {
+    // whether a biometric and the device credential are accepted
+    fn parts(self) -> (bool, bool) {
+        match self {
+            AuthType::Biometric => (true, false),
+            AuthType::DeviceCredential => (false, true),
+            AuthType::Any => (true, true),
+        }
+    }
+}
+
+impl std::str::FromStr for AuthType {
+    type Err = String;
+
+    /// Parse either a name (`biometric`, `device_credential`, or `any`)
+    /// or a `|`-separated combination of the `BiometricManager.Authenticators`
+    /// names (`biometric_strong`, `biometric_weak`, and `device_credential`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "biometric" => return Ok(AuthType::Biometric),
+            "any" => return Ok(AuthType::Any),
+            _ => {}
+        }
+        let (mut biometric, mut device_credential) = (false, false);
+        for part in s.split('|').map(str::trim) {
+            match part {
+                "biometric_strong" => biometric = true,
+                "device_credential" => device_credential = true,
+                "biometric_weak" => {
+                    return Err("keys can only require a strong biometric".to_string());
+                }
+                _ => {
+                    return Err("must be biometric_strong, device_credential, or both".to_string());
+                }
+            }
+        }
+        match (biometric, device_credential) {
+            (true, false) => Ok(AuthType::Biometric),
+            (false, true) => Ok(AuthType::DeviceCredential),
+            _ => Ok(AuthType::Any),
+        }
+    }
+}
+
+/// When the user must authenticate to use a service's protected key.
+///
+/// The policy is fixed when the key is generated, so it only applies
+/// to a service whose protected key doesn't exist yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthPolicy {
+    /// How long, in seconds, the key can be used after the user authenticates.
+    /// If this is 0, the user must authenticate every use of the key.
+    pub validity_seconds: u32,
+    /// How the user can authenticate. Before API level 30, keys that can
+    /// be used for a while accept either type, and keys that must be
+    /// authenticated for each use only accept a biometric.
+    pub auth_type: AuthType,
+}
+
+impl Default for AuthPolicy {
+    fn default() -> Self {
+        Self {
+            validity_seconds: AUTH_VALIDITY_SECONDS,
+            auth_type: AuthType::default(),
+        }
+    }
+}
+
+/// The alias of the key for a service's protected credentials.
+pub(crate) fn protected_alias(service: &str) -> String {
+    format!("{service}{PROTECTED_SUFFIX}")
+}
+
+/// Whether an operation failed because the key it used has been replaced,
+/// for example because it was invalidated or rotated, so a fresh key may work.
+///
+/// A key whose keystore entry has been deleted fails with a plain
+/// `InvalidKeyException`. Its subclasses aren't matched, because they
+/// report keys that exist but can't be used right now.
+fn key_was_replaced(env: &mut JNIEnv, err: &AndroidKeyringError) -> bool {
+    let is_invalid_key = |class: &str| class.replace('.', "/") == INVALID_KEY_EXCEPTION;
+    match err {
+        AndroidKeyringError::KeyInvalidated => true,
+        AndroidKeyringError::JavaExceptionThrow { class, .. } => is_invalid_key(class),
+        AndroidKeyringError::JniError(jni::errors::Error::JavaException) => {
+            pending_exception_class(env).is_some_and(|class| is_invalid_key(&class))
+        }
+        _ => false,
+    }
+}
+
+/// What using a credential does when its key
+/// has been permanently invalidated by the platform.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnKeyInvalidated {
+    /// Fail with a [NoStorageAccess](keyring_core::Error::NoStorageAccess)
+    /// error that wraps [AndroidKeyringError::KeyInvalidated].
+    #[default]
+    Error,
+    /// Reading the secret deletes it and fails with a
+    /// [NoEntry](keyring_core::Error::NoEntry) error, and setting the
+    /// secret replaces the key with a newly generated one.
+    Delete,
+}
+
+impl std::str::FromStr for OnKeyInvalidated {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OnKeyInvalidated::Error),
+            "delete" => Ok(OnKeyInvalidated::Delete),
+            _ => Err(()),
+        }
+    }
+}
 
 pub struct Cred {
     java_vm: Arc<JavaVM>,
     context: Context,
     service: String,
     user: String,
+    // the file shared with other services, if not the service's own
+    shared_file: Option<String>,
+    require_auth: bool,
+    // when the user must authenticate, if `require_auth` is true
+    auth_policy: AuthPolicy,
+    // what using the credential does when its key is invalidated
+    on_key_invalidated: OnKeyInvalidated,
+    // whether deleting a missing credential succeeds
+    idempotent_delete: bool,
+    // how many times reads are retried after a transient JNI failure
+    read_retries: u32,
+    // whether a secret in the legacy layout is rewritten with a header
+    upgrade_envelopes: bool,
+    // the size in bits of the credential's key, if it's generated, or the default
+    key_size: Option<i32>,
+    // how generating the credential's key is retried after a transient failure
+    key_generation: Backoff,
+    // the credential's key, once it has been looked up
+    key: Mutex<Option<Key>>,
 }
 
 impl std::fmt::Debug for Cred {
@@ -28,64 +455,883 @@ impl std::fmt::Debug for Cred {
         f.debug_struct("AndroidCredential")
             .field("service", &self.service)
             .field("user", &self.user)
+            .field("shared_file", &self.shared_file)
+            .field("require_auth", &self.require_auth)
+            .field("auth_policy", &self.auth_policy)
+            .field("on_key_invalidated", &self.on_key_invalidated)
+            .field("idempotent_delete", &self.idempotent_delete)
+            .field("read_retries", &self.read_retries)
+            .field("upgrade_envelopes", &self.upgrade_envelopes)
+            .field("key_size", &self.key_size)
+            .field("key_generation", &self.key_generation)
             .finish()
     }
 }
 
 impl Cred {
     pub fn new(java_vm: Arc<JavaVM>, context: Context, service: &str, user: &str) -> Self {
+        Self::new_with_auth(java_vm, context, service, user, false)
+    }
+
+    /// Create a credential whose secret is encrypted with a key that
+    /// requires user authentication, if `require_auth` is true.
+    ///
+    /// Protected and unprotected credentials of a service share its file
+    /// but not its key, so a credential must always be built the same way.
+    pub fn new_with_auth(
+        java_vm: Arc<JavaVM>,
+        context: Context,
+        service: &str,
+        user: &str,
+        require_auth: bool,
+    ) -> Self {
         Self {
             java_vm,
             context,
             service: service.to_owned(),
             user: user.to_owned(),
+            shared_file: None,
+            require_auth,
+            auth_policy: AuthPolicy::default(),
+            on_key_invalidated: OnKeyInvalidated::default(),
+            idempotent_delete: false,
+            read_retries: DEFAULT_READ_RETRIES,
+            upgrade_envelopes: true,
+            key_size: None,
+            key_generation: Backoff::default(),
+            key: Mutex::new(None),
+        }
+    }
+
+    /// Use the given policy for the key of the service's protected
+    /// credentials, if `require_auth` is true and the key is generated.
+    pub fn with_auth_policy(mut self, auth_policy: AuthPolicy) -> Self {
+        self.auth_policy = auth_policy;
+        self
+    }
+
+    /// Choose what using this credential does when its key has been
+    /// permanently invalidated. See [OnKeyInvalidated].
+    pub fn with_on_key_invalidated(mut self, on_key_invalidated: OnKeyInvalidated) -> Self {
+        self.on_key_invalidated = on_key_invalidated;
+        self
+    }
+
+    /// Make deleting this credential succeed even if it has no secret,
+    /// if `idempotent_delete` is true, rather than failing with
+    /// a [NoEntry](keyring_core::Error::NoEntry) error.
+    pub fn with_idempotent_delete(mut self, idempotent_delete: bool) -> Self {
+        self.idempotent_delete = idempotent_delete;
+        self
+    }
+
+    /// Retry reads of this credential up to `read_retries` times after a
+    /// transient JNI failure, rather than the default of once. Writes are
+    /// never retried.
+    pub fn with_read_retries(mut self, read_retries: u32) -> Self {
+        self.read_retries = read_retries;
+        self
+    }
+
+    /// Keep this credential in the file with the given name, which it shares
+    /// with other services, rather than in the service's own file.
+    ///
+    /// In a shared file, the credential's secret is kept under the key
+    /// `{service}\0{user}`, so the service can't contain a null character.
+    /// The service's key is the same either way.
+    pub fn with_file_name(mut self, file_name: Option<&str>) -> Self {
+        self.shared_file = file_name.map(str::to_owned);
+        self
+    }
+
+    /// Keep this credential's secret in the legacy layout when it's rewritten,
+    /// if it's in that layout and `upgrade_envelopes` is false, so that
+    /// earlier versions of this crate can still read it.
+    pub fn with_upgrade_envelopes(mut self, upgrade_envelopes: bool) -> Self {
+        self.upgrade_envelopes = upgrade_envelopes;
+        self
+    }
+
+    /// Generate this credential's key with the given size in bits, rather than
+    /// the platform default, if the key doesn't exist yet.
+    pub fn with_key_size(mut self, key_size: Option<i32>) -> Self {
+        self.key_size = key_size;
+        self
+    }
+
+    /// Try generating this credential's key up to `attempts` times
+    /// if it fails transiently, waiting `base_delay` before the first
+    /// retry and twice as long before each one after that.
+    pub fn with_key_generation_backoff(mut self, attempts: u32, base_delay: Duration) -> Self {
+        self.key_generation = Backoff {
+            max_attempts: attempts,
+            base_delay,
+        };
+        self
+    }
+
+    /// The name of the file that holds this credential.
+    fn physical_file_name(&self) -> String {
+        file_name(self.shared_file.as_deref().unwrap_or(&self.service))
+    }
+
+    /// The key of this credential's secret in its file, which its
+    /// attributes and timestamps are kept next to.
+    fn entry_key(&self) -> String {
+        match self.shared_file {
+            Some(_) => shared_key(&self.service, &self.user),
+            None => self.user.clone(),
         }
     }
 
-    fn get_key(env: &mut JNIEnv, service: &str) -> AndroidKeyringResult<Key> {
+    /// The alias of the key that encrypts this credential's secret.
+    pub(crate) fn key_alias(&self) -> String {
+        if self.require_auth {
+            protected_alias(&self.service)
+        } else {
+            self.service.clone()
+        }
+    }
+
+    /// The policy for this credential's key, if it requires authentication.
+    fn auth(&self) -> Option<AuthPolicy> {
+        self.require_auth.then_some(self.auth_policy)
+    }
+
+    /// Get the key with the given alias, generating it if necessary.
+    ///
+    /// If the key is generated with an authentication policy, it can only be
+    /// used as the policy allows after the user authenticates, which requires
+    /// the device to have a secure lock screen. If it's generated with a size
+    /// (in bits), it has that size rather than the platform default. Failures
+    /// to generate it that might be transient are retried as `backoff` says.
+    fn get_key(
+        env: &mut JNIEnv,
+        context: &Context,
+        alias: &str,
+        auth: Option<AuthPolicy>,
+        key_size: Option<i32>,
+        backoff: Backoff,
+    ) -> AndroidKeyringResult<Key> {
         static SERVICE_LOCK: Mutex<()> = Mutex::new(());
         let _lock = SERVICE_LOCK.lock().unwrap();
 
         let keystore = KeyStore::get_instance(env, PROVIDER)?;
         keystore.load(env)?;
 
-        Ok(match keystore.get_key(env, service)? {
+        Ok(match keystore.get_key(env, alias)? {
             Some(key) => key,
             None => {
-                let key_generator_spec = KeyGenParameterSpecBuilder::new(
-                    env,
-                    service,
-                    PURPOSE_DECRYPT | PURPOSE_ENCRYPT,
-                )?
-                .set_block_modes(env, &[BLOCK_MODE_GCM])?
-                .set_encryption_paddings(env, &[ENCRYPTION_PADDING_NONE])?
-                .set_user_authentication_required(env, false)?
-                .build(env)?;
+                let key_generator_spec = Self::key_spec(env, context, alias, auth, key_size)?;
                 let key_generator = KeyGenerator::get_instance(env, KEY_ALGORITHM_AES, PROVIDER)?;
                 key_generator.init(env, key_generator_spec.into())?;
-                let key = key_generator.generate_key(env)?;
+                let key = backoff.retry(|| {
+                    key_generator
+                        .generate_key(env)
+                        .map_err(|e| classify_keystore_failure(env, e))
+                })?;
                 key.into()
             }
         })
     }
 
+    /// The spec for generating a key with the given alias.
+    ///
+    /// The platform call that sets the authentication policy
+    /// depends on the device's API level. From API level 30, a policy
+    /// the user can't currently satisfy (for example, a biometric when
+    /// none is enrolled) is rejected rather than used to generate a key.
+    pub(crate) fn key_spec(
+        env: &mut JNIEnv,
+        context: &Context,
+        alias: &str,
+        auth: Option<AuthPolicy>,
+        key_size: Option<i32>,
+    ) -> AndroidKeyringResult<KeyGenParameterSpec> {
+        let purposes = PURPOSE_DECRYPT | PURPOSE_ENCRYPT;
+        let mut builder = KeyGenParameterSpecBuilder::new(env, KEY_ALGORITHM_AES, alias, purposes)?
+            .set_block_modes(env, &[BLOCK_MODE_GCM])?
+            .set_encryption_paddings(env, &[ENCRYPTION_PADDING_NONE])?
+            .set_user_authentication_required(env, auth.is_some())?;
+        if let Some(bits) = key_size {
+            builder = builder.set_key_size(env, bits)?;
+        }
+        let Some(policy) = auth else {
+            return Ok(builder.build(env)?);
+        };
+        let seconds = i32::try_from(policy.validity_seconds).map_err(|_| {
+            let err = "is too long".to_string();
+            keyring_core::Error::Invalid("auth_validity_seconds".to_string(), err)
+        })?;
+        let (biometric, device_credential) = policy.auth_type.parts();
+        let builder = if sdk_int(env)? >= AUTH_PARAMETERS_SDK {
+            let authenticators =
+                BiometricManager::authenticators(env, biometric, device_credential)?;
+            let status = BiometricManager::from_context(env, context)?
+                .can_authenticate(env, authenticators)?;
+            if status != BIOMETRIC_SUCCESS {
+                let err = format!(
+                    "User can't authenticate with {:?} (BiometricManager status {status})",
+                    policy.auth_type
+                );
+                return Err(keyring_core::Error::NotSupportedByStore(err).into());
+            }
+            let auth_type = key_auth_type(env, biometric, device_credential)?;
+            builder.set_user_authentication_parameters(env, seconds, auth_type)?
+        } else if seconds > 0 {
+            builder.set_user_authentication_validity_duration_seconds(env, seconds)?
+        } else if policy.auth_type != AuthType::DeviceCredential {
+            // before API level 30, -1 means a biometric for every use
+            builder.set_user_authentication_validity_duration_seconds(env, -1)?
+        } else {
+            let err = "Authenticating every use with the device credential needs API level 30"
+                .to_string();
+            return Err(keyring_core::Error::NotSupportedByStore(err).into());
+        };
+        Ok(builder.build(env)?)
+    }
+
+    /// Run an operation with the credential's key, which is only
+    /// looked up the first time it's needed.
+    ///
+    /// The key may be replaced after it's been cached, for example because
+    /// it was invalidated or rotated, so if the operation fails because the
+    /// cached key is gone, the cache is dropped and the operation is retried
+    /// with a fresh one. Other failures are returned as they are.
+    fn with_key<T, F>(&self, env: &mut JNIEnv, mut f: F) -> AndroidKeyringResult<T>
+    where
+        F: FnMut(&mut JNIEnv, Key) -> AndroidKeyringResult<T>,
+    {
+        let mut cached = self.key.lock().expect("Key lock poisoned: report a bug!");
+        if let Some(key) = cached.take() {
+            match self.use_key(env, key.clone(), &mut f) {
+                Err(e) if key_was_replaced(env, &e) => {
+                    log::debug!(
+                        "Retrying with a fresh key for service {:?}: {e}",
+                        self.service
+                    );
+                    env.exception_clear()?;
+                }
+                r => {
+                    *cached = Some(key);
+                    return r;
+                }
+            }
+        }
+        let key = self.lookup_key(env)?;
+        *cached = Some(key.clone());
+        self.use_key(env, key, &mut f)
+    }
+
+    /// Delete this credential's invalidated key, so that
+    /// the next use of the key generates a new one.
+    fn replace_invalidated_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<()> {
+        log::warn!("Key {:?} was invalidated, replacing it", self.key_alias());
+        env.exception_clear()?;
+        *self.key.lock().expect("Key lock poisoned: report a bug!") = None;
+        self.delete_key(env)
+    }
+
+    /// Look up this credential's key in the keystore,
+    /// generating it if it doesn't exist.
+    #[cfg(not(feature = "compile-tests"))]
+    fn lookup_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<Key> {
+        Self::get_key(
+            env,
+            &self.context,
+            &self.key_alias(),
+            self.auth(),
+            self.key_size,
+            self.key_generation,
+        )
+    }
+
+    /// Run an operation with this credential's key.
+    #[cfg(not(feature = "compile-tests"))]
+    fn use_key<T, F>(&self, env: &mut JNIEnv, key: Key, f: &mut F) -> AndroidKeyringResult<T>
+    where
+        F: FnMut(&mut JNIEnv, Key) -> AndroidKeyringResult<T>,
+    {
+        f(env, key)
+    }
+
+    /// Delete this credential's key from the keystore.
+    #[cfg(not(feature = "compile-tests"))]
+    fn delete_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<()> {
+        let keystore = KeyStore::get_instance(env, PROVIDER)?;
+        keystore.load(env)?;
+        keystore.delete_entry(env, &self.key_alias())?;
+        Ok(())
+    }
+
+    /// Remove this credential's secret, along with its attributes,
+    /// timestamps, and index entry, returning whether the removal was committed.
+    fn remove_secret(&self, env: &mut JNIEnv, file: &SharedPreferences) -> JResult<bool> {
+        let key = self.entry_key();
+        let lock = file_lock(&self.physical_file_name());
+        let _lock = lock.lock().unwrap();
+        let edit = file.edit(env)?;
+        put_index(env, file, &edit, &[&key], false)?;
+        edit.remove(env, &self.attributes_key())?
+            .remove(env, &format!("{key}{CTIME_SUFFIX}"))?
+            .remove(env, &format!("{key}{MTIME_SUFFIX}"))?
+            .remove(env, &key)?
+            .commit(env)
+    }
+
+    /// Decrypt a secret with the key that has the given alias.
+    ///
+    /// If that key can't decrypt it, the secret may have been left
+    /// under the rotation key by an interrupted [rotate_key](Cred::rotate_key),
+    /// so that key is tried as well.
+    fn decrypt_secret(
+        env: &mut JNIEnv,
+        key: Key,
+        alias: &str,
+        aad: &[u8],
+        data: Vec<u8>,
+    ) -> AndroidKeyringResult<Vec<u8>> {
+        match decrypt(env, key, Some(aad), data) {
+            Err(AndroidKeyringError::CorruptedData(data, CorruptedData::DecryptionFailure)) => {
+                env.exception_clear()?;
+                let keystore = KeyStore::get_instance(env, PROVIDER)?;
+                keystore.load(env)?;
+                match keystore.get_key(env, &rotation_alias(alias))? {
+                    Some(key) => decrypt(env, key, Some(aad), data),
+                    None => Err(AndroidKeyringError::CorruptedData(
+                        data,
+                        CorruptedData::DecryptionFailure,
+                    )),
+                }
+            }
+            r => r,
+        }
+    }
+
+    /// Record in the editor that the user's secret was set at the given time,
+    /// which is also its creation time if it didn't have a secret before.
+    fn put_timestamps(
+        env: &mut JNIEnv,
+        file: &SharedPreferences,
+        edit: &SharedPreferencesEditor,
+        user: &str,
+        now: i64,
+    ) -> AndroidKeyringResult<()> {
+        edit.put_long(env, &format!("{user}{MTIME_SUFFIX}"), now)?;
+        let ctime_key = format!("{user}{CTIME_SUFFIX}");
+        if !file.contains(env, &ctime_key)? {
+            edit.put_long(env, &ctime_key, now)?;
+        }
+        Ok(())
+    }
+
+    /// Encrypt the given secrets with the key at the given alias, and write
+    /// them to the service's file, or to the given shared file, in a single commit.
+    ///
+    /// If `modified` is true, the secrets' timestamps are updated as well.
+    /// If `upgrade_envelopes` is false, secrets that are in the legacy layout
+    /// are kept in it (see [with_upgrade_envelopes](Cred::with_upgrade_envelopes)).
+    /// If any secret can't be encrypted, nothing is written.
+    #[allow(clippy::too_many_arguments)]
+    fn encrypt_secrets<U: AsRef<str>, S: AsRef<[u8]>>(
+        env: &mut JNIEnv,
+        context: &Context,
+        service: &str,
+        shared_file: Option<&str>,
+        alias: &str,
+        secrets: &[(U, S)],
+        modified: bool,
+        upgrade_envelopes: bool,
+    ) -> AndroidKeyringResult<()> {
+        let key = Self::get_key(env, context, alias, None, None, Backoff::default())?;
+        let now = current_time_millis(env)?;
+        let name = file_name(shared_file.unwrap_or(service));
+        let file = context.get_shared_preferences(env, &name, MODE_PRIVATE)?;
+        let lock = file_lock(&name);
+        let _lock = lock.lock().unwrap();
+        let edit = file.edit(env)?;
+        let mut keys = Vec::with_capacity(secrets.len());
+        for (user, secret) in secrets {
+            let entry_key = match shared_file {
+                Some(_) => shared_key(service, user.as_ref()),
+                None => user.as_ref().to_string(),
+            };
+            let legacy_bound = previous_binding(env, &file, &entry_key, upgrade_envelopes)?;
+            let aad = aad(service, user.as_ref());
+            let ciphertext = encrypt_secret(env, key.clone(), &aad, legacy_bound, secret.as_ref())?;
+            #[cfg(feature = "iv-check")]
+            crate::crypto::check_iv_unique(alias, &ciphertext)?;
+            edit.put_binary(env, &entry_key, &ciphertext)?;
+            if modified {
+                Self::put_timestamps(env, &file, &edit, &entry_key, now)?;
+            }
+            keys.push(entry_key);
+        }
+        put_index(env, &file, &edit, &keys, true)?;
+        if !edit.commit(env)? {
+            let err = format!("Failed to write secrets under key {alias:?}");
+            return Err(keyring_core::Error::PlatformFailure(err.into()).into());
+        }
+        Ok(())
+    }
+
+    /// Set the secrets of several users of the given service at once,
+    /// with a single lookup of the service's key and a single commit.
+    pub(crate) fn set_many(
+        env: &mut JNIEnv,
+        context: &Context,
+        service: &str,
+        secrets: &[(&str, &[u8])],
+        upgrade_envelopes: bool,
+    ) -> AndroidKeyringResult<()> {
+        Self::encrypt_secrets(
+            env,
+            context,
+            service,
+            None,
+            service,
+            secrets,
+            true,
+            upgrade_envelopes,
+        )
+    }
+
+    /// Replace the key of the given service with a new one,
+    /// re-encrypting the secrets of all of its users.
+    ///
+    /// Android Keystore keys can't be renamed, so the secrets are first
+    /// moved to a temporary key, then the service's key is replaced and the
+    /// secrets are moved back. Each move is a single commit and every secret
+    /// is always readable by one key or the other, so a rotation that fails
+    /// part way leaves nothing unreadable, and can simply be run again.
+    ///
+    /// The secrets in the shared files that the service's credentials are kept
+    /// in (see [service_files]) are re-encrypted as well, each file's in a
+    /// commit of its own.
+    ///
+    /// Only the key of unprotected credentials is rotated. The secrets of
+    /// protected credentials can't be decrypted with it, so a service
+    /// that has any can't be rotated: every secret is decrypted before
+    /// anything is written, so such a service is left as it was.
+    pub(crate) fn rotate_key(
+        env: &mut JNIEnv,
+        context: &Context,
+        service: &str,
+        upgrade_envelopes: bool,
+    ) -> AndroidKeyringResult<()> {
+        let key = Self::get_key(env, context, service, None, None, Backoff::default())?;
+        // the new keys are generated with the size of the old one
+        let key_size = Some(key.get_key_info(env)?.get_key_size(env)?);
+        let keystore = KeyStore::get_instance(env, PROVIDER)?;
+        keystore.load(env)?;
+        let has_protected_key = keystore.contains_alias(env, &protected_alias(service))?;
+        let own = Self::get_file(env, context, service)?;
+        let mut files = Vec::new();
+        for shared_file in service_files(env, &own)? {
+            let file = Self::get_file(env, context, shared_file.as_deref().unwrap_or(service))?;
+            let mut secrets = Vec::new();
+            for (user, entry_key) in service_users(env, &file, service, shared_file.is_some())? {
+                let Some(data) = file.get_binary(env, &entry_key)? else {
+                    continue;
+                };
+                let aad = aad(service, &user);
+                let secret = match Self::decrypt_secret(env, key.clone(), service, &aad, data) {
+                    // the service's key can't decrypt a protected credential's secret
+                    Err(AndroidKeyringError::CorruptedData(
+                        _,
+                        CorruptedData::DecryptionFailure,
+                    )) if has_protected_key => {
+                        env.exception_clear()?;
+                        let err = format!(
+                            "Service {service:?} has protected credentials, so its key can't be rotated"
+                        );
+                        return Err(keyring_core::Error::NotSupportedByStore(err).into());
+                    }
+                    r => r?,
+                };
+                secrets.push((user, Zeroizing::new(secret)));
+            }
+            if shared_file.is_none() || !secrets.is_empty() {
+                files.push((shared_file, secrets));
+            }
+        }
+        let rotation_alias = rotation_alias(service);
+        Self::get_key(
+            env,
+            context,
+            &rotation_alias,
+            None,
+            key_size,
+            Backoff::default(),
+        )?;
+        for (shared_file, secrets) in &files {
+            let shared_file = shared_file.as_deref();
+            Self::encrypt_secrets(
+                env,
+                context,
+                service,
+                shared_file,
+                &rotation_alias,
+                secrets,
+                false,
+                upgrade_envelopes,
+            )?;
+        }
+        keystore.delete_entry(env, service)?;
+        Self::get_key(env, context, service, None, key_size, Backoff::default())?;
+        for (shared_file, secrets) in &files {
+            let shared_file = shared_file.as_deref();
+            Self::encrypt_secrets(
+                env,
+                context,
+                service,
+                shared_file,
+                service,
+                secrets,
+                false,
+                upgrade_envelopes,
+            )?;
+        }
+        keystore.delete_entry(env, &rotation_alias)?;
+        Ok(())
+    }
+
+    /// Record in the service's own file that this credential is kept in
+    /// the given shared file, so the service-wide operations can reach it.
+    fn record_shared_file(&self, env: &mut JNIEnv, shared_file: &str) -> AndroidKeyringResult<()> {
+        let name = file_name(&self.service);
+        let file = self
+            .context
+            .get_shared_preferences(env, &name, MODE_PRIVATE)?;
+        let lock = file_lock(&name);
+        let _lock = lock.lock().unwrap();
+        let mut shared = file
+            .get_string_set(env, SHARED_FILES_KEY)?
+            .unwrap_or_default();
+        if shared.0.insert(shared_file.to_string()) {
+            file.edit(env)?
+                .put_string_set(env, SHARED_FILES_KEY, &shared)?
+                .commit(env)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the secrets of the given service that are in the legacy layout
+    /// in the current one, with the service's existing key, returning how many
+    /// were rewritten.
+    ///
+    /// The secrets in each of the service's files (see [service_files]) are
+    /// rewritten in a single commit per file, and keep their timestamps. Secrets that
+    /// the service's key can't decrypt, such as those of protected credentials,
+    /// are left as they are, and are still read.
+    pub(crate) fn migrate_legacy(
+        env: &mut JNIEnv,
+        context: &Context,
+        service: &str,
+    ) -> AndroidKeyringResult<usize> {
+        let own = Self::get_file(env, context, service)?;
+        let mut migrated = 0;
+        let mut key = None;
+        for shared_file in service_files(env, &own)? {
+            let file = Self::get_file(env, context, shared_file.as_deref().unwrap_or(service))?;
+            let mut legacy = Vec::new();
+            for (user, entry_key) in service_users(env, &file, service, shared_file.is_some())? {
+                match file.get_binary(env, &entry_key) {
+                    Ok(Some(data)) if is_legacy(&data) => legacy.push((user, data)),
+                    // a corrupted value can't be migrated, and is reported when it's read
+                    Ok(_) | Err(AndroidKeyringError::CorruptedData(_, _)) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            if legacy.is_empty() {
+                continue;
+            }
+            let key = match &key {
+                Some(key) => key,
+                None => key.insert(Self::get_key(
+                    env,
+                    context,
+                    service,
+                    None,
+                    None,
+                    Backoff::default(),
+                )?),
+            };
+            let mut secrets = Vec::new();
+            for (user, data) in legacy {
+                let aad = aad(service, &user);
+                match Self::decrypt_secret(env, key.clone(), service, &aad, data) {
+                    Ok(secret) => secrets.push((user, Zeroizing::new(secret))),
+                    Err(AndroidKeyringError::CorruptedData(_, _)) => {
+                        log::warn!(
+                            "Not migrating the secret of user {user:?}: it can't be decrypted"
+                        );
+                        env.exception_clear()?;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            if !secrets.is_empty() {
+                let shared_file = shared_file.as_deref();
+                Self::encrypt_secrets(
+                    env,
+                    context,
+                    service,
+                    shared_file,
+                    service,
+                    &secrets,
+                    false,
+                    true,
+                )?;
+            }
+            migrated += secrets.len();
+        }
+        Ok(migrated)
+    }
+
+    /// Whether this credential has a secret, checked without
+    /// fetching the service's key or decrypting the secret.
+    ///
+    /// A secret that is present but corrupted still counts.
+    pub fn exists(&self) -> keyring_core::Result<bool> {
+        let exists = self.check_for_exception_retrying(|env| {
+            let file = self.get_own_file(env)?;
+            Ok(file.contains(env, &self.entry_key())?)
+        })?;
+        Ok(exists)
+    }
+
+    /// Whether this credential's key requires the user to authenticate,
+    /// as reported by the key itself, so an application can decide whether
+    /// to prompt the user before reading the secret.
+    ///
+    /// The key isn't generated by this check, so if the credential's
+    /// service has no such key yet, this fails with a
+    /// [NoEntry](keyring_core::Error::NoEntry) error.
+    pub fn requires_authentication(&self) -> keyring_core::Result<bool> {
+        let required = self.check_for_exception_retrying(|env| {
+            let keystore = KeyStore::get_instance(env, PROVIDER)?;
+            keystore.load(env)?;
+            match keystore.get_key(env, &self.key_alias())? {
+                Some(key) => Ok(Some(
+                    key.get_key_info(env)?
+                        .is_user_authentication_required(env)?,
+                )),
+                None => Ok(None),
+            }
+        })?;
+        required.ok_or(keyring_core::Error::NoEntry)
+    }
+
+    /// When this credential's secret was first set, in milliseconds
+    /// since the Unix epoch.
+    ///
+    /// This is `None` if the credential has no secret, or if its secret
+    /// was set by a version of this crate that didn't record the time.
+    pub fn created_at(&self) -> keyring_core::Result<Option<i64>> {
+        self.get_timestamp(CTIME_SUFFIX)
+    }
+
+    /// When this credential's secret was last set, in milliseconds
+    /// since the Unix epoch. See [created_at](Cred::created_at).
+    pub fn modified_at(&self) -> keyring_core::Result<Option<i64>> {
+        self.get_timestamp(MTIME_SUFFIX)
+    }
+
+    fn get_timestamp(&self, suffix: &str) -> keyring_core::Result<Option<i64>> {
+        let timestamp = self.check_for_exception_retrying(|env| {
+            let file = self.get_own_file(env)?;
+            Ok(file.get_long(env, &format!("{}{suffix}", self.entry_key()))?)
+        })?;
+        Ok(timestamp)
+    }
+
+    /// Get this credential's secret, prompting the user to authenticate
+    /// with a `BiometricPrompt` shown in the given `FragmentActivity`
+    /// if the credential's key requires it.
+    ///
+    /// This blocks until the user has dealt with the prompt, so it must not
+    /// be called on the main thread. See the [biometric](crate::biometric)
+    /// module for the Kotlin shim it needs. If the user dismisses the prompt,
+    /// this fails with a [NoStorageAccess](keyring_core::Error::NoStorageAccess)
+    /// error that wraps [AndroidKeyringError::AuthCanceled](crate::AndroidKeyringError::AuthCanceled),
+    /// and if the user fails to authenticate, with one that wraps
+    /// [AndroidKeyringError::AuthenticationRequired](crate::AndroidKeyringError::AuthenticationRequired).
+    #[cfg(feature = "biometric")]
+    pub fn get_secret_with_prompt(
+        &self,
+        activity: &jni::objects::JObject,
+        info: &crate::biometric::PromptInfo,
+    ) -> keyring_core::Result<Vec<u8>> {
+        let r = self.check_for_exception(|env| {
+            let file = self.get_own_file(env)?;
+            let Some(data) = file.get_binary(env, &self.entry_key())? else {
+                return Ok(None);
+            };
+            let key = Self::get_key(
+                env,
+                &self.context,
+                &self.key_alias(),
+                self.auth(),
+                self.key_size,
+                self.key_generation,
+            )?;
+            let plaintext = crate::biometric::decrypt_with_prompt(
+                env,
+                &self.context,
+                activity,
+                key,
+                Some(&self.aad()),
+                data,
+                info,
+            )?;
+            Ok(Some(plaintext))
+        })?;
+        r.ok_or(keyring_core::Error::NoEntry)
+    }
+
+    /// Get a [CryptoObject](crate::biometric::CryptoObject) whose cipher
+    /// can decrypt this credential's secret, for an application that shows
+    /// a `BiometricPrompt` of its own.
+    ///
+    /// Once the prompt (shown with the object) succeeds, pass the object to
+    /// [get_secret_with_crypto_object](Cred::get_secret_with_crypto_object).
+    /// The cipher only fits the secret as it was when the object was made.
+    #[cfg(feature = "biometric")]
+    pub fn crypto_object(&self) -> keyring_core::Result<crate::biometric::CryptoObject> {
+        let r = self.check_for_exception(|env| {
+            let file = self.get_own_file(env)?;
+            let Some(data) = file.get_binary(env, &self.entry_key())? else {
+                return Ok(None);
+            };
+            let key = Self::get_key(
+                env,
+                &self.context,
+                &self.key_alias(),
+                self.auth(),
+                self.key_size,
+                self.key_generation,
+            )?;
+            let cipher = decryption_cipher(env, key, &data)?;
+            Ok(Some(crate::biometric::CryptoObject::new(
+                env,
+                &self.context,
+                &cipher,
+            )?))
+        })?;
+        r.ok_or(keyring_core::Error::NoEntry)
+    }
+
+    /// Get this credential's secret, using the authenticated cipher of a
+    /// [CryptoObject](crate::biometric::CryptoObject) from
+    /// [crypto_object](Cred::crypto_object).
+    #[cfg(feature = "biometric")]
+    pub fn get_secret_with_crypto_object(
+        &self,
+        crypto_object: &crate::biometric::CryptoObject,
+    ) -> keyring_core::Result<Vec<u8>> {
+        let r = self.check_for_exception(|env| {
+            let file = self.get_own_file(env)?;
+            let Some(data) = file.get_binary(env, &self.entry_key())? else {
+                return Ok(None);
+            };
+            let Some(cipher) = crypto_object.get_cipher(env)? else {
+                let err = "must hold a cipher".to_string();
+                return Err(keyring_core::Error::Invalid("crypto_object".to_string(), err).into());
+            };
+            Ok(Some(decrypt_with_cipher(
+                env,
+                &cipher,
+                Some(&self.aad()),
+                data,
+            )?))
+        })?;
+        r.ok_or(keyring_core::Error::NoEntry)
+    }
+
+    /// If this credential's secret should keep the legacy layout when it's
+    /// rewritten (see [with_upgrade_envelopes](Cred::with_upgrade_envelopes)),
+    /// whether it's bound to additional authenticated data.
+    fn legacy_binding(
+        &self,
+        env: &mut JNIEnv,
+        file: &SharedPreferences,
+    ) -> AndroidKeyringResult<Option<bool>> {
+        previous_binding(env, file, &self.entry_key(), self.upgrade_envelopes)
+    }
+
+    /// The additional authenticated data that binds this credential's
+    /// secret to its service and user, so it can't be swapped with another's.
+    fn aad(&self) -> Vec<u8> {
+        aad(&self.service, &self.user)
+    }
+
+    fn attributes_key(&self) -> String {
+        format!("{}{ATTRIBUTES_SUFFIX}", self.entry_key())
+    }
+
+    fn read_attributes(
+        &self,
+        env: &mut JNIEnv,
+        file: &SharedPreferences,
+    ) -> AndroidKeyringResult<HashMap<String, String>> {
+        let Some(json) = file.get_string(env, &self.attributes_key())? else {
+            return Ok(HashMap::new());
+        };
+        let attributes = serde_json::from_str(&json).map_err(|e| {
+            keyring_core::Error::BadDataFormat(json.as_bytes().to_vec(), Box::new(e))
+        })?;
+        Ok(attributes)
+    }
+
     fn get_file(
         env: &mut JNIEnv,
         context: &Context,
         service: &str,
     ) -> AndroidKeyringResult<SharedPreferences> {
-        Ok(context.get_shared_preferences(env, service, MODE_PRIVATE)?)
+        Ok(context.get_shared_preferences(env, &file_name(service), MODE_PRIVATE)?)
+    }
+
+    /// Get the file that holds this credential, which is
+    /// its service's file unless it's in a shared one.
+    fn get_own_file(&self, env: &mut JNIEnv) -> AndroidKeyringResult<SharedPreferences> {
+        let name = self.physical_file_name();
+        Ok(self
+            .context
+            .get_shared_preferences(env, &name, MODE_PRIVATE)?)
     }
 }
 
 impl CredentialApi for Cred {
     fn set_secret(&self, secret: &[u8]) -> keyring_core::Result<()> {
         self.check_for_exception(|env| {
-            let file = Self::get_file(env, &self.context, &self.service)?;
-            let key = Self::get_key(env, &self.service)?;
-            let ciphertext = encrypt(env, key, secret)?;
-            let edit = file.edit(env)?;
-            edit.put_binary(env, &self.user, &ciphertext)?.commit(env)?;
+            let file = self.get_own_file(env)?;
+            let legacy_bound = self.legacy_binding(env, &file)?;
+            let aad = self.aad();
+            let encrypt_secret =
+                |env: &mut JNIEnv, key: Key| encrypt_secret(env, key, &aad, legacy_bound, secret);
+            let ciphertext = match self.with_key(env, encrypt_secret) {
+                Err(AndroidKeyringError::KeyInvalidated)
+                    if self.on_key_invalidated == OnKeyInvalidated::Delete =>
+                {
+                    self.replace_invalidated_key(env)?;
+                    self.with_key(env, encrypt_secret)?
+                }
+                r => r?,
+            };
+            let now = current_time_millis(env)?;
+            #[cfg(feature = "iv-check")]
+            crate::crypto::check_iv_unique(&self.key_alias(), &ciphertext)?;
+            if let Some(shared_file) = &self.shared_file {
+                self.record_shared_file(env, shared_file)?;
+            }
+            let key = self.entry_key();
+            let lock = file_lock(&self.physical_file_name());
+            let _lock = lock.lock().unwrap();
+            let edit = file.edit(env)?.put_binary(env, &key, &ciphertext)?;
+            Self::put_timestamps(env, &file, &edit, &key, now)?;
+            put_index(env, &file, &edit, &[&key], true)?;
+            edit.commit(env)?;
             Ok(())
         })?;
 
@@ -93,17 +1339,35 @@ impl CredentialApi for Cred {
     }
 
     fn get_secret(&self) -> keyring_core::Result<Vec<u8>> {
-        let r = self.check_for_exception(|env| {
-            let file = Self::get_file(env, &self.context, &self.service)?;
-            let key = Self::get_key(env, &self.service)?;
-            let ciphertext = file.get_binary(env, &self.user)?;
-            Ok(match ciphertext {
+        let r = self.check_for_exception_retrying(|env| {
+            let file = self.get_own_file(env)?;
+            let ciphertext = file.get_binary(env, &self.entry_key())?;
+            // the key is fetched even if there's no secret, so that it exists for writing
+            let result = self.with_key(env, |env, key| match &ciphertext {
                 Some(data) => {
-                    let plaintext = decrypt(env, key, data)?;
-                    Some(plaintext)
+                    let plaintext = Self::decrypt_secret(
+                        env,
+                        key,
+                        &self.key_alias(),
+                        &self.aad(),
+                        data.clone(),
+                    )?;
+                    Ok(Some(plaintext))
                 }
-                None => None,
-            })
+                None => Ok(None),
+            });
+            match result {
+                Err(AndroidKeyringError::KeyInvalidated)
+                    if self.on_key_invalidated == OnKeyInvalidated::Delete =>
+                {
+                    // the secret can never be decrypted, so it's as good as gone
+                    log::warn!("Deleting the secret of user {:?}", self.user);
+                    env.exception_clear()?;
+                    self.remove_secret(env, &file)?;
+                    Ok(None)
+                }
+                r => r,
+            }
         })?;
 
         match r {
@@ -112,12 +1376,60 @@ impl CredentialApi for Cred {
         }
     }
 
+    /// Attributes are kept next to the secret, so a credential
+    /// only has attributes while it has a secret.
+    fn get_attributes(&self) -> keyring_core::Result<HashMap<String, String>> {
+        let r = self.check_for_exception_retrying(|env| {
+            let file = self.get_own_file(env)?;
+            if !file.contains(env, &self.entry_key())? {
+                return Ok(None);
+            }
+            Ok(Some(self.read_attributes(env, &file)?))
+        })?;
+        r.ok_or(keyring_core::Error::NoEntry)
+    }
+
+    /// The given attributes are added to the credential's existing ones,
+    /// replacing any with the same name. Attributes can't be given
+    /// to a credential that has no secret.
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> keyring_core::Result<()> {
+        let updated = self.check_for_exception(|env| {
+            let file = self.get_own_file(env)?;
+            let lock = file_lock(&self.physical_file_name());
+            let _lock = lock.lock().unwrap();
+            if !file.contains(env, &self.entry_key())? {
+                return Ok(false);
+            }
+            let mut existing = self.read_attributes(env, &file)?;
+            for (name, value) in attributes {
+                existing.insert(name.to_string(), value.to_string());
+            }
+            let json = serde_json::to_string(&existing).unwrap();
+            let edit = file.edit(env)?;
+            edit.put_string(env, &self.attributes_key(), &json)?
+                .commit(env)?;
+            Ok(true)
+        })?;
+        if updated {
+            Ok(())
+        } else {
+            Err(keyring_core::Error::NoEntry)
+        }
+    }
+
     fn delete_credential(&self) -> keyring_core::Result<()> {
         self.check_for_exception(|env| {
-            let file = Self::get_file(env, &self.context, &self.service)?;
-            let edit = file.edit(env)?;
-            edit.remove(env, &self.user)?.commit(env)?;
-            edit.commit(env)?;
+            let file = self.get_own_file(env)?;
+            if !file.contains(env, &self.entry_key())? {
+                if self.idempotent_delete {
+                    return Ok(());
+                }
+                return Err(keyring_core::Error::NoEntry.into());
+            }
+            if !self.remove_secret(env, &file)? {
+                let err = format!("Failed to delete credential for user {:?}", self.user);
+                return Err(keyring_core::Error::PlatformFailure(err.into()).into());
+            }
             Ok(())
         })?;
 
@@ -146,4 +1458,84 @@ impl HasJavaVm for Cred {
     fn java_vm(&self) -> &JavaVM {
         &self.java_vm
     }
+
+    fn read_retries(&self) -> u32 {
+        self.read_retries
+    }
+}
+
+/// Versions of the keystore operations on a credential's key
+/// that let tests count lookups and simulate invalidated keys.
+#[cfg(feature = "compile-tests")]
+pub(crate) mod test_hooks {
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use jni::JNIEnv;
+
+    use super::Cred;
+    use crate::{
+        error::{AndroidKeyringError, AndroidKeyringResult},
+        keystore::{Key, KeyStore, PROVIDER},
+    };
+
+    /// The number of times a credential's key has been looked up in the keystore,
+    /// so tests can check that it's cached.
+    pub(crate) static KEY_LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+    /// The aliases of keys that tests treat as permanently invalidated,
+    /// until the keys are deleted.
+    static SIMULATED_INVALIDATIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Treat the key with the given alias as permanently invalidated,
+    /// so tests can exercise the code paths that handle invalidation.
+    pub(crate) fn simulate_key_invalidated(alias: &str) {
+        invalidations().push(alias.to_string());
+    }
+
+    fn invalidations() -> std::sync::MutexGuard<'static, Vec<String>> {
+        SIMULATED_INVALIDATIONS
+            .lock()
+            .expect("Simulated invalidations poisoned: report a bug!")
+    }
+
+    impl Cred {
+        pub(super) fn lookup_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<Key> {
+            KEY_LOOKUPS.fetch_add(1, Ordering::SeqCst);
+            Self::get_key(
+                env,
+                &self.context,
+                &self.key_alias(),
+                self.auth(),
+                self.key_size,
+                self.key_generation,
+            )
+        }
+
+        pub(super) fn use_key<T, F>(
+            &self,
+            env: &mut JNIEnv,
+            key: Key,
+            f: &mut F,
+        ) -> AndroidKeyringResult<T>
+        where
+            F: FnMut(&mut JNIEnv, Key) -> AndroidKeyringResult<T>,
+        {
+            if invalidations().contains(&self.key_alias()) {
+                return Err(AndroidKeyringError::KeyInvalidated);
+            }
+            f(env, key)
+        }
+
+        pub(super) fn delete_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<()> {
+            let alias = self.key_alias();
+            let keystore = KeyStore::get_instance(env, PROVIDER)?;
+            keystore.load(env)?;
+            keystore.delete_entry(env, &alias)?;
+            invalidations().retain(|a| a != &alias);
+            Ok(())
+        }
+    }
 }
@@ -6,11 +6,66 @@ file and Keystore entry _per service name_, rather than _per store name_.
 This was the only implementation available in versions 0.5 and earlier of this
 crate, but it is deprecated and may be removed in future versions.
 
+A service's file is named by the service, except that any `/`, null, or `%`
+characters in it are percent-encoded, because file names can't contain the first
+two. Earlier versions used the service name unchanged, so the credentials of a
+service whose name contains `%` that were stored by them aren't found.
+Service names can't end with `/rotation` or `/auth`, because those suffixes
+are used for the aliases of the service's other keys.
+
+Besides its users' secrets, a service's file holds each user's attributes
+and timestamps under the user's name followed by `.attrs`, `.ctime`, or `.mtime`,
+and an index of its users under the `.users` key. So a user can't be named
+`.users` or have a name ending in one of those suffixes. The index is updated in
+the same commit as the secrets, and lets searches list a service's users without
+reading its whole file.
+
+A credential can instead be kept in a file shared by several services, by building
+it with a `file_name` modifier (see [Store]'s `build`). Its keys in such a file are
+prefixed by its service and a null character, so searches of a shared file can
+list each service's credentials separately. A service's own file lists the shared
+files its credentials are kept in under the `.files` key (so a user can't be named
+`.files` either), so the service-wide operations can find them.
+
 # Migration Guide
 
-If your application was built against the legacy implementation, it will continue to work
-with the current implementation, but you should migrate your credentials from the legacy
-store to a named store of your choosing.
+## Upgrading from earlier versions
+
+Applications built against earlier versions of this crate keep working without
+migrating anything. The `AndroidStore` and `AndroidCredential` types of those
+versions, which were written against the `keyring` crate's API, are replaced by
+[Store] and [Cred], which implement the `keyring_core` API. The old names remain
+as the deprecated aliases [AndroidStore] and [AndroidCredential], so code that
+names them still compiles. The new types use the same file and Keystore entry for
+each service, and read the values written by earlier versions unchanged.
+
+The differences from earlier versions are:
+
+- Values written by this version start with a versioned header, which earlier
+  versions can't read. A credential built with an `upgrade_envelopes` value of
+  `false` keeps a value written by an earlier version in that layout when it's
+  rewritten (see [Store]'s `build`).
+- Values written by this version are bound to their service and user, so one user's
+  value can't be copied over another's and still decrypt. Values written by
+  earlier versions aren't bound.
+- Each service's file also holds an index of its users, and each user's attributes
+  and timestamps, so a few user names are reserved (see above).
+- The file of a service whose name contains `%` is named differently, so
+  credentials that earlier versions stored for such a service aren't found
+  (see above).
+- Unreadable values are reported differently: earlier versions returned a bare
+  corrupted-data error, while [Cred] reports
+  [BadDataFormat](keyring_core::Error::BadDataFormat) with the reason the value
+  couldn't be read (see [CorruptedData](crate::CorruptedData)).
+
+Values are rewritten in the current layout as they are set, so a service's
+values can be in both layouts at once. To rewrite all of them at once, with
+the service's existing key, call [migrate_legacy](Store::migrate_legacy).
+
+## Moving to a named store
+
+The legacy implementation is deprecated, so applications should eventually
+move their credentials to a named store of their choosing.
 
 The legacy store does not interact with named stores in any way, except in the unlikely event
 that the named store's filename is the same as a legacy credential's service name.
@@ -22,9 +77,9 @@ can simply
 - Read the legacy credential's secret and write it to the named store.
 - If desired, delete the legacy credential.
 
-Since the legacy store doesn't support search, an application doing the migration
-must know in advance which credentials are to be migrated. Here's some sample code
-for how that might be done (targeting the default named store):
+An application doing the migration can find the credentials to be migrated by
+searching the legacy store, or it can list them itself if it knows them in advance.
+Here's some sample code for how that might be done (targeting the default named store):
 
 ```rust
 #[cfg(feature = "legacy")]
@@ -49,29 +104,81 @@ pub mod store;
 pub use store::Store;
 
 pub mod cred;
-use crate::error::{AndroidKeyringError, AndroidKeyringResult};
-pub use cred::Cred;
+use crate::backoff::{Backoff, Failure};
+use crate::error::{AndroidKeyringError, AndroidKeyringResult, take_pending_exception};
+use crate::methods::{LOCAL_FRAME_CAPACITY, attach};
+pub use cred::{AuthPolicy, AuthType, Cred, OnKeyInvalidated};
 use jni::{JNIEnv, JavaVM};
 
-trait HasJavaVm {
+/// The name of [Store] in versions 0.5 and earlier.
+#[deprecated(
+    since = "0.6.0",
+    note = "use `Store` (or `crate::LegacyStore`) instead"
+)]
+pub type AndroidStore = Store;
+
+/// The name of [Cred] in versions 0.5 and earlier.
+#[deprecated(since = "0.6.0", note = "use `Cred` (or `crate::LegacyCred`) instead")]
+pub type AndroidCredential = Cred;
+
+/// How many times a read is retried after a transient JNI failure,
+/// unless the credential was built with a `read_retries` modifier.
+pub(crate) const DEFAULT_READ_RETRIES: u32 = 1;
+
+pub(crate) trait HasJavaVm {
     fn java_vm(&self) -> &JavaVM;
 
+    fn read_retries(&self) -> u32 {
+        DEFAULT_READ_RETRIES
+    }
+
     fn check_for_exception<T, F>(&self, f: F) -> AndroidKeyringResult<T>
     where
         F: FnOnce(&mut JNIEnv) -> AndroidKeyringResult<T>,
     {
-        let vm = self.java_vm();
-        let mut env = vm.attach_current_thread()?;
-        let t_result = f(&mut env);
+        let mut env = attach(self.java_vm())?;
+        let t_result = env.with_local_frame(LOCAL_FRAME_CAPACITY, f);
         if env.exception_check()? {
-            env.exception_describe()?;
-            env.exception_clear()?;
-
-            if t_result.is_ok() {
-                return Err(AndroidKeyringError::JavaExceptionThrow);
-            }
+            let thrown = take_pending_exception(&mut env)?;
+            log::error!("Exception in legacy store: {thrown}");
+            return match t_result {
+                Ok(_) | Err(AndroidKeyringError::JniError(jni::errors::Error::JavaException)) => {
+                    Err(thrown)
+                }
+                Err(e) => Err(e),
+            };
         }
 
         t_result
     }
+
+    /// Like [check_for_exception](HasJavaVm::check_for_exception), but `f` is
+    /// tried again after a transient JNI failure (such as a thread attach race
+    /// during app startup), up to [read_retries](HasJavaVm::read_retries) times.
+    ///
+    /// Failures that threw a Java exception are never retried. Because `f`
+    /// may run more than once, this is only for operations that don't write.
+    fn check_for_exception_retrying<T, F>(&self, mut f: F) -> AndroidKeyringResult<T>
+    where
+        F: FnMut(&mut JNIEnv) -> AndroidKeyringResult<T>,
+    {
+        let backoff = Backoff {
+            max_attempts: self.read_retries().saturating_add(1),
+            ..Backoff::default()
+        };
+        backoff.retry(|| match self.check_for_exception(&mut f) {
+            Ok(t) => Ok(t),
+            Err(e) if is_transient(&e) => Err(Failure::Transient(e)),
+            Err(e) => Err(Failure::Permanent(e)),
+        })
+    }
+}
+
+/// Whether a failure is one of the JNI failures that can go away on their own.
+fn is_transient(err: &AndroidKeyringError) -> bool {
+    use jni::errors::{Error, JniError};
+    matches!(
+        err,
+        AndroidKeyringError::JniError(Error::TryLock | Error::JniCall(JniError::ThreadDetached))
+    )
 }
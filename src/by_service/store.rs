@@ -1,16 +1,37 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
-use jni::JavaVM;
-use keyring_core::{Entry, api::CredentialStoreApi};
+use jni::{JNIEnv, JavaVM, objects::JObject};
+use keyring_core::{Entry, api::CredentialStoreApi, attributes::parse_attributes};
 
-use crate::{error::AndroidKeyringResult, shared_preferences::Context};
+use crate::{
+    backoff::Backoff,
+    by_store::{CONFIG_KEY, Capabilities},
+    error::AndroidKeyringResult,
+    keystore::{KeyStore, PROVIDER},
+    shared_preferences::{Context, MODE_PRIVATE},
+};
 
-use super::{Cred, HasJavaVm};
+use super::{
+    AuthPolicy, Cred, DEFAULT_READ_RETRIES, HasJavaVm, OnKeyInvalidated,
+    cred::{
+        SERVICE_SEPARATOR, check_user, file_lock, file_name, indexed_users, is_reserved_alias,
+        protected_alias, remove_from_shared_file, rotation_alias, service_files,
+    },
+};
 
 pub struct Store {
     java_vm: Arc<JavaVM>,
     context: Context,
     instance_id: String,
+    // whether secrets in the legacy layout are rewritten with a header
+    upgrade_envelopes: AtomicBool,
 }
 
 impl std::fmt::Debug for Store {
@@ -26,6 +47,9 @@ impl std::fmt::Debug for Store {
 impl Store {
     /// Initializes the store using the AndroidContext available
     /// on the `ndk-context` crate.
+    ///
+    /// As with [new](Store::new), the store keeps that context's
+    /// application context rather than the context itself.
     pub fn from_ndk_context() -> AndroidKeyringResult<Arc<Self>> {
         let ctx = ndk_context::android_context();
         let vm = ctx.vm().cast();
@@ -34,16 +58,270 @@ impl Store {
         let java_vm = unsafe { JavaVM::from_raw(vm)? };
         let env = java_vm.attach_current_thread()?;
 
-        let j_context = unsafe { jni::objects::JObject::from_raw(activity as jni::sys::jobject) };
-        let context = Context::new(&env, j_context)?;
+        let j_context = unsafe { JObject::from_raw(activity as jni::sys::jobject) };
+        Self::new(&env, j_context)
+    }
+
+    /// Initializes the store using the given Android `Context` object.
+    ///
+    /// This is for applications that have a context of their own, for
+    /// example in a JNI function called from Java, and haven't initialized
+    /// the `ndk-context` crate. The store keeps a global reference to
+    /// the context's application context, which lives as long as the
+    /// process, so the given context can be a local reference to an
+    /// Activity without the store keeping that Activity alive.
+    pub fn new(env: &JNIEnv, context: JObject) -> AndroidKeyringResult<Arc<Self>> {
+        Self::with_context(env, context, true)
+    }
+
+    /// Initializes the store using the given Android `Context` object itself,
+    /// rather than its application context as [new](Store::new) does.
+    ///
+    /// The store keeps a global reference to the context for as long
+    /// as the store exists, so only use this if the store really must
+    /// use that context, and never pass an Activity that can be destroyed
+    /// while the store is still in use.
+    pub fn new_keeping_context(env: &JNIEnv, context: JObject) -> AndroidKeyringResult<Arc<Self>> {
+        Self::with_context(env, context, false)
+    }
+
+    fn with_context(
+        env: &JNIEnv,
+        context: JObject,
+        application: bool,
+    ) -> AndroidKeyringResult<Arc<Self>> {
+        let mut context = Context::new(env, context)?;
         let java_vm = Arc::new(env.get_java_vm()?);
+        if application {
+            // the thread is already attached, so this just gets a mutable env
+            let mut env = java_vm.attach_current_thread()?;
+            context = context.get_application_context(&mut env)?;
+        }
         let instance_id = generate_instance_id();
         Ok(Arc::new(Self {
             java_vm,
             context,
             instance_id,
+            upgrade_envelopes: AtomicBool::new(true),
         }))
     }
+
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+impl Store {
+    /// Choose whether secrets that are still in the layout written by earlier
+    /// versions of this crate get the current header when they're rewritten.
+    ///
+    /// This is the default for the credentials this store builds without an
+    /// `upgrade_envelopes` modifier (see [build](CredentialStoreApi::build)) and
+    /// for those it finds, and it applies to [set_many](Store::set_many) and
+    /// [rotate_key](Store::rotate_key). Passing `false` keeps those secrets
+    /// readable by an older version of the application after a downgrade.
+    /// The setting is specific to this store object, and every newly
+    /// created store starts out upgrading them.
+    pub fn set_upgrade_envelopes(&self, upgrade_envelopes: bool) {
+        self.upgrade_envelopes
+            .store(upgrade_envelopes, Ordering::SeqCst);
+    }
+
+    /// Whether this store upgrades secrets in the legacy layout.
+    /// See [set_upgrade_envelopes](Store::set_upgrade_envelopes).
+    pub fn upgrades_envelopes(&self) -> bool {
+        self.upgrade_envelopes.load(Ordering::SeqCst)
+    }
+
+    /// Delete the credentials of every user of the given service.
+    ///
+    /// Each service has its own file, so this empties that file, after
+    /// removing the service's credentials from any shared files they're kept in.
+    /// The service's key is left in place for future use.
+    pub fn purge_service(&self, service: &str) -> keyring_core::Result<()> {
+        self.check_for_exception(|env| {
+            let name = file_name(service);
+            let file = self
+                .context
+                .get_shared_preferences(env, &name, MODE_PRIVATE)?;
+            for shared_file in service_files(env, &file)?.into_iter().flatten() {
+                remove_from_shared_file(env, &self.context, &shared_file, service)?;
+            }
+            let lock = file_lock(&name);
+            let _lock = lock.lock().unwrap();
+            file.edit(env)?.clear(env)?.commit(env)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Delete every credential of the given service, along with
+    /// the service's file and keys.
+    ///
+    /// Unlike [purge_service](Store::purge_service), this leaves nothing
+    /// behind. The service's credentials in shared files are deleted too.
+    /// If the service has neither credentials nor a key,
+    /// this fails with a [NoEntry](keyring_core::Error::NoEntry) error.
+    pub fn delete_service(&self, service: &str) -> keyring_core::Result<()> {
+        let found = self.check_for_exception(|env| {
+            // deleting a file that doesn't exist succeeds, so check for entries first
+            let name = file_name(service);
+            let file = self
+                .context
+                .get_shared_preferences(env, &name, MODE_PRIVATE)?;
+            let mut had_entries = !file.get_all_keys(env)?.is_empty();
+            for shared_file in service_files(env, &file)?.into_iter().flatten() {
+                had_entries |= remove_from_shared_file(env, &self.context, &shared_file, service)?;
+            }
+            let keystore = KeyStore::get_instance(env, PROVIDER)?;
+            keystore.load(env)?;
+            let had_key = keystore.contains_alias(env, service)?;
+            keystore.delete_entry(env, service)?;
+            keystore.delete_entry(env, &rotation_alias(service))?;
+            keystore.delete_entry(env, &protected_alias(service))?;
+            if !self.context.delete_shared_preferences(env, &name)? {
+                log::warn!("Failed to delete file for service {service:?}");
+            }
+            Ok(had_entries || had_key)
+        })?;
+        if found {
+            Ok(())
+        } else {
+            Err(keyring_core::Error::NoEntry)
+        }
+    }
+
+    /// Set the secrets of several users of the given service in one transaction.
+    ///
+    /// This is much faster than setting each credential's secret in turn,
+    /// because the service's key is looked up once and the file is written
+    /// once. If any secret can't be encrypted, none of them are written.
+    /// The secrets are those of unprotected credentials
+    /// (see [build](CredentialStoreApi::build)).
+    pub fn set_many(&self, service: &str, secrets: &[(&str, &[u8])]) -> keyring_core::Result<()> {
+        if is_reserved_alias(service) {
+            return Err(keyring_core::Error::Invalid(
+                "service".to_string(),
+                "cannot end with /rotation or /auth".to_string(),
+            ));
+        }
+        for (user, _) in secrets {
+            check_user(user)?;
+        }
+        let upgrade_envelopes = self.upgrades_envelopes();
+        self.check_for_exception(|env| {
+            Cred::set_many(env, &self.context, service, secrets, upgrade_envelopes)
+        })?;
+        Ok(())
+    }
+
+    /// Rewrite the secrets of the given service that are still in the layout
+    /// written by earlier versions of this crate in the current one, returning
+    /// how many were rewritten.
+    ///
+    /// The secrets stay encrypted with the service's existing key, so this
+    /// only changes their layout (see the [migration guide](super#migration-guide)).
+    /// Afterwards, earlier versions can't read them. Secrets of protected
+    /// credentials (see [build](CredentialStoreApi::build)) can't be decrypted
+    /// without the user, so they are left in their layout, and are
+    /// rewritten in the current one when they're next set.
+    pub fn migrate_legacy(&self, service: &str) -> keyring_core::Result<usize> {
+        if is_reserved_alias(service) {
+            return Err(keyring_core::Error::Invalid(
+                "service".to_string(),
+                "cannot end with /rotation or /auth".to_string(),
+            ));
+        }
+        let migrated =
+            self.check_for_exception(|env| Cred::migrate_legacy(env, &self.context, service))?;
+        Ok(migrated)
+    }
+
+    /// Report what this store supports.
+    ///
+    /// Legacy credentials can be searched and can have attributes.
+    /// Each service has its own key, so whether the store is
+    /// hardware-backed isn't reported.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            attributes: true,
+            persistent: true,
+            hardware_backed: None,
+        }
+    }
+
+    /// Replace the key of the given service with a newly generated one,
+    /// re-encrypting the secrets of all of the service's users.
+    ///
+    /// Every user of a service shares its key, so this is the way to retire
+    /// a key that may have been compromised. The service's credentials in
+    /// shared files are re-encrypted too. If the rotation fails part way,
+    /// every secret remains readable, and the rotation can be retried.
+    ///
+    /// Protected credentials (see [build](CredentialStoreApi::build)) have
+    /// a key of their own, so rotating a service that has any fails with a
+    /// [NotSupportedByStore](keyring_core::Error::NotSupportedByStore) error,
+    /// before anything is changed.
+    ///
+    /// Secrets written to the service while its key is being rotated
+    /// may be lost, so don't rotate a key while the service is in use.
+    pub fn rotate_key(&self, service: &str) -> keyring_core::Result<()> {
+        let upgrade_envelopes = self.upgrades_envelopes();
+        self.check_for_exception(|env| {
+            Cred::rotate_key(env, &self.context, service, upgrade_envelopes)
+        })?;
+        Ok(())
+    }
+}
+
+impl Store {
+    /// Search the credentials kept in a file shared by several services.
+    ///
+    /// Every key of such a file starts with its service and the separator,
+    /// so a service's credentials are those whose keys have that prefix.
+    fn search_shared_file(
+        &self,
+        shared_file: &str,
+        spec: &HashMap<String, String>,
+    ) -> keyring_core::Result<Vec<Entry>> {
+        let key_prefix = match spec.get("service") {
+            Some(service) => format!("{service}{SERVICE_SEPARATOR}"),
+            None => String::new(),
+        };
+        let user_prefix = spec.get("user").cloned().unwrap_or_default();
+        let pairs = self.check_for_exception_retrying(|env| {
+            let name = file_name(shared_file);
+            let file = self
+                .context
+                .get_shared_preferences(env, &name, MODE_PRIVATE)?;
+            let mut pairs = Vec::new();
+            for key in indexed_users(env, &file)?.0 {
+                if !key.starts_with(&key_prefix) {
+                    continue;
+                }
+                let Some((service, user)) = key.split_once(SERVICE_SEPARATOR) else {
+                    continue;
+                };
+                if user.starts_with(&user_prefix) {
+                    pairs.push((service.to_string(), user.to_string()));
+                }
+            }
+            Ok(pairs)
+        })?;
+        let entries = pairs
+            .iter()
+            .map(|(service, user)| {
+                let credential =
+                    Cred::new(self.java_vm.clone(), self.context.clone(), service, user)
+                        .with_file_name(Some(shared_file))
+                        .with_upgrade_envelopes(self.upgrades_envelopes());
+                Entry::new_with_credential(Arc::new(credential))
+            })
+            .collect();
+        Ok(entries)
+    }
 }
 
 impl CredentialStoreApi for Store {
@@ -55,17 +333,296 @@ impl CredentialStoreApi for Store {
         self.instance_id.clone()
     }
 
+    /// See the API documentation for [CredentialStoreApi::build].
+    ///
+    /// The allowed modifiers are `require_auth`, `auth_validity_seconds`, `auth_type`,
+    /// `on_key_invalidated`, `idempotent_delete`, `read_retries`, `file_name`,
+    /// `upgrade_envelopes`, `key_size`, `key_generation_attempts`, and
+    /// `key_generation_delay_ms`.
+    /// If the value of `require_auth` is `true`,
+    /// the credential's secret is encrypted with a key that can only be used
+    /// for a short time after the user authenticates by unlocking the device,
+    /// which requires the device to have a secure lock screen. Each service has
+    /// one such key, which is separate from the key for its other credentials,
+    /// so a protected credential must always be built with the modifier.
+    /// Reading or writing its secret when the user hasn't authenticated
+    /// recently enough fails with a [NoStorageAccess](keyring_core::Error::NoStorageAccess)
+    /// error that wraps
+    /// [AndroidKeyringError::AuthenticationRequired](crate::AndroidKeyringError::AuthenticationRequired),
+    /// so the application can prompt the user to unlock the device and retry.
+    /// (Under the `biometric` feature flag, the secret can instead be read with
+    /// [get_secret_with_prompt](Cred::get_secret_with_prompt), which prompts
+    /// the user itself.)
+    /// If the user removes the device's secure lock screen, the platform
+    /// permanently invalidates the service's protected key, and reading or
+    /// writing a protected credential's secret fails with a
+    /// [NoStorageAccess](keyring_core::Error::NoStorageAccess) error that wraps
+    /// [AndroidKeyringError::KeyInvalidated](crate::AndroidKeyringError::KeyInvalidated).
+    /// The protected secrets are then lost, and deleting the service
+    /// (see [delete_service](Store::delete_service)) lets it start over.
+    /// Alternatively, if the value of `on_key_invalidated` is `delete` (rather
+    /// than the default `error`), reading such a secret deletes it and fails
+    /// with a [NoEntry](keyring_core::Error::NoEntry) error, and setting one
+    /// replaces the invalidated key with a new one (see [OnKeyInvalidated]).
+    ///
+    /// The values of `auth_validity_seconds` and `auth_type` set the
+    /// [AuthPolicy] of a service's protected key when it is generated.
+    /// The key can be used for `auth_validity_seconds` after the user
+    /// authenticates (the default is 30), or if that is 0, the user must
+    /// authenticate every use of the key. The value of `auth_type` says how
+    /// the user can authenticate: `biometric_strong`, `device_credential`, or
+    /// both, joined by `|` (the default). (The shorter `biometric` and `any`
+    /// are accepted too, but `biometric_weak` isn't, because keys can only
+    /// require a strong biometric.) From API level 30, generating a key
+    /// whose `auth_type` the user can't currently satisfy fails with a
+    /// [NotSupportedByStore](keyring_core::Error::NotSupportedByStore) error.
+    /// These modifiers can only be given along with `require_auth`.
+    ///
+    /// Deleting a credential that has no secret fails with a
+    /// [NoEntry](keyring_core::Error::NoEntry) error, unless the value of
+    /// `idempotent_delete` is `true`, in which case it succeeds (as it did
+    /// in earlier versions of this crate).
+    ///
+    /// Reads of the credential that fail because of a transient JNI error,
+    /// such as a thread attach race during app startup, are retried. The
+    /// value of `read_retries` is how many times (it defaults to 1, and 0
+    /// turns retries off). Writes and failures that threw a Java exception
+    /// are never retried.
+    ///
+    /// Each service normally has a file of its own. If a value of `file_name`
+    /// is given, the credential is instead kept in the file with that name,
+    /// which any number of services can share, under the key `{service}\0{user}`
+    /// (so the service can't contain a null character). Only the file changes:
+    /// the service's keys are the same either way. A credential must always
+    /// be built with the same `file_name`, and shared files should be named
+    /// so that they can't be mistaken for a service's own file. A service's
+    /// own file records the shared files its credentials are kept in, so
+    /// [rotate_key](Store::rotate_key), [migrate_legacy](Store::migrate_legacy),
+    /// [purge_service](Store::purge_service), and
+    /// [delete_service](Store::delete_service) reach them as well.
+    ///
+    /// The value of `key_size` is the size in bits (128, 192, or 256) of the
+    /// AES key generated for the credential's service (or for its protected
+    /// credentials). If not specified, the platform default is used. Like the
+    /// authentication policy, it has no effect on a key that already exists.
+    /// Rotating a service's key (see [rotate_key](Store::rotate_key)) keeps its size.
+    ///
+    /// Key generation can fail transiently on some devices, so it is retried
+    /// with exponential backoff, as it is for the named stores. The value of
+    /// `key_generation_attempts` (default 3) bounds the number of tries, and
+    /// the value of `key_generation_delay_ms` (default 50) is the delay before
+    /// the first retry. Failures caused by a bad key specification are never
+    /// retried. The keys generated by [set_many](Store::set_many),
+    /// [rotate_key](Store::rotate_key), and [migrate_legacy](Store::migrate_legacy)
+    /// aren't generated for a credential, so they always use the defaults.
+    ///
+    /// Secrets written by earlier versions of this crate have no header, and
+    /// are always read transparently. If the value of `upgrade_envelopes` is
+    /// `true` (the default, unless changed by
+    /// [set_upgrade_envelopes](Store::set_upgrade_envelopes)), setting such a
+    /// secret writes it with the current header, which earlier versions can't
+    /// read. Setting it to `false` keeps a secret that's in the old layout in
+    /// that layout when it's rewritten, so an older version of the application
+    /// can still read it after a downgrade. New secrets always get the current header.
     fn build(
         &self,
         service: &str,
         user: &str,
-        _modifiers: Option<&HashMap<&str, &str>>,
+        modifiers: Option<&HashMap<&str, &str>>,
     ) -> keyring_core::Result<Entry> {
-        let credential = Cred::new(self.java_vm.clone(), self.context.clone(), service, user);
+        if is_reserved_alias(service) {
+            return Err(keyring_core::Error::Invalid(
+                "service".to_string(),
+                "cannot end with /rotation or /auth".to_string(),
+            ));
+        }
+        check_user(user)?;
+        let mods = parse_attributes(
+            &[
+                "*require_auth",
+                "+auth_validity_seconds",
+                "+auth_type",
+                "+on_key_invalidated",
+                "*idempotent_delete",
+                "+read_retries",
+                "+file_name",
+                "*upgrade_envelopes",
+                "+key_size",
+                "+key_generation_attempts",
+                "+key_generation_delay_ms",
+            ],
+            modifiers,
+        )?;
+        let shared_file = mods.get("file_name");
+        if shared_file.is_some() && service.contains(SERVICE_SEPARATOR) {
+            return Err(keyring_core::Error::Invalid(
+                "service".to_string(),
+                "cannot contain a null character in a shared file".to_string(),
+            ));
+        }
+        let require_auth = mods.get("require_auth").is_some_and(|v| v == "true");
+        let mut auth_policy = AuthPolicy::default();
+        if let Some(seconds) = mods.get("auth_validity_seconds") {
+            auth_policy.validity_seconds = seconds.parse::<u32>().map_err(|_| {
+                let err = "must be a non-negative integer".to_string();
+                keyring_core::Error::Invalid("auth_validity_seconds".to_string(), err)
+            })?;
+        }
+        if let Some(auth_type) = mods.get("auth_type") {
+            auth_policy.auth_type = auth_type
+                .parse()
+                .map_err(|err| keyring_core::Error::Invalid("auth_type".to_string(), err))?;
+        }
+        let auth_mods = ["auth_validity_seconds", "auth_type"];
+        match auth_mods.iter().find(|name| mods.contains_key(**name)) {
+            Some(name) if !require_auth => {
+                let err = "can only be given with require_auth".to_string();
+                return Err(keyring_core::Error::Invalid(name.to_string(), err));
+            }
+            _ => {}
+        }
+        let on_key_invalidated = match mods.get("on_key_invalidated") {
+            Some(policy) => policy.parse().map_err(|_| {
+                let err = "must be error or delete".to_string();
+                keyring_core::Error::Invalid("on_key_invalidated".to_string(), err)
+            })?,
+            None => OnKeyInvalidated::default(),
+        };
+        let idempotent_delete = mods.get("idempotent_delete").is_some_and(|v| v == "true");
+        let read_retries = match mods.get("read_retries") {
+            Some(retries) => retries.parse::<u32>().map_err(|_| {
+                let err = "must be a non-negative integer".to_string();
+                keyring_core::Error::Invalid("read_retries".to_string(), err)
+            })?,
+            None => DEFAULT_READ_RETRIES,
+        };
+        let upgrade_envelopes = match mods.get("upgrade_envelopes") {
+            Some(upgrade) => upgrade == "true",
+            None => self.upgrades_envelopes(),
+        };
+        let key_size = match mods.get("key_size").map(String::as_str) {
+            None => None,
+            Some("128") => Some(128),
+            Some("192") => Some(192),
+            Some("256") => Some(256),
+            Some(_) => {
+                let err = "must be one of 128, 192, or 256".to_string();
+                return Err(keyring_core::Error::Invalid("key_size".to_string(), err));
+            }
+        };
+        let backoff = Backoff::default();
+        let key_generation_attempts = match mods.get("key_generation_attempts") {
+            Some(attempts) => match attempts.parse::<u32>() {
+                Ok(attempts) if attempts > 0 => attempts,
+                _ => {
+                    let err = "must be a positive integer".to_string();
+                    return Err(keyring_core::Error::Invalid(
+                        "key_generation_attempts".to_string(),
+                        err,
+                    ));
+                }
+            },
+            None => backoff.max_attempts,
+        };
+        let key_generation_delay = match mods.get("key_generation_delay_ms") {
+            Some(delay) => delay
+                .parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| {
+                    let err = "must be a non-negative integer".to_string();
+                    keyring_core::Error::Invalid("key_generation_delay_ms".to_string(), err)
+                })?,
+            None => backoff.base_delay,
+        };
+        let credential = Cred::new_with_auth(
+            self.java_vm.clone(),
+            self.context.clone(),
+            service,
+            user,
+            require_auth,
+        )
+        .with_auth_policy(auth_policy)
+        .with_on_key_invalidated(on_key_invalidated)
+        .with_idempotent_delete(idempotent_delete)
+        .with_read_retries(read_retries)
+        .with_file_name(shared_file.map(String::as_str))
+        .with_upgrade_envelopes(upgrade_envelopes)
+        .with_key_size(key_size)
+        .with_key_generation_backoff(key_generation_attempts, key_generation_delay);
 
         Ok(Entry::new_with_credential(Arc::new(credential)))
     }
 
+    /// See the API documentation for [CredentialStoreApi::search].
+    ///
+    /// Allowed specifiers are `service`, `user`, and `file_name`. The `service`
+    /// value must match a credential's service exactly, while the `user` value
+    /// need only be a prefix of the credential's user. Omitted specifiers match
+    /// anything, so an empty spec returns every credential in the store,
+    /// except those kept in shared files. If a `file_name` value is given,
+    /// only the credentials in that shared file (see
+    /// [build](CredentialStoreApi::build)) are returned, filtered by the
+    /// `service` and `user` values, and they're built with that `file_name`.
+    ///
+    /// Each service's users are read from the index kept in its file, so the
+    /// keys that hold credentials' attributes and timestamps are never returned.
+    /// Files last written by earlier versions have no index, so their keys are read instead.
+    /// Credentials are returned as if built without modifiers, so the
+    /// secrets of protected credentials can't be read through them.
+    ///
+    /// Every service has its own key, so searches without a `service` value
+    /// look at the file of every key in the Android Keystore. Files that belong
+    /// to a named [Store](crate::Store) are recognized by their configuration
+    /// entry and skipped, but files written by third parties are not.
+    fn search(&self, spec: &HashMap<&str, &str>) -> keyring_core::Result<Vec<Entry>> {
+        let spec = parse_attributes(&["service", "user", "file_name"], Some(spec))?;
+        if let Some(shared_file) = spec.get("file_name") {
+            return self.search_shared_file(shared_file, &spec);
+        }
+        let user_prefix = spec.get("user").cloned().unwrap_or_default();
+        let pairs = self.check_for_exception_retrying(|env| {
+            let services = match spec.get("service") {
+                Some(service) => vec![service.clone()],
+                None => {
+                    let keystore = KeyStore::get_instance(env, PROVIDER)?;
+                    keystore.load(env)?;
+                    let aliases = keystore.aliases(env)?;
+                    aliases
+                        .into_iter()
+                        .filter(|a| !is_reserved_alias(a))
+                        .collect()
+                }
+            };
+            let mut pairs = Vec::new();
+            for service in services {
+                let name = file_name(&service);
+                let file = self
+                    .context
+                    .get_shared_preferences(env, &name, MODE_PRIVATE)?;
+                if file.contains(env, CONFIG_KEY)? {
+                    log::debug!("Skipping named store file {service:?}");
+                    continue;
+                }
+                for user in indexed_users(env, &file)?.0 {
+                    if user.starts_with(&user_prefix) {
+                        pairs.push((service.clone(), user));
+                    }
+                }
+            }
+            Ok(pairs)
+        })?;
+        let entries = pairs
+            .iter()
+            .map(|(service, user)| {
+                let credential =
+                    Cred::new(self.java_vm.clone(), self.context.clone(), service, user)
+                        .with_upgrade_envelopes(self.upgrades_envelopes());
+                Entry::new_with_credential(Arc::new(credential))
+            })
+            .collect();
+        Ok(entries)
+    }
+
     fn as_any(&self) -> &dyn std::any::Any {
         self
     }
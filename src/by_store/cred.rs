@@ -1,10 +1,155 @@
-use std::sync::Arc;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
 
+use jni::JNIEnv;
 use keyring_core::{Credential, api::CredentialApi};
+use zeroize::Zeroizing;
 
-use crate::crypto::{decrypt, encrypt};
+use crate::crypto::{envelope_info, inspect};
+use crate::error::{AndroidKeyringError, AndroidKeyringResult, CorruptedData};
+use crate::keystore::{
+    PROVIDER, SECURITY_LEVEL_SOFTWARE, SECURITY_LEVEL_STRONGBOX,
+    SECURITY_LEVEL_TRUSTED_ENVIRONMENT, SECURITY_LEVEL_UNKNOWN, SECURITY_LEVEL_UNKNOWN_SECURE,
+};
+#[cfg(feature = "transfer")]
+use crate::transfer::{TransportKey, seal};
 
-use super::vault::AtomicVault;
+use super::vault::{AtomicVault, Vault};
+
+/// The additional authenticated data for a credential: `service || 0x00 || user`.
+pub(crate) fn aad(service: &str, user: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(service.len() + 1 + user.len());
+    aad.extend_from_slice(service.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(user.as_bytes());
+    aad
+}
+
+/// The [backend](ProtectionInfo::backend) of the values of encrypted files.
+const ENCRYPTED_PREFERENCES_BACKEND: &str = "EncryptedSharedPreferences";
+
+/// How a credential's secret is protected at rest.
+///
+/// This is reported by [protection_info](Cred::protection_info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtectionInfo {
+    /// What encrypts the secret: `AndroidKeyStore`, for a secret the store
+    /// encrypts with its Keystore key, or `EncryptedSharedPreferences`, for
+    /// the value of an [encrypted file](super#encrypted-files), which that
+    /// library encrypts with its own master key (kept in the Keystore).
+    pub backend: String,
+    /// Whether the encrypted secret is authenticated as well as encrypted.
+    pub aead: bool,
+    /// Whether the key lives in secure hardware (a TEE or StrongBox).
+    pub hardware_backed: bool,
+    /// Whether the key can only be used after the user authenticates.
+    pub auth_required: bool,
+}
+
+/// Where the key protecting a credential lives, as reported by the platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLevel {
+    /// The key is in secure hardware, but the platform can't say which kind.
+    UnknownSecure,
+    /// The platform can't say where the key is.
+    Unknown,
+    /// The key is in software, outside of secure hardware.
+    Software,
+    /// The key is in a Trusted Execution Environment.
+    TrustedEnvironment,
+    /// The key is in a StrongBox security chip.
+    StrongBox,
+    /// A level that this crate doesn't know about.
+    Other(i32),
+}
+
+impl From<i32> for SecurityLevel {
+    fn from(value: i32) -> Self {
+        match value {
+            SECURITY_LEVEL_UNKNOWN_SECURE => SecurityLevel::UnknownSecure,
+            SECURITY_LEVEL_UNKNOWN => SecurityLevel::Unknown,
+            SECURITY_LEVEL_SOFTWARE => SecurityLevel::Software,
+            SECURITY_LEVEL_TRUSTED_ENVIRONMENT => SecurityLevel::TrustedEnvironment,
+            SECURITY_LEVEL_STRONGBOX => SecurityLevel::StrongBox,
+            other => SecurityLevel::Other(other),
+        }
+    }
+}
+
+/// Where the key protecting a credential lives.
+///
+/// This is reported by [key_security_info](Cred::key_security_info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySecurityInfo {
+    /// Whether the key lives in secure hardware (a TEE or StrongBox).
+    pub inside_secure_hardware: bool,
+    /// The key's security level. This is only available on API 31 and later.
+    pub security_level: Option<SecurityLevel>,
+}
+
+/// A credential's stored value, as returned by [dump_raw](Cred::dump_raw).
+///
+/// The value's bytes are left out of its `Debug` output, so logging a dump
+/// shows only the summary of its header. Call [bytes](RawValue::bytes)
+/// to see them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RawValue {
+    bytes: Vec<u8>,
+    /// The envelope's version, which is 0 for the legacy layout,
+    /// or `None` if the header couldn't be parsed.
+    pub version: Option<u8>,
+    /// The length of the IV, or `None` if the header couldn't be parsed.
+    pub iv_len: Option<usize>,
+    /// Why the header couldn't be parsed, named as by
+    /// [CorruptedData::kind](crate::CorruptedData::kind), if it couldn't.
+    pub error: Option<&'static str>,
+}
+
+impl RawValue {
+    fn new(bytes: Vec<u8>, header: Result<(u8, usize), CorruptedData>) -> Self {
+        let (version, iv_len, error) = match header {
+            Ok((version, iv_len)) => (Some(version), Some(iv_len), None),
+            Err(err) => (None, None, Some(err.kind())),
+        };
+        RawValue {
+            bytes,
+            version,
+            iv_len,
+            error,
+        }
+    }
+
+    /// The stored bytes: the envelope, after base64 decoding.
+    ///
+    /// A value that isn't valid base64 can't be decoded, so its bytes
+    /// are those of the stored string instead.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The number of stored bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether there are no stored bytes.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl std::fmt::Debug for RawValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawValue")
+            .field("len", &self.len())
+            .field("version", &self.version)
+            .field("iv_len", &self.iv_len)
+            .field("error", &self.error)
+            .finish()
+    }
+}
 
 /// The Cred struct is public, so you can read the cred's ID and specifiers.
 ///
@@ -14,8 +159,11 @@ use super::vault::AtomicVault;
 /// Every Cred operation works by locking its containing vault and then asking
 /// the vault to perform the operation. This means that all credential operations
 /// are serialized and never interfere with one another.
+///
+/// Every Cred also shares the locked flag of the store that built it.
 pub struct Cred {
     vault: AtomicVault,
+    locked: Arc<AtomicBool>,
     pub id: String,
     pub specifiers: (String, String),
 }
@@ -35,45 +183,335 @@ impl Cred {
     /// service and user.
     ///
     /// No validity checking is done, that's assumed to be done by the caller.
-    pub fn new_specifier(vault: AtomicVault, id: &str, service: &str, user: &str) -> Self {
+    pub fn new_specifier(
+        vault: AtomicVault,
+        locked: Arc<AtomicBool>,
+        id: &str,
+        service: &str,
+        user: &str,
+    ) -> Self {
         Self {
             vault,
+            locked,
             id: id.to_owned(),
             specifiers: (service.to_owned(), user.to_owned()),
         }
     }
+
+    /// Whether this credential has a secret, checked without decrypting it.
+    ///
+    /// If the store treats corrupted secrets as missing (see the `on_corrupt`
+    /// option of [Store::new_with_configuration](super::Store::new_with_configuration)),
+    /// a secret whose header is malformed doesn't count. Corruption that
+    /// only decryption can detect isn't noticed.
+    pub fn exists(&self) -> keyring_core::Result<bool> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        let exists = vault.with_env(|env| {
+            let file = vault.get_file(env)?;
+            if !vault.corrupt_secrets_are_missing() {
+                return Ok(file.contains(env, &self.id)?);
+            }
+            let data = match file.get_binary(env, &self.id) {
+                Ok(data) => data,
+                Err(AndroidKeyringError::CorruptedData(_, _)) => return Ok(false),
+                Err(err) => return Err(err),
+            };
+            Ok(match data {
+                // values of encrypted files have no header of ours
+                Some(_) if vault.uses_encrypted_preferences() => true,
+                Some(data) => inspect(data).is_ok(),
+                None => false,
+            })
+        })?;
+        Ok(exists)
+    }
+
+    /// Replace this credential's secret, but only if it already has one.
+    ///
+    /// The check and the write are made while the store's file is locked,
+    /// so no other credential object in this process can delete the secret
+    /// in between. If the credential has no secret, this fails with a
+    /// [NoEntry](keyring_core::Error::NoEntry) error and nothing is written.
+    pub fn update_secret(&self, secret: &[u8]) -> keyring_core::Result<()> {
+        self.check_store_unlocked()?;
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.check_secret_size(secret)?;
+        let updated = vault.with_env(|env| {
+            vault.check_unlocked(env)?;
+            let file = vault.get_file(env)?;
+            if !file.contains(env, &self.id)? {
+                log::debug!("No secret to update for id {:?}", self.id);
+                return Ok(false);
+            }
+            let ciphertext =
+                vault.encrypt_replacement(env, &file, &self.id, &self.aad(), secret)?;
+            let ciphertext = Zeroizing::new(ciphertext);
+            let edit = file.edit(env)?;
+            let editor = edit.put_binary(env, &self.id, &ciphertext)?;
+            vault.save(env, editor)?;
+            Ok(true)
+        })?;
+        if updated {
+            Ok(())
+        } else {
+            Err(keyring_core::Error::NoEntry)
+        }
+    }
+
+    /// Read this credential's secret into a buffer that is wiped when dropped.
+    ///
+    /// This is [get_secret](CredentialApi::get_secret) for callers that want
+    /// the secret gone from memory as soon as they are done with it.
+    pub fn get_secret_zeroizing(&self) -> keyring_core::Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(self.get_secret()?))
+    }
+
+    /// Report how this credential's secret is protected, without decrypting it.
+    ///
+    /// Only the header of the stored value is examined; the key's properties
+    /// come from the Android KeyStore.
+    pub fn protection_info(&self) -> keyring_core::Result<ProtectionInfo> {
+        self.check_store_unlocked()?;
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        let result = vault.with_key_and_file(|env, key, file| {
+            let Some(data) = file.get_binary(env, &self.id)? else {
+                log::debug!("No secret to inspect for id {:?}", self.id);
+                return Ok(None);
+            };
+            // values of encrypted files are always encrypted with AES256-GCM
+            let aead = vault.uses_encrypted_preferences() || inspect(data)?.is_aead();
+            let key_info = key.get_key_info(env)?;
+            let backend = match vault.uses_encrypted_preferences() {
+                true => ENCRYPTED_PREFERENCES_BACKEND,
+                false => PROVIDER,
+            };
+            Ok(Some(ProtectionInfo {
+                backend: backend.to_string(),
+                aead,
+                hardware_backed: key_info.is_inside_secure_hardware(env)?,
+                auth_required: key_info.is_user_authentication_required(env)?,
+            }))
+        })?;
+        result.ok_or(keyring_core::Error::NoEntry)
+    }
+
+    /// Read this credential's stored value without decrypting it,
+    /// for diagnosing reports of corrupted secrets.
+    ///
+    /// The value is returned as stored, along with a summary of its header,
+    /// or `None` if the credential has no secret. Neither the cipher nor the
+    /// store's key is used, so this works even when the value is corrupted.
+    /// The values of [encrypted files](super#encrypted-files) are secrets
+    /// in the clear, so they can't be dumped: this fails with a
+    /// [NotSupportedByStore](keyring_core::Error::NotSupportedByStore) error.
+    pub fn dump_raw(&self) -> keyring_core::Result<Option<RawValue>> {
+        self.check_store_unlocked()?;
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        if vault.uses_encrypted_preferences() {
+            return Err(keyring_core::Error::NotSupportedByStore(
+                "The values of encrypted files can't be dumped".to_string(),
+            ));
+        }
+        let dump = vault.with_env(|env| {
+            let file = vault.get_file(env)?;
+            let data = match file.get_binary(env, &self.id) {
+                Ok(Some(data)) => data,
+                Ok(None) => return Ok(None),
+                Err(AndroidKeyringError::CorruptedData(data, err)) => {
+                    return Ok(Some(RawValue::new(data, Err(err))));
+                }
+                Err(err) => return Err(err),
+            };
+            let header = envelope_info(&data).map(|info| (info.version, info.iv_len));
+            Ok(Some(RawValue::new(data, header)))
+        })?;
+        Ok(dump)
+    }
+
+    /// Report where the key protecting this credential lives.
+    ///
+    /// All the credentials in a store share a key, so this reflects
+    /// the store's key whether or not this credential has a secret.
+    pub fn key_security_info(&self) -> keyring_core::Result<KeySecurityInfo> {
+        self.check_store_unlocked()?;
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        let info = vault.with_key_and_file(|env, key, _| {
+            let key_info = key.get_key_info(env)?;
+            Ok(KeySecurityInfo {
+                inside_secure_hardware: key_info.is_inside_secure_hardware(env)?,
+                security_level: key_info.get_security_level(env)?.map(SecurityLevel::from),
+            })
+        })?;
+        Ok(info)
+    }
+
+    /// Export this credential's secret for transfer to another device.
+    ///
+    /// The secret is sealed to the public key of the destination device's
+    /// [TransportKey], so only that device can read the returned blob.
+    /// The secret is decrypted in memory in order to seal it, but it is
+    /// never written anywhere in the clear.
+    #[cfg(feature = "transfer")]
+    pub fn export_blob(&self, transport_public_key: &[u8]) -> keyring_core::Result<Vec<u8>> {
+        self.check_store_unlocked()?;
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        let result = vault.with_env(|env| {
+            vault.check_unlocked(env)?;
+            let Some(secret) = self.read_secret(env, &vault)? else {
+                return Ok(None);
+            };
+            let secret = Zeroizing::new(secret);
+            Ok(Some(seal(env, transport_public_key, &secret)?))
+        })?;
+        result.ok_or(keyring_core::Error::NoEntry)
+    }
+
+    /// Import a secret exported from another device by [export_blob](Cred::export_blob).
+    ///
+    /// The blob is opened with the given transport key and the secret
+    /// is stored in this credential, encrypted with this device's key.
+    #[cfg(feature = "transfer")]
+    pub fn import_blob(
+        &self,
+        transport_key: &TransportKey,
+        blob: &[u8],
+    ) -> keyring_core::Result<()> {
+        self.check_store_unlocked()?;
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.with_env(|env| {
+            vault.check_unlocked(env)?;
+            let secret = Zeroizing::new(transport_key.open(env, blob)?);
+            vault.check_secret_size(&secret)?;
+            let ciphertext = Zeroizing::new(vault.encrypt_secret(env, &self.aad(), &secret)?);
+            let file = vault.get_file(env)?;
+            let edit = file.edit(env)?;
+            let editor = edit.put_binary(env, &self.id, &ciphertext)?;
+            vault.save(env, editor)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+}
+
+impl Cred {
+    /// The additional authenticated data that binds a secret to this
+    /// credential, so that an encrypted secret copied to another
+    /// credential can't be decrypted there.
+    fn aad(&self) -> Vec<u8> {
+        let (service, user) = &self.specifiers;
+        aad(service, user)
+    }
+
+    /// Read and decrypt this credential's secret, or `None` if it has none.
+    ///
+    /// A secret that can't be decrypted is reprovisioned if the store
+    /// allows it, and treated as missing if the store's `on_corrupt`
+    /// option says so.
+    fn read_secret(
+        &self,
+        env: &mut JNIEnv,
+        vault: &Vault,
+    ) -> AndroidKeyringResult<Option<Vec<u8>>> {
+        let file = vault.get_file(env)?;
+        let ciphertext = match file.get_binary(env, &self.id) {
+            Ok(Some(data)) => Some(Ok(data)),
+            Ok(None) => None,
+            // a value that isn't base64 is as corrupt as one that doesn't decrypt
+            Err(err @ AndroidKeyringError::CorruptedData(_, _)) => Some(Err(err)),
+            Err(err) => return Err(err),
+        };
+        if let Some(data) = ciphertext {
+            log::debug!("Found secret for id {:?}", self.id);
+            let decrypted = data.and_then(|data| vault.decrypt_secret(env, &self.aad(), data));
+            let plaintext = match decrypted {
+                Ok(plaintext) => plaintext,
+                Err(err) => {
+                    let (service, user) = &self.specifiers;
+                    match vault.reprovision(env, err, &self.id, service, user) {
+                        Ok(plaintext) => plaintext,
+                        Err(AndroidKeyringError::CorruptedData(_, reason))
+                            if vault.corrupt_secrets_are_missing() =>
+                        {
+                            log::warn!(
+                                "Treating corrupted secret for id {:?} as missing: {reason}",
+                                self.id
+                            );
+                            return Ok(None);
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            };
+            Ok(Some(plaintext))
+        } else {
+            log::debug!("No secret found for id {:?}", self.id);
+            Ok(None)
+        }
+    }
+
+    fn check_store_unlocked(&self) -> keyring_core::Result<()> {
+        if self.locked.load(Ordering::SeqCst) {
+            log::debug!("Store is locked, refusing access to id {:?}", self.id);
+            return Err(keyring_core::Error::NotSupportedByStore(
+                "store is locked".to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl CredentialApi for Cred {
     fn set_secret(&self, secret: &[u8]) -> keyring_core::Result<()> {
+        self.check_store_unlocked()?;
         let vault = self
             .vault
             .lock()
             .expect("Vault lock poisoned: report a bug!");
-        vault.with_key_and_file(|env, key, file| {
-            let ciphertext = encrypt(env, key, secret)?;
+        vault.check_secret_size(secret)?;
+        vault.with_env(|env| {
+            vault.check_unlocked(env)?;
+            let file = vault.get_file(env)?;
+            let ciphertext =
+                vault.encrypt_replacement(env, &file, &self.id, &self.aad(), secret)?;
+            let ciphertext = Zeroizing::new(ciphertext);
             let edit = file.edit(env)?;
-            edit.put_binary(env, &self.id, &ciphertext)?.commit(env)?;
+            let editor = edit.put_binary(env, &self.id, &ciphertext)?;
+            vault.save(env, editor)?;
             Ok(())
         })?;
         Ok(())
     }
 
     fn get_secret(&self) -> keyring_core::Result<Vec<u8>> {
+        self.check_store_unlocked()?;
         let vault = self
             .vault
             .lock()
             .expect("Vault lock poisoned: report a bug!");
-        let result = vault.with_key_and_file(|env, key, file| {
-            let ciphertext = file.get_binary(env, &self.id)?;
-            if let Some(data) = ciphertext {
-                log::debug!("Found secret for id {:?}", self.id);
-                let plaintext = decrypt(env, key, data)?;
-                Ok(Some(plaintext))
-            } else {
-                log::debug!("No secret found for id {:?}", self.id);
-                Ok(None)
-            }
+        let result = vault.with_env(|env| {
+            vault.check_unlocked(env)?;
+            self.read_secret(env, &vault)
         })?;
         match result {
             Some(secret) => Ok(secret),
@@ -82,11 +520,13 @@ impl CredentialApi for Cred {
     }
 
     fn delete_credential(&self) -> keyring_core::Result<()> {
+        self.check_store_unlocked()?;
         let vault = self
             .vault
             .lock()
             .expect("Vault lock poisoned: report a bug!");
         vault.with_env(|env| {
+            vault.check_unlocked(env)?;
             let file = vault.get_file(env)?;
             if !file.contains(env, &self.id)? {
                 log::debug!("No credential to delete for id {:?}", self.id);
@@ -94,7 +534,8 @@ impl CredentialApi for Cred {
             }
             log::debug!("Deleting credential for id {:?}", self.id);
             let editor = file.edit(env)?;
-            editor.remove(env, &self.id)?.commit(env)?;
+            let editor = editor.remove(env, &self.id)?;
+            vault.save(env, editor)?;
             Ok(())
         })?;
         Ok(())
@@ -29,10 +29,26 @@ SharedPreferences entry named by the key `vaultConfig`. Since dividers must cont
 characters, and every credential's key contains the divider, there is no way the `vaultConfig`
 entry can be confused with an entry's credential key.
 
+Each credential's secret is encrypted with the credential's service and user names
+as additional authenticated data. So an encrypted secret that is copied from one
+credential's entry to another's can't be decrypted there. (Secrets written by versions
+of this crate that predate this binding can still be read.)
+
 Accessing a store's SharedPreference file directly is not recommended. To avoid
 interactions with third-party software that might do so, store operations are careful to
 ignore entries that don't look like they are credential IDs.
 
+## Encrypted Files
+
+Under the `encrypted-preferences` feature flag, a store can instead be backed by an
+AndroidX `EncryptedSharedPreferences` file, by setting the `encrypted_preferences`
+configuration key to `true` when the store is created. The library encrypts the keys
+as well as the values of such a file, so neither credential IDs nor the `vaultConfig`
+entry can be read from the file directly. Secrets are stored in it as they are, with
+no envelope or IV of this crate's own, and the key that protects them is the library's
+master key, which is shared by every such file in the application rather than being
+dedicated to the store. The application must depend on `androidx.security:security-crypto`.
+
 ## Ambiguity
 
 Stores do not allow either user or service names to contain the
@@ -56,11 +72,13 @@ only have been added by third parties, so they are assumed
 not to be valid credentials.
  */
 mod vault;
+#[cfg(feature = "legacy")]
+pub(crate) use vault::CONFIG_KEY;
 #[cfg(feature = "compile-tests")]
 pub use vault::clear_vault_list;
 
 pub mod store;
-pub use store::Store;
+pub use store::{Capabilities, OnCorrupt, Reprovisioner, Store};
 
 pub mod cred;
-pub use cred::Cred;
+pub use cred::{Cred, KeySecurityInfo, ProtectionInfo, RawValue, SecurityLevel};
@@ -1,8 +1,21 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 use keyring_core::{Entry, Error, Result, api::CredentialStoreApi, attributes::parse_attributes};
 use regex::{Error as RegexError, Regex};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "backup")]
+use zeroize::Zeroizing;
+
+use crate::backoff::Backoff;
+use crate::change_listener::{ChangeCallback, ChangeListener};
+use crate::crypto::{EnvelopeFormat, EnvelopeInfo, Transformation, envelope_info};
+use crate::error::AndroidKeyringError;
 
 use super::Cred;
 use super::vault::{AtomicVault, delete, lookup};
@@ -11,11 +24,107 @@ use super::vault::{AtomicVault, delete, lookup};
 ///
 /// It's serializable so that it can be kept
 /// in the store's SharedPreferences file as a JSON string.
+///
+/// The name, filename, and divider identify the store. The remaining
+/// fields are operational settings that were added in later versions
+/// of this crate, so they have defaults when missing from a stored config.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StoreConfig {
     pub name: String,
     pub filename: String,
     pub divider: String,
+    #[serde(default = "default_key_generation_attempts")]
+    pub key_generation_attempts: u32,
+    #[serde(default = "default_key_generation_delay_ms")]
+    pub key_generation_delay_ms: u64,
+    #[serde(default)]
+    pub key_size: Option<i32>,
+    #[serde(default)]
+    pub readonly_if_locked: bool,
+    #[serde(default = "default_randomized_encryption_required")]
+    pub randomized_encryption_required: bool,
+    #[serde(default)]
+    pub strongbox: bool,
+    #[serde(default)]
+    pub key_validity_start: Option<i64>,
+    #[serde(default)]
+    pub key_validity_end: Option<i64>,
+    #[serde(default)]
+    pub unlocked_device_required: bool,
+    #[serde(default)]
+    pub transformation: Transformation,
+    #[serde(default = "default_synchronous_writes")]
+    pub synchronous_writes: bool,
+    #[serde(default)]
+    pub encrypted_preferences: bool,
+    #[serde(default)]
+    pub on_corrupt: OnCorrupt,
+    #[serde(default = "default_max_secret_size")]
+    pub max_secret_size: u32,
+    #[serde(default)]
+    pub persistent_id: bool,
+    #[serde(default = "default_upgrade_envelopes")]
+    pub upgrade_envelopes: bool,
+    #[serde(default)]
+    pub envelope_format: EnvelopeFormat,
+    #[serde(default = "default_application_context")]
+    pub application_context: bool,
+    #[serde(default)]
+    pub multi_process: bool,
+}
+
+/// What reading a credential's secret does when
+/// the stored secret is corrupted and can't be decrypted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnCorrupt {
+    /// Fail with a [BadDataFormat](Error::BadDataFormat) error
+    /// that holds the stored secret.
+    #[default]
+    Error,
+    /// Fail with a [NoEntry](Error::NoEntry) error,
+    /// as if the credential had no secret.
+    NoEntry,
+}
+
+impl std::str::FromStr for OnCorrupt {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OnCorrupt::Error),
+            "no_entry" => Ok(OnCorrupt::NoEntry),
+            _ => Err(()),
+        }
+    }
+}
+
+fn default_key_generation_attempts() -> u32 {
+    Backoff::default().max_attempts
+}
+
+fn default_key_generation_delay_ms() -> u64 {
+    Backoff::default().base_delay.as_millis() as u64
+}
+
+fn default_randomized_encryption_required() -> bool {
+    true
+}
+
+fn default_synchronous_writes() -> bool {
+    true
+}
+
+fn default_max_secret_size() -> u32 {
+    64 * 1024
+}
+
+fn default_upgrade_envelopes() -> bool {
+    true
+}
+
+fn default_application_context() -> bool {
+    true
 }
 
 impl Default for StoreConfig {
@@ -24,6 +133,25 @@ impl Default for StoreConfig {
             name: "default".to_string(),
             filename: "keyring-default".to_string(),
             divider: "\u{FEFF}@\u{FEFF}".to_string(),
+            key_generation_attempts: default_key_generation_attempts(),
+            key_generation_delay_ms: default_key_generation_delay_ms(),
+            key_size: None,
+            readonly_if_locked: false,
+            randomized_encryption_required: default_randomized_encryption_required(),
+            strongbox: false,
+            key_validity_start: None,
+            key_validity_end: None,
+            unlocked_device_required: false,
+            transformation: Transformation::default(),
+            synchronous_writes: default_synchronous_writes(),
+            encrypted_preferences: false,
+            on_corrupt: OnCorrupt::default(),
+            max_secret_size: default_max_secret_size(),
+            persistent_id: false,
+            upgrade_envelopes: default_upgrade_envelopes(),
+            envelope_format: EnvelopeFormat::default(),
+            application_context: default_application_context(),
+            multi_process: false,
         }
     }
 }
@@ -31,7 +159,9 @@ impl Default for StoreConfig {
 impl StoreConfig {
     /// Diff this config against another.
     ///
-    /// If any fields differ, return an error naming one of the differences.
+    /// Only the identifying fields (name, filename, divider, and whether
+    /// the file is an encrypted one) are compared. If any of them differ,
+    /// return an error naming one of the differences.
     pub fn diff(&self, other: &Self) -> Result<()> {
         if self.name != other.name {
             let msg = format!("doesn't match existing name {:?}", other.name);
@@ -45,12 +175,45 @@ impl StoreConfig {
             let msg = format!("doesn't match existing divider {:?}", other.divider);
             return Err(Error::Invalid("divider".to_string(), msg));
         }
+        if self.encrypted_preferences != other.encrypted_preferences {
+            let msg = format!(
+                "doesn't match existing encrypted_preferences {}",
+                other.encrypted_preferences
+            );
+            return Err(Error::Invalid("encrypted_preferences".to_string(), msg));
+        }
         Ok(())
     }
 
     /// Create a StoreConfig from a configuration HashMap
     pub fn from_configuration(configuration: &HashMap<&str, &str>) -> Result<Self> {
-        let mods = parse_attributes(&["+name", "+filename", "+divider"], Some(configuration))?;
+        let mods = parse_attributes(
+            &[
+                "+name",
+                "+filename",
+                "+divider",
+                "+key_generation_attempts",
+                "+key_generation_delay_ms",
+                "+key_size",
+                "*readonly_if_locked",
+                "*randomized_encryption_required",
+                "*strongbox",
+                "+key_validity_start",
+                "+key_validity_end",
+                "*unlocked_device_required",
+                "+transformation",
+                "*synchronous_writes",
+                "*encrypted_preferences",
+                "+on_corrupt",
+                "+max_secret_size",
+                "*persistent_id",
+                "*upgrade_envelopes",
+                "+envelope_format",
+                "*application_context",
+                "*multi_process",
+            ],
+            Some(configuration),
+        )?;
         let mut config = StoreConfig::default();
         if let Some(name) = mods.get("name") {
             config.name = name.to_string();
@@ -69,16 +232,150 @@ impl StoreConfig {
             }
             config.divider = divider.to_string();
         }
+        if let Some(attempts) = mods.get("key_generation_attempts") {
+            config.key_generation_attempts = match attempts.parse::<u32>() {
+                Ok(attempts) if attempts > 0 => attempts,
+                _ => {
+                    let err = "must be a positive integer".to_string();
+                    return Err(Error::Invalid("key_generation_attempts".to_string(), err));
+                }
+            };
+        }
+        if let Some(delay) = mods.get("key_generation_delay_ms") {
+            config.key_generation_delay_ms = delay.parse::<u64>().map_err(|_| {
+                let err = "must be a non-negative integer".to_string();
+                Error::Invalid("key_generation_delay_ms".to_string(), err)
+            })?;
+        }
+        if let Some(key_size) = mods.get("key_size") {
+            config.key_size = match key_size.as_str() {
+                "128" => Some(128),
+                "192" => Some(192),
+                "256" => Some(256),
+                _ => {
+                    let err = "must be one of 128, 192, or 256".to_string();
+                    return Err(Error::Invalid("key_size".to_string(), err));
+                }
+            };
+        }
+        if let Some(readonly) = mods.get("readonly_if_locked") {
+            config.readonly_if_locked = readonly == "true";
+        }
+        if let Some(required) = mods.get("randomized_encryption_required") {
+            config.randomized_encryption_required = required == "true";
+        }
+        if let Some(strongbox) = mods.get("strongbox") {
+            config.strongbox = strongbox == "true";
+        }
+        if let Some(required) = mods.get("unlocked_device_required") {
+            config.unlocked_device_required = required == "true";
+        }
+        if let Some(persistent) = mods.get("persistent_id") {
+            config.persistent_id = persistent == "true";
+        }
+        if let Some(upgrade) = mods.get("upgrade_envelopes") {
+            config.upgrade_envelopes = upgrade == "true";
+        }
+        if let Some(application) = mods.get("application_context") {
+            config.application_context = application == "true";
+        }
+        if let Some(synchronous) = mods.get("synchronous_writes") {
+            config.synchronous_writes = synchronous == "true";
+        }
+        if let Some(encrypted) = mods.get("encrypted_preferences") {
+            config.encrypted_preferences = encrypted == "true";
+            if config.encrypted_preferences && cfg!(not(feature = "encrypted-preferences")) {
+                let err = "requires the encrypted-preferences feature".to_string();
+                return Err(Error::Invalid("encrypted_preferences".to_string(), err));
+            }
+        }
+        if let Some(multi_process) = mods.get("multi_process") {
+            config.multi_process = multi_process == "true";
+            if config.multi_process && config.encrypted_preferences {
+                let err = "can't be used with encrypted_preferences".to_string();
+                return Err(Error::Invalid("multi_process".to_string(), err));
+            }
+        }
+        if let Some(transformation) = mods.get("transformation") {
+            config.transformation = transformation.parse().map_err(|_| {
+                let err = "must be AES/GCM/NoPadding or AES/CBC/PKCS7Padding".to_string();
+                Error::Invalid("transformation".to_string(), err)
+            })?;
+        }
+        if let Some(format) = mods.get("envelope_format") {
+            config.envelope_format = format.parse().map_err(|_| {
+                let err = "must be compact or cbor".to_string();
+                Error::Invalid("envelope_format".to_string(), err)
+            })?;
+        }
+        if let Some(on_corrupt) = mods.get("on_corrupt") {
+            config.on_corrupt = on_corrupt.parse().map_err(|_| {
+                let err = "must be error or no_entry".to_string();
+                Error::Invalid("on_corrupt".to_string(), err)
+            })?;
+        }
+        if let Some(size) = mods.get("max_secret_size") {
+            config.max_secret_size = match size.parse::<u32>() {
+                Ok(size) if size > 0 => size,
+                _ => {
+                    let err = "must be a positive integer".to_string();
+                    return Err(Error::Invalid("max_secret_size".to_string(), err));
+                }
+            };
+        }
+        for key in ["key_validity_start", "key_validity_end"] {
+            if let Some(millis) = mods.get(key) {
+                let millis = millis.parse::<i64>().map_err(|_| {
+                    let err = "must be milliseconds since the Unix epoch".to_string();
+                    Error::Invalid(key.to_string(), err)
+                })?;
+                match key {
+                    "key_validity_start" => config.key_validity_start = Some(millis),
+                    _ => config.key_validity_end = Some(millis),
+                }
+            }
+        }
         Ok(config)
     }
+
+    /// The retry policy used when generating this store's encryption key.
+    pub fn key_generation_backoff(&self) -> Backoff {
+        Backoff {
+            max_attempts: self.key_generation_attempts,
+            base_delay: std::time::Duration::from_millis(self.key_generation_delay_ms),
+        }
+    }
 }
 
+/// What a credential store supports, as reported by [Store::capabilities]
+/// (and by the `capabilities` method of the legacy store).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether the store's credentials can be found by searching.
+    pub search: bool,
+    /// Whether the store's credentials can have attributes.
+    pub attributes: bool,
+    /// Whether the store's credentials outlive the process that wrote them.
+    pub persistent: bool,
+    /// Whether the store's keys live in secure hardware (a TEE or StrongBox).
+    /// This is `None` if the store has no single key to check.
+    pub hardware_backed: Option<bool>,
+}
+
+/// A callback that supplies a fresh secret for a credential whose
+/// stored secret can no longer be decrypted.
+///
+/// It is passed the credential's service and user, and it is called while
+/// the credential's store is locked, so it must not use that store.
+pub type Reprovisioner = Arc<dyn Fn(&str, &str) -> Result<Vec<u8>> + Send + Sync>;
+
 /// A Store is a wrapper around a Vault
 /// that keeps its configuration handy.
 pub struct Store {
     pub id: String,
     pub config: StoreConfig,
     vault: AtomicVault,
+    locked: Arc<AtomicBool>,
 }
 
 impl std::fmt::Debug for Store {
@@ -87,6 +384,7 @@ impl std::fmt::Debug for Store {
             .field("vendor", &self.vendor())
             .field("id", &self.id)
             .field("config", &self.config)
+            .field("locked", &self.is_locked())
             .finish()
     }
 }
@@ -99,10 +397,27 @@ impl Store {
         Store::new_with_store_config(config)
     }
 
+    /// Returns a store with the given name and otherwise default configuration,
+    /// creating one if necessary.
+    ///
+    /// This is the same as calling [new_with_configuration](Store::new_with_configuration)
+    /// with just a `name`. Differently named stores are fully isolated: each
+    /// has its own file and its own key, so an application can keep, say,
+    /// protected and unprotected credentials apart.
+    pub fn new_named(name: &str) -> Result<Arc<Self>> {
+        Store::new_with_configuration(&HashMap::from([("name", name)]))
+    }
+
     /// Returns a store with the specified configuration,
     /// creating one if necessary.
     ///
-    /// Allowed configuration keys are `name`, `filename`, and `divider`.
+    /// Allowed configuration keys are `name`, `filename`, `divider`,
+    /// `key_generation_attempts`, `key_generation_delay_ms`, `key_size`,
+    /// `readonly_if_locked`, `randomized_encryption_required`, `strongbox`,
+    /// `key_validity_start`, `key_validity_end`, `unlocked_device_required`,
+    /// `transformation`, `synchronous_writes`, `encrypted_preferences`, `on_corrupt`,
+    /// `max_secret_size`, `persistent_id`, `upgrade_envelopes`, `envelope_format`,
+    /// `application_context`, and `multi_process`.
     /// None are required, but any that are supplied must be non-empty.
     ///
     /// The value of `name` defaults to `default`. Stores names are unique, so you can't
@@ -115,6 +430,144 @@ impl Store {
     /// when printed as part of a string, looks like `@` because
     /// the BOM character is considered a non-spacing word-joining
     /// character. The divider _must_ contain a non-alphabetic character.
+    ///
+    /// Key generation can fail transiently on some devices, so it is retried
+    /// with exponential backoff. The value of `key_generation_attempts` (default 3)
+    /// bounds the number of tries, and the value of `key_generation_delay_ms`
+    /// (default 50) is the delay before the first retry. Failures caused by
+    /// a bad key specification are never retried.
+    ///
+    /// The value of `key_size` is the size in bits (128, 192, or 256) of the
+    /// AES key generated for a new store. If not specified, the platform default
+    /// is used. It has no effect on a store whose key already exists.
+    ///
+    /// If the value of `readonly_if_locked` is `true` (default `false`), then
+    /// while the device is locked, reading or writing a credential's secret
+    /// fails with a [NoStorageAccess](Error::NoStorageAccess) error that wraps
+    /// [AndroidKeyringError::DeviceLocked](crate::AndroidKeyringError::DeviceLocked),
+    /// so applications can prompt the user to unlock. Checking whether a
+    /// credential exists and searching the store still work.
+    ///
+    /// The value of `randomized_encryption_required` (default `true`) controls
+    /// whether the keystore insists that every encryption with a new store's key
+    /// uses a fresh, keystore-generated IV. Setting it to `false` allows callers
+    /// to supply their own IVs, which is only useful for deterministic testing:
+    /// reusing an IV with AES/GCM reveals the relationship between the secrets
+    /// encrypted with it and allows forgery, so never do this with real data.
+    ///
+    /// If the value of `strongbox` is `true` (default `false`), a new store's key
+    /// is generated in the device's StrongBox hardware security module. Devices
+    /// without StrongBox fall back to keeping the key in the TEE. StrongBox
+    /// is only available on Android 9 (API level 28) and later.
+    ///
+    /// The values of `key_validity_start` and `key_validity_end`, if specified,
+    /// are times given in milliseconds since the Unix epoch. A new store's key
+    /// can't be used before the start time or after the end time: reading or
+    /// writing a secret outside that window fails with a
+    /// [NoStorageAccess](Error::NoStorageAccess) error that wraps
+    /// [AndroidKeyringError::KeyNotYetValid](crate::AndroidKeyringError::KeyNotYetValid) or
+    /// [AndroidKeyringError::KeyExpired](crate::AndroidKeyringError::KeyExpired).
+    ///
+    /// If the value of `unlocked_device_required` is `true` (default `false`),
+    /// the keystore refuses to use a new store's key while the device is locked,
+    /// so secrets are protected at rest even if the app is running. Reading or
+    /// writing a secret while the device is locked fails with a
+    /// [NoStorageAccess](Error::NoStorageAccess) error that wraps
+    /// [AndroidKeyringError::AuthenticationRequired](crate::AndroidKeyringError::AuthenticationRequired).
+    /// This is only available on Android 9 (API level 28) and later.
+    ///
+    /// The value of `transformation` is the cipher used to encrypt secrets:
+    /// either `AES/GCM/NoPadding` (the default) or `AES/CBC/PKCS7Padding`.
+    /// CBC doesn't authenticate the encrypted data, so only use it when
+    /// interoperating with a legacy format requires it. Every encrypted
+    /// secret records its cipher, so secrets written with either one can
+    /// always be read. But a store's key only supports CBC if the store
+    /// was created with it, so a store created with GCM can't be
+    /// switched to CBC later.
+    ///
+    /// If the value of `synchronous_writes` is `true` (the default), setting
+    /// or deleting a credential's secret doesn't return until the change
+    /// has been written to disk. Setting it to `false` makes those operations
+    /// return as soon as the change is made in memory, with the disk write
+    /// happening in the background. That's much faster when setting many
+    /// secrets in a loop, but changes can be lost if the process dies before
+    /// they are written. Either way, all subsequent reads see the change.
+    ///
+    /// If the value of `encrypted_preferences` is `true` (default `false`),
+    /// the store's file is an AndroidX `EncryptedSharedPreferences` file
+    /// rather than a plain SharedPreferences file that holds secrets encrypted
+    /// by this crate. The library encrypts both the keys and the values of the
+    /// file, so credential IDs aren't visible in it either, and all such stores
+    /// share the library's master key rather than having a key of their own.
+    /// This requires the `encrypted-preferences` feature, and the application
+    /// must depend on `androidx.security:security-crypto`. The key-related
+    /// settings (`key_size`, `strongbox`, `transformation`, and so on) have no
+    /// effect on such a store, and a store's backend can't be changed once
+    /// it has been created.
+    ///
+    /// The value of `on_corrupt` controls what reading a credential's secret
+    /// does when the stored secret can't be decrypted. If it's `error` (the
+    /// default), reading fails with a [BadDataFormat](Error::BadDataFormat)
+    /// error that holds the stored secret, so applications can inspect it
+    /// before deleting it. If it's `no_entry`, reading fails with a
+    /// [NoEntry](Error::NoEntry) error, as if the credential had no secret,
+    /// and the next write of the secret replaces the corrupted one.
+    /// If the store has a reprovisioner, it's tried first either way.
+    ///
+    /// The value of `max_secret_size` (default 65536) is the size in bytes
+    /// of the largest secret that can be stored. Setting a larger secret fails
+    /// with a [TooLong](Error::TooLong) error. SharedPreferences is a poor fit
+    /// for large blobs: the whole file is held in memory once it's read, every
+    /// write rewrites the whole file, and secrets are stored in it as base64
+    /// text that is a third larger than the secret itself. So raise this limit
+    /// only for a store that holds a few large secrets.
+    ///
+    /// If the value of `persistent_id` is `true` (default `false`), the store's
+    /// [id](CredentialStoreApi::id) is based on a UUID that is generated when
+    /// the id is first needed and kept on the device, so every store with
+    /// the same name has the same id, even across process restarts, until the
+    /// store is deleted. Otherwise the id is based on the time the store
+    /// object was created, so every store object has a different one.
+    ///
+    /// Secrets written by version 0.3 and earlier of this crate have no
+    /// header, only the IV length, the IV, and the ciphertext. They are
+    /// always read transparently. If the value of `upgrade_envelopes` is
+    /// `true` (the default), the next write of such a secret stores it with
+    /// the current header. Setting it to `false` keeps each secret that's
+    /// in the old layout in that layout when it's rewritten, so an older
+    /// version of the application can still read it after a downgrade.
+    /// New secrets always get the current header.
+    ///
+    /// The value of `envelope_format` is the layout that encrypted secrets
+    /// are written in: either `compact` (the default), a few bytes of binary
+    /// header before the IV and the ciphertext, or `cbor`, a CBOR map whose
+    /// named fields can be extended without a new envelope version, at the cost
+    /// of a couple of dozen bytes per secret. Secrets in either format can
+    /// always be read, so a store's format can be changed at any time. See
+    /// [EnvelopeFormat](crate::EnvelopeFormat) for details.
+    ///
+    /// The context that `ndk_context` provides may be an Activity, which
+    /// the store would keep alive (and use after it's destroyed) for as long
+    /// as the store exists. So if the value of `application_context` is `true`
+    /// (the default), the store instead keeps that context's Application
+    /// context, which lives as long as the process. Set it to `false` only
+    /// if the store must use the provided context itself. Since all stores
+    /// with the same name share their file, this only has an effect when
+    /// the first store with a given name is created.
+    ///
+    /// Each process keeps its own in-memory copy of a store's file, so an
+    /// application with more than one process (such as one with a `:remote`
+    /// service) can read stale secrets in one process after they are changed
+    /// in another. If the value of `multi_process` is `true` (default `false`),
+    /// the store opens its file with `MODE_MULTI_PROCESS`, so every operation
+    /// first re-reads the file from disk if another process has changed it.
+    /// This has limitations: Android deprecated the mode because it doesn't
+    /// coordinate writes, so if two processes change the same store at about
+    /// the same time, one process's change can be lost, and a change isn't
+    /// visible elsewhere until it's been written, so such stores should keep
+    /// `synchronous_writes` on. Re-reading the file also makes operations
+    /// slower after every change. It can't be used with `encrypted_preferences`,
+    /// whose library always opens its file in the default mode.
     pub fn new_with_configuration(configuration: &HashMap<&str, &str>) -> Result<Arc<Self>> {
         let config = StoreConfig::from_configuration(configuration)?;
         Store::new_with_store_config(config)
@@ -124,8 +577,22 @@ impl Store {
     /// creating one if necessary.
     pub fn new_with_store_config(config: StoreConfig) -> Result<Arc<Self>> {
         let vault = lookup(&config)?;
-        let id = generate_instance_id();
-        Ok(Arc::new(Store { id, config, vault }))
+        let id = if config.persistent_id {
+            let uuid = vault
+                .lock()
+                .expect("Vault lock poisoned: report a bug!")
+                .persistent_id()?;
+            format!("One File per Store storage, Instance {uuid}")
+        } else {
+            generate_instance_id()
+        };
+        let locked = Arc::new(AtomicBool::new(false));
+        Ok(Arc::new(Store {
+            id,
+            config,
+            vault,
+            locked,
+        }))
     }
 
     /// Attempts to delete the store with the specified configuration.
@@ -143,6 +610,308 @@ impl Store {
         delete(&config)
     }
 
+    /// Lock or unlock this store.
+    ///
+    /// While a store is locked, reading, writing, or deleting the secret of any
+    /// credential built by the store, or inspecting the store's key, fails with
+    /// a [NotSupportedByStore](Error::NotSupportedByStore) error, but no stored
+    /// data is touched. This is meant as an emergency
+    /// control, for example after an application detects that it's been compromised.
+    ///
+    /// The lock is specific to this store object and is not persisted, so
+    /// other stores with the same name are unaffected, and every
+    /// newly created store starts out unlocked.
+    pub fn set_locked(&self, locked: bool) {
+        log::debug!("Setting store {:?} locked: {locked}", self.config.name);
+        self.locked.store(locked, Ordering::SeqCst);
+    }
+
+    /// Whether this store is locked. See [set_locked](Store::set_locked).
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    /// Opt in to (or, by passing `None`, out of) self-healing reads.
+    ///
+    /// Normally, reading a credential whose secret can't be decrypted fails
+    /// with a [BadDataFormat](Error::BadDataFormat) error, and reading a
+    /// credential whose key has been permanently invalidated fails with a
+    /// [NoStorageAccess](Error::NoStorageAccess) error that wraps
+    /// [AndroidKeyringError::KeyInvalidated](crate::AndroidKeyringError::KeyInvalidated).
+    /// When a reprovisioner
+    /// has been set, such reads instead delete the unreadable secret
+    /// (regenerating the store's key if it was invalidated), call the
+    /// reprovisioner for a fresh secret, store that, and return it.
+    ///
+    /// The reprovisioner is shared by all stores with the same name.
+    pub fn set_reprovisioner(&self, reprovisioner: Option<Reprovisioner>) {
+        let mut vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.reprovisioner = reprovisioner;
+    }
+
+    /// Replace this store's encryption key with one derived elsewhere.
+    ///
+    /// This is for applications that derive their key from something else
+    /// (such as a password, via a KDF) rather than relying on a generated one.
+    /// The raw AES key must be 16, 24, or 32 bytes long. It is imported into
+    /// the Android KeyStore with the same protections as a generated key
+    /// (except that StrongBox is never used), and can't be read back out.
+    ///
+    /// Secrets encrypted with the old key would become unreadable, so the
+    /// key can only be imported while the store holds no credentials.
+    /// Otherwise, this fails with an [Invalid](Error::Invalid) error.
+    pub fn import_key(&self, raw_key: &[u8]) -> Result<()> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.import_key(raw_key)
+    }
+
+    /// Get a key attestation certificate chain for this store.
+    ///
+    /// The store's encryption key is symmetric and can't be attested, so this
+    /// generates a fresh EC key pair for the store (replacing any prior one)
+    /// with the given challenge, and returns that pair's DER-encoded certificate
+    /// chain, leaf first. Servers can verify the chain to confirm that the device
+    /// has a genuine hardware-backed keystore.
+    ///
+    /// Devices that can't attest keys fail with a
+    /// [NotSupportedByStore](Error::NotSupportedByStore) error.
+    pub fn attestation_chain(&self, challenge: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.attestation_chain(challenge)
+    }
+
+    /// Check whether the user can authenticate on this device with the given
+    /// authenticators, a combination of the constants in the
+    /// [biometric](crate::biometric) module, so that the application can
+    /// decide whether to offer credentials that require authentication.
+    ///
+    /// This uses AndroidX's `BiometricManager`, so the application
+    /// must depend on `androidx.biometric`.
+    #[cfg(feature = "biometric")]
+    pub fn can_authenticate(
+        &self,
+        authenticators: i32,
+    ) -> Result<crate::biometric::AuthenticationStatus> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.can_authenticate(authenticators)
+    }
+
+    /// Generate an EC (P-256) signing key pair in the Android KeyStore for
+    /// this store, and return its public key in X.509 (SubjectPublicKeyInfo)
+    /// encoding, so the application can register it with a server.
+    ///
+    /// The private key never leaves the KeyStore. Each store has its own
+    /// namespace of signing keys, and generating a key with an alias that's
+    /// in use replaces the old key. The keys are deleted along with the store.
+    pub fn generate_signing_key(&self, alias: &str) -> Result<Vec<u8>> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.generate_signing_key(alias)
+    }
+
+    /// Compute the HMAC-SHA256 of the given data with the HMAC key
+    /// of the given alias in the Android KeyStore for this store.
+    ///
+    /// The key is generated on first use and never leaves the KeyStore,
+    /// so the same alias and data always give the same MAC on this device.
+    /// Like signing keys, HMAC keys are deleted along with the store.
+    pub fn hmac(&self, alias: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.hmac(alias, data)
+    }
+
+    /// Export all the credentials of the given service to a [backup](crate::backup)
+    /// blob, encrypted under a key derived from the given passphrase.
+    ///
+    /// Each secret is decrypted with this store's KeyStore key and
+    /// re-encrypted in Rust, so the blob can be opened on another device
+    /// (or off the device) with [backup::open](crate::backup::open).
+    /// A service with no credentials produces a blob holding none.
+    #[cfg(feature = "backup")]
+    pub fn export_service(&self, service: &str, passphrase: &str) -> Result<Vec<u8>> {
+        let pattern = format!("^{}$", regex::escape(service));
+        let entries = self.search(&HashMap::from([("service", pattern.as_str())]))?;
+        let mut credentials = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let (_, user) = entry
+                .get_specifiers()
+                .expect("Search results are specifiers: report a bug!");
+            match entry.get_secret() {
+                Ok(secret) => credentials.push((user, Zeroizing::new(secret))),
+                // deleted since the search
+                Err(Error::NoEntry) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        log::debug!(
+            "Exporting {} credentials of service {service:?}",
+            credentials.len()
+        );
+        crate::backup::seal(service, &credentials, passphrase)
+    }
+
+    /// Import the credentials in a [backup](crate::backup) blob, made by
+    /// [export_service](Store::export_service) with the given passphrase,
+    /// into the given service, and return how many were imported.
+    ///
+    /// The credentials are imported under the given service, whichever
+    /// service they were exported from, and each is written like any other,
+    /// so it's encrypted with this store's KeyStore key. The blob is fully
+    /// decrypted and checked before anything is written, and if writing any
+    /// credential fails, the ones already written are put back as they were,
+    /// so either all the credentials are imported or none are.
+    ///
+    /// A wrong passphrase fails with an [Invalid](Error::Invalid) error about
+    /// the `passphrase`, and a blob that's malformed, truncated, or of an
+    /// unsupported version fails with one about the `blob`.
+    #[cfg(feature = "backup")]
+    pub fn import_service(&self, service: &str, passphrase: &str, blob: &[u8]) -> Result<usize> {
+        let backup = crate::backup::open(blob, passphrase)?;
+        let mut imports = Vec::with_capacity(backup.credentials.len());
+        for (user, secret) in &backup.credentials {
+            let entry = self.build(service, user, None)?;
+            let previous = match entry.get_secret() {
+                Ok(secret) => Some(Zeroizing::new(secret)),
+                Err(Error::NoEntry) => None,
+                Err(err) => return Err(err),
+            };
+            imports.push((entry, secret, previous));
+        }
+        for (i, (entry, secret, _)) in imports.iter().enumerate() {
+            if let Err(err) = entry.set_secret(secret) {
+                log::error!("Failed to import credential {i} of service {service:?}: {err}");
+                for (entry, _, previous) in imports[..i].iter().rev() {
+                    let restored = match previous {
+                        Some(previous) => entry.set_secret(previous),
+                        None => entry.delete_credential(),
+                    };
+                    if let Err(err) = restored {
+                        log::error!("Failed to undo import into {entry:?}: {err}");
+                    }
+                }
+                return Err(err);
+            }
+        }
+        log::debug!(
+            "Imported {} credentials into service {service:?}",
+            imports.len()
+        );
+        Ok(imports.len())
+    }
+
+    /// Describe the credentials of the given service as a JSON string,
+    /// without decrypting any of them.
+    ///
+    /// The JSON is an object with the `service` and an array of its
+    /// `credentials`, sorted by user. Each credential has its `user` and
+    /// what the header of its stored value says: the envelope `version`
+    /// (0 for the legacy layout), the `iv_len`, and the `ciphertext_len`.
+    /// A credential whose value is corrupted has, instead, an `error` naming
+    /// the kind of [CorruptedData](crate::CorruptedData), such as `DataTooSmall`,
+    /// so it's listed rather than left out. The values in a store that uses
+    /// encrypted preferences have no header of ours, so only their users are
+    /// listed. Credentials in named stores have no attributes or timestamps.
+    pub fn describe_service(&self, service: &str) -> Result<String> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        let mut users: Vec<(String, String)> = vault
+            .get_ids(&Regex::new("").unwrap())?
+            .into_iter()
+            .filter(|(_, s, _)| s == service)
+            .map(|(id, _, user)| (user, id))
+            .collect();
+        users.sort();
+        let credentials = vault.with_env(|env| {
+            let file = vault.get_file(env)?;
+            let mut credentials = Vec::with_capacity(users.len());
+            for (user, id) in users {
+                let header = match file.get_binary(env, &id) {
+                    Ok(Some(_)) if vault.uses_encrypted_preferences() => Ok(None),
+                    Ok(Some(data)) => envelope_info(&data).map(Some),
+                    // deleted since the IDs were read
+                    Ok(None) => continue,
+                    Err(AndroidKeyringError::CorruptedData(_, err)) => Err(err),
+                    Err(err) => return Err(err),
+                };
+                credentials.push(match header {
+                    Ok(header) => CredentialDescription {
+                        user,
+                        header,
+                        error: None,
+                    },
+                    Err(err) => CredentialDescription {
+                        user,
+                        header: None,
+                        error: Some(err.kind()),
+                    },
+                });
+            }
+            Ok(credentials)
+        })?;
+        let description = ServiceDescription {
+            service,
+            credentials,
+        };
+        serde_json::to_string(&description).map_err(|e| Error::PlatformFailure(Box::new(e)))
+    }
+
+    /// Call the given callback whenever this store's file changes,
+    /// until the returned listener is dropped.
+    ///
+    /// The callback is passed the key of the changed entry, which for
+    /// a credential is its ID, or `None` if the whole file was cleared.
+    /// It is called on the application's main thread, whichever
+    /// process or component made the change, so it shouldn't block.
+    /// The application must include the Kotlin shim described in
+    /// the [change_listener](crate::change_listener) module.
+    pub fn listen_for_changes(&self, callback: ChangeCallback) -> Result<ChangeListener> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.listen(callback)
+    }
+
+    /// Report what this store supports.
+    ///
+    /// Named stores can be searched but their credentials can't have
+    /// attributes. Whether they're hardware-backed depends on the device,
+    /// so that is checked with the store's key.
+    pub fn capabilities(&self) -> Result<Capabilities> {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        let hardware_backed = vault.with_key_and_file(|env, key, _| {
+            Ok(key.get_key_info(env)?.is_inside_secure_hardware(env)?)
+        })?;
+        Ok(Capabilities {
+            search: true,
+            attributes: false,
+            persistent: true,
+            hardware_backed: Some(hardware_backed),
+        })
+    }
+
     #[cfg(feature = "compile-tests")]
     pub fn change_key(&self) -> Result<()> {
         let vault = self
@@ -151,6 +920,15 @@ impl Store {
             .expect("Vault lock poisoned: report a bug!");
         vault.change_key()
     }
+
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn context(&self) -> crate::shared_preferences::Context {
+        let vault = self
+            .vault
+            .lock()
+            .expect("Vault lock poisoned: report a bug!");
+        vault.context()
+    }
 }
 
 impl CredentialStoreApi for Store {
@@ -195,7 +973,8 @@ impl CredentialStoreApi for Store {
         }
         let id = format!("{user}{divider}{service}");
         log::debug!("Building entry {id:?} for ({service:?}, {user:?})");
-        let credential = Cred::new_specifier(self.vault.clone(), &id, service, user);
+        let credential =
+            Cred::new_specifier(self.vault.clone(), self.locked.clone(), &id, service, user);
         Ok(Entry::new_with_credential(Arc::new(credential)))
     }
 
@@ -239,7 +1018,8 @@ impl CredentialStoreApi for Store {
         let triples = vault.get_ids(&id_exp)?;
         for (id, service, user) in triples.iter() {
             if user_exp.is_match(user) && service_exp.is_match(service) {
-                let credential = Cred::new_specifier(self.vault.clone(), id, service, user);
+                let credential =
+                    Cred::new_specifier(self.vault.clone(), self.locked.clone(), id, service, user);
                 results.push(Entry::new_with_credential(Arc::new(credential)));
             }
         }
@@ -255,6 +1035,22 @@ impl CredentialStoreApi for Store {
     }
 }
 
+/// The JSON written by [Store::describe_service].
+#[derive(Serialize)]
+struct ServiceDescription<'a> {
+    service: &'a str,
+    credentials: Vec<CredentialDescription>,
+}
+
+#[derive(Serialize)]
+struct CredentialDescription {
+    user: String,
+    #[serde(flatten)]
+    header: Option<EnvelopeInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
 fn generate_instance_id() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -4,17 +4,34 @@ use jni::{JNIEnv, JavaVM, objects::GlobalRef};
 use keyring_core::{Error, Result};
 use regex::Regex;
 
+#[cfg(feature = "encrypted-preferences")]
+use crate::encrypted_preferences::DEFAULT_MASTER_KEY_ALIAS;
 use crate::{
-    error::AndroidKeyringResult,
+    backoff::classify_keystore_failure,
+    change_listener::{ChangeCallback, ChangeListener},
+    cipher::Mac,
+    crypto::{Transformation, decrypt, encrypt_in_format, encrypt_legacy, legacy_binding},
+    error::{
+        AndroidKeyringError, AndroidKeyringResult, CorruptedData, pending_exception_is,
+        pending_exception_message, take_pending_exception,
+    },
+    keyguard::KeyguardManager,
     keystore::{
-        BLOCK_MODE_GCM, ENCRYPTION_PADDING_NONE, KEY_ALGORITHM_AES, Key,
-        KeyGenParameterSpecBuilder, KeyGenerator, KeyStore, PROVIDER, PURPOSE_DECRYPT,
-        PURPOSE_ENCRYPT,
+        DIGEST_SHA256, KEY_ALGORITHM_AES, KEY_ALGORITHM_EC, KEY_ALGORITHM_HMAC_SHA256, Key,
+        KeyGenParameterSpec, KeyGenParameterSpecBuilder, KeyGenerator, KeyPairGenerator,
+        KeyProtection, KeyProtectionBuilder, KeyStore, PROVIDER, PROVIDER_EXCEPTION,
+        PURPOSE_DECRYPT, PURPOSE_ENCRYPT, PURPOSE_SIGN, PURPOSE_VERIFY,
+        STRONGBOX_UNAVAILABLE_EXCEPTION, SecretKeySpec,
+    },
+    methods::{JResult, LOCAL_FRAME_CAPACITY, attach},
+    shared_preferences::{
+        Context, MODE_MULTI_PROCESS, MODE_PRIVATE, SharedPreferences, SharedPreferencesEditor,
+        random_uuid,
     },
-    shared_preferences::{Context, MODE_PRIVATE, SharedPreferences},
 };
 
-use super::store::StoreConfig;
+use super::cred::aad;
+use super::store::{OnCorrupt, Reprovisioner, StoreConfig};
 
 /// An AtomicVault is a [Vault] protected by a mutex.
 ///
@@ -28,6 +45,11 @@ pub type AtomicVault = Arc<Mutex<Vault>>;
 // of all the known vaults so we can look up the one a user is requesting.
 static VAULTS: Mutex<Vec<AtomicVault>> = Mutex::new(Vec::new());
 
+// The persistent ids of stores are kept in a file of their own, keyed
+// by store name. Store files are named `keyring-{name}` by default,
+// so this name can't collide with theirs unless a store is given it.
+const PERSISTENT_IDS_FILENAME: &str = "keyring.persistent-ids";
+
 /// Look up a vault by name, creating it if it doesn't exist.
 ///
 /// If an existing vault with that name has a different config, return
@@ -104,6 +126,7 @@ pub struct Vault {
     vm: Arc<JavaVM>,
     context: GlobalRef,
     config: StoreConfig,
+    pub reprovisioner: Option<Reprovisioner>,
 }
 
 impl std::fmt::Debug for Vault {
@@ -114,16 +137,17 @@ impl std::fmt::Debug for Vault {
     }
 }
 
-const CONFIG_KEY: &str = "vaultConfig";
+pub(crate) const CONFIG_KEY: &str = "vaultConfig";
 
 impl Vault {
     // Find an existing vault with the same name and config
     fn find(config: &StoreConfig) -> Result<Option<Self>> {
-        let (vm, context) = get_ndk_context()?;
+        let (vm, context) = get_ndk_context(config.application_context)?;
         let vault = Self {
             vm,
             context,
             config: config.clone(),
+            reprovisioner: None,
         };
         let result = vault.with_env(|env| {
             let file = vault.get_file(env)?;
@@ -148,15 +172,23 @@ impl Vault {
             let err = "must contain a non-alphabetic character".to_string();
             return Err(Error::Invalid("divider".to_string(), err));
         }
+        if config.encrypted_preferences && cfg!(not(feature = "encrypted-preferences")) {
+            let err = "requires the encrypted-preferences feature".to_string();
+            return Err(Error::Invalid("encrypted_preferences".to_string(), err));
+        }
         log::debug!("Creating new vault with config {config:?}");
-        let (vm, context) = get_ndk_context()?;
+        let (vm, context) = get_ndk_context(config.application_context)?;
         let mut vault = Self {
             vm,
             context,
             config: config.clone(),
+            reprovisioner: None,
         };
         vault.initialize_config()?;
-        vault.initialize_key()?;
+        // encrypted files share the master key, which opening the file created
+        if !vault.config.encrypted_preferences {
+            vault.initialize_key()?;
+        }
         Ok(vault)
     }
 
@@ -188,21 +220,48 @@ impl Vault {
         log::debug!("Deleting vault with config {:?}", self.config);
         self.with_env(|env| {
             self.delete_key(env)?;
+            self.delete_application_keys(env)?;
             if !self.delete_file(env)? {
                 log::warn!("Failed to find file {:?}", self.config.filename);
             }
+            let ids = self.get_persistent_ids_file(env)?;
+            ids.edit(env)?.remove(env, &self.config.name)?.commit(env)?;
             Ok(())
         })?;
         Ok(())
     }
 
+    /// The persistent id of this vault's stores, generating it if necessary.
+    pub fn persistent_id(&self) -> Result<String> {
+        let id = self.with_env(|env| {
+            let ids = self.get_persistent_ids_file(env)?;
+            if let Some(id) = ids.get_string(env, &self.config.name)? {
+                return Ok(id);
+            }
+            let id = random_uuid(env)?;
+            log::debug!("Generated id {id:?} for vault {:?}", self.config.name);
+            let editor = ids.edit(env)?.put_string(env, &self.config.name, &id)?;
+            if !editor.commit(env)? {
+                let err = format!("Failed to save id of vault {:?}", self.config.name);
+                return Err(Error::PlatformFailure(err.into()).into());
+            }
+            Ok(id)
+        })?;
+        Ok(id)
+    }
+
+    fn get_persistent_ids_file(&self, env: &mut JNIEnv) -> JResult<SharedPreferences> {
+        let ctx = Context::from_raw(self.context.clone());
+        ctx.get_shared_preferences(env, PERSISTENT_IDS_FILENAME, MODE_PRIVATE)
+    }
+
     /// Find all credentials whose ids match a given regular expression, returning
     /// the triple (id, service, user) for each matching credential.
     pub fn get_ids(&self, re: &Regex) -> Result<Vec<(String, String, String)>> {
         let mut ids = Vec::new();
         self.with_env(|env| {
             let file = self.get_file(env)?;
-            let keys = file.get_all(env)?.get_keys(env)?;
+            let keys = file.get_all_keys(env)?;
             for key in keys {
                 if let Some((user, service)) = key.split_once(&self.config.divider)
                     && !service.contains(&self.config.divider)
@@ -216,6 +275,197 @@ impl Vault {
         Ok(ids)
     }
 
+    /// Replace a credential's unreadable secret with a fresh one from the reprovisioner.
+    ///
+    /// Called when decrypting the credential's secret failed with the given error.
+    /// If there is no reprovisioner, or the error isn't one that reprovisioning
+    /// can fix, the error is returned unchanged.
+    pub fn reprovision(
+        &self,
+        env: &mut JNIEnv,
+        err: AndroidKeyringError,
+        id: &str,
+        service: &str,
+        user: &str,
+    ) -> AndroidKeyringResult<Vec<u8>> {
+        let Some(reprovisioner) = &self.reprovisioner else {
+            return Err(err);
+        };
+        let key_invalidated = match &err {
+            AndroidKeyringError::CorruptedData(_, CorruptedData::DecryptionFailure) => false,
+            AndroidKeyringError::KeyInvalidated => true,
+            _ => return Err(err),
+        };
+        if key_invalidated && self.config.encrypted_preferences {
+            // the master key is shared by other files, so it can't be replaced here
+            return Err(err);
+        }
+        env.exception_clear()?;
+        if key_invalidated {
+            log::warn!(
+                "Key for vault {:?} was invalidated, replacing it",
+                self.config.name
+            );
+            self.delete_key(env)?;
+            self.create_key(env)?;
+        }
+        log::debug!("Reprovisioning secret for id {id:?}");
+        let file = self.get_file(env)?;
+        let editor = file.edit(env)?.remove(env, id)?;
+        self.save(env, editor)?;
+        let secret = reprovisioner(service, user)?;
+        let ciphertext = self.encrypt_secret(env, &aad(service, user), &secret)?;
+        let editor = file.edit(env)?.put_binary(env, id, &ciphertext)?;
+        self.save(env, editor)?;
+        Ok(secret)
+    }
+
+    /// Replace this vault's key with one imported from raw AES key material.
+    ///
+    /// Secrets encrypted with the old key would become unreadable,
+    /// so this is only allowed while the vault holds no credentials.
+    pub fn import_key(&self, raw_key: &[u8]) -> Result<()> {
+        if ![16, 24, 32].contains(&raw_key.len()) {
+            let err = "must be 16, 24, or 32 bytes long".to_string();
+            return Err(Error::Invalid("key".to_string(), err));
+        }
+        if self.config.encrypted_preferences {
+            let err = "Encrypted preferences stores use the shared master key".to_string();
+            return Err(Error::NotSupportedByStore(err));
+        }
+        if !self.get_ids(&Regex::new("").unwrap())?.is_empty() {
+            let err = "can only be imported into an empty store".to_string();
+            return Err(Error::Invalid("key".to_string(), err));
+        }
+        self.with_env(|env| {
+            let protection = self.key_protection(env)?;
+            let key = SecretKeySpec::new(env, raw_key, KEY_ALGORITHM_AES)?.into();
+            let _lock = KEY_SERVICE_LOCK
+                .lock()
+                .expect("Key service lock poisoned: report a bug!");
+            let keystore = KeyStore::get_instance(env, PROVIDER)?;
+            keystore.load(env)?;
+            keystore.delete_entry(env, &self.config.filename)?;
+            keystore.set_entry(env, &self.config.filename, &key, protection)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Generate a fresh attestation key pair for this vault, with the given
+    /// challenge, and return its DER-encoded certificate chain.
+    pub fn attestation_chain(&self, challenge: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let chain = self.with_env(|env| {
+            let _lock = KEY_SERVICE_LOCK
+                .lock()
+                .expect("Key service lock poisoned: report a bug!");
+            let alias = self.attestation_alias();
+            let spec =
+                KeyGenParameterSpecBuilder::new(env, KEY_ALGORITHM_EC, &alias, PURPOSE_SIGN)?
+                    .set_digests(env, &[DIGEST_SHA256])?
+                    .set_attestation_challenge(env, challenge)?
+                    .build(env)?;
+            let generator = KeyPairGenerator::get_instance(env, KEY_ALGORITHM_EC, PROVIDER)?;
+            generator.initialize(env, spec.into())?;
+            if let Err(e) = generator.generate_key_pair(env) {
+                // devices without attestation keys fail to generate the chain
+                if !pending_exception_is(env, PROVIDER_EXCEPTION) {
+                    return Err(e.into());
+                }
+                let message = pending_exception_message(env).unwrap_or_default();
+                env.exception_clear()?;
+                let err = format!("Key attestation isn't available on this device: {message}");
+                return Err(Error::NotSupportedByStore(err).into());
+            }
+            let keystore = KeyStore::get_instance(env, PROVIDER)?;
+            keystore.load(env)?;
+            Ok(keystore.get_certificate_chain(env, &alias)?)
+        })?;
+        if chain.is_empty() {
+            let err = "Key attestation isn't available on this device".to_string();
+            return Err(Error::NotSupportedByStore(err));
+        }
+        Ok(chain)
+    }
+
+    /// Check whether the user can authenticate with the given authenticators.
+    #[cfg(feature = "biometric")]
+    pub fn can_authenticate(
+        &self,
+        authenticators: i32,
+    ) -> Result<crate::biometric::AuthenticationStatus> {
+        let status = self.with_env(|env| {
+            let ctx = Context::from_raw(self.context.clone());
+            Ok(crate::biometric::can_authenticate(
+                env,
+                &ctx,
+                authenticators,
+            )?)
+        })?;
+        Ok(status)
+    }
+
+    /// Generate an EC signing key pair for this vault with the given alias,
+    /// replacing any prior one, and return the X.509 encoding of its public key.
+    pub fn generate_signing_key(&self, alias: &str) -> Result<Vec<u8>> {
+        if alias.is_empty() {
+            return Err(Error::Invalid(
+                "alias".to_string(),
+                "cannot be empty".to_string(),
+            ));
+        }
+        let public_key = self.with_env(|env| {
+            let _lock = KEY_SERVICE_LOCK
+                .lock()
+                .expect("Key service lock poisoned: report a bug!");
+            let purposes = PURPOSE_SIGN | PURPOSE_VERIFY;
+            let alias = self.signing_alias(alias);
+            let spec = KeyGenParameterSpecBuilder::new(env, KEY_ALGORITHM_EC, &alias, purposes)?
+                .set_digests(env, &[DIGEST_SHA256])?
+                .build(env)?;
+            let generator = KeyPairGenerator::get_instance(env, KEY_ALGORITHM_EC, PROVIDER)?;
+            generator.initialize(env, spec.into())?;
+            let key_pair = generator.generate_key_pair(env)?;
+            Ok(key_pair.get_public(env)?.get_encoded(env)?)
+        })?;
+        Ok(public_key)
+    }
+
+    /// Compute the HMAC-SHA256 of the given data with this vault's HMAC key
+    /// of the given alias, generating the key if there isn't one yet.
+    pub fn hmac(&self, alias: &str, data: &[u8]) -> Result<Vec<u8>> {
+        if alias.is_empty() {
+            return Err(Error::Invalid(
+                "alias".to_string(),
+                "cannot be empty".to_string(),
+            ));
+        }
+        let mac = self.with_env(|env| {
+            let key = self.get_or_create_hmac_key(env, &self.hmac_alias(alias))?;
+            let mac = Mac::get_instance(env, KEY_ALGORITHM_HMAC_SHA256)?;
+            mac.init(env, &key)?;
+            Ok(mac.do_final(env, data)?)
+        })?;
+        Ok(mac)
+    }
+
+    /// Register a callback for changes to this vault's file.
+    pub fn listen(&self, callback: ChangeCallback) -> Result<ChangeListener> {
+        let listener = self.with_env(|env| {
+            let ctx = Context::from_raw(self.context.clone());
+            let listener = ChangeListener::new(env, &ctx, callback)?;
+            let file = self.get_file(env)?;
+            file.register_on_shared_preference_change_listener(env, &listener)?;
+            Ok(listener)
+        })?;
+        Ok(listener)
+    }
+
+    #[cfg(feature = "compile-tests")]
+    pub fn context(&self) -> Context {
+        Context::from_raw(self.context.clone())
+    }
+
     #[cfg(feature = "compile-tests")]
     pub fn change_key(&self) -> Result<()> {
         self.with_env(|env| {
@@ -234,12 +484,14 @@ impl Vault {
     where
         F: FnOnce(&mut JNIEnv) -> AndroidKeyringResult<T>,
     {
-        let mut env = self.vm.attach_current_thread()?;
-        let result = f(&mut env);
+        let mut env = attach(&self.vm)?;
+        let result = env.with_local_frame(LOCAL_FRAME_CAPACITY, f);
         if env.exception_check()? {
-            log::error!("Exception in vault {:?}: see console", self.config.name);
-            env.exception_describe()?;
-            env.exception_clear()?;
+            let thrown = take_pending_exception(&mut env)?;
+            log::error!("Exception in vault {:?}: {thrown}", self.config.name);
+            if let Err(AndroidKeyringError::JniError(jni::errors::Error::JavaException)) = result {
+                return Err(thrown);
+            }
         }
         result
     }
@@ -256,6 +508,126 @@ impl Vault {
         self.with_env(wrapper)
     }
 
+    /// Save the edits to a credential, synchronously or not as configured.
+    pub fn save(&self, env: &mut JNIEnv, editor: SharedPreferencesEditor) -> JResult<()> {
+        if self.config.synchronous_writes {
+            editor.commit(env)?;
+        } else {
+            editor.apply(env)?;
+        }
+        Ok(())
+    }
+
+    /// Whether this vault's file is encrypted by AndroidX, so that
+    /// secrets are stored in it as they are.
+    pub fn uses_encrypted_preferences(&self) -> bool {
+        self.config.encrypted_preferences
+    }
+
+    /// Fail with a [TooLong](Error::TooLong) error if the secret
+    /// is larger than this vault allows.
+    pub fn check_secret_size(&self, secret: &[u8]) -> Result<()> {
+        let max = self.config.max_secret_size;
+        if secret.len() > max as usize {
+            log::debug!(
+                "Secret of {} bytes is over the limit of {max}",
+                secret.len()
+            );
+            return Err(Error::TooLong("secret".to_string(), max));
+        }
+        Ok(())
+    }
+
+    /// Whether secrets that can't be decrypted are treated as missing.
+    pub fn corrupt_secrets_are_missing(&self) -> bool {
+        self.config.on_corrupt == OnCorrupt::NoEntry
+    }
+
+    /// Encrypt a secret with this vault's key, ready to be stored in its file.
+    ///
+    /// Encrypted files encrypt their values themselves, so secrets
+    /// bound for them are returned unchanged.
+    pub fn encrypt_secret(
+        &self,
+        env: &mut JNIEnv,
+        aad: &[u8],
+        secret: &[u8],
+    ) -> AndroidKeyringResult<Vec<u8>> {
+        if self.config.encrypted_preferences {
+            return Ok(secret.to_vec());
+        }
+        let key = self.get_key(env)?;
+        let (transformation, format) = (self.config.transformation, self.config.envelope_format);
+        let data = encrypt_in_format(env, key, transformation, format, Some(aad), secret)?;
+        #[cfg(feature = "iv-check")]
+        crate::crypto::check_iv_unique(self.key_alias(), &data)?;
+        Ok(data)
+    }
+
+    /// Encrypt a secret to replace the value stored under the given id.
+    ///
+    /// This is [encrypt_secret](Vault::encrypt_secret), unless the vault doesn't
+    /// upgrade envelopes and the stored value is in the legacy layout. Then the
+    /// replacement is in that layout too, and bound to the additional data only
+    /// if the stored value was, so the versions that wrote it can still read it.
+    pub fn encrypt_replacement(
+        &self,
+        env: &mut JNIEnv,
+        file: &SharedPreferences,
+        id: &str,
+        aad: &[u8],
+        secret: &[u8],
+    ) -> AndroidKeyringResult<Vec<u8>> {
+        if self.config.upgrade_envelopes || self.config.encrypted_preferences {
+            return self.encrypt_secret(env, aad, secret);
+        }
+        let previous = match file.get_binary(env, id) {
+            Ok(previous) => previous,
+            // a corrupted value is replaced by one in the current layout
+            Err(AndroidKeyringError::CorruptedData(_, _)) => None,
+            Err(err) => return Err(err),
+        };
+        let Some(bound) = previous.as_deref().and_then(legacy_binding) else {
+            return self.encrypt_secret(env, aad, secret);
+        };
+        log::debug!("Keeping the legacy layout of the value for id {id:?}");
+        let key = self.get_key(env)?;
+        let aad = Some(aad).filter(|_| bound);
+        let data = encrypt_legacy(env, key, self.config.transformation, aad, secret)?;
+        #[cfg(feature = "iv-check")]
+        crate::crypto::check_iv_unique(self.key_alias(), &data)?;
+        Ok(data)
+    }
+
+    /// Decrypt a secret read from this vault's file. See [encrypt_secret](Vault::encrypt_secret).
+    pub fn decrypt_secret(
+        &self,
+        env: &mut JNIEnv,
+        aad: &[u8],
+        data: Vec<u8>,
+    ) -> AndroidKeyringResult<Vec<u8>> {
+        if self.config.encrypted_preferences {
+            return Ok(data);
+        }
+        let key = self.get_key(env)?;
+        decrypt(env, key, Some(aad), data)
+    }
+
+    /// Fail with [AndroidKeyringError::DeviceLocked] if this vault
+    /// is configured to be read-only while the device is locked
+    /// and the device is currently locked.
+    pub fn check_unlocked(&self, env: &mut JNIEnv) -> AndroidKeyringResult<()> {
+        if !self.config.readonly_if_locked {
+            return Ok(());
+        }
+        let ctx = Context::from_raw(self.context.clone());
+        if KeyguardManager::from_context(env, &ctx)?.is_device_locked(env)? {
+            log::debug!("Vault {:?} is locked", self.config.name);
+            return Err(AndroidKeyringError::DeviceLocked);
+        }
+        Ok(())
+    }
+
     fn create_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<Key> {
         let _lock = KEY_SERVICE_LOCK
             .lock()
@@ -266,34 +638,140 @@ impl Vault {
             let err = "Encryption key already exists";
             return Err(Error::BadStoreFormat(err.to_string()))?;
         }
-        let key_generator_spec = KeyGenParameterSpecBuilder::new(
-            env,
-            &self.config.filename,
-            PURPOSE_DECRYPT | PURPOSE_ENCRYPT,
-        )?
-        .set_block_modes(env, &[BLOCK_MODE_GCM])?
-        .set_encryption_paddings(env, &[ENCRYPTION_PADDING_NONE])?
-        .set_user_authentication_required(env, false)?
-        .build(env)?;
+        let key = match self.generate_key(env, self.config.strongbox) {
+            Err(_)
+                if self.config.strongbox
+                    && pending_exception_is(env, STRONGBOX_UNAVAILABLE_EXCEPTION) =>
+            {
+                env.exception_clear()?;
+                log::warn!("StrongBox is unavailable, falling back to the TEE");
+                self.generate_key(env, false)?
+            }
+            result => result?,
+        };
+        Ok(key)
+    }
+
+    fn generate_key(&self, env: &mut JNIEnv, strongbox: bool) -> AndroidKeyringResult<Key> {
+        let key_generator_spec = self.key_spec(env, strongbox)?;
         let key_generator = KeyGenerator::get_instance(env, KEY_ALGORITHM_AES, PROVIDER)?;
         key_generator.init(env, key_generator_spec.into())?;
-        let key = key_generator.generate_key(env)?;
+        let key = self.config.key_generation_backoff().retry(|| {
+            key_generator
+                .generate_key(env)
+                .map_err(|e| classify_keystore_failure(env, e))
+        })?;
         Ok(key.into())
     }
 
+    fn key_spec(
+        &self,
+        env: &mut JNIEnv,
+        strongbox: bool,
+    ) -> AndroidKeyringResult<KeyGenParameterSpec> {
+        let purposes = PURPOSE_DECRYPT | PURPOSE_ENCRYPT;
+        let mut builder = KeyGenParameterSpecBuilder::new(
+            env,
+            KEY_ALGORITHM_AES,
+            &self.config.filename,
+            purposes,
+        )?
+        .set_block_modes(env, &self.block_modes())?
+        .set_encryption_paddings(env, &self.encryption_paddings())?
+        .set_user_authentication_required(env, false)?;
+        if let Some(bits) = self.config.key_size {
+            builder = builder.set_key_size(env, bits)?;
+        }
+        if !self.config.randomized_encryption_required {
+            builder = builder.set_randomized_encryption_required(env, false)?;
+        }
+        if strongbox {
+            builder = builder.set_is_strong_box_backed(env, true)?;
+        }
+        if let Some(millis) = self.config.key_validity_start {
+            builder = builder.set_key_validity_start(env, millis)?;
+        }
+        if let Some(millis) = self.config.key_validity_end {
+            builder = builder.set_key_validity_end(env, millis)?;
+        }
+        if self.config.unlocked_device_required {
+            builder = builder.set_unlocked_device_required(env, true)?;
+        }
+        Ok(builder.build(env)?)
+    }
+
+    // The protection for an imported key mirrors the spec for a generated one,
+    // except that the key size is that of the imported key, and StrongBox
+    // isn't available for imported keys.
+    fn key_protection(&self, env: &mut JNIEnv) -> AndroidKeyringResult<KeyProtection> {
+        let purposes = PURPOSE_DECRYPT | PURPOSE_ENCRYPT;
+        let mut builder = KeyProtectionBuilder::new(env, KEY_ALGORITHM_AES, purposes)?
+            .set_block_modes(env, &self.block_modes())?
+            .set_encryption_paddings(env, &self.encryption_paddings())?;
+        if !self.config.randomized_encryption_required {
+            builder = builder.set_randomized_encryption_required(env, false)?;
+        }
+        if let Some(millis) = self.config.key_validity_start {
+            builder = builder.set_key_validity_start(env, millis)?;
+        }
+        if let Some(millis) = self.config.key_validity_end {
+            builder = builder.set_key_validity_end(env, millis)?;
+        }
+        if self.config.unlocked_device_required {
+            builder = builder.set_unlocked_device_required(env, true)?;
+        }
+        Ok(builder.build(env)?)
+    }
+
+    // Keys always support the default transformation, so that values
+    // encrypted with it can be read whatever the configured transformation.
+    fn block_modes(&self) -> Vec<&'static str> {
+        let mut modes = vec![Transformation::default().block_mode()];
+        if self.config.transformation != Transformation::default() {
+            modes.push(self.config.transformation.block_mode());
+        }
+        modes
+    }
+
+    fn encryption_paddings(&self) -> Vec<&'static str> {
+        let mut paddings = vec![Transformation::default().padding()];
+        if self.config.transformation != Transformation::default() {
+            paddings.push(self.config.transformation.padding());
+        }
+        paddings
+    }
+
     fn get_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<Key> {
         let _lock = KEY_SERVICE_LOCK
             .lock()
             .expect("Key service lock poisoned: report a bug!");
         let keystore = KeyStore::get_instance(env, PROVIDER)?;
         keystore.load(env)?;
-        if let Some(key) = keystore.get_key(env, &self.config.filename)? {
+        if let Some(key) = keystore.get_key(env, self.key_alias())? {
             Ok(key)
         } else {
             Err(Error::BadStoreFormat("Encryption key not found".to_string()).into())
         }
     }
 
+    fn get_or_create_hmac_key(&self, env: &mut JNIEnv, alias: &str) -> AndroidKeyringResult<Key> {
+        let _lock = KEY_SERVICE_LOCK
+            .lock()
+            .expect("Key service lock poisoned: report a bug!");
+        let keystore = KeyStore::get_instance(env, PROVIDER)?;
+        keystore.load(env)?;
+        if let Some(key) = keystore.get_key(env, alias)? {
+            return Ok(key);
+        }
+        let purposes = PURPOSE_SIGN | PURPOSE_VERIFY;
+        let spec =
+            KeyGenParameterSpecBuilder::new(env, KEY_ALGORITHM_HMAC_SHA256, alias, purposes)?
+                .build(env)?;
+        let generator = KeyGenerator::get_instance(env, KEY_ALGORITHM_HMAC_SHA256, PROVIDER)?;
+        generator.init(env, spec.into())?;
+        Ok(generator.generate_key(env)?.into())
+    }
+
     fn delete_key(&self, env: &mut JNIEnv) -> AndroidKeyringResult<()> {
         log::debug!("Deleting key for {:?}", self.config.filename);
         let _lock = KEY_SERVICE_LOCK
@@ -301,13 +779,71 @@ impl Vault {
             .expect("Key service lock poisoned: report a bug!");
         let keystore = KeyStore::get_instance(env, PROVIDER)?;
         keystore.load(env)?;
-        keystore.delete_entry(env, &self.config.filename)?;
+        // the master key of encrypted files is shared, so it's left alone
+        if !self.config.encrypted_preferences {
+            keystore.delete_entry(env, &self.config.filename)?;
+        }
+        keystore.delete_entry(env, &self.attestation_alias())?;
         Ok(())
     }
 
+    // Signing and HMAC keys are used elsewhere by the application, so unlike
+    // the vault's own keys they're only deleted along with the vault.
+    fn delete_application_keys(&self, env: &mut JNIEnv) -> AndroidKeyringResult<()> {
+        let _lock = KEY_SERVICE_LOCK
+            .lock()
+            .expect("Key service lock poisoned: report a bug!");
+        let keystore = KeyStore::get_instance(env, PROVIDER)?;
+        keystore.load(env)?;
+        let prefixes = [self.signing_alias(""), self.hmac_alias("")];
+        for alias in keystore.aliases(env)? {
+            if prefixes.iter().any(|prefix| alias.starts_with(prefix)) {
+                keystore.delete_entry(env, &alias)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Plain vaults have a key of their own, whose alias is their filename,
+    // while encrypted files all use the default master key.
+    fn key_alias(&self) -> &str {
+        #[cfg(feature = "encrypted-preferences")]
+        if self.config.encrypted_preferences {
+            return DEFAULT_MASTER_KEY_ALIAS;
+        }
+        &self.config.filename
+    }
+
+    // The vault's own key is an AES key, which can't be attested, so attestation
+    // uses a separate EC key pair. Filenames can't contain a slash, so this alias
+    // can't collide with the key of any vault.
+    fn attestation_alias(&self) -> String {
+        format!("{}/attestation", self.config.filename)
+    }
+
+    // Signing keys are EC key pairs too, named by the application,
+    // so they're kept apart from the attestation key in the same way.
+    fn signing_alias(&self, alias: &str) -> String {
+        format!("{}/signing/{alias}", self.config.filename)
+    }
+
+    fn hmac_alias(&self, alias: &str) -> String {
+        format!("{}/hmac/{alias}", self.config.filename)
+    }
+
     pub fn get_file(&self, env: &mut JNIEnv) -> AndroidKeyringResult<SharedPreferences> {
         let ctx = Context::from_raw(self.context.clone());
-        Ok(ctx.get_shared_preferences(env, &self.config.filename, MODE_PRIVATE)?)
+        #[cfg(feature = "encrypted-preferences")]
+        if self.config.encrypted_preferences {
+            return Ok(ctx.get_encrypted_shared_preferences(env, &self.config.filename)?);
+        }
+        // with multi-process mode, opening the file reloads it if it changed on disk
+        let mode = if self.config.multi_process {
+            MODE_PRIVATE | MODE_MULTI_PROCESS
+        } else {
+            MODE_PRIVATE
+        };
+        Ok(ctx.get_shared_preferences(env, &self.config.filename, mode)?)
     }
 
     pub fn delete_file(&self, env: &mut JNIEnv) -> AndroidKeyringResult<bool> {
@@ -317,17 +853,23 @@ impl Vault {
     }
 }
 
-fn get_ndk_context() -> AndroidKeyringResult<(Arc<JavaVM>, GlobalRef)> {
+/// Get the VM and the context that `ndk_context` provides, or that
+/// context's application context if `application` is true.
+fn get_ndk_context(application: bool) -> AndroidKeyringResult<(Arc<JavaVM>, GlobalRef)> {
     let ctx = ndk_context::android_context();
     let vm = ctx.vm().cast();
     let activity = ctx.context();
 
     let java_vm = unsafe { JavaVM::from_raw(vm)? };
-    let env = java_vm.attach_current_thread()?;
+    let mut env = java_vm.attach_current_thread()?;
     let vm = Arc::new(env.get_java_vm()?);
 
     let j_context = unsafe { jni::objects::JObject::from_raw(activity as jni::sys::jobject) };
     let context = env.new_global_ref(j_context)?;
+    if !application {
+        return Ok((vm, context));
+    }
+    let context = Context::from_raw(context).get_application_context(&mut env)?;
 
-    Ok((vm, context))
+    Ok((vm, context.into_raw()))
 }
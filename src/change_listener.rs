@@ -0,0 +1,170 @@
+/*!
+# Change notifications
+
+A SharedPreferences file can notify listeners when one of its entries
+changes, for example because another component of the application wrote it.
+The listener must implement a Java interface, which native code can't do,
+so applications that want notifications must include this Kotlin shim,
+which forwards each notification to the Rust callback it was created for:
+
+```kotlin
+package io.crates.keyring
+
+import android.content.SharedPreferences
+
+class KeyringChangeListener(private val handle: Long) :
+    SharedPreferences.OnSharedPreferenceChangeListener {
+    override fun onSharedPreferenceChanged(sharedPreferences: SharedPreferences?, key: String?) {
+        onChanged(handle, key)
+    }
+
+    private external fun onChanged(handle: Long, key: String?)
+}
+```
+
+Notifications are delivered on the application's main thread.
+ */
+
+use std::collections::HashMap;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicI64, Ordering},
+};
+
+use jni::{
+    JNIEnv,
+    objects::{GlobalRef, JObject, JString, JValueGen},
+    sys::jlong,
+};
+
+use crate::{
+    methods::{ClassDecl, Constructible, FromValue, JResult, SignatureComp, ToValue},
+    shared_preferences::Context,
+};
+
+const LISTENER_CLASS: &str = "io.crates.keyring.KeyringChangeListener";
+
+/// A callback that is passed the key of each changed entry,
+/// or `None` if the whole file was cleared.
+pub type ChangeCallback = Arc<dyn Fn(Option<&str>) + Send + Sync>;
+
+// The Java listener only holds a handle, so a notification that arrives
+// after its listener has been dropped finds nothing to call.
+static CALLBACKS: Mutex<Option<HashMap<jlong, ChangeCallback>>> = Mutex::new(None);
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
+
+/// A listener for changes to a SharedPreferences file.
+///
+/// A file only holds weak references to its listeners, so notifications
+/// stop once this is dropped, and the file forgets the listener once
+/// the Java object has been garbage collected.
+pub struct ChangeListener {
+    self_: GlobalRef,
+    handle: jlong,
+}
+
+impl std::fmt::Debug for ChangeListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChangeListener")
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
+impl ChangeListener {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/content/SharedPreferences$OnSharedPreferenceChangeListener;")
+    }
+
+    /// Create a listener that calls the given callback. It does nothing
+    /// until it's registered with a file.
+    pub(crate) fn new(
+        env: &mut JNIEnv,
+        context: &Context,
+        callback: ChangeCallback,
+    ) -> JResult<Self> {
+        struct ThisMethod;
+        impl Constructible for ThisMethod {
+            type Param = jlong;
+            type Return = GlobalRefValue;
+        }
+        struct GlobalRefValue(GlobalRef);
+        impl FromValue for GlobalRefValue {
+            fn signature() -> SignatureComp {
+                ChangeListener::class().into()
+            }
+
+            fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+                Ok(Self(self_))
+            }
+        }
+
+        let class = context
+            .get_class_loader(env)?
+            .load_class(env, LISTENER_CLASS)?;
+        let handle = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
+        let GlobalRefValue(self_) = ThisMethod::call_new_loaded(&class, env, handle)?;
+        CALLBACKS
+            .lock()
+            .expect("Callbacks lock poisoned: report a bug!")
+            .get_or_insert_with(HashMap::new)
+            .insert(handle, callback);
+        Ok(Self { self_, handle })
+    }
+}
+
+impl ToValue for ChangeListener {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl Drop for ChangeListener {
+    fn drop(&mut self) {
+        if let Some(callbacks) = CALLBACKS
+            .lock()
+            .expect("Callbacks lock poisoned: report a bug!")
+            .as_mut()
+        {
+            callbacks.remove(&self.handle);
+        }
+    }
+}
+
+/// Forward a change notification from the Kotlin shim to its callback.
+///
+/// See the [module documentation](self) for the shim.
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_crates_keyring_KeyringChangeListener_onChanged(
+    mut env: JNIEnv,
+    _this: JObject,
+    handle: jlong,
+    key: JString,
+) {
+    let key = if key.is_null() {
+        None
+    } else {
+        match env.get_string(&key) {
+            Ok(key) => Some(String::from(key)),
+            Err(e) => {
+                tracing::error!(%e, "error reading changed key, ignoring notification");
+                tracing::debug!(?e);
+                return;
+            }
+        }
+    };
+    // the callback is called without the lock held, so it can drop listeners
+    let callback = CALLBACKS
+        .lock()
+        .expect("Callbacks lock poisoned: report a bug!")
+        .as_ref()
+        .and_then(|callbacks| callbacks.get(&handle).cloned());
+    if let Some(callback) = callback {
+        callback(key.as_deref());
+    }
+}
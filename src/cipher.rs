@@ -11,6 +11,9 @@ use jni::{
 };
 use std::marker::PhantomData;
 
+pub const ENCRYPT_MODE: i32 = 1;
+pub const DECRYPT_MODE: i32 = 2;
+
 pub struct Cipher {
     self_: GlobalRef,
 }
@@ -23,6 +26,15 @@ impl FromValue for Cipher {
         Ok(Self { self_ })
     }
 }
+impl ToValue for Cipher {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
 impl Cipher {
     fn class() -> ClassDecl {
         ClassDecl("Ljavax/crypto/Cipher;")
@@ -68,17 +80,70 @@ impl Cipher {
         ThisMethod::call(&self.self_, env, (mode, key, spec))
     }
 
-    pub fn get_iv(&self, env: &mut JNIEnv) -> JResult<Vec<u8>> {
+    /// The IV the cipher was initialized with (or generated), which is
+    /// `None` if the cipher doesn't use one.
+    pub fn get_iv(&self, env: &mut JNIEnv) -> JResult<Option<Vec<u8>>> {
         struct ThisMethod;
         impl Method for ThisMethod {
             type Param = NoParam;
-            type Return = Vec<u8>;
+            type Return = Option<Vec<u8>>;
 
             const NAME: &str = "getIV";
         }
         ThisMethod::call(&self.self_, env, NoParam)
     }
 
+    /// The parameters the cipher was initialized with (or generated),
+    /// which is `None` if the cipher doesn't use any.
+    pub fn get_parameters(&self, env: &mut JNIEnv) -> JResult<Option<AlgorithmParameters>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Option<AlgorithmParameters>;
+
+            const NAME: &str = "getParameters";
+        }
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    pub fn update_aad(&self, env: &mut JNIEnv, aad: &[u8]) -> JResult<()> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [u8];
+            type Return = ();
+
+            const NAME: &'static str = "updateAAD";
+        }
+        ThisMethod::call(&self.self_, env, aad)
+    }
+
+    /// The length of the output that the next `update` or `doFinal`
+    /// call would produce, given an input of the given length.
+    pub fn get_output_size(&self, env: &mut JNIEnv, input_len: i32) -> JResult<i32> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = i32;
+            type Return = i32;
+
+            const NAME: &str = "getOutputSize";
+        }
+        ThisMethod::call(&self.self_, env, input_len)
+    }
+
+    /// Process part of a multi-part operation.
+    ///
+    /// The result is `None` if no output is ready yet.
+    pub fn update(&self, env: &mut JNIEnv, input: &[u8]) -> JResult<Option<Vec<u8>>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [u8];
+            type Return = Option<Vec<u8>>;
+
+            const NAME: &'static str = "update";
+        }
+        ThisMethod::call(&self.self_, env, input)
+    }
+
     pub fn do_final(&self, env: &mut JNIEnv, input: &[u8]) -> JResult<Vec<u8>> {
         struct ThisMethod<'a>(PhantomData<&'a ()>);
         impl<'a> Method for ThisMethod<'a> {
@@ -91,6 +156,188 @@ impl Cipher {
     }
 }
 
+pub struct Mac {
+    self_: GlobalRef,
+}
+impl FromValue for Mac {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+impl Mac {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljavax/crypto/Mac;")
+    }
+
+    pub fn get_instance(env: &mut JNIEnv, algorithm: &str) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = Mac;
+
+            const NAME: &'static str = "getInstance";
+        }
+        ThisMethod::call(Self::class(), env, algorithm)
+    }
+
+    pub fn init(&self, env: &mut JNIEnv, key: &Key) -> JResult<()> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a Key;
+            type Return = ();
+
+            const NAME: &'static str = "init";
+        }
+        ThisMethod::call(&self.self_, env, key)
+    }
+
+    pub fn do_final(&self, env: &mut JNIEnv, input: &[u8]) -> JResult<Vec<u8>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [u8];
+            type Return = Vec<u8>;
+
+            const NAME: &'static str = "doFinal";
+        }
+        ThisMethod::call(&self.self_, env, input)
+    }
+}
+
+/// An AndroidX `BiometricPrompt.CryptoObject`, which ties a cipher to
+/// the user's authentication: once a prompt shown with it succeeds, its
+/// cipher can use a key that must be authenticated for each use.
+#[cfg(feature = "biometric")]
+pub struct CryptoObject {
+    self_: GlobalRef,
+}
+#[cfg(feature = "biometric")]
+impl FromValue for CryptoObject {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+#[cfg(feature = "biometric")]
+impl CryptoObject {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroidx/biometric/BiometricPrompt$CryptoObject;")
+    }
+
+    /// Wrap an initialized cipher. AndroidX classes aren't available to
+    /// native threads, so the class is loaded by the context's class loader.
+    #[cfg(feature = "legacy")]
+    pub(crate) fn new(
+        env: &mut JNIEnv,
+        context: &crate::shared_preferences::Context,
+        cipher: &Cipher,
+    ) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = &'a Cipher;
+            type Return = CryptoObject;
+        }
+
+        let class = context
+            .get_class_loader(env)?
+            .load_class(env, "androidx.biometric.BiometricPrompt$CryptoObject")?;
+        ThisMethod::call_new_loaded(&class, env, cipher)
+    }
+
+    /// The cipher, which is authenticated once a prompt shown with this succeeds.
+    #[cfg(feature = "legacy")]
+    pub(crate) fn get_cipher(&self, env: &mut JNIEnv) -> JResult<Option<Cipher>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Option<Cipher>;
+
+            const NAME: &str = "getCipher";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    /// The Java object, to pass to `BiometricPrompt.authenticate`.
+    pub fn as_obj(&self) -> &JObject<'static> {
+        self.self_.as_obj()
+    }
+}
+
+/// A `java.security.AlgorithmParameters`, as returned by [Cipher::get_parameters].
+pub struct AlgorithmParameters {
+    self_: GlobalRef,
+}
+impl FromValue for AlgorithmParameters {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+impl AlgorithmParameters {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/AlgorithmParameters;")
+    }
+
+    /// The IV held by the parameters, read through the spec class with the
+    /// given name (such as `javax/crypto/spec/GCMParameterSpec`), which must
+    /// be one that has a `getIV` method.
+    pub fn get_iv(&self, env: &mut JNIEnv, spec_class: &'static str) -> JResult<Vec<u8>> {
+        struct SpecClass(&'static str);
+        impl ToValue for SpecClass {
+            fn signature() -> SignatureComp {
+                ClassDecl("Ljava/lang/Class;").into()
+            }
+
+            fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+                let class: JObject = env.find_class(self.0)?.into();
+                Ok(class.into())
+            }
+        }
+
+        struct GetParameterSpec;
+        impl Method for GetParameterSpec {
+            type Param = SpecClass;
+            type Return = ParameterSpecWithIv;
+
+            const NAME: &str = "getParameterSpec";
+        }
+
+        struct GetIv;
+        impl Method for GetIv {
+            type Param = NoParam;
+            type Return = Vec<u8>;
+
+            const NAME: &str = "getIV";
+        }
+
+        let spec = GetParameterSpec::call(&self.self_, env, SpecClass(spec_class))?;
+        GetIv::call(&spec.0, env, NoParam)
+    }
+}
+
+/// A parameter spec that has an IV, such as a [GCMParameterSpec].
+struct ParameterSpecWithIv(GlobalRef);
+impl FromValue for ParameterSpecWithIv {
+    fn signature() -> SignatureComp {
+        // `AlgorithmParameters.getParameterSpec` is declared to return any spec
+        AlgorithmParameterSpec::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self(self_))
+    }
+}
+
 pub struct AlgorithmParameterSpec {
     self_: GlobalRef,
 }
@@ -149,3 +396,35 @@ impl From<GCMParameterSpec> for AlgorithmParameterSpec {
         Self { self_: value.self_ }
     }
 }
+
+pub struct IvParameterSpec {
+    self_: GlobalRef,
+}
+impl FromValue for IvParameterSpec {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+impl IvParameterSpec {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljavax/crypto/spec/IvParameterSpec;")
+    }
+
+    pub fn new(env: &mut JNIEnv, iv: &[u8]) -> JResult<IvParameterSpec> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = &'a [u8];
+            type Return = IvParameterSpec;
+        }
+        ThisMethod::call_new(Self::class(), env, iv)
+    }
+}
+impl From<IvParameterSpec> for AlgorithmParameterSpec {
+    fn from(value: IvParameterSpec) -> Self {
+        Self { self_: value.self_ }
+    }
+}
@@ -0,0 +1,524 @@
+/*!
+# Sharing Credentials with Sibling Apps
+
+An application that belongs to a suite of apps from the same developer can
+own the suite's credentials and share some of them with its siblings through
+a `ContentProvider`. The provider must be a Java class, which native code
+can't implement, so the owning application must include this Kotlin shim,
+which forwards each request to the entry points in this module:
+
+```kotlin
+package io.crates.keyring
+
+import android.content.ContentProvider
+import android.content.ContentValues
+import android.database.Cursor
+import android.net.Uri
+
+class KeyringContentProvider : ContentProvider() {
+    override fun onCreate(): Boolean {
+        // providers are created before the application's onCreate runs
+        Keyring.initializeNdkContext(context!!.applicationContext)
+        return true
+    }
+
+    override fun query(
+        uri: Uri,
+        projection: Array<String>?,
+        selection: String?,
+        selectionArgs: Array<String>?,
+        sortOrder: String?,
+    ): Cursor? = query(uri)
+
+    override fun insert(uri: Uri, values: ContentValues?): Uri? {
+        val secret = values?.getAsByteArray("secret") ?: return null
+        return if (insert(uri, secret)) uri else null
+    }
+
+    override fun delete(uri: Uri, selection: String?, selectionArgs: Array<String>?): Int =
+        delete(uri)
+
+    override fun update(
+        uri: Uri,
+        values: ContentValues?,
+        selection: String?,
+        selectionArgs: Array<String>?,
+    ): Int = 0
+
+    override fun getType(uri: Uri): String? = null
+
+    private external fun query(uri: Uri): Cursor?
+    private external fun insert(uri: Uri, secret: ByteArray): Boolean
+    private external fun delete(uri: Uri): Int
+}
+```
+
+Nothing is exposed until the owning application calls [share] for each
+service whose credentials its siblings may use, typically when it starts.
+The path of a request's URI names a shared service, as in
+`content://<authority>/<service>`, or a credential of one, as in
+`content://<authority>/<service>/<user>`. A query returns a cursor with the
+[COLUMNS] `service`, `user`, and `secret` (a blob), with a row for each
+credential named; an insert sets the secret of the named credential to the
+`secret` value; and a delete deletes the named credentials and returns how
+many there were. A request for a service that isn't shared, or with a path
+of any other shape, fails: queries return `null`, inserts return `false`,
+and deletes return 0.
+
+## Security
+
+By default, the `secret` column of a query holds each credential's envelope,
+the encrypted value that the store keeps (see [Cred::dump_raw](crate::Cred::dump_raw)),
+not its secret. That value is encrypted with a Keystore key that only the
+owning application can use, so a sibling can hold on to it or hand it back,
+but can't read it. A service shared with `plaintext` set returns the secrets
+in the clear instead. The values of [encrypted files](crate::by_store#encrypted-files)
+can't be read without decrypting them, so a service in a store that uses
+them can only be queried if it's shared that way.
+
+Either way, anyone allowed to use the provider can replace and delete the
+shared secrets, and the provider must be guarded as described below.
+Only share the services that the siblings need.
+
+## Manifest Setup
+
+The provider must be guarded by a permission with a `signature` protection
+level, which is only granted to apps signed with the same certificate.
+The owning application declares the permission and the provider:
+
+```xml
+<permission
+    android:name="com.example.suite.permission.KEYRING"
+    android:protectionLevel="signature" />
+
+<application>
+    <provider
+        android:name="io.crates.keyring.KeyringContentProvider"
+        android:authorities="com.example.suite.keyring"
+        android:exported="true"
+        android:permission="com.example.suite.permission.KEYRING" />
+</application>
+```
+
+Each sibling requests the permission and, on Android 11 (API level 30)
+and later, declares that it uses the provider, so the provider is visible to it:
+
+```xml
+<uses-permission android:name="com.example.suite.permission.KEYRING" />
+
+<queries>
+    <provider android:authorities="com.example.suite.keyring" />
+</queries>
+```
+ */
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+use jni::{
+    JNIEnv,
+    objects::{GlobalRef, JByteArray, JList, JObject, JString, JValueGen},
+    sys::{JNI_FALSE, JNI_TRUE, jboolean, jint, jobject},
+};
+use keyring_core::{Entry, Error, api::CredentialStoreApi};
+
+#[cfg(feature = "compile-tests")]
+use crate::methods::StaticMethod;
+use crate::{
+    Cred, Store,
+    error::AndroidKeyringResult,
+    methods::{
+        ClassDecl, Constructible, FromValue, JResult, Method, NoParam, SignatureComp, ToValue,
+    },
+};
+
+/// The columns of the cursors returned by queries.
+pub const COLUMNS: [&str; 3] = ["service", "user", "secret"];
+
+static SHARED: Mutex<Option<HashMap<String, Shared>>> = Mutex::new(None);
+
+/// The store a service is shared from, and whether its secrets are shared in the clear.
+#[derive(Clone)]
+struct Shared {
+    store: Arc<Store>,
+    plaintext: bool,
+}
+
+/// Share the credentials of the given service in the given store through
+/// the provider. Sharing a service that's already shared replaces the
+/// store it's shared from.
+///
+/// Queries return the credentials' envelopes unless `plaintext` is set,
+/// in which case they return the secrets in the clear
+/// (see [Security](self#security)).
+pub fn share(store: Arc<Store>, service: &str, plaintext: bool) {
+    SHARED
+        .lock()
+        .expect("Shared services lock poisoned: report a bug!")
+        .get_or_insert_with(HashMap::new)
+        .insert(service.to_string(), Shared { store, plaintext });
+}
+
+/// Stop sharing the credentials of the given service through the provider.
+///
+/// Returns whether the service was shared.
+pub fn unshare(service: &str) -> bool {
+    SHARED
+        .lock()
+        .expect("Shared services lock poisoned: report a bug!")
+        .as_mut()
+        .is_some_and(|shared| shared.remove(service).is_some())
+}
+
+fn shared(service: &str) -> Result<Shared, Error> {
+    let shared = SHARED
+        .lock()
+        .expect("Shared services lock poisoned: report a bug!")
+        .as_ref()
+        .and_then(|shared| shared.get(service).cloned());
+    shared.ok_or_else(|| {
+        let msg = format!("service {service:?} isn't shared");
+        Error::NotSupportedByStore(msg)
+    })
+}
+
+/// An `android.net.Uri`.
+pub(crate) struct Uri {
+    self_: GlobalRef,
+}
+
+impl FromValue for Uri {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl Uri {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/net/Uri;")
+    }
+
+    /// Parse a URI string, as `Uri.parse` does.
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn parse(env: &mut JNIEnv, uri: &str) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = Uri;
+
+            const NAME: &'static str = "parse";
+        }
+        ThisMethod::call(Self::class(), env, uri)
+    }
+
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn as_obj(&self) -> &JObject<'static> {
+        self.self_.as_obj()
+    }
+
+    fn get_path_segments(&self, env: &mut JNIEnv) -> JResult<Vec<String>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = PathSegments;
+
+            const NAME: &str = "getPathSegments";
+        }
+        let PathSegments(segments) = ThisMethod::call(self.self_.as_obj(), env, NoParam)?;
+        Ok(segments)
+    }
+
+    /// The service and, if there is one, the user named by this URI's path.
+    fn specifiers(&self, env: &mut JNIEnv) -> AndroidKeyringResult<(String, Option<String>)> {
+        let mut segments = self.get_path_segments(env)?.into_iter();
+        match (segments.next(), segments.next(), segments.next()) {
+            (Some(service), user, None) => Ok((service, user)),
+            _ => {
+                let msg = "must be /<service> or /<service>/<user>".to_string();
+                Err(Error::Invalid("uri".to_string(), msg).into())
+            }
+        }
+    }
+}
+
+struct PathSegments(Vec<String>);
+
+impl FromValue for PathSegments {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/util/List;").into()
+    }
+
+    fn from_object(self_: GlobalRef, env: &mut JNIEnv) -> JResult<Self> {
+        let mut segments = Vec::new();
+        let list = JList::from_env(env, self_.as_obj())?;
+        let mut iterator = list.iter(env)?;
+        while let Some(segment) = iterator.next(env)? {
+            let segment = env.auto_local(JString::from(segment));
+            segments.push(env.get_string(&segment)?.into());
+        }
+        Ok(Self(segments))
+    }
+}
+
+/// An `android.database.MatrixCursor` with the provider's [COLUMNS].
+pub(crate) struct MatrixCursor {
+    self_: GlobalRef,
+}
+
+impl FromValue for MatrixCursor {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl MatrixCursor {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/database/MatrixCursor;")
+    }
+
+    fn new(env: &mut JNIEnv) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = &'a [&'a str];
+            type Return = MatrixCursor;
+        }
+        ThisMethod::call_new(Self::class(), env, &COLUMNS[..])
+    }
+
+    fn add_row(&self, env: &mut JNIEnv, service: &str, user: &str, secret: &[u8]) -> JResult<()> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = Row<'a>;
+            type Return = ();
+
+            const NAME: &'static str = "addRow";
+        }
+        ThisMethod::call(self.self_.as_obj(), env, Row(service, user, secret))
+    }
+
+    /// Wrap a cursor returned by [the query entry point](Java_io_crates_keyring_KeyringContentProvider_query).
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn from_raw(env: &mut JNIEnv, cursor: jobject) -> JResult<Option<Self>> {
+        let cursor = unsafe { JObject::from_raw(cursor) };
+        if cursor.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            self_: env.new_global_ref(cursor)?,
+        }))
+    }
+
+    /// Move to the next row, returning whether there is one.
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn move_to_next(&self, env: &mut JNIEnv) -> JResult<bool> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = bool;
+
+            const NAME: &str = "moveToNext";
+        }
+        ThisMethod::call(self.self_.as_obj(), env, NoParam)
+    }
+
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn get_string(&self, env: &mut JNIEnv, column: i32) -> JResult<String> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = i32;
+            type Return = String;
+
+            const NAME: &str = "getString";
+        }
+        ThisMethod::call(self.self_.as_obj(), env, column)
+    }
+
+    #[cfg(feature = "compile-tests")]
+    pub(crate) fn get_blob(&self, env: &mut JNIEnv, column: i32) -> JResult<Vec<u8>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = i32;
+            type Return = Vec<u8>;
+
+            const NAME: &str = "getBlob";
+        }
+        ThisMethod::call(self.self_.as_obj(), env, column)
+    }
+}
+
+/// The values of a cursor row, as an `Object[]`.
+struct Row<'a>(&'a str, &'a str, &'a [u8]);
+
+impl ToValue for Row<'_> {
+    fn signature() -> SignatureComp {
+        ClassDecl("[Ljava/lang/Object;").into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        let array = env.new_object_array(3, "java/lang/Object", JObject::null())?;
+        let service = env.new_string(self.0)?;
+        env.set_object_array_element(&array, 0, service)?;
+        let user = env.new_string(self.1)?;
+        env.set_object_array_element(&array, 1, user)?;
+        let secret = env.byte_array_from_slice(self.2)?;
+        env.set_object_array_element(&array, 2, secret)?;
+        let array: JObject = array.into();
+        Ok(array.into())
+    }
+}
+
+/// How the service named by a URI is shared, and the entries it names.
+fn entries(env: &mut JNIEnv, uri: &Uri) -> AndroidKeyringResult<(Shared, Vec<Entry>)> {
+    let (service, user) = uri.specifiers(env)?;
+    let shared = shared(&service)?;
+    let store = &shared.store;
+    let entries = match user {
+        Some(user) => vec![store.build(&service, &user, None)?],
+        None => {
+            let pattern = format!("^{}$", regex::escape(&service));
+            store.search(&HashMap::from([("service", pattern.as_str())]))?
+        }
+    };
+    Ok((shared, entries))
+}
+
+fn query(env: &mut JNIEnv, uri: &Uri) -> AndroidKeyringResult<MatrixCursor> {
+    let cursor = MatrixCursor::new(env)?;
+    let (shared, entries) = entries(env, uri)?;
+    for entry in entries {
+        let (service, user) = entry
+            .get_specifiers()
+            .expect("Named store entries are specifiers: report a bug!");
+        if shared.plaintext {
+            match entry.get_secret() {
+                Ok(secret) => {
+                    let secret = zeroize::Zeroizing::new(secret);
+                    cursor.add_row(env, &service, &user, &secret)?;
+                }
+                Err(Error::NoEntry) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        } else {
+            let cred = entry
+                .as_any()
+                .downcast_ref::<Cred>()
+                .expect("Store entries are store credentials: report a bug!");
+            if let Some(envelope) = cred.dump_raw()? {
+                cursor.add_row(env, &service, &user, envelope.bytes())?;
+            }
+        }
+    }
+    Ok(cursor)
+}
+
+fn insert(env: &mut JNIEnv, uri: &Uri, secret: &JByteArray) -> AndroidKeyringResult<()> {
+    if uri.specifiers(env)?.1.is_none() {
+        let msg = "must name a user to insert".to_string();
+        return Err(Error::Invalid("uri".to_string(), msg).into());
+    }
+    let secret = zeroize::Zeroizing::new(env.convert_byte_array(secret)?);
+    for entry in entries(env, uri)?.1 {
+        entry.set_secret(&secret)?;
+    }
+    Ok(())
+}
+
+fn delete(env: &mut JNIEnv, uri: &Uri) -> AndroidKeyringResult<i32> {
+    let mut deleted = 0;
+    for entry in entries(env, uri)?.1 {
+        match entry.delete_credential() {
+            Ok(()) => deleted += 1,
+            Err(Error::NoEntry) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(deleted)
+}
+
+fn uri_from(env: &mut JNIEnv, uri: &JObject) -> JResult<Uri> {
+    Ok(Uri {
+        self_: env.new_global_ref(uri)?,
+    })
+}
+
+/// Answer a query forwarded by the Kotlin shim, returning a cursor
+/// or, if the query fails, `null`.
+///
+/// See the [module documentation](self) for the shim.
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_crates_keyring_KeyringContentProvider_query(
+    mut env: JNIEnv,
+    _this: JObject,
+    uri: JObject,
+) -> jobject {
+    let result = uri_from(&mut env, &uri)
+        .map_err(Into::into)
+        .and_then(|uri| query(&mut env, &uri))
+        .and_then(|cursor| Ok(env.new_local_ref(&cursor.self_)?));
+    match result {
+        Ok(cursor) => cursor.into_raw(),
+        Err(e) => {
+            tracing::error!(%e, "error answering content provider query");
+            tracing::debug!(?e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Answer an insert forwarded by the Kotlin shim, returning whether it succeeded.
+///
+/// See the [module documentation](self) for the shim.
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_crates_keyring_KeyringContentProvider_insert(
+    mut env: JNIEnv,
+    _this: JObject,
+    uri: JObject,
+    secret: JByteArray,
+) -> jboolean {
+    let result = uri_from(&mut env, &uri)
+        .map_err(Into::into)
+        .and_then(|uri| insert(&mut env, &uri, &secret));
+    match result {
+        Ok(()) => JNI_TRUE,
+        Err(e) => {
+            tracing::error!(%e, "error answering content provider insert");
+            tracing::debug!(?e);
+            JNI_FALSE
+        }
+    }
+}
+
+/// Answer a delete forwarded by the Kotlin shim, returning
+/// how many credentials were deleted.
+///
+/// See the [module documentation](self) for the shim.
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_io_crates_keyring_KeyringContentProvider_delete(
+    mut env: JNIEnv,
+    _this: JObject,
+    uri: JObject,
+) -> jint {
+    let result = uri_from(&mut env, &uri)
+        .map_err(Into::into)
+        .and_then(|uri| delete(&mut env, &uri));
+    match result {
+        Ok(deleted) => deleted,
+        Err(e) => {
+            tracing::error!(%e, "error answering content provider delete");
+            tracing::debug!(?e);
+            0
+        }
+    }
+}
@@ -1,56 +1,965 @@
+use std::borrow::Cow;
+#[cfg(feature = "iv-check")]
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+
 use jni::JNIEnv;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
 use crate::{
-    cipher::{Cipher, GCMParameterSpec},
-    error::{AndroidKeyringError, AndroidKeyringResult, CorruptedData},
-    keystore::Key,
+    cipher::{
+        AlgorithmParameterSpec, Cipher, DECRYPT_MODE, ENCRYPT_MODE, GCMParameterSpec,
+        IvParameterSpec,
+    },
+    error::{
+        AndroidKeyringError, AndroidKeyringResult, CorruptedData, pending_exception_is,
+        pending_exception_message,
+    },
+    keystore::{
+        BLOCK_MODE_CBC, BLOCK_MODE_GCM, ENCRYPTION_PADDING_NONE, ENCRYPTION_PADDING_PKCS7,
+        KEY_EXPIRED_EXCEPTION, KEY_NOT_YET_VALID_EXCEPTION, KEY_PERMANENTLY_INVALIDATED_EXCEPTION,
+        Key, USER_NOT_AUTHENTICATED_EXCEPTION,
+    },
 };
 
-const ENCRYPT_MODE: i32 = 1;
-const DECRYPT_MODE: i32 = 2;
-const CIPHER_TRANSFORMATION: &str = "AES/GCM/NoPadding";
-const IV_LEN: usize = 12;
-
-pub fn encrypt(env: &mut JNIEnv, key: Key, data: &[u8]) -> AndroidKeyringResult<Vec<u8>> {
-    let cipher = Cipher::get_instance(env, CIPHER_TRANSFORMATION)?;
-    cipher.init(env, ENCRYPT_MODE, &key)?;
-    let iv = cipher.get_iv(env)?;
-    assert_eq!(iv.len(), IV_LEN, "IV len is wrong, please file a bug!");
-    let ciphertext = cipher.do_final(env, data)?;
-    let iv_len = iv.len() as u8;
-    let mut value = vec![iv_len];
+const GCM_TAG_LEN: i32 = 128;
+const AES_BLOCK_LEN: usize = 16;
+/// The first bytes of every versioned encrypted value. No legacy value
+/// starts with them, because its first byte is always a valid IV length.
+pub(crate) const MAGIC: [u8; 2] = *b"AK";
+/// The version of the envelope written by [encrypt].
+pub(crate) const VERSION: u8 = 1;
+/// The length of the versioned header: magic, version, and flags.
+const HEADER_LEN: usize = MAGIC.len() + 2;
+/// In a legacy value, set in the IV length byte if its encryption was
+/// bound to additional authenticated data. In a versioned value, set in
+/// the flags byte instead. Values written before AAD binding
+/// was introduced don't have it.
+const AAD_FLAG: u8 = 0x80;
+/// Inputs larger than this are passed to the cipher in chunks of this size,
+/// so they are never copied into a single Java array.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// The cipher transformations that secrets can be encrypted with.
+///
+/// Encrypted values start with a header of [MAGIC], the envelope version,
+/// and a flags byte recording whether the value is bound to additional
+/// authenticated data (which only authenticated transformations support).
+/// The header is followed by the length of the IV, and the
+/// transformations have different IV lengths, so that byte identifies
+/// the value's transformation.
+///
+/// Values written by earlier versions of this crate have no header
+/// (they are version 0), and record the binding in the high bit
+/// of the IV length byte. Values can also be written in the
+/// [Cbor](EnvelopeFormat::Cbor) format, which names its fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transformation {
+    /// AES in Galois/Counter Mode, which authenticates as well as encrypts.
+    #[default]
+    #[serde(rename = "AES/GCM/NoPadding")]
+    AesGcm,
+    /// AES in Cipher Block Chaining mode, for interoperating with legacy formats.
+    /// This doesn't authenticate, so corrupted data may decrypt without error.
+    #[serde(rename = "AES/CBC/PKCS7Padding")]
+    AesCbc,
+}
+
+impl Transformation {
+    /// The name of this transformation, as understood by `Cipher.getInstance`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transformation::AesGcm => "AES/GCM/NoPadding",
+            Transformation::AesCbc => "AES/CBC/PKCS7Padding",
+        }
+    }
+
+    pub fn block_mode(&self) -> &'static str {
+        match self {
+            Transformation::AesGcm => BLOCK_MODE_GCM,
+            Transformation::AesCbc => BLOCK_MODE_CBC,
+        }
+    }
+
+    pub fn padding(&self) -> &'static str {
+        match self {
+            Transformation::AesGcm => ENCRYPTION_PADDING_NONE,
+            Transformation::AesCbc => ENCRYPTION_PADDING_PKCS7,
+        }
+    }
+
+    /// Whether values encrypted with this transformation are authenticated.
+    pub fn is_aead(&self) -> bool {
+        matches!(self, Transformation::AesGcm)
+    }
+
+    /// The length in bytes of the authentication tag at the end of
+    /// the ciphertext, which is 0 for unauthenticated transformations.
+    fn tag_len(&self) -> usize {
+        match self {
+            Transformation::AesGcm => (GCM_TAG_LEN / 8) as usize,
+            Transformation::AesCbc => 0,
+        }
+    }
+
+    fn iv_len(&self) -> usize {
+        match self {
+            Transformation::AesGcm => 12,
+            Transformation::AesCbc => 16,
+        }
+    }
+
+    /// The shortest ciphertext (after the IV) this transformation can produce:
+    /// a GCM value always has a tag, and a CBC value at least one block.
+    fn min_ciphertext_len(&self) -> usize {
+        match self {
+            Transformation::AesGcm => (GCM_TAG_LEN / 8) as usize,
+            Transformation::AesCbc => AES_BLOCK_LEN,
+        }
+    }
+
+    fn from_iv_len(iv_len: usize) -> Option<Self> {
+        [Transformation::AesGcm, Transformation::AesCbc]
+            .into_iter()
+            .find(|t| t.iv_len() == iv_len)
+    }
+
+    /// The name of the parameter spec class that holds this transformation's IV.
+    fn spec_class(&self) -> &'static str {
+        match self {
+            Transformation::AesGcm => "javax/crypto/spec/GCMParameterSpec",
+            Transformation::AesCbc => "javax/crypto/spec/IvParameterSpec",
+        }
+    }
+
+    fn parameter_spec(
+        &self,
+        env: &mut JNIEnv,
+        iv: &[u8],
+    ) -> AndroidKeyringResult<AlgorithmParameterSpec> {
+        let spec = match self {
+            Transformation::AesGcm => GCMParameterSpec::new(env, GCM_TAG_LEN, iv)?.into(),
+            Transformation::AesCbc => IvParameterSpec::new(env, iv)?.into(),
+        };
+        Ok(spec)
+    }
+}
+
+/// The layouts that encrypted values can be written in.
+///
+/// Whatever a store writes, it reads values in either format, so
+/// a store's format can be changed at any time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvelopeFormat {
+    /// The header described under [Transformation], followed by the IV and
+    /// the ciphertext. This is the smallest format, so it's the default.
+    #[default]
+    Compact,
+    /// A CBOR map with the envelope version `v`, the transformation `alg`,
+    /// the `iv`, the `tag_len` in bytes, whether the value is bound to additional
+    /// authenticated data (`aad_used`), and the ciphertext `ct`. Readers ignore
+    /// fields they don't know, so fields can be added without a new version.
+    Cbor,
+}
+
+impl std::str::FromStr for EnvelopeFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compact" => Ok(EnvelopeFormat::Compact),
+            "cbor" => Ok(EnvelopeFormat::Cbor),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The fields of a value in the [Cbor](EnvelopeFormat::Cbor) format.
+#[derive(Serialize, Deserialize)]
+struct CborEnvelope {
+    v: u8,
+    alg: Transformation,
+    #[serde(with = "serde_bytes")]
+    iv: Vec<u8>,
+    tag_len: u8,
+    aad_used: bool,
+    #[serde(with = "serde_bytes")]
+    ct: Vec<u8>,
+}
+
+impl std::str::FromStr for Transformation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Transformation::AesGcm, Transformation::AesCbc]
+            .into_iter()
+            .find(|t| t.name() == s)
+            .ok_or(())
+    }
+}
+
+/// Encrypt data with the given transformation.
+///
+/// If the transformation is authenticated, the result is bound to the given
+/// additional authenticated data (if any), so decrypting it requires the same data.
+/// Otherwise the additional data is ignored.
+pub fn encrypt(
+    env: &mut JNIEnv,
+    key: Key,
+    transformation: Transformation,
+    aad: Option<&[u8]>,
+    data: &[u8],
+) -> AndroidKeyringResult<Vec<u8>> {
+    encrypt_with(env, key, transformation, aad, None, data)
+}
+
+/// Encrypt data like [encrypt], but with the given IV rather than one
+/// generated by the cipher, so that the result is reproducible.
+///
+/// Keys only accept a given IV if they were imported or generated without
+/// randomized encryption, so this is only for test fixtures.
+#[cfg(feature = "test-fixtures")]
+pub(crate) fn encrypt_with_iv(
+    env: &mut JNIEnv,
+    key: Key,
+    transformation: Transformation,
+    aad: Option<&[u8]>,
+    iv: &[u8],
+    data: &[u8],
+) -> AndroidKeyringResult<Vec<u8>> {
+    encrypt_with(env, key, transformation, aad, Some(iv), data)
+}
+
+fn encrypt_with(
+    env: &mut JNIEnv,
+    key: Key,
+    transformation: Transformation,
+    aad: Option<&[u8]>,
+    iv: Option<&[u8]>,
+    data: &[u8],
+) -> AndroidKeyringResult<Vec<u8>> {
+    let cipher = Cipher::get_instance(env, transformation.name())?;
+    let init = match iv {
+        Some(iv) => {
+            let spec = transformation.parameter_spec(env, iv)?;
+            cipher.init2(env, ENCRYPT_MODE, &key, spec)
+        }
+        None => cipher.init(env, ENCRYPT_MODE, &key),
+    };
+    init.map_err(|e| init_error(env, e))?;
+    let aad = aad.filter(|_| transformation.is_aead());
+    if let Some(aad) = aad {
+        cipher.update_aad(env, aad)?;
+    }
+    let Some(iv) = generated_iv(env, &cipher, transformation)? else {
+        let err = "Cipher generated no IV, please file a bug!".to_string();
+        return Err(keyring_core::Error::PlatformFailure(err.into()).into());
+    };
+    assert_eq!(
+        iv.len(),
+        transformation.iv_len(),
+        "IV len is wrong, please file a bug!"
+    );
+    let ciphertext = run_cipher(env, &cipher, data)?;
+    let flags = if aad.is_some() { AAD_FLAG } else { 0 };
+    let mut value = MAGIC.to_vec();
+    value.extend_from_slice(&[VERSION, flags, iv.len() as u8]);
     value.extend_from_slice(&iv);
     value.extend_from_slice(&ciphertext);
     Ok(value)
 }
 
-pub fn decrypt(env: &mut JNIEnv, key: Key, data: Vec<u8>) -> AndroidKeyringResult<Vec<u8>> {
-    if data.is_empty() {
-        let err = CorruptedData::MissingIvLen;
-        return Err(AndroidKeyringError::CorruptedData(data, err));
+/// The IV a cipher generated when it was initialized for encryption.
+///
+/// It's read from the cipher's parameters, which every provider should
+/// supply, or from `getIV` if the cipher has none.
+fn generated_iv(
+    env: &mut JNIEnv,
+    cipher: &Cipher,
+    transformation: Transformation,
+) -> jni::errors::Result<Option<Vec<u8>>> {
+    match cipher.get_parameters(env)? {
+        Some(parameters) => Ok(Some(parameters.get_iv(env, transformation.spec_class())?)),
+        None => cipher.get_iv(env),
     }
-    let iv_len = data[0] as usize;
-    if iv_len != IV_LEN {
-        let err = CorruptedData::InvalidIvLen {
-            actual: iv_len,
-            expected: IV_LEN,
-        };
-        return Err(AndroidKeyringError::CorruptedData(data, err));
-    }
-    let ciphertext = &data[1..];
-    let ciphertext_len = ciphertext.len();
-    if ciphertext_len <= iv_len {
-        let err = CorruptedData::DataTooSmall(ciphertext_len);
-        return Err(AndroidKeyringError::CorruptedData(data, err));
-    }
-    let iv = &ciphertext[..iv_len];
-    let iv = &iv[..iv_len];
-    let ciphertext = &ciphertext[iv_len..];
-    let spec = GCMParameterSpec::new(env, 128, iv)?;
-    let cipher = Cipher::get_instance(env, CIPHER_TRANSFORMATION)?;
-    cipher.init2(env, DECRYPT_MODE, &key, spec.into())?;
-    let plaintext = cipher.do_final(env, ciphertext).map_err(move |_| {
+}
+
+/// Encrypt data like [encrypt], but in the legacy (version 0) layout,
+/// which has no header, for older versions of this crate to read.
+///
+/// An unbound value (with no additional data) is in the exact layout
+/// written by version 0.3 and earlier.
+pub fn encrypt_legacy(
+    env: &mut JNIEnv,
+    key: Key,
+    transformation: Transformation,
+    aad: Option<&[u8]>,
+    data: &[u8],
+) -> AndroidKeyringResult<Vec<u8>> {
+    let mut value = encrypt(env, key, transformation, aad, data)?;
+    let flags = value[HEADER_LEN - 1];
+    let mut legacy = value.split_off(HEADER_LEN);
+    legacy[0] |= flags;
+    Ok(legacy)
+}
+
+/// Whether an encrypted value is in the legacy (version 0) layout,
+/// which is every value that doesn't start with the magic bytes
+/// and isn't in the CBOR format.
+pub(crate) fn is_legacy(data: &[u8]) -> bool {
+    !data.starts_with(&MAGIC) && !is_cbor(data)
+}
+
+/// Whether an encrypted value is in the [Cbor](EnvelopeFormat::Cbor) format,
+/// which is every value whose first byte has the CBOR major type of a map.
+/// No other value starts with such a byte: that of a versioned value is
+/// the first byte of [MAGIC], and that of a legacy value is an IV length,
+/// which is less than 32, with or without the AAD flag in its high bit.
+fn is_cbor(data: &[u8]) -> bool {
+    data.first().is_some_and(|&first| first >> 5 == 5)
+}
+
+/// If an encrypted value is well formed and in the legacy layout,
+/// whether it is bound to additional authenticated data.
+pub(crate) fn legacy_binding(data: &[u8]) -> Option<bool> {
+    if !is_legacy(data) {
+        return None;
+    }
+    parse_envelope(data).ok().map(|envelope| envelope.bound)
+}
+
+/// Encrypt data like [encrypt], writing the result in the given format.
+pub fn encrypt_in_format(
+    env: &mut JNIEnv,
+    key: Key,
+    transformation: Transformation,
+    format: EnvelopeFormat,
+    aad: Option<&[u8]>,
+    data: &[u8],
+) -> AndroidKeyringResult<Vec<u8>> {
+    let value = encrypt(env, key, transformation, aad, data)?;
+    Ok(match format {
+        EnvelopeFormat::Compact => value,
+        EnvelopeFormat::Cbor => to_cbor(&value),
+    })
+}
+
+/// Rewrite a value written by [encrypt] in the [Cbor](EnvelopeFormat::Cbor) format.
+fn to_cbor(value: &[u8]) -> Vec<u8> {
+    let envelope = parse_envelope(value).expect("Encrypted value is malformed: report a bug!");
+    let transformation = envelope.transformation;
+    let cbor = CborEnvelope {
+        v: VERSION,
+        alg: transformation,
+        iv: envelope.iv.into_owned(),
+        tag_len: transformation.tag_len() as u8,
+        aad_used: envelope.bound,
+        ct: envelope.ciphertext.into_owned(),
+    };
+    let mut encoded = Vec::new();
+    ciborium::into_writer(&cbor, &mut encoded).expect("CBOR encoding can't fail: report a bug!");
+    encoded
+}
+
+/// Decrypt data encrypted by [encrypt].
+///
+/// If the data was bound to additional authenticated data when it was
+/// encrypted, the same additional data must be supplied here.
+pub fn decrypt(
+    env: &mut JNIEnv,
+    key: Key,
+    aad: Option<&[u8]>,
+    data: Vec<u8>,
+) -> AndroidKeyringResult<Vec<u8>> {
+    decrypt_authorized(env, key, aad, data, |_, cipher| Ok(cipher))
+}
+
+/// Like [decrypt], but the cipher is passed through `authorize` as soon as
+/// it's initialized, so that the user can authenticate its use.
+pub(crate) fn decrypt_authorized<F>(
+    env: &mut JNIEnv,
+    key: Key,
+    aad: Option<&[u8]>,
+    data: Vec<u8>,
+    authorize: F,
+) -> AndroidKeyringResult<Vec<u8>>
+where
+    F: FnOnce(&mut JNIEnv, Cipher) -> AndroidKeyringResult<Cipher>,
+{
+    let cipher = decryption_cipher(env, key, &data)?;
+    let cipher = authorize(env, cipher)?;
+    decrypt_with_cipher(env, &cipher, aad, data)
+}
+
+/// Initialize a cipher that can decrypt data encrypted by [encrypt].
+pub(crate) fn decryption_cipher(
+    env: &mut JNIEnv,
+    key: Key,
+    data: &[u8],
+) -> AndroidKeyringResult<Cipher> {
+    let envelope = match parse_envelope(data) {
+        Ok(envelope) => envelope,
+        Err(err) => return Err(AndroidKeyringError::CorruptedData(data.to_vec(), err)),
+    };
+    let transformation = envelope.transformation;
+    let spec = transformation.parameter_spec(env, &envelope.iv)?;
+    let cipher = Cipher::get_instance(env, transformation.name())?;
+    cipher
+        .init2(env, DECRYPT_MODE, &key, spec)
+        .map_err(|e| init_error(env, e))?;
+    Ok(cipher)
+}
+
+/// Decrypt data encrypted by [encrypt] with a cipher from [decryption_cipher].
+pub(crate) fn decrypt_with_cipher(
+    env: &mut JNIEnv,
+    cipher: &Cipher,
+    aad: Option<&[u8]>,
+    data: Vec<u8>,
+) -> AndroidKeyringResult<Vec<u8>> {
+    let envelope = match parse_envelope(&data) {
+        Ok(envelope) => envelope,
+        Err(err) => return Err(AndroidKeyringError::CorruptedData(data, err)),
+    };
+    let transformation = envelope.transformation;
+    let ciphertext = &envelope.ciphertext;
+    if envelope.bound {
+        match aad {
+            Some(aad) if transformation.is_aead() => cipher.update_aad(env, aad)?,
+            _ => {
+                let err = CorruptedData::DecryptionFailure;
+                return Err(AndroidKeyringError::CorruptedData(data, err));
+            }
+        }
+    }
+    let plaintext = run_cipher(env, cipher, ciphertext).map_err(move |_| {
         AndroidKeyringError::CorruptedData(data, CorruptedData::DecryptionFailure)
     })?;
     Ok(plaintext)
 }
+
+/// Pass the input through an initialized cipher, returning all of its output.
+///
+/// Small inputs are processed in one call. Large ones are streamed
+/// through the cipher a chunk at a time.
+fn run_cipher(env: &mut JNIEnv, cipher: &Cipher, input: &[u8]) -> jni::errors::Result<Vec<u8>> {
+    if input.len() <= CHUNK_LEN {
+        return cipher.do_final(env, input);
+    }
+    // the output is allocated up front, and the parts are wiped once they're
+    // copied into it, so no copy of a decrypted secret is left behind
+    let capacity = cipher.get_output_size(env, input.len() as i32)?;
+    let mut output = Vec::with_capacity(capacity as usize);
+    for chunk in input.chunks(CHUNK_LEN) {
+        if let Some(part) = cipher.update(env, chunk)? {
+            output.extend_from_slice(&Zeroizing::new(part));
+        }
+    }
+    output.extend_from_slice(&Zeroizing::new(cipher.do_final(env, &[])?));
+    Ok(output)
+}
+
+/// Check the header of an encrypted value without decrypting it.
+///
+/// On success, returns the transformation the value was encrypted with.
+pub fn inspect(data: Vec<u8>) -> AndroidKeyringResult<Transformation> {
+    match parse_envelope(&data) {
+        Ok(envelope) => Ok(envelope.transformation),
+        Err(err) => Err(AndroidKeyringError::CorruptedData(data, err)),
+    }
+}
+
+/// What the header of an encrypted value says about it,
+/// as listed by [Store::describe_service](crate::Store::describe_service).
+#[derive(Debug, Serialize)]
+pub(crate) struct EnvelopeInfo {
+    /// The envelope's version, which is 0 for the legacy layout.
+    pub version: u8,
+    pub iv_len: usize,
+    pub ciphertext_len: usize,
+}
+
+/// Describe the header of an encrypted value without decrypting it.
+pub(crate) fn envelope_info(data: &[u8]) -> Result<EnvelopeInfo, CorruptedData> {
+    let envelope = parse_envelope(data)?;
+    Ok(EnvelopeInfo {
+        version: if is_legacy(data) { 0 } else { VERSION },
+        iv_len: envelope.iv.len(),
+        ciphertext_len: envelope.ciphertext.len(),
+    })
+}
+
+/// The number of IVs remembered for each key by [check_iv_unique].
+#[cfg(feature = "iv-check")]
+const IV_HISTORY_LEN: usize = 256;
+
+#[cfg(feature = "iv-check")]
+static IV_HISTORY: Mutex<BTreeMap<String, VecDeque<Vec<u8>>>> = Mutex::new(BTreeMap::new());
+
+/// Check that the IV of a value just encrypted with the key of the given
+/// alias wasn't among the last ones used with that key, and remember it.
+///
+/// The cipher generates a random IV for each encryption, so a repeat means
+/// something is badly wrong: with GCM, it exposes the key stream and lets
+/// anyone forge values. This is a safety net for debugging, so it's only
+/// compiled with the `iv-check` feature, and only GCM values are checked.
+#[cfg(feature = "iv-check")]
+pub(crate) fn check_iv_unique(key_alias: &str, data: &[u8]) -> AndroidKeyringResult<()> {
+    let envelope = parse_envelope(data)
+        .map_err(|err| AndroidKeyringError::CorruptedData(data.to_vec(), err))?;
+    if !envelope.transformation.is_aead() {
+        return Ok(());
+    }
+    let iv = &envelope.iv;
+    let mut history = IV_HISTORY
+        .lock()
+        .expect("IV history lock poisoned: report a bug!");
+    let ivs = history.entry(key_alias.to_string()).or_default();
+    if ivs.iter().any(|used| used.as_slice() == iv.as_ref()) {
+        log::error!("IV reused with key {key_alias:?}");
+        let err = format!("IV was reused with key {key_alias:?}");
+        return Err(keyring_core::Error::PlatformFailure(err.into()).into());
+    }
+    if ivs.len() == IV_HISTORY_LEN {
+        ivs.pop_front();
+    }
+    ivs.push_back(iv.to_vec());
+    Ok(())
+}
+
+/// Seal data in the envelope written by [encrypt], without encrypting it,
+/// for the [mock](crate::mock) store.
+///
+/// The value is bound to the given additional data like a GCM value, but its
+/// "ciphertext" is the data itself, followed by a tag that detects changes to
+/// the value (and to the additional data) without offering any security.
+#[cfg(feature = "mock")]
+pub(crate) fn mock_seal(iv: &[u8], aad: &[u8], data: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        iv.len(),
+        Transformation::AesGcm.iv_len(),
+        "IV len is wrong, please file a bug!"
+    );
+    let mut value = MAGIC.to_vec();
+    value.extend_from_slice(&[VERSION, AAD_FLAG, iv.len() as u8]);
+    value.extend_from_slice(iv);
+    value.extend_from_slice(data);
+    value.extend_from_slice(&mock_tag(iv, aad, data));
+    value
+}
+
+/// Open a value sealed by [mock_seal], failing as [decrypt] would
+/// on a value that's malformed or has been tampered with.
+#[cfg(feature = "mock")]
+pub(crate) fn mock_open(aad: &[u8], data: Vec<u8>) -> AndroidKeyringResult<Vec<u8>> {
+    let envelope = match parse_envelope(&data) {
+        Ok(envelope) => envelope,
+        Err(err) => return Err(AndroidKeyringError::CorruptedData(data, err)),
+    };
+    // the mock has no key, so it can only open the values it sealed
+    if envelope.transformation != Transformation::AesGcm || !envelope.bound {
+        return Err(AndroidKeyringError::CorruptedData(
+            data,
+            CorruptedData::DecryptionFailure,
+        ));
+    }
+    let ciphertext = &envelope.ciphertext;
+    let (plaintext, tag) = ciphertext.split_at(ciphertext.len() - (GCM_TAG_LEN / 8) as usize);
+    if tag != mock_tag(&envelope.iv, aad, plaintext) {
+        return Err(AndroidKeyringError::CorruptedData(
+            data,
+            CorruptedData::DecryptionFailure,
+        ));
+    }
+    Ok(plaintext.to_vec())
+}
+
+#[cfg(feature = "mock")]
+fn mock_tag(iv: &[u8], aad: &[u8], data: &[u8]) -> Vec<u8> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    (0..(GCM_TAG_LEN / 64) as u8)
+        .flat_map(|half| {
+            let mut hasher = DefaultHasher::new();
+            (half, iv, aad, data).hash(&mut hasher);
+            hasher.finish().to_be_bytes()
+        })
+        .collect()
+}
+
+/// The parts of an encrypted value.
+#[derive(Debug)]
+struct Envelope<'a> {
+    transformation: Transformation,
+    /// Whether the value is bound to additional authenticated data.
+    bound: bool,
+    /// The IV, whose length is that of the transformation's.
+    iv: Cow<'a, [u8]>,
+    /// The ciphertext, which is at least as long as the transformation's shortest.
+    ciphertext: Cow<'a, [u8]>,
+}
+
+/// Split an encrypted value into its parts, in the versioned layout,
+/// the legacy (version 0) one, or the CBOR format.
+///
+/// This doesn't touch the cipher, so it can be tested (and fuzzed) off-device.
+/// It never panics, whatever the data: it either returns the parts
+/// or says why the data can't have been produced by [encrypt].
+fn parse_envelope(data: &[u8]) -> Result<Envelope<'_>, CorruptedData> {
+    if is_cbor(data) {
+        return parse_cbor(data);
+    }
+    if is_legacy(data) {
+        let Some(&iv_len) = data.first() else {
+            return Err(CorruptedData::MissingIvLen);
+        };
+        return check_body(data, iv_len & !AAD_FLAG, iv_len & AAD_FLAG != 0, 1);
+    }
+    let Some(&version) = data.get(MAGIC.len()) else {
+        return Err(CorruptedData::MissingIvLen);
+    };
+    if version != VERSION {
+        return Err(CorruptedData::UnsupportedVersion(version));
+    }
+    let (Some(&flags), Some(&iv_len)) = (data.get(HEADER_LEN - 1), data.get(HEADER_LEN)) else {
+        return Err(CorruptedData::MissingIvLen);
+    };
+    if flags & !AAD_FLAG != 0 {
+        return Err(CorruptedData::UnknownFlags(flags));
+    }
+    check_body(data, iv_len, flags & AAD_FLAG != 0, HEADER_LEN + 1)
+}
+
+/// Decode a value in the [Cbor](EnvelopeFormat::Cbor) format, and check
+/// its fields as [check_body] checks those of the other layouts.
+fn parse_cbor(data: &[u8]) -> Result<Envelope<'static>, CorruptedData> {
+    let cbor: CborEnvelope = ciborium::from_reader(data).map_err(|_| CorruptedData::BadCbor)?;
+    if cbor.v != VERSION {
+        return Err(CorruptedData::UnsupportedVersion(cbor.v));
+    }
+    let transformation = cbor.alg;
+    if cbor.iv.len() != transformation.iv_len() {
+        return Err(CorruptedData::InvalidIvLen {
+            actual: cbor.iv.len(),
+            expected: transformation.iv_len(),
+        });
+    }
+    if cbor.tag_len as usize != transformation.tag_len() {
+        return Err(CorruptedData::BadCbor);
+    }
+    if cbor.ct.len() < transformation.min_ciphertext_len() {
+        return Err(CorruptedData::DataTooSmall(cbor.ct.len()));
+    }
+    Ok(Envelope {
+        transformation,
+        bound: cbor.aad_used,
+        iv: Cow::Owned(cbor.iv),
+        ciphertext: Cow::Owned(cbor.ct),
+    })
+}
+
+/// Check that the IV length identifies a transformation, and that the
+/// data after the IV length byte at `body_start - 1` is long enough.
+///
+/// Values that are too short to have been produced by the cipher are
+/// rejected here rather than leaving the cipher to fail on them.
+fn check_body(
+    data: &[u8],
+    iv_len: u8,
+    bound: bool,
+    body_start: usize,
+) -> Result<Envelope<'_>, CorruptedData> {
+    let iv_len = iv_len as usize;
+    let Some(transformation) = Transformation::from_iv_len(iv_len) else {
+        // report the length expected by the default transformation
+        return Err(CorruptedData::InvalidIvLen {
+            actual: iv_len,
+            expected: Transformation::default().iv_len(),
+        });
+    };
+    let body = &data[body_start..];
+    let ciphertext_len = body.len().saturating_sub(iv_len);
+    if ciphertext_len < transformation.min_ciphertext_len() {
+        return Err(CorruptedData::DataTooSmall(ciphertext_len));
+    }
+    let (iv, ciphertext) = body.split_at(iv_len);
+    Ok(Envelope {
+        transformation,
+        bound,
+        iv: Cow::Borrowed(iv),
+        ciphertext: Cow::Borrowed(ciphertext),
+    })
+}
+
+/// Keys with a validity window are rejected when a cipher is initialized
+/// outside that window, keys that require an unlocked device are
+/// rejected while the device is locked, and keys that the platform has
+/// permanently invalidated are always rejected. Those failures get their
+/// own errors, so clients can tell them apart from other platform failures.
+///
+/// A `UserNotAuthenticatedException` means that the key requires the user
+/// to have authenticated recently (as the legacy store's protected keys do),
+/// or requires an unlocked device, and the user needs to authenticate
+/// before the operation is retried.
+fn init_error(env: &mut JNIEnv, err: jni::errors::Error) -> AndroidKeyringError {
+    let mapped = if pending_exception_is(env, KEY_EXPIRED_EXCEPTION) {
+        AndroidKeyringError::KeyExpired
+    } else if pending_exception_is(env, KEY_NOT_YET_VALID_EXCEPTION) {
+        AndroidKeyringError::KeyNotYetValid
+    } else if pending_exception_is(env, USER_NOT_AUTHENTICATED_EXCEPTION) {
+        AndroidKeyringError::AuthenticationRequired(pending_exception_message(env))
+    } else if pending_exception_is(env, KEY_PERMANENTLY_INVALIDATED_EXCEPTION) {
+        AndroidKeyringError::KeyInvalidated
+    } else {
+        return err.into();
+    };
+    _ = env.exception_clear();
+    mapped
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    /// Parse the data and check that the result is consistent with it.
+    fn check_parse(data: &[u8]) {
+        if is_cbor(data) {
+            return check_cbor_parse(data);
+        }
+        match parse_envelope(data) {
+            Ok(envelope) => {
+                let transformation = envelope.transformation;
+                assert_eq!(envelope.iv.len(), transformation.iv_len());
+                assert!(envelope.ciphertext.len() >= transformation.min_ciphertext_len());
+                // the IV and ciphertext are the end of the data, after the IV length
+                let body_len = envelope.iv.len() + envelope.ciphertext.len();
+                assert!(body_len < data.len());
+                let body = &data[data.len() - body_len..];
+                assert_eq!(body, [&envelope.iv[..], &envelope.ciphertext[..]].concat());
+                let iv_len = data[data.len() - body_len - 1] & !AAD_FLAG;
+                assert_eq!(iv_len as usize, transformation.iv_len());
+            }
+            Err(CorruptedData::DataTooSmall(len)) => assert!(len < AES_BLOCK_LEN),
+            Err(CorruptedData::InvalidIvLen { actual, expected }) => {
+                assert!(Transformation::from_iv_len(actual).is_none());
+                assert_eq!(expected, Transformation::default().iv_len());
+            }
+            Err(CorruptedData::UnsupportedVersion(version)) => assert_ne!(version, VERSION),
+            Err(CorruptedData::UnknownFlags(flags)) => assert_ne!(flags & !AAD_FLAG, 0),
+            Err(CorruptedData::MissingIvLen) => {}
+            Err(err) => panic!("parsing can't fail with {err:?}"),
+        }
+    }
+
+    /// Like [check_parse], for data in the CBOR format.
+    fn check_cbor_parse(data: &[u8]) {
+        match parse_envelope(data) {
+            Ok(envelope) => {
+                let transformation = envelope.transformation;
+                assert_eq!(envelope.iv.len(), transformation.iv_len());
+                assert!(envelope.ciphertext.len() >= transformation.min_ciphertext_len());
+            }
+            Err(CorruptedData::DataTooSmall(_)) => {}
+            Err(CorruptedData::InvalidIvLen { .. }) => {}
+            Err(CorruptedData::UnsupportedVersion(version)) => assert_ne!(version, VERSION),
+            Err(CorruptedData::BadCbor) => {}
+            Err(err) => panic!("parsing CBOR can't fail with {err:?}"),
+        }
+    }
+
+    /// A value in the compact layout, in either its versioned or legacy form.
+    fn compact_value(
+        transformation: Transformation,
+        versioned: bool,
+        bound: bool,
+        iv: &[u8],
+        ciphertext: &[u8],
+    ) -> Vec<u8> {
+        let iv = &iv[..transformation.iv_len()];
+        let flag = if bound { AAD_FLAG } else { 0 };
+        let mut data = Vec::new();
+        if versioned {
+            data.extend_from_slice(&MAGIC);
+            data.extend_from_slice(&[VERSION, flag, iv.len() as u8]);
+        } else {
+            data.push(iv.len() as u8 | flag);
+        }
+        data.extend_from_slice(iv);
+        data.extend_from_slice(ciphertext);
+        data
+    }
+
+    fn transformation() -> impl Strategy<Value = Transformation> {
+        prop_oneof![Just(Transformation::AesGcm), Just(Transformation::AesCbc)]
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_data(data in vec(any::<u8>(), 0..64)) {
+            check_parse(&data);
+        }
+
+        #[test]
+        fn arbitrary_versioned_headers(
+            version in prop_oneof![Just(VERSION), any::<u8>()],
+            flags in prop_oneof![Just(0), Just(AAD_FLAG), any::<u8>()],
+            iv_len in prop_oneof![Just(12u8), Just(16u8), any::<u8>()],
+            body in vec(any::<u8>(), 0..48),
+        ) {
+            let mut data = MAGIC.to_vec();
+            data.extend_from_slice(&[version, flags, iv_len]);
+            data.extend_from_slice(&body);
+            check_parse(&data);
+        }
+
+        #[test]
+        fn well_formed_values(
+            transformation in transformation(),
+            versioned in any::<bool>(),
+            bound in any::<bool>(),
+            iv in vec(any::<u8>(), 16),
+            ciphertext in vec(any::<u8>(), 16..64),
+        ) {
+            let data = compact_value(transformation, versioned, bound, &iv, &ciphertext);
+            let iv = &iv[..transformation.iv_len()];
+            let envelope = parse_envelope(&data).unwrap();
+            prop_assert_eq!(envelope.transformation, transformation);
+            prop_assert_eq!(envelope.bound, bound);
+            prop_assert_eq!(envelope.iv, iv);
+            prop_assert_eq!(envelope.ciphertext, &ciphertext[..]);
+        }
+
+        #[test]
+        fn cbor_round_trip(
+            transformation in transformation(),
+            versioned in any::<bool>(),
+            bound in any::<bool>(),
+            iv in vec(any::<u8>(), 16),
+            ciphertext in vec(any::<u8>(), 16..64),
+        ) {
+            let data = compact_value(transformation, versioned, bound, &iv, &ciphertext);
+            let cbor = to_cbor(&data);
+            prop_assert!(is_cbor(&cbor));
+            prop_assert!(!is_legacy(&cbor));
+            let envelope = parse_envelope(&cbor).unwrap();
+            prop_assert_eq!(envelope.transformation, transformation);
+            prop_assert_eq!(envelope.bound, bound);
+            prop_assert_eq!(envelope.iv, &iv[..transformation.iv_len()]);
+            prop_assert_eq!(envelope.ciphertext, &ciphertext[..]);
+        }
+
+        #[test]
+        fn arbitrary_cbor(
+            first in 0xa0u8..=0xbf,
+            body in vec(any::<u8>(), 0..64),
+        ) {
+            let mut data = vec![first];
+            data.extend_from_slice(&body);
+            check_parse(&data);
+        }
+    }
+
+    // Values a byte either side of the shortest ciphertext each transformation
+    // can produce, which were once rejected (or accepted) wrongly.
+    #[test]
+    fn data_too_small_boundary() {
+        let mut gcm = MAGIC.to_vec();
+        gcm.extend_from_slice(&[VERSION, 0, 12]);
+        gcm.extend_from_slice(&[0; 12 + 16]);
+        assert!(parse_envelope(&gcm).is_ok());
+        assert!(matches!(
+            parse_envelope(&gcm[..gcm.len() - 1]),
+            Err(CorruptedData::DataTooSmall(15))
+        ));
+        let mut cbc = vec![16];
+        cbc.extend_from_slice(&[0; 16 + 16]);
+        assert!(parse_envelope(&cbc).is_ok());
+        assert!(matches!(
+            parse_envelope(&cbc[..cbc.len() - 1]),
+            Err(CorruptedData::DataTooSmall(15))
+        ));
+    }
+
+    // An empty secret encrypts to the shortest ciphertext, which must
+    // parse in every layout, unlike a value with no bytes at all.
+    #[test]
+    fn empty_plaintext() {
+        for transformation in [Transformation::AesGcm, Transformation::AesCbc] {
+            let iv = vec![7; transformation.iv_len()];
+            let ciphertext = vec![9; transformation.min_ciphertext_len()];
+            for versioned in [true, false] {
+                let data = compact_value(transformation, versioned, false, &iv, &ciphertext);
+                let envelope = parse_envelope(&data).unwrap();
+                assert_eq!(envelope.ciphertext, &ciphertext[..]);
+                let cbor = to_cbor(&data);
+                let envelope = parse_envelope(&cbor).unwrap();
+                assert_eq!(envelope.ciphertext, &ciphertext[..]);
+            }
+        }
+        assert!(matches!(
+            parse_envelope(&[]),
+            Err(CorruptedData::MissingIvLen)
+        ));
+    }
+
+    // Fields that this version doesn't know are ignored, so later versions
+    // can add them, but the known ones must have the right types.
+    #[test]
+    fn cbor_fields() {
+        use ciborium::Value;
+
+        let data = compact_value(Transformation::AesGcm, true, true, &[7; 16], &[9; 20]);
+        let cbor = to_cbor(&data);
+        assert_eq!(cbor[0], 0xa6);
+        let Value::Map(mut fields) = ciborium::from_reader(&cbor[..]).unwrap() else {
+            panic!("CBOR envelope isn't a map");
+        };
+        let keys: Vec<_> = fields.iter().filter_map(|(k, _)| k.as_text()).collect();
+        assert_eq!(keys, ["v", "alg", "iv", "tag_len", "aad_used", "ct"]);
+        let encode = |fields: Vec<(Value, Value)>| {
+            let mut encoded = Vec::new();
+            ciborium::into_writer(&Value::Map(fields), &mut encoded).unwrap();
+            encoded
+        };
+        let mut extended = fields.clone();
+        extended.push(("kdf".into(), "none".into()));
+        let encoded = encode(extended);
+        let envelope = parse_envelope(&encoded).unwrap();
+        assert_eq!(envelope.ciphertext, &[9; 20][..]);
+        fields[3].1 = 12.into();
+        assert!(matches!(
+            parse_envelope(&encode(fields)),
+            Err(CorruptedData::BadCbor)
+        ));
+    }
+
+    // Values that end inside their header or IV must be rejected,
+    // not sliced past their end.
+    #[test]
+    fn truncated_values() {
+        for data in [&b""[..], b"AK", b"AK\x01", b"AK\x01\x00"] {
+            assert!(matches!(
+                parse_envelope(data),
+                Err(CorruptedData::MissingIvLen)
+            ));
+        }
+        assert!(matches!(
+            parse_envelope(b"AK\x01\x80\x0c\x00\x01\x02"),
+            Err(CorruptedData::DataTooSmall(0))
+        ));
+        assert!(matches!(
+            parse_envelope(b"\x8c\x00\x01"),
+            Err(CorruptedData::DataTooSmall(0))
+        ));
+        assert!(matches!(
+            parse_envelope(b"AK\x02"),
+            Err(CorruptedData::UnsupportedVersion(2))
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+use jni::{
+    JNIEnv,
+    objects::{GlobalRef, JObject, JValueGen},
+};
+use std::marker::PhantomData;
+
+use crate::{
+    methods::{
+        ClassDecl, Constructible, FromValue, JResult, LoadedClass, Method, NoParam, SignatureComp,
+        StaticMethod, ToValue,
+    },
+    shared_preferences::{ClassLoader, Context, SharedPreferences},
+};
+
+/// The alias of the Android Keystore key that `MasterKey` uses by default.
+///
+/// Every file encrypted with the default master key shares this key.
+pub const DEFAULT_MASTER_KEY_ALIAS: &str = "_androidx_security_master_key_";
+
+// AndroidX classes aren't part of the platform, so they are
+// loaded by their binary names through the application's class loader.
+const ENCRYPTED_SHARED_PREFERENCES: &str = "androidx.security.crypto.EncryptedSharedPreferences";
+const PREF_KEY_ENCRYPTION_SCHEME: &str =
+    "androidx.security.crypto.EncryptedSharedPreferences$PrefKeyEncryptionScheme";
+const PREF_VALUE_ENCRYPTION_SCHEME: &str =
+    "androidx.security.crypto.EncryptedSharedPreferences$PrefValueEncryptionScheme";
+const MASTER_KEY_BUILDER: &str = "androidx.security.crypto.MasterKey$Builder";
+const KEY_SCHEME: &str = "androidx.security.crypto.MasterKey$KeyScheme";
+
+pub struct EncryptedSharedPreferences;
+
+impl EncryptedSharedPreferences {
+    /// Open (creating if necessary) the named file, with its keys
+    /// encrypted by AES256-SIV and its values by AES256-GCM.
+    pub fn create(
+        env: &mut JNIEnv,
+        loader: &ClassLoader,
+        context: &Context,
+        name: &str,
+        master_key: &MasterKey,
+    ) -> JResult<SharedPreferences> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = (
+                &'a Context,
+                &'a str,
+                &'a MasterKey,
+                PrefKeyEncryptionScheme,
+                PrefValueEncryptionScheme,
+            );
+            type Return = SharedPreferences;
+
+            const NAME: &'static str = "create";
+        }
+
+        let class = loader.load_class(env, ENCRYPTED_SHARED_PREFERENCES)?;
+        let key_scheme = PrefKeyEncryptionScheme::aes256_siv(env, loader)?;
+        let value_scheme = PrefValueEncryptionScheme::aes256_gcm(env, loader)?;
+        ThisMethod::call_loaded(
+            &class,
+            env,
+            (context, name, master_key, key_scheme, value_scheme),
+        )
+    }
+}
+
+pub struct PrefKeyEncryptionScheme {
+    self_: GlobalRef,
+}
+
+impl FromValue for PrefKeyEncryptionScheme {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for PrefKeyEncryptionScheme {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl PrefKeyEncryptionScheme {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroidx/security/crypto/EncryptedSharedPreferences$PrefKeyEncryptionScheme;")
+    }
+
+    pub fn aes256_siv(env: &mut JNIEnv, loader: &ClassLoader) -> JResult<Self> {
+        let class = loader.load_class(env, PREF_KEY_ENCRYPTION_SCHEME)?;
+        class.get_static_field(env, "AES256_SIV")
+    }
+}
+
+pub struct PrefValueEncryptionScheme {
+    self_: GlobalRef,
+}
+
+impl FromValue for PrefValueEncryptionScheme {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for PrefValueEncryptionScheme {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl PrefValueEncryptionScheme {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroidx/security/crypto/EncryptedSharedPreferences$PrefValueEncryptionScheme;")
+    }
+
+    pub fn aes256_gcm(env: &mut JNIEnv, loader: &ClassLoader) -> JResult<Self> {
+        let class = loader.load_class(env, PREF_VALUE_ENCRYPTION_SCHEME)?;
+        class.get_static_field(env, "AES256_GCM")
+    }
+}
+
+/// The key that encrypts the keysets of encrypted files.
+///
+/// It lives in the Android Keystore, and the keysets that actually
+/// encrypt a file's keys and values are stored in the file itself.
+pub struct MasterKey {
+    self_: GlobalRef,
+}
+
+impl FromValue for MasterKey {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for MasterKey {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl MasterKey {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroidx/security/crypto/MasterKey;")
+    }
+
+    /// Get the default AES256-GCM master key, generating it if necessary.
+    pub fn get_or_create(
+        env: &mut JNIEnv,
+        loader: &ClassLoader,
+        context: &Context,
+    ) -> JResult<Self> {
+        let class = loader.load_class(env, MASTER_KEY_BUILDER)?;
+        let scheme = KeyScheme::aes256_gcm(env, loader)?;
+        MasterKeyBuilder::new(env, &class, context)?
+            .set_key_scheme(env, &scheme)?
+            .build(env)
+    }
+}
+
+pub struct KeyScheme {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyScheme {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for KeyScheme {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl KeyScheme {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroidx/security/crypto/MasterKey$KeyScheme;")
+    }
+
+    pub fn aes256_gcm(env: &mut JNIEnv, loader: &ClassLoader) -> JResult<Self> {
+        let class = loader.load_class(env, KEY_SCHEME)?;
+        class.get_static_field(env, "AES256_GCM")
+    }
+}
+
+pub struct MasterKeyBuilder {
+    self_: GlobalRef,
+}
+
+impl FromValue for MasterKeyBuilder {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl MasterKeyBuilder {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroidx/security/crypto/MasterKey$Builder;")
+    }
+
+    pub fn new(env: &mut JNIEnv, class: &LoadedClass, context: &Context) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = &'a Context;
+            type Return = MasterKeyBuilder;
+        }
+
+        ThisMethod::call_new_loaded(class, env, context)
+    }
+
+    pub fn set_key_scheme(self, env: &mut JNIEnv, scheme: &KeyScheme) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a KeyScheme;
+            type Return = MasterKeyBuilder;
+
+            const NAME: &'static str = "setKeyScheme";
+        }
+
+        ThisMethod::call(&self.self_, env, scheme)
+    }
+
+    pub fn build(self, env: &mut JNIEnv) -> JResult<MasterKey> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = MasterKey;
+
+            const NAME: &str = "build";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
@@ -1,13 +1,69 @@
+use jni::{JNIEnv, objects::GlobalRef};
+
+use crate::keystore::{
+    KEY_STORE_EXCEPTION, NO_SUCH_ALGORITHM_EXCEPTION, NO_SUCH_PROVIDER_EXCEPTION,
+};
+use crate::methods::{ClassDecl, FromValue, JResult, Method, NoParam, SignatureComp};
+
 #[derive(thiserror::Error, Debug)]
 pub enum AndroidKeyringError {
     #[error(transparent)]
     JniError(#[from] jni::errors::Error),
-    #[error("Java exception was thrown")]
-    JavaExceptionThrow,
+    /// A Java exception was thrown and not handled. It holds the exception's
+    /// class name and its message, if there is one.
+    #[error(
+        "Java exception was thrown: {class}{}",
+        .message.as_ref().map(|m| format!(": {m}")).unwrap_or_default()
+    )]
+    JavaExceptionThrow {
+        class: String,
+        message: Option<String>,
+    },
     #[error("{1}")]
     CorruptedData(Vec<u8>, CorruptedData),
     #[error(transparent)]
     KeyringError(#[from] keyring_core::Error),
+    #[error("Device is locked")]
+    DeviceLocked,
+    #[error("Encryption key has expired")]
+    KeyExpired,
+    #[error("Encryption key is not yet valid")]
+    KeyNotYetValid,
+    /// The key can only be used after the user authenticates, and the user
+    /// hasn't done so recently enough. The application can prompt the user
+    /// to authenticate (for example, by unlocking the device) and retry.
+    /// It holds the platform's message about the failure, if there is one.
+    #[error(
+        "User must authenticate to use the encryption key{}",
+        .0.as_ref().map(|m| format!(": {m}")).unwrap_or_default()
+    )]
+    AuthenticationRequired(Option<String>),
+    /// The key was permanently invalidated by the platform, and can't be used again.
+    ///
+    /// This happens to keys that require user authentication (such as the
+    /// legacy store's protected keys) when the user removes the device's secure
+    /// lock screen, and to keys that are invalidated by biometric enrollment
+    /// when the user enrolls a new biometric. The secrets encrypted with such
+    /// a key are lost. To recover, delete them along with the key (using the
+    /// legacy store's `delete_service`, or a named store's
+    /// [reprovisioner](crate::Store::set_reprovisioner)) and set them again.
+    #[error("Encryption key has been permanently invalidated")]
+    KeyInvalidated,
+    /// The user dismissed the prompt to authenticate, or it was canceled
+    /// (for example, because the application went into the background).
+    /// Nothing is wrong with the key or the data, so the application
+    /// can simply carry on without the secret, or ask again later.
+    #[error("User canceled authentication")]
+    AuthCanceled,
+    /// The device has no `AndroidKeyStore` security provider, as happens on
+    /// some stripped-down or non-Google Android images, so keys can't be kept.
+    /// Nothing will change that, so the application can fall back to another
+    /// credential store. It holds the platform's message, if there is one.
+    #[error(
+        "The AndroidKeyStore provider is unavailable{}",
+        .0.as_ref().map(|m| format!(": {m}")).unwrap_or_default()
+    )]
+    ProviderUnavailable(Option<String>),
 }
 
 impl From<AndroidKeyringError> for keyring_core::Error {
@@ -16,13 +72,24 @@ impl From<AndroidKeyringError> for keyring_core::Error {
             AndroidKeyringError::JniError(error) => {
                 keyring_core::Error::PlatformFailure(Box::new(error))
             }
-            e @ AndroidKeyringError::JavaExceptionThrow => {
+            e @ AndroidKeyringError::JavaExceptionThrow { .. } => {
                 keyring_core::Error::PlatformFailure(Box::new(e))
             }
             AndroidKeyringError::CorruptedData(data, error) => {
                 keyring_core::Error::BadDataFormat(data, Box::new(error))
             }
             AndroidKeyringError::KeyringError(error) => error,
+            e @ (AndroidKeyringError::DeviceLocked
+            | AndroidKeyringError::KeyExpired
+            | AndroidKeyringError::KeyNotYetValid
+            | AndroidKeyringError::AuthenticationRequired(_)
+            | AndroidKeyringError::KeyInvalidated
+            | AndroidKeyringError::AuthCanceled) => {
+                keyring_core::Error::NoStorageAccess(Box::new(e))
+            }
+            e @ AndroidKeyringError::ProviderUnavailable(_) => {
+                keyring_core::Error::NotSupportedByStore(e.to_string())
+            }
         }
     }
 }
@@ -35,8 +102,201 @@ pub enum CorruptedData {
     MissingIvLen,
     #[error("IV length in data is {actual}, but should be {expected}")]
     InvalidIvLen { actual: usize, expected: usize },
-    #[error("Data is too small to contain IV and ciphertext, length = {0}")]
+    #[error("Data is too small to contain IV and ciphertext, ciphertext length = {0}")]
     DataTooSmall(usize),
     #[error("Verification of data signature/MAC failed")]
     DecryptionFailure,
+    #[error("Stored value is not valid base64")]
+    BadBase64,
+    #[error("Encrypted value has unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Encrypted value has unknown flags {0:#04x}")]
+    UnknownFlags(u8),
+    #[error("Stored value is not a valid CBOR envelope")]
+    BadCbor,
+}
+
+impl CorruptedData {
+    /// The name of this kind of corruption, such as `DataTooSmall`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CorruptedData::MissingIvLen => "MissingIvLen",
+            CorruptedData::InvalidIvLen { .. } => "InvalidIvLen",
+            CorruptedData::DataTooSmall(_) => "DataTooSmall",
+            CorruptedData::DecryptionFailure => "DecryptionFailure",
+            CorruptedData::BadBase64 => "BadBase64",
+            CorruptedData::UnsupportedVersion(_) => "UnsupportedVersion",
+            CorruptedData::UnknownFlags(_) => "UnknownFlags",
+            CorruptedData::BadCbor => "BadCbor",
+        }
+    }
+}
+
+/// Check whether the pending Java exception, if any, is an instance of the named class.
+///
+/// The exception is left pending. If the named class doesn't exist on this
+/// device (because it was introduced in a later API level), the answer is `false`.
+pub fn pending_exception_is(env: &mut JNIEnv, class: &str) -> bool {
+    let exception = match env.exception_occurred() {
+        Ok(exception) if !exception.is_null() => exception,
+        _ => return false,
+    };
+    // class lookups aren't allowed while an exception is pending
+    if env.exception_clear().is_err() {
+        return false;
+    }
+    let result = env.is_instance_of(&exception, class).unwrap_or(false);
+    if env.exception_check().unwrap_or(false) {
+        _ = env.exception_clear();
+    }
+    _ = env.throw(exception);
+    result
+}
+
+/// A `java.lang.Throwable`, such as the cause of an exception.
+struct Throwable {
+    self_: GlobalRef,
+}
+
+impl FromValue for Throwable {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/lang/Throwable;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+/// Check whether the pending Java exception, if any, has a cause
+/// that is an instance of the named class.
+///
+/// The exception is left pending.
+pub fn pending_exception_cause_is(env: &mut JNIEnv, class: &str) -> bool {
+    struct ThisMethod;
+    impl Method for ThisMethod {
+        type Param = NoParam;
+        type Return = Option<Throwable>;
+
+        const NAME: &str = "getCause";
+    }
+    let exception = match env.exception_occurred() {
+        Ok(exception) if !exception.is_null() => exception,
+        _ => return false,
+    };
+    // methods can't be called while an exception is pending
+    if env.exception_clear().is_err() {
+        return false;
+    }
+    let result = match ThisMethod::call(&exception, env, NoParam) {
+        Ok(Some(cause)) => env.is_instance_of(&cause.self_, class).unwrap_or(false),
+        _ => false,
+    };
+    if env.exception_check().unwrap_or(false) {
+        _ = env.exception_clear();
+    }
+    _ = env.throw(exception);
+    result
+}
+
+/// Whether the pending Java exception, if any, means that the device has no
+/// `AndroidKeyStore` provider. Getting an instance of a keystore class from
+/// a missing provider throws a `NoSuchProviderException`, while getting
+/// a `KeyStore` of a missing type throws a `KeyStoreException` that's
+/// caused by a `NoSuchAlgorithmException`.
+fn pending_exception_is_provider_unavailable(env: &mut JNIEnv) -> bool {
+    pending_exception_is(env, NO_SUCH_PROVIDER_EXCEPTION)
+        || (pending_exception_is(env, KEY_STORE_EXCEPTION)
+            && pending_exception_cause_is(env, NO_SUCH_ALGORITHM_EXCEPTION))
+}
+
+/// Get the message of the pending Java exception, if there is one and it has a message.
+///
+/// The exception is left pending.
+pub fn pending_exception_message(env: &mut JNIEnv) -> Option<String> {
+    struct ThisMethod;
+    impl Method for ThisMethod {
+        type Param = NoParam;
+        type Return = Option<String>;
+
+        const NAME: &str = "getMessage";
+    }
+    let exception = match env.exception_occurred() {
+        Ok(exception) if !exception.is_null() => exception,
+        _ => return None,
+    };
+    // methods can't be called while an exception is pending
+    if env.exception_clear().is_err() {
+        return None;
+    }
+    let message = ThisMethod::call(&exception, env, NoParam).unwrap_or(None);
+    if env.exception_check().unwrap_or(false) {
+        _ = env.exception_clear();
+    }
+    _ = env.throw(exception);
+    message
+}
+
+/// Get the class name of the pending Java exception, if there is one.
+///
+/// The exception is left pending.
+pub fn pending_exception_class(env: &mut JNIEnv) -> Option<String> {
+    struct ThisMethod;
+    impl Method for ThisMethod {
+        type Param = NoParam;
+        type Return = String;
+
+        const NAME: &str = "getName";
+    }
+    let exception = match env.exception_occurred() {
+        Ok(exception) if !exception.is_null() => exception,
+        _ => return None,
+    };
+    // methods can't be called while an exception is pending
+    if env.exception_clear().is_err() {
+        return None;
+    }
+    let class = env
+        .get_object_class(&exception)
+        .and_then(|class| ThisMethod::call(&class, env, NoParam))
+        .ok();
+    if env.exception_check().unwrap_or(false) {
+        _ = env.exception_clear();
+    }
+    _ = env.throw(exception);
+    class
+}
+
+/// Clear the pending Java exception, returning an error that holds its class and message.
+///
+/// The exception is also described to the console (logcat), as it was
+/// before its details were captured.
+pub fn take_pending_exception(env: &mut JNIEnv) -> jni::errors::Result<AndroidKeyringError> {
+    // every exception is a Throwable, even one whose class can't be read
+    let class = pending_exception_class(env).unwrap_or_else(|| "java.lang.Throwable".to_string());
+    let message = pending_exception_message(env);
+    env.exception_describe()?;
+    env.exception_clear()?;
+    Ok(AndroidKeyringError::JavaExceptionThrow { class, message })
+}
+
+/// Check the result of getting an instance of a keystore class
+/// from the `AndroidKeyStore` provider.
+///
+/// If it failed because the device has no such provider, the exception is
+/// cleared and [ProviderUnavailable](AndroidKeyringError::ProviderUnavailable)
+/// is returned, so applications can tell that failure apart from others.
+/// Any other exception is left pending.
+pub(crate) fn check_provider<T>(env: &mut JNIEnv, result: JResult<T>) -> AndroidKeyringResult<T> {
+    match result {
+        Err(jni::errors::Error::JavaException)
+            if pending_exception_is_provider_unavailable(env) =>
+        {
+            let message = pending_exception_message(env);
+            env.exception_describe()?;
+            env.exception_clear()?;
+            Err(AndroidKeyringError::ProviderUnavailable(message))
+        }
+        result => Ok(result?),
+    }
 }
@@ -0,0 +1,190 @@
+use jni::{JNIEnv, objects::GlobalRef};
+
+#[cfg(feature = "biometric")]
+use crate::methods::StaticMethod;
+use crate::{
+    methods::{ClassDecl, FromValue, JResult, Method, NoParam, SignatureComp},
+    shared_preferences::{Context, SystemService},
+};
+
+pub const KEYGUARD_SERVICE: &str = "keyguard";
+
+#[cfg(feature = "compile-tests")]
+static SIMULATED_LOCK: std::sync::Mutex<Option<bool>> = std::sync::Mutex::new(None);
+
+/// Override the device's actual lock state, so tests can exercise
+/// the locked-device code paths. Passing `None` removes the override.
+#[cfg(feature = "compile-tests")]
+pub fn simulate_device_locked(locked: Option<bool>) {
+    *SIMULATED_LOCK
+        .lock()
+        .expect("Simulated lock poisoned: report a bug!") = locked;
+}
+
+pub struct KeyguardManager {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyguardManager {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl From<SystemService> for KeyguardManager {
+    fn from(value: SystemService) -> Self {
+        Self {
+            self_: value.into_raw(),
+        }
+    }
+}
+
+impl KeyguardManager {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/app/KeyguardManager;")
+    }
+
+    pub fn from_context(env: &mut JNIEnv, context: &Context) -> JResult<Self> {
+        Ok(context.get_system_service(env, KEYGUARD_SERVICE)?.into())
+    }
+
+    pub fn is_device_locked(&self, env: &mut JNIEnv) -> JResult<bool> {
+        #[cfg(feature = "compile-tests")]
+        if let Some(locked) = *SIMULATED_LOCK
+            .lock()
+            .expect("Simulated lock poisoned: report a bug!")
+        {
+            return Ok(locked);
+        }
+
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = bool;
+
+            const NAME: &str = "isDeviceLocked";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    #[cfg(feature = "compile-tests")]
+    pub fn is_device_secure(&self, env: &mut JNIEnv) -> JResult<bool> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = bool;
+
+            const NAME: &str = "isDeviceSecure";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+#[cfg(feature = "legacy")]
+pub const BIOMETRIC_SERVICE: &str = "biometric";
+
+/// The `BiometricManager.canAuthenticate` result for a user who can authenticate.
+#[cfg(feature = "legacy")]
+pub const BIOMETRIC_SUCCESS: i32 = 0;
+
+/// A `BiometricManager`, which reports whether the user can authenticate.
+///
+/// The legacy store uses the platform's (API level 29), which is always
+/// available. The [biometric](crate::biometric) module uses AndroidX's,
+/// which the application must include but which also works on older
+/// versions. Both have the same `canAuthenticate` method.
+#[cfg(any(feature = "legacy", feature = "biometric"))]
+pub struct BiometricManager {
+    self_: GlobalRef,
+}
+
+#[cfg(feature = "legacy")]
+impl From<SystemService> for BiometricManager {
+    fn from(value: SystemService) -> Self {
+        Self {
+            self_: value.into_raw(),
+        }
+    }
+}
+
+/// Returned by AndroidX's `BiometricManager.from`.
+#[cfg(feature = "biometric")]
+impl FromValue for BiometricManager {
+    fn signature() -> SignatureComp {
+        ClassDecl("Landroidx/biometric/BiometricManager;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+#[cfg(any(feature = "legacy", feature = "biometric"))]
+impl BiometricManager {
+    #[cfg(feature = "legacy")]
+    fn authenticators_class() -> ClassDecl {
+        ClassDecl("Landroid/hardware/biometrics/BiometricManager$Authenticators;")
+    }
+
+    #[cfg(feature = "legacy")]
+    pub fn from_context(env: &mut JNIEnv, context: &Context) -> JResult<Self> {
+        Ok(context.get_system_service(env, BIOMETRIC_SERVICE)?.into())
+    }
+
+    /// AndroidX's manager, whose class is loaded by the application's class loader.
+    #[cfg(feature = "biometric")]
+    pub fn from_androidx(env: &mut JNIEnv, context: &Context) -> JResult<Self> {
+        struct ThisMethod<'a>(std::marker::PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = &'a Context;
+            type Return = BiometricManager;
+
+            const NAME: &'static str = "from";
+        }
+
+        let class = context
+            .get_class_loader(env)?
+            .load_class(env, "androidx.biometric.BiometricManager")?;
+        ThisMethod::call_loaded(&class, env, context)
+    }
+
+    /// The `BiometricManager.Authenticators` flags (API level 30) for
+    /// a strong biometric, the device credential, or either of them.
+    #[cfg(feature = "legacy")]
+    pub fn authenticators(
+        env: &mut JNIEnv,
+        biometric: bool,
+        device_credential: bool,
+    ) -> JResult<i32> {
+        let class = Self::authenticators_class();
+        let mut flags = 0;
+        if biometric {
+            flags |= class.get_static_field::<i32>(env, "BIOMETRIC_STRONG")?;
+        }
+        if device_credential {
+            flags |= class.get_static_field::<i32>(env, "DEVICE_CREDENTIAL")?;
+        }
+        Ok(flags)
+    }
+
+    /// Whether the user can authenticate with the given authenticators
+    /// (API level 30 for the platform's), as one of the `BIOMETRIC_*`
+    /// result codes.
+    pub fn can_authenticate(&self, env: &mut JNIEnv, authenticators: i32) -> JResult<i32> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = i32;
+            type Return = i32;
+
+            const NAME: &str = "canAuthenticate";
+        }
+
+        ThisMethod::call(&self.self_, env, authenticators)
+    }
+}
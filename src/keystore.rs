@@ -2,20 +2,110 @@ use std::marker::PhantomData;
 
 use jni::{
     JNIEnv,
-    objects::{GlobalRef, JObject, JValueGen},
+    objects::{GlobalRef, JObject, JObjectArray, JValueGen},
 };
 
+use crate::error::{AndroidKeyringResult, check_provider, pending_exception_is};
 use crate::methods::{
     ClassDecl, Constructible, FromValue, JResult, Method, NoParam, SignatureComp, StaticMethod,
     ToValue,
 };
 
+pub const BLOCK_MODE_CBC: &str = "CBC";
 pub const BLOCK_MODE_GCM: &str = "GCM";
 pub const ENCRYPTION_PADDING_NONE: &str = "NoPadding";
+pub const ENCRYPTION_PADDING_PKCS7: &str = "PKCS7Padding";
+pub const DIGEST_SHA256: &str = "SHA-256";
 pub const KEY_ALGORITHM_AES: &str = "AES";
+pub const KEY_ALGORITHM_EC: &str = "EC";
+pub const KEY_ALGORITHM_HMAC_SHA256: &str = "HmacSHA256";
+pub const KEY_ALGORITHM_RSA: &str = "RSA";
 pub const PROVIDER: &str = "AndroidKeyStore";
 pub const PURPOSE_ENCRYPT: i32 = 1;
 pub const PURPOSE_DECRYPT: i32 = 2;
+pub const PURPOSE_SIGN: i32 = 4;
+pub const PURPOSE_VERIFY: i32 = 8;
+pub const PURPOSE_WRAP_KEY: i32 = 32;
+pub const PURPOSE_ATTEST_KEY: i32 = 128;
+pub const PURPOSE_AGREE_KEY: i32 = 256;
+pub const SECURITY_LEVEL_UNKNOWN_SECURE: i32 = -1;
+pub const SECURITY_LEVEL_UNKNOWN: i32 = -2;
+pub const SECURITY_LEVEL_SOFTWARE: i32 = 0;
+pub const SECURITY_LEVEL_TRUSTED_ENVIRONMENT: i32 = 1;
+pub const SECURITY_LEVEL_STRONGBOX: i32 = 2;
+pub const PROVIDER_EXCEPTION: &str = "java/security/ProviderException";
+pub const NO_SUCH_PROVIDER_EXCEPTION: &str = "java/security/NoSuchProviderException";
+pub const NO_SUCH_ALGORITHM_EXCEPTION: &str = "java/security/NoSuchAlgorithmException";
+pub const KEY_STORE_EXCEPTION: &str = "java/security/KeyStoreException";
+#[cfg(feature = "legacy")]
+pub const INVALID_KEY_EXCEPTION: &str = "java/security/InvalidKeyException";
+pub const KEY_PERMANENTLY_INVALIDATED_EXCEPTION: &str =
+    "android/security/keystore/KeyPermanentlyInvalidatedException";
+pub const KEY_EXPIRED_EXCEPTION: &str = "android/security/keystore/KeyExpiredException";
+pub const KEY_NOT_YET_VALID_EXCEPTION: &str = "android/security/keystore/KeyNotYetValidException";
+pub const USER_NOT_AUTHENTICATED_EXCEPTION: &str =
+    "android/security/keystore/UserNotAuthenticatedException";
+pub const STRONGBOX_UNAVAILABLE_EXCEPTION: &str =
+    "android/security/keystore/StrongBoxUnavailableException";
+
+/// The purposes that keys of the given algorithm can be used for,
+/// or `None` if the algorithm isn't one this crate knows about.
+///
+/// Only RSA keys can have `PURPOSE_WRAP_KEY`, which is for the key that
+/// wraps others in a secure key import. An AES key that wraps keys with
+/// a `Cipher` in `WRAP_MODE` does so with `PURPOSE_ENCRYPT` and `PURPOSE_DECRYPT`.
+fn algorithm_purposes(algorithm: &str) -> Option<i32> {
+    match algorithm {
+        KEY_ALGORITHM_AES => Some(PURPOSE_ENCRYPT | PURPOSE_DECRYPT),
+        KEY_ALGORITHM_RSA => Some(
+            PURPOSE_ENCRYPT | PURPOSE_DECRYPT | PURPOSE_SIGN | PURPOSE_VERIFY | PURPOSE_WRAP_KEY,
+        ),
+        KEY_ALGORITHM_EC => {
+            Some(PURPOSE_SIGN | PURPOSE_VERIFY | PURPOSE_ATTEST_KEY | PURPOSE_AGREE_KEY)
+        }
+        KEY_ALGORITHM_HMAC_SHA256 => Some(PURPOSE_SIGN | PURPOSE_VERIFY),
+        _ => None,
+    }
+}
+
+/// Check that a key of the given algorithm can be made for the given
+/// `KeyProperties.PURPOSE_*` flags, before the keystore is asked to.
+///
+/// The keystore only rejects a key whose purposes don't suit its algorithm
+/// (for example, an AES key that signs) once it's generated or imported,
+/// with an exception that doesn't say what's wrong. So this is checked
+/// first, and fails with an [Invalid](keyring_core::Error::Invalid) error.
+pub fn check_purposes(algorithm: &str, purposes: i32) -> keyring_core::Result<()> {
+    let invalid = |msg: String| Err(keyring_core::Error::Invalid("purposes".to_string(), msg));
+    let Some(allowed) = algorithm_purposes(algorithm) else {
+        return invalid(format!("can't be checked for algorithm {algorithm:?}"));
+    };
+    if purposes == 0 {
+        return invalid("must include at least one purpose".to_string());
+    }
+    if purposes & !allowed != 0 {
+        return invalid(format!(
+            "{purposes:#x} includes purposes {:#x} that {algorithm} keys can't have",
+            purposes & !allowed
+        ));
+    }
+    Ok(())
+}
+
+/// The `KeyProperties.AUTH_*` flags for a key that the user can authenticate
+/// to use with a strong biometric, the device credential, or either of them.
+#[cfg(feature = "legacy")]
+pub fn key_auth_type(env: &mut JNIEnv, biometric: bool, device_credential: bool) -> JResult<i32> {
+    let class = ClassDecl("Landroid/security/keystore/KeyProperties;");
+    let mut flags = 0;
+    if biometric {
+        flags |= class.get_static_field::<i32>(env, "AUTH_BIOMETRIC_STRONG")?;
+    }
+    if device_credential {
+        flags |= class.get_static_field::<i32>(env, "AUTH_DEVICE_CREDENTIAL")?;
+    }
+    Ok(flags)
+}
 
 pub struct KeyStore {
     self_: GlobalRef,
@@ -36,7 +126,7 @@ impl KeyStore {
         ClassDecl("Ljava/security/KeyStore;")
     }
 
-    pub fn get_instance(env: &mut JNIEnv<'_>, store: &str) -> JResult<KeyStore> {
+    pub fn get_instance(env: &mut JNIEnv<'_>, store: &str) -> AndroidKeyringResult<KeyStore> {
         struct ThisMethod<'a>(PhantomData<&'a ()>);
         impl<'a> StaticMethod for ThisMethod<'a> {
             type Param = &'a str;
@@ -45,7 +135,8 @@ impl KeyStore {
             const NAME: &'static str = "getInstance";
         }
 
-        ThisMethod::call(Self::class(), env, store)
+        let result = ThisMethod::call(Self::class(), env, store);
+        check_provider(env, result)
     }
 
     pub fn load(&self, env: &mut JNIEnv<'_>) -> JResult<()> {
@@ -95,6 +186,26 @@ impl KeyStore {
         ThisMethod::call(&self.self_, env, (alias, None))
     }
 
+    /// Import a secret key under the given alias, with the given protection.
+    pub fn set_entry(
+        &self,
+        env: &mut JNIEnv<'_>,
+        alias: &str,
+        secret_key: &Key,
+        protection: KeyProtection,
+    ) -> JResult<()> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, SecretKeyEntry, KeyProtection);
+            type Return = ();
+
+            const NAME: &'static str = "setEntry";
+        }
+
+        let entry = SecretKeyEntry::new(env, secret_key)?;
+        ThisMethod::call(&self.self_, env, (alias, entry, protection))
+    }
+
     pub fn delete_entry(&self, env: &mut JNIEnv<'_>, alias: &str) -> JResult<()> {
         struct ThisMethod<'a>(PhantomData<&'a ()>);
         impl<'a> Method for ThisMethod<'a> {
@@ -106,9 +217,147 @@ impl KeyStore {
 
         ThisMethod::call(&self.self_, env, alias)
     }
+
+    /// Get the DER-encoded certificate chain of the entry with the given alias.
+    ///
+    /// The chain is empty if there is no such entry, or if the entry is
+    /// a secret key (which has no certificates).
+    pub fn get_certificate_chain(
+        &self,
+        env: &mut JNIEnv<'_>,
+        alias: &str,
+    ) -> JResult<Vec<Vec<u8>>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = Option<CertificateChain>;
+
+            const NAME: &'static str = "getCertificateChain";
+        }
+
+        let chain = ThisMethod::call(&self.self_, env, alias)?;
+        Ok(chain.map(|chain| chain.0).unwrap_or_default())
+    }
+
+    pub fn aliases(&self, env: &mut JNIEnv<'_>) -> JResult<Vec<String>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Enumeration;
+
+            const NAME: &str = "aliases";
+        }
+
+        let aliases = ThisMethod::call(&self.self_, env, NoParam)?;
+        let mut result = Vec::new();
+        while aliases.has_more_elements(env)? {
+            let EnumerationElement(alias) = aliases.next_element(env)?;
+            result.push(alias);
+        }
+        Ok(result)
+    }
+}
+
+struct Enumeration {
+    self_: GlobalRef,
+}
+
+impl FromValue for Enumeration {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl Enumeration {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/util/Enumeration;")
+    }
+
+    fn has_more_elements(&self, env: &mut JNIEnv<'_>) -> JResult<bool> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = bool;
+
+            const NAME: &str = "hasMoreElements";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    fn next_element(&self, env: &mut JNIEnv<'_>) -> JResult<EnumerationElement> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = EnumerationElement;
+
+            const NAME: &str = "nextElement";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+/// The elements of a keystore's alias enumeration are strings,
+/// but `nextElement` is declared as returning an `Object`.
+struct EnumerationElement(String);
+
+impl FromValue for EnumerationElement {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/lang/Object;").into()
+    }
+
+    fn from_object(value: GlobalRef, env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self(String::from_object(value, env)?))
+    }
+}
+
+/// A `Certificate[]`, read as the DER encoding of each certificate.
+struct CertificateChain(Vec<Vec<u8>>);
+
+impl FromValue for CertificateChain {
+    fn signature() -> SignatureComp {
+        ClassDecl("[Ljava/security/cert/Certificate;").into()
+    }
+
+    fn from_object(value: GlobalRef, env: &mut JNIEnv) -> JResult<Self> {
+        let array: &JObjectArray = value.as_obj().into();
+        let len = env.get_array_length(array)?;
+        let mut chain = Vec::with_capacity(len as usize);
+        for i in 0..len {
+            let element = env.get_object_array_element(array, i)?;
+            let certificate = Certificate {
+                self_: env.new_global_ref(element)?,
+            };
+            chain.push(certificate.get_encoded(env)?);
+        }
+        Ok(Self(chain))
+    }
+}
+
+struct Certificate {
+    self_: GlobalRef,
+}
+
+impl Certificate {
+    fn get_encoded(&self, env: &mut JNIEnv) -> JResult<Vec<u8>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Vec<u8>;
+
+            const NAME: &str = "getEncoded";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Key {
     self_: GlobalRef,
 }
@@ -137,6 +386,12 @@ impl Key {
     fn class() -> ClassDecl {
         ClassDecl("Ljava/security/Key;")
     }
+
+    /// Get the [KeyInfo] for an AES key held in the Android KeyStore.
+    pub fn get_key_info(&self, env: &mut JNIEnv) -> JResult<KeyInfo> {
+        let factory = SecretKeyFactory::get_instance(env, KEY_ALGORITHM_AES, PROVIDER)?;
+        factory.get_key_info(env, self)
+    }
 }
 
 pub struct SecretKey {
@@ -165,11 +420,12 @@ impl From<SecretKey> for Key {
     }
 }
 
-pub struct KeyGenerator {
+/// A secret key built from raw key material.
+pub struct SecretKeySpec {
     self_: GlobalRef,
 }
 
-impl FromValue for KeyGenerator {
+impl FromValue for SecretKeySpec {
     fn signature() -> SignatureComp {
         Self::class().into()
     }
@@ -179,53 +435,86 @@ impl FromValue for KeyGenerator {
     }
 }
 
-impl KeyGenerator {
+impl SecretKeySpec {
     fn class() -> ClassDecl {
-        ClassDecl("Ljavax/crypto/KeyGenerator;")
+        ClassDecl("Ljavax/crypto/spec/SecretKeySpec;")
     }
 
-    pub fn get_instance(env: &mut JNIEnv, algorithm: &str, provider: &str) -> JResult<Self> {
+    pub fn new(env: &mut JNIEnv, key: &[u8], algorithm: &str) -> JResult<Self> {
         struct ThisMethod<'a>(PhantomData<&'a ()>);
-        impl<'a> StaticMethod for ThisMethod<'a> {
-            type Param = (&'a str, &'a str);
-            type Return = KeyGenerator;
-
-            const NAME: &'static str = "getInstance";
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = (&'a [u8], &'a str);
+            type Return = SecretKeySpec;
         }
 
-        ThisMethod::call(Self::class(), env, (algorithm, provider))
+        ThisMethod::call_new(Self::class(), env, (key, algorithm))
     }
+}
 
-    pub fn init(&self, env: &mut JNIEnv, spec: AlgorithmParameterSpec) -> JResult<()> {
-        struct ThisMethod;
-        impl Method for ThisMethod {
-            type Param = AlgorithmParameterSpec;
-            type Return = ();
+impl From<SecretKeySpec> for Key {
+    fn from(value: SecretKeySpec) -> Self {
+        Key { self_: value.self_ }
+    }
+}
 
-            const NAME: &str = "init";
-        }
+struct SecretKeyEntry {
+    self_: GlobalRef,
+}
 
-        ThisMethod::call(&self.self_, env, spec)
+impl FromValue for SecretKeyEntry {
+    fn signature() -> SignatureComp {
+        Self::class().into()
     }
 
-    pub fn generate_key(&self, env: &mut JNIEnv) -> JResult<SecretKey> {
-        struct ThisMethod;
-        impl Method for ThisMethod {
-            type Param = NoParam;
-            type Return = SecretKey;
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
 
-            const NAME: &str = "generateKey";
+impl ToValue for SecretKeyEntry {
+    fn signature() -> SignatureComp {
+        // `KeyStore.setEntry` takes any `KeyStore.Entry`
+        ClassDecl("Ljava/security/KeyStore$Entry;").into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl SecretKeyEntry {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/KeyStore$SecretKeyEntry;")
+    }
+
+    fn new(env: &mut JNIEnv, secret_key: &Key) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = AsSecretKey<'a>;
+            type Return = SecretKeyEntry;
         }
 
-        ThisMethod::call(&self.self_, env, NoParam)
+        ThisMethod::call_new(Self::class(), env, AsSecretKey(secret_key))
     }
 }
 
-pub struct KeyGenParameterSpecBuilder {
+struct AsSecretKey<'a>(&'a Key);
+
+impl ToValue for AsSecretKey<'_> {
+    fn signature() -> SignatureComp {
+        SecretKey::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.0.self_)?.into())
+    }
+}
+
+pub struct SecretKeyFactory {
     self_: GlobalRef,
 }
 
-impl FromValue for KeyGenParameterSpecBuilder {
+impl FromValue for SecretKeyFactory {
     fn signature() -> SignatureComp {
         Self::class().into()
     }
@@ -235,81 +524,800 @@ impl FromValue for KeyGenParameterSpecBuilder {
     }
 }
 
-impl KeyGenParameterSpecBuilder {
+impl SecretKeyFactory {
     fn class() -> ClassDecl {
-        ClassDecl("Landroid/security/keystore/KeyGenParameterSpec$Builder;")
+        ClassDecl("Ljavax/crypto/SecretKeyFactory;")
     }
-}
 
-impl KeyGenParameterSpecBuilder {
-    pub fn new(env: &mut JNIEnv, alias: &str, purpose: i32) -> JResult<Self> {
+    pub fn get_instance(env: &mut JNIEnv, algorithm: &str, provider: &str) -> JResult<Self> {
         struct ThisMethod<'a>(PhantomData<&'a ()>);
-        impl<'a> Constructible for ThisMethod<'a> {
-            type Param = (&'a str, i32);
-            type Return = KeyGenParameterSpecBuilder;
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = (&'a str, &'a str);
+            type Return = SecretKeyFactory;
+
+            const NAME: &'static str = "getInstance";
         }
 
-        ThisMethod::call_new(Self::class(), env, (alias, purpose))
+        ThisMethod::call(Self::class(), env, (algorithm, provider))
     }
 
-    pub fn set_block_modes(
-        &self,
-        env: &mut JNIEnv,
-        modes: &[&str],
-    ) -> JResult<KeyGenParameterSpecBuilder> {
+    /// Get the [KeyInfo] for a key held in the Android KeyStore.
+    pub fn get_key_info(&self, env: &mut JNIEnv, key: &Key) -> JResult<KeyInfo> {
+        struct KeyInfoClass;
+        impl ToValue for KeyInfoClass {
+            fn signature() -> SignatureComp {
+                ClassDecl("Ljava/lang/Class;").into()
+            }
+
+            fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+                let class: JObject = env.find_class("android/security/keystore/KeyInfo")?.into();
+                Ok(class.into())
+            }
+        }
+
         struct ThisMethod<'a>(PhantomData<&'a ()>);
         impl<'a> Method for ThisMethod<'a> {
-            type Param = &'a [&'a str];
-            type Return = KeyGenParameterSpecBuilder;
+            type Param = (AsSecretKey<'a>, KeyInfoClass);
+            type Return = KeyInfo;
 
-            const NAME: &'static str = "setBlockModes";
+            const NAME: &'static str = "getKeySpec";
         }
 
-        ThisMethod::call(&self.self_, env, modes)
+        ThisMethod::call(&self.self_, env, (AsSecretKey(key), KeyInfoClass))
     }
+}
 
-    pub fn set_encryption_paddings(
-        &self,
-        env: &mut JNIEnv,
-        modes: &[&str],
-    ) -> JResult<KeyGenParameterSpecBuilder> {
-        struct ThisMethod<'a>(PhantomData<&'a ()>);
-        impl<'a> Method for ThisMethod<'a> {
-            type Param = &'a [&'a str];
-            type Return = KeyGenParameterSpecBuilder;
+/// Information about a key held in the Android KeyStore.
+pub struct KeyInfo {
+    self_: GlobalRef,
+}
 
-            const NAME: &'static str = "setEncryptionPaddings";
+impl FromValue for KeyInfo {
+    fn signature() -> SignatureComp {
+        // `SecretKeyFactory.getKeySpec` is declared to return any `KeySpec`
+        ClassDecl("Ljava/security/spec/KeySpec;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyInfo {
+    pub fn is_inside_secure_hardware(&self, env: &mut JNIEnv) -> JResult<bool> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = bool;
+
+            const NAME: &str = "isInsideSecureHardware";
         }
 
-        ThisMethod::call(&self.self_, env, modes)
+        ThisMethod::call(&self.self_, env, NoParam)
     }
 
-    pub fn set_user_authentication_required(
+    pub fn is_user_authentication_required(&self, env: &mut JNIEnv) -> JResult<bool> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = bool;
+
+            const NAME: &str = "isUserAuthenticationRequired";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    /// The size of the key in bits.
+    pub fn get_key_size(&self, env: &mut JNIEnv) -> JResult<i32> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = i32;
+
+            const NAME: &str = "getKeySize";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    /// How long the key can be used after the user authenticates, in seconds.
+    ///
+    /// If this is 0 or less, the user must authenticate each use of the key.
+    pub fn get_user_authentication_validity_duration_seconds(
         &self,
         env: &mut JNIEnv,
-        required: bool,
-    ) -> JResult<KeyGenParameterSpecBuilder> {
+    ) -> JResult<i32> {
         struct ThisMethod;
         impl Method for ThisMethod {
-            type Param = bool;
-            type Return = KeyGenParameterSpecBuilder;
+            type Param = NoParam;
+            type Return = i32;
 
-            const NAME: &str = "setUserAuthenticationRequired";
+            const NAME: &str = "getUserAuthenticationValidityDurationSeconds";
         }
 
-        ThisMethod::call(&self.self_, env, required)
+        ThisMethod::call(&self.self_, env, NoParam)
     }
 
-    pub fn build(&self, env: &mut JNIEnv) -> JResult<KeyGenParameterSpec> {
+    /// The security level of the key, one of the `SECURITY_LEVEL_*` constants.
+    ///
+    /// This was added in API 31, so on earlier devices the answer is `None`.
+    pub fn get_security_level(&self, env: &mut JNIEnv) -> JResult<Option<i32>> {
         struct ThisMethod;
         impl Method for ThisMethod {
             type Param = NoParam;
-            type Return = KeyGenParameterSpec;
+            type Return = i32;
 
-            const NAME: &str = "build";
+            const NAME: &str = "getSecurityLevel";
         }
 
-        ThisMethod::call(&self.self_, env, NoParam)
+        match ThisMethod::call(&self.self_, env, NoParam) {
+            Ok(level) => Ok(Some(level)),
+            Err(_) if pending_exception_is(env, "java/lang/NoSuchMethodError") => {
+                env.exception_clear()?;
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+pub struct KeyPairGenerator {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyPairGenerator {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyPairGenerator {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/KeyPairGenerator;")
+    }
+
+    pub fn get_instance(
+        env: &mut JNIEnv,
+        algorithm: &str,
+        provider: &str,
+    ) -> AndroidKeyringResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = (&'a str, &'a str);
+            type Return = KeyPairGenerator;
+
+            const NAME: &'static str = "getInstance";
+        }
+
+        let result = ThisMethod::call(Self::class(), env, (algorithm, provider));
+        check_provider(env, result)
+    }
+
+    pub fn initialize(&self, env: &mut JNIEnv, spec: AlgorithmParameterSpec) -> JResult<()> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = AlgorithmParameterSpec;
+            type Return = ();
+
+            const NAME: &str = "initialize";
+        }
+
+        ThisMethod::call(&self.self_, env, spec)
+    }
+
+    /// Generate a key pair in the Android KeyStore.
+    ///
+    /// The private key stays in the KeyStore, where it's looked up by alias.
+    pub fn generate_key_pair(&self, env: &mut JNIEnv) -> JResult<KeyPair> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = KeyPair;
+
+            const NAME: &str = "generateKeyPair";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+pub struct KeyPair {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyPair {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/security/KeyPair;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyPair {
+    pub fn get_public(&self, env: &mut JNIEnv) -> JResult<PublicKey> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = PublicKey;
+
+            const NAME: &str = "getPublic";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+pub struct PublicKey {
+    self_: GlobalRef,
+}
+
+impl FromValue for PublicKey {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/security/PublicKey;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl PublicKey {
+    /// The key's X.509 (SubjectPublicKeyInfo) encoding.
+    pub fn get_encoded(&self, env: &mut JNIEnv) -> JResult<Vec<u8>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Vec<u8>;
+
+            const NAME: &str = "getEncoded";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+pub struct KeyGenerator {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyGenerator {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyGenerator {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljavax/crypto/KeyGenerator;")
+    }
+
+    pub fn get_instance(
+        env: &mut JNIEnv,
+        algorithm: &str,
+        provider: &str,
+    ) -> AndroidKeyringResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = (&'a str, &'a str);
+            type Return = KeyGenerator;
+
+            const NAME: &'static str = "getInstance";
+        }
+
+        let result = ThisMethod::call(Self::class(), env, (algorithm, provider));
+        check_provider(env, result)
+    }
+
+    pub fn init(&self, env: &mut JNIEnv, spec: AlgorithmParameterSpec) -> JResult<()> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = AlgorithmParameterSpec;
+            type Return = ();
+
+            const NAME: &str = "init";
+        }
+
+        ThisMethod::call(&self.self_, env, spec)
+    }
+
+    pub fn generate_key(&self, env: &mut JNIEnv) -> JResult<SecretKey> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = SecretKey;
+
+            const NAME: &str = "generateKey";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+pub struct KeyGenParameterSpecBuilder {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyGenParameterSpecBuilder {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyGenParameterSpecBuilder {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/security/keystore/KeyGenParameterSpec$Builder;")
+    }
+}
+
+impl KeyGenParameterSpecBuilder {
+    /// Start the spec of a key of the given algorithm, after checking that
+    /// it can have the given purposes (see [check_purposes]).
+    pub fn new(
+        env: &mut JNIEnv,
+        algorithm: &str,
+        alias: &str,
+        purpose: i32,
+    ) -> AndroidKeyringResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = (&'a str, i32);
+            type Return = KeyGenParameterSpecBuilder;
+        }
+
+        check_purposes(algorithm, purpose)?;
+        Ok(ThisMethod::call_new(Self::class(), env, (alias, purpose))?)
+    }
+
+    pub fn set_block_modes(
+        &self,
+        env: &mut JNIEnv,
+        modes: &[&str],
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [&'a str];
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &'static str = "setBlockModes";
+        }
+
+        ThisMethod::call(&self.self_, env, modes)
+    }
+
+    pub fn set_digests(
+        &self,
+        env: &mut JNIEnv,
+        digests: &[&str],
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [&'a str];
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &'static str = "setDigests";
+        }
+
+        ThisMethod::call(&self.self_, env, digests)
+    }
+
+    /// Request an attestation certificate chain for the generated key pair,
+    /// with the given challenge embedded in the leaf certificate.
+    pub fn set_attestation_challenge(
+        &self,
+        env: &mut JNIEnv,
+        challenge: &[u8],
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [u8];
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &'static str = "setAttestationChallenge";
+        }
+
+        ThisMethod::call(&self.self_, env, challenge)
+    }
+
+    pub fn set_encryption_paddings(
+        &self,
+        env: &mut JNIEnv,
+        modes: &[&str],
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [&'a str];
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &'static str = "setEncryptionPaddings";
+        }
+
+        ThisMethod::call(&self.self_, env, modes)
+    }
+
+    pub fn set_key_size(&self, env: &mut JNIEnv, bits: i32) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = i32;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setKeySize";
+        }
+
+        ThisMethod::call(&self.self_, env, bits)
+    }
+
+    pub fn set_randomized_encryption_required(
+        &self,
+        env: &mut JNIEnv,
+        required: bool,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = bool;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setRandomizedEncryptionRequired";
+        }
+
+        ThisMethod::call(&self.self_, env, required)
+    }
+
+    pub fn set_unlocked_device_required(
+        &self,
+        env: &mut JNIEnv,
+        required: bool,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = bool;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setUnlockedDeviceRequired";
+        }
+
+        ThisMethod::call(&self.self_, env, required)
+    }
+
+    pub fn set_is_strong_box_backed(
+        &self,
+        env: &mut JNIEnv,
+        strongbox: bool,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = bool;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setIsStrongBoxBacked";
+        }
+
+        ThisMethod::call(&self.self_, env, strongbox)
+    }
+
+    #[cfg(feature = "compile-tests")]
+    pub fn set_invalidated_by_biometric_enrollment(
+        &self,
+        env: &mut JNIEnv,
+        invalidate: bool,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = bool;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setInvalidatedByBiometricEnrollment";
+        }
+
+        ThisMethod::call(&self.self_, env, invalidate)
+    }
+
+    pub fn set_key_validity_start(
+        &self,
+        env: &mut JNIEnv,
+        date_millis: i64,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = Date;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setKeyValidityStart";
+        }
+
+        let date = Date::new(env, date_millis)?;
+        ThisMethod::call(&self.self_, env, date)
+    }
+
+    pub fn set_key_validity_end(
+        &self,
+        env: &mut JNIEnv,
+        date_millis: i64,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = Date;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setKeyValidityEnd";
+        }
+
+        let date = Date::new(env, date_millis)?;
+        ThisMethod::call(&self.self_, env, date)
+    }
+
+    pub fn set_user_authentication_required(
+        &self,
+        env: &mut JNIEnv,
+        required: bool,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = bool;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setUserAuthenticationRequired";
+        }
+
+        ThisMethod::call(&self.self_, env, required)
+    }
+
+    #[cfg(any(feature = "legacy", feature = "compile-tests"))]
+    pub fn set_user_authentication_validity_duration_seconds(
+        &self,
+        env: &mut JNIEnv,
+        seconds: i32,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = i32;
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setUserAuthenticationValidityDurationSeconds";
+        }
+
+        ThisMethod::call(&self.self_, env, seconds)
+    }
+
+    /// Available from API level 30, which replaced
+    /// [set_user_authentication_validity_duration_seconds](Self::set_user_authentication_validity_duration_seconds).
+    #[cfg(feature = "legacy")]
+    pub fn set_user_authentication_parameters(
+        &self,
+        env: &mut JNIEnv,
+        timeout_seconds: i32,
+        auth_type: i32,
+    ) -> JResult<KeyGenParameterSpecBuilder> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = (i32, i32);
+            type Return = KeyGenParameterSpecBuilder;
+
+            const NAME: &str = "setUserAuthenticationParameters";
+        }
+
+        ThisMethod::call(&self.self_, env, (timeout_seconds, auth_type))
+    }
+
+    pub fn build(&self, env: &mut JNIEnv) -> JResult<KeyGenParameterSpec> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = KeyGenParameterSpec;
+
+            const NAME: &str = "build";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+pub struct Date {
+    self_: GlobalRef,
+}
+
+impl FromValue for Date {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for Date {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl Date {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/util/Date;")
+    }
+
+    /// A date given as milliseconds since the Unix epoch.
+    pub fn new(env: &mut JNIEnv, millis: i64) -> JResult<Self> {
+        struct ThisMethod;
+        impl Constructible for ThisMethod {
+            type Param = i64;
+            type Return = Date;
+        }
+
+        ThisMethod::call_new(Self::class(), env, millis)
+    }
+}
+
+pub struct KeyProtectionBuilder {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyProtectionBuilder {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyProtectionBuilder {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/security/keystore/KeyProtection$Builder;")
+    }
+
+    /// Start the protection of a key of the given algorithm, after checking
+    /// that it can have the given purposes (see [check_purposes]).
+    pub fn new(env: &mut JNIEnv, algorithm: &str, purpose: i32) -> AndroidKeyringResult<Self> {
+        struct ThisMethod;
+        impl Constructible for ThisMethod {
+            type Param = i32;
+            type Return = KeyProtectionBuilder;
+        }
+
+        check_purposes(algorithm, purpose)?;
+        Ok(ThisMethod::call_new(Self::class(), env, purpose)?)
+    }
+
+    pub fn set_block_modes(&self, env: &mut JNIEnv, modes: &[&str]) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [&'a str];
+            type Return = KeyProtectionBuilder;
+
+            const NAME: &'static str = "setBlockModes";
+        }
+
+        ThisMethod::call(&self.self_, env, modes)
+    }
+
+    pub fn set_encryption_paddings(&self, env: &mut JNIEnv, modes: &[&str]) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [&'a str];
+            type Return = KeyProtectionBuilder;
+
+            const NAME: &'static str = "setEncryptionPaddings";
+        }
+
+        ThisMethod::call(&self.self_, env, modes)
+    }
+
+    pub fn set_randomized_encryption_required(
+        &self,
+        env: &mut JNIEnv,
+        required: bool,
+    ) -> JResult<Self> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = bool;
+            type Return = KeyProtectionBuilder;
+
+            const NAME: &str = "setRandomizedEncryptionRequired";
+        }
+
+        ThisMethod::call(&self.self_, env, required)
+    }
+
+    pub fn set_unlocked_device_required(&self, env: &mut JNIEnv, required: bool) -> JResult<Self> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = bool;
+            type Return = KeyProtectionBuilder;
+
+            const NAME: &str = "setUnlockedDeviceRequired";
+        }
+
+        ThisMethod::call(&self.self_, env, required)
+    }
+
+    pub fn set_key_validity_start(&self, env: &mut JNIEnv, date_millis: i64) -> JResult<Self> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = Date;
+            type Return = KeyProtectionBuilder;
+
+            const NAME: &str = "setKeyValidityStart";
+        }
+
+        let date = Date::new(env, date_millis)?;
+        ThisMethod::call(&self.self_, env, date)
+    }
+
+    pub fn set_key_validity_end(&self, env: &mut JNIEnv, date_millis: i64) -> JResult<Self> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = Date;
+            type Return = KeyProtectionBuilder;
+
+            const NAME: &str = "setKeyValidityEnd";
+        }
+
+        let date = Date::new(env, date_millis)?;
+        ThisMethod::call(&self.self_, env, date)
+    }
+
+    pub fn build(&self, env: &mut JNIEnv) -> JResult<KeyProtection> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = KeyProtection;
+
+            const NAME: &str = "build";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+pub struct KeyProtection {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyProtection {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for KeyProtection {
+    fn signature() -> SignatureComp {
+        // `KeyStore.setEntry` takes any `KeyStore.ProtectionParameter`
+        ClassDecl("Ljava/security/KeyStore$ProtectionParameter;").into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl KeyProtection {
+    fn class() -> ClassDecl {
+        ClassDecl("Landroid/security/keystore/KeyProtection;")
     }
 }
 
@@ -18,13 +18,83 @@ or provide any attributes on credentials.
 
 Earlier versions of this crate provided a single store that used one SharedPreferences
 file and Keystore entry _per service name_, rather than _per store name_. This
-legacy implementation, found in the [by_service] module, does not support search and leaves
+legacy implementation, found in the [by_service] module, only supports limited search and leaves
 keys behind even when all of their associated credentials are deleted. It is still
 available under the `legacy` feature flag via the [LegacyStore::from_ndk_context]
 constructor, but it is deprecated and may be removed in future versions of the crate. All
 client applications are advised to migrate any existing credentials from legacy storage to
 a named store. See the [Migration Guide](by_service#migration-guide) for details.
 
+# Device-to-Device Transfer
+
+Under the `transfer` feature flag, credentials can be moved from one device
+to another without their secrets being stored in the clear along the way.
+See the [transfer] module for details.
+
+# Backups
+
+Under the `backup` feature flag, all the credentials of a service in a named
+store can be exported to a blob encrypted under a passphrase, which can be
+opened off the device. See the [backup] module for details.
+
+# Sharing with Sibling Apps
+
+Under the `content-provider` feature flag, an application can share the
+credentials of selected services with sibling apps signed by the same
+developer, through a `ContentProvider` guarded by a signature-level
+permission. This needs a small Kotlin shim and some manifest entries in
+the application; see the [content_provider] module.
+
+# Change Notifications
+
+Applications can be notified when a store's file changes out from under
+them, for example because another component wrote a credential. This needs
+a small Kotlin shim in the application; see the [change_listener] module.
+
+# Encrypted SharedPreferences
+
+Under the `encrypted-preferences` feature flag, a named store can be backed
+by an AndroidX `EncryptedSharedPreferences` file instead, which encrypts
+credential IDs as well as secrets. See [Encrypted Files](by_store#encrypted-files)
+for details.
+
+# Biometric Prompts
+
+Under the `biometric` feature flag, the secrets of the legacy store's
+protected credentials can be read after prompting the user to authenticate
+with an AndroidX `BiometricPrompt`. This needs a small Kotlin shim in the
+application; see the [biometric] module.
+
+# Testing Off-Device
+
+Under the `mock` feature flag, an in-memory store that behaves like a
+named store can be used to unit-test an application's keyring integration
+on a development host. See the [mock] module.
+
+Under the `test-fixtures` feature flag, on-device tests can import a known
+key and encrypt with a known IV, so that the values they write can be
+compared with golden files. See the [test_fixtures] module.
+
+# Logging
+
+Under the `android-log` feature flag, this crate's `tracing` events can be
+sent to logcat, either from Kotlin or from Rust. See the [android_log] module.
+
+# IV Reuse Checks
+
+Under the `iv-check` feature flag, every secret written with GCM has its
+IV checked against the last ones used with the same key, and the write
+fails if one is repeated. The cipher always picks a fresh random IV, so
+this is a debugging aid that shouldn't be enabled in production builds.
+
+# Secrets in Memory
+
+This crate wipes the copies of secrets that it makes in Rust memory
+once it's done with them, and [Cred::get_secret_zeroizing] returns a
+secret in a buffer that is wiped when dropped. But encryption and decryption
+happen in Java, and the Java arrays that hold secrets along the way are
+managed by the garbage collector, so they can't be wiped from Rust.
+
 ## Application Requirements
 
 This crate compiles to produce a native library that can be loaded into an Android
@@ -35,6 +105,11 @@ crate before they can create credential stores. The
 [README](https://github.com/open-source-cooperative/android-native-keyring-store) for this
 crate provides detailed instructions for how to do this.
 
+If the context given to `ndk-context` is an Activity rather than the application
+context, stores keep that Activity's application context instead, so they don't
+keep the Activity alive. See the `application_context` configuration option of
+[Store::new_with_configuration] and [LegacyStore::new_keeping_context] to opt out.
+
  */
 
 use std::ffi::c_void;
@@ -48,6 +123,7 @@ use jni::{
 pub mod by_store;
 pub use by_store::Cred;
 pub use by_store::Store;
+pub use by_store::{Capabilities, KeySecurityInfo, ProtectionInfo, RawValue, SecurityLevel};
 
 #[cfg(feature = "legacy")]
 pub mod by_service;
@@ -57,13 +133,36 @@ pub use by_service::Cred as LegacyCred;
 pub use by_service::Store as LegacyStore;
 
 #[cfg(feature = "android-log")]
-mod android_log;
+pub mod android_log;
+mod backoff;
+#[cfg(feature = "backup")]
+pub mod backup;
+#[cfg(feature = "biometric")]
+pub mod biometric;
+pub mod change_listener;
 mod cipher;
+#[cfg(feature = "content-provider")]
+pub mod content_provider;
 mod crypto;
+pub use crypto::{EnvelopeFormat, Transformation};
+#[cfg(feature = "encrypted-preferences")]
+mod encrypted_preferences;
 mod error;
+pub use error::{AndroidKeyringError, CorruptedData};
+pub use zeroize::Zeroizing;
+mod keyguard;
 mod keystore;
+#[cfg(any(feature = "android-log", test))]
+mod log_format;
 mod methods;
+pub use methods::sdk_int;
+#[cfg(feature = "mock")]
+pub mod mock;
 mod shared_preferences;
+#[cfg(feature = "test-fixtures")]
+pub mod test_fixtures;
+#[cfg(feature = "transfer")]
+pub mod transfer;
 
 #[cfg(feature = "compile-tests")]
 pub mod tests;
@@ -0,0 +1,265 @@
+//! Formatting of `tracing` events for logcat, with secrets redacted.
+//!
+//! This is kept apart from the `android_log` module,
+//! which links against the platform's log library, so it can be tested on the host.
+
+use std::{fmt::Write, sync::LazyLock};
+
+use regex::{Captures, Regex};
+use tracing::{Level, Subscriber, span};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The level and target of an event. Those of a `log` record that was
+/// forwarded as an event are the record's own, rather than the forwarder's.
+pub(crate) fn level_and_target(event: &tracing::Event<'_>) -> (Level, String) {
+    let normalized = event.normalized_metadata();
+    let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+    (*metadata.level(), metadata.target().to_string())
+}
+
+pub(crate) struct SpanPrefix {
+    name: String,
+    values: String,
+}
+impl SpanPrefix {
+    pub fn on_new_span<S>(
+        attrs: &span::Attributes<'_>,
+        id: &span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanPrefix>() {
+            Some(prefix) => {
+                prefix.name = attrs.metadata().name().to_string();
+                attrs.record(&mut prefix.visit());
+            }
+            None => {
+                let mut prefix = SpanPrefix::new(span.name().to_string());
+                attrs.record(&mut prefix.visit());
+                extensions.insert(prefix)
+            }
+        }
+    }
+
+    pub fn on_record<S>(
+        id: &span::Id,
+        values: &span::Record<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanPrefix>() {
+            Some(prefix) => {
+                values.record(&mut prefix.visit());
+            }
+            None => {
+                let mut prefix = SpanPrefix::new(span.name().to_string());
+                values.record(&mut prefix.visit());
+                extensions.insert(prefix)
+            }
+        }
+    }
+
+    pub fn on_event<S>(
+        event: &tracing::Event<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) -> String
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut message = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(prefix) = span.extensions().get::<SpanPrefix>() {
+                    write!(message, "{prefix}").unwrap();
+                }
+            }
+        };
+        if !message.is_empty() {
+            write!(message, " ").unwrap();
+        }
+        write!(message, "{}:", level_and_target(event).1).unwrap();
+
+        event.record(&mut Visitor(&mut message));
+        message
+    }
+
+    fn new(name: String) -> Self {
+        SpanPrefix {
+            name,
+            values: Default::default(),
+        }
+    }
+
+    fn visit(&mut self) -> impl tracing::field::Visit + '_ {
+        Visitor(&mut self.values)
+    }
+}
+impl std::fmt::Display for SpanPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = &self.name;
+        let values = &self.values;
+
+        match values.is_empty() {
+            true => write!(f, "{name}:"),
+            false => write!(f, "{name}{{{values}}}:"),
+        }
+    }
+}
+
+/// Fields whose values are never logged, matched by part of their names.
+const REDACTED_FIELDS: [&str; 4] = ["secret", "password", "value", "b64"];
+
+/// Debug output of bytes, such as the data in a corrupted data error.
+static BYTES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\d{1,3}(, \d{1,3})*\]").unwrap());
+
+fn redacted(len: usize) -> String {
+    format!("<redacted len={len}>")
+}
+
+/// Mask any bytes in debug output, keeping only their count.
+fn redact_bytes(debug: &str) -> String {
+    BYTES
+        .replace_all(debug, |bytes: &Captures| {
+            redacted(bytes[0].matches(',').count() + 1)
+        })
+        .into_owned()
+}
+
+struct Visitor<'a>(&'a mut String);
+impl Visitor<'_> {
+    /// Write the field's name, and whether its value must be redacted.
+    ///
+    /// The fields that describe where a `log` record came from
+    /// are left out, and `None` is returned for them.
+    fn record_field(&mut self, field: &tracing::field::Field) -> Option<bool> {
+        if field.name().starts_with("log.") {
+            return None;
+        }
+        if !self.0.is_empty() {
+            write!(self.0, " ").unwrap();
+        }
+
+        if field.as_ref() == "message" {
+            return Some(false);
+        }
+        write!(self.0, "{field}=").unwrap();
+        let name = field.name().to_ascii_lowercase();
+        Some(
+            REDACTED_FIELDS
+                .iter()
+                .any(|redacted| name.contains(redacted)),
+        )
+    }
+}
+impl tracing::field::Visit for Visitor<'_> {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        match self.record_field(field) {
+            Some(true) => write!(self.0, "{}", redacted(value.len())).unwrap(),
+            Some(false) => write!(self.0, "{value}").unwrap(),
+            None => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        let Some(redact) = self.record_field(field) else {
+            return;
+        };
+        let debug = format!("{value:?}");
+        let debug = match redact {
+            // a value that is all bytes is measured in bytes
+            true if BYTES.find(&debug).is_some_and(|m| m.len() == debug.len()) => {
+                redact_bytes(&debug)
+            }
+            true => redacted(debug.len()),
+            false => redact_bytes(&debug),
+        };
+        write!(self.0, "{debug}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::{Layer, Registry, layer::SubscriberExt};
+
+    use super::*;
+
+    /// Formats events the way the logcat layer does, but keeps them.
+    struct Capture(Arc<Mutex<Vec<(String, String)>>>);
+    impl<S> Layer<S> for Capture
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let (_, target) = level_and_target(event);
+            let message = SpanPrefix::on_event(event, ctx);
+            self.0.lock().unwrap().push((target, message));
+        }
+    }
+
+    /// The targets and messages of the events logged by `f`.
+    fn capture(f: impl FnOnce()) -> Vec<(String, String)> {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = Registry::default().with(Capture(messages.clone()));
+        tracing::subscriber::with_default(subscriber, f);
+        messages.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn redacts_secret_fields() {
+        let messages = capture(|| {
+            tracing::info!(password = "hunter2", user = "alice", "Logging in");
+            tracing::info!(secret = ?b"hunter2".to_vec(), "Setting");
+        });
+        let [(_, login), (_, setting)] = messages.as_slice() else {
+            panic!("Expected two messages, got {messages:?}");
+        };
+        assert!(login.contains("password=<redacted len=7>"), "{login}");
+        assert!(login.contains("user=alice"), "{login}");
+        assert!(!login.contains("hunter2"), "{login}");
+        assert!(setting.contains("secret=<redacted len=7>"), "{setting}");
+    }
+
+    #[test]
+    fn redacts_bytes() {
+        let messages = capture(|| {
+            tracing::info!(data = ?vec![1u8, 2, 3], "Corrupted");
+            tracing::info!("Corrupted data: {:?}", vec![1u8, 2, 3]);
+        });
+        let [(_, field), (_, message)] = messages.as_slice() else {
+            panic!("Expected two messages, got {messages:?}");
+        };
+        assert!(field.contains("data=<redacted len=3>"), "{field}");
+        assert!(
+            message.contains("Corrupted data: <redacted len=3>"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn redacts_log_records() {
+        _ = tracing_log::LogTracer::init();
+        let messages = capture(|| {
+            log::error!(target: "keyring::test", "Bad value {:?}", vec![4u8, 5]);
+        });
+        let [(target, message)] = messages.as_slice() else {
+            panic!("Expected one message, got {messages:?}");
+        };
+        assert_eq!(target, "keyring::test");
+        assert!(message.starts_with("keyring::test:"), "{message}");
+        assert!(message.contains("Bad value <redacted len=2>"), "{message}");
+        assert!(!message.contains("log."), "{message}");
+    }
+}
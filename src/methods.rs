@@ -1,8 +1,25 @@
 use jni::{
-    JNIEnv,
-    objects::{GlobalRef, JByteArray, JObject, JValue, JValueGen, ReleaseMode},
+    JNIEnv, JavaVM,
+    objects::{
+        GlobalRef, JByteArray, JClass, JObject, JValue, JValueGen, JValueOwned, ReleaseMode,
+    },
     signature::{Primitive, ReturnType},
 };
+use std::sync::OnceLock;
+
+/// The number of local references that a keyring operation reserves room for.
+///
+/// Threads stay attached to the JVM between operations, and a native thread
+/// never returns to Java to free its local references, so every operation
+/// runs in its own local frame. Frames grow as needed, so this is only a hint.
+pub const LOCAL_FRAME_CAPACITY: i32 = 16;
+
+/// Attach the current thread to the JVM for a keyring operation.
+///
+/// Attaching is expensive, so the thread stays attached until it exits.
+pub fn attach(vm: &JavaVM) -> JResult<JNIEnv<'_>> {
+    vm.attach_current_thread_permanently()
+}
 
 #[derive(Clone, Copy)]
 pub enum SignatureComp {
@@ -97,6 +114,72 @@ impl ClassDecl {
 
         &self.0[..(self.0.len() - 1)][1..]
     }
+
+    /// Read a static field of the class, such as a constant.
+    pub fn get_static_field<T: FromValue>(self, env: &mut JNIEnv, name: &str) -> JResult<T> {
+        let r = env.get_static_field(self.for_finding(), name, T::signature().as_str())?;
+        from_returned(env, r)
+    }
+}
+
+fn from_returned<R: FromValue>(env: &mut JNIEnv, r: JValueOwned) -> JResult<R> {
+    match r {
+        JValueGen::Object(obj) if obj.is_null() => Ok(FromValue::from_null()?),
+        JValueGen::Object(obj) => Ok(FromValue::from_object(env.new_global_ref(obj)?, env)?),
+        JValueGen::Byte(value) => Ok(FromValue::from_value(JValueGen::Byte(value))?),
+        JValueGen::Char(value) => Ok(FromValue::from_value(JValueGen::Char(value))?),
+        JValueGen::Short(value) => Ok(FromValue::from_value(JValueGen::Short(value))?),
+        JValueGen::Int(value) => Ok(FromValue::from_value(JValueGen::Int(value))?),
+        JValueGen::Long(value) => Ok(FromValue::from_value(JValueGen::Long(value))?),
+        JValueGen::Bool(value) => Ok(FromValue::from_value(JValueGen::Bool(value))?),
+        JValueGen::Float(value) => Ok(FromValue::from_value(JValueGen::Float(value))?),
+        JValueGen::Double(value) => Ok(FromValue::from_value(JValueGen::Double(value))?),
+        JValueGen::Void => Ok(FromValue::from_value(JValueGen::Void)?),
+    }
+}
+
+/// A class loaded through an application's class loader.
+///
+/// Classes that come from the application's dependencies (such as AndroidX)
+/// can't be found by name from threads attached by native code, so they
+/// must be loaded through the application's class loader and called this way.
+pub struct LoadedClass(pub GlobalRef);
+
+impl FromValue for LoadedClass {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/lang/Class;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self(self_))
+    }
+}
+
+impl LoadedClass {
+    fn as_class(&self) -> &JClass<'static> {
+        <&JClass>::from(self.0.as_obj())
+    }
+
+    /// Read a static field of the class, such as an enum constant.
+    #[cfg(feature = "encrypted-preferences")]
+    pub fn get_static_field<T: FromValue>(&self, env: &mut JNIEnv, name: &str) -> JResult<T> {
+        let r = env.get_static_field(self.as_class(), name, T::signature().as_str())?;
+        from_returned(env, r)
+    }
+}
+
+/// The device's Android API level (`Build.VERSION.SDK_INT`).
+///
+/// Some platform calls are only available from a given API level,
+/// so code that makes them can check this first. The level can't
+/// change while the application runs, so it's only read once.
+pub fn sdk_int(env: &mut JNIEnv) -> JResult<i32> {
+    static SDK_INT: OnceLock<i32> = OnceLock::new();
+    if let Some(sdk_int) = SDK_INT.get() {
+        return Ok(*sdk_int);
+    }
+    let sdk_int = ClassDecl("Landroid/os/Build$VERSION;").get_static_field(env, "SDK_INT")?;
+    Ok(*SDK_INT.get_or_init(|| sdk_int))
 }
 
 pub trait Method {
@@ -111,19 +194,7 @@ pub trait Method {
         let param = borrow_params(&param);
         let r = env.call_method(self_, Self::NAME, &signature, param.as_slice())?;
 
-        match r {
-            JValueGen::Object(obj) if obj.is_null() => Ok(FromValue::from_null()?),
-            JValueGen::Object(obj) => Ok(FromValue::from_object(env.new_global_ref(obj)?, env)?),
-            JValueGen::Byte(value) => Ok(FromValue::from_value(JValueGen::Byte(value))?),
-            JValueGen::Char(value) => Ok(FromValue::from_value(JValueGen::Char(value))?),
-            JValueGen::Short(value) => Ok(FromValue::from_value(JValueGen::Short(value))?),
-            JValueGen::Int(value) => Ok(FromValue::from_value(JValueGen::Int(value))?),
-            JValueGen::Long(value) => Ok(FromValue::from_value(JValueGen::Long(value))?),
-            JValueGen::Bool(value) => Ok(FromValue::from_value(JValueGen::Bool(value))?),
-            JValueGen::Float(value) => Ok(FromValue::from_value(JValueGen::Float(value))?),
-            JValueGen::Double(value) => Ok(FromValue::from_value(JValueGen::Double(value))?),
-            JValueGen::Void => Ok(FromValue::from_value(JValueGen::Void)?),
-        }
+        from_returned(env, r)
     }
 }
 
@@ -144,19 +215,23 @@ pub trait StaticMethod {
             param.as_slice(),
         )?;
 
-        match r {
-            JValueGen::Object(obj) if obj.is_null() => Ok(FromValue::from_null()?),
-            JValueGen::Object(obj) => Ok(FromValue::from_object(env.new_global_ref(obj)?, env)?),
-            JValueGen::Byte(value) => Ok(FromValue::from_value(JValueGen::Byte(value))?),
-            JValueGen::Char(value) => Ok(FromValue::from_value(JValueGen::Char(value))?),
-            JValueGen::Short(value) => Ok(FromValue::from_value(JValueGen::Short(value))?),
-            JValueGen::Int(value) => Ok(FromValue::from_value(JValueGen::Int(value))?),
-            JValueGen::Long(value) => Ok(FromValue::from_value(JValueGen::Long(value))?),
-            JValueGen::Bool(value) => Ok(FromValue::from_value(JValueGen::Bool(value))?),
-            JValueGen::Float(value) => Ok(FromValue::from_value(JValueGen::Float(value))?),
-            JValueGen::Double(value) => Ok(FromValue::from_value(JValueGen::Double(value))?),
-            JValueGen::Void => Ok(FromValue::from_value(JValueGen::Void)?),
-        }
+        from_returned(env, r)
+    }
+
+    /// Like [call](StaticMethod::call), for a class that had to be loaded.
+    #[cfg(any(feature = "encrypted-preferences", feature = "biometric"))]
+    fn call_loaded(
+        self_: &LoadedClass,
+        env: &mut JNIEnv,
+        params: Self::Param,
+    ) -> JResult<Self::Return> {
+        let signature = make_signature(&Self::Param::signature(), Self::Return::signature());
+        let param = params.as_param(env)?;
+        let param = borrow_params(&param);
+        let r =
+            env.call_static_method(self_.as_class(), Self::NAME, &signature, param.as_slice())?;
+
+        from_returned(env, r)
     }
 }
 
@@ -172,6 +247,20 @@ pub trait Constructible {
 
         FromValue::from_object(env.new_global_ref(obj)?, env)
     }
+
+    /// Like [call_new](Constructible::call_new), for a class that had to be loaded.
+    fn call_new_loaded(
+        self_: &LoadedClass,
+        env: &mut JNIEnv,
+        params: Self::Param,
+    ) -> JResult<Self::Return> {
+        let signature = make_signature(&Self::Param::signature(), <() as FromValue>::signature());
+        let param = params.as_param(env)?;
+        let param = borrow_params(&param);
+        let obj = env.new_object(self_.as_class(), &signature, param.as_slice())?;
+
+        FromValue::from_object(env.new_global_ref(obj)?, env)
+    }
 }
 
 pub trait AsParam {
@@ -222,6 +311,35 @@ where
     }
 }
 
+impl<T1, T2, T3, T4, T5> AsParam for (T1, T2, T3, T4, T5)
+where
+    T1: ToValue,
+    T2: ToValue,
+    T3: ToValue,
+    T4: ToValue,
+    T5: ToValue,
+{
+    fn signature() -> Vec<SignatureComp> {
+        vec![
+            T1::signature(),
+            T2::signature(),
+            T3::signature(),
+            T4::signature(),
+            T5::signature(),
+        ]
+    }
+
+    fn as_param<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<Vec<JValueGen<JObject<'a>>>> {
+        Ok(vec![
+            self.0.to_value(env)?,
+            self.1.to_value(env)?,
+            self.2.to_value(env)?,
+            self.3.to_value(env)?,
+            self.4.to_value(env)?,
+        ])
+    }
+}
+
 pub struct NoParam;
 impl AsParam for NoParam {
     fn signature() -> Vec<SignatureComp> {
@@ -321,6 +439,15 @@ impl ToValue for i32 {
         Ok((*self).into())
     }
 }
+impl ToValue for i64 {
+    fn signature() -> SignatureComp {
+        SignatureComp::Long
+    }
+
+    fn to_value<'a>(&self, _env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok((*self).into())
+    }
+}
 impl ToValue for bool {
     fn signature() -> SignatureComp {
         SignatureComp::Boolean
@@ -389,6 +516,24 @@ impl FromValue for bool {
         value.z()
     }
 }
+impl FromValue for i32 {
+    fn signature() -> SignatureComp {
+        SignatureComp::Int
+    }
+
+    fn from_value(value: JValue) -> JResult<Self> {
+        value.i()
+    }
+}
+impl FromValue for i64 {
+    fn signature() -> SignatureComp {
+        SignatureComp::Long
+    }
+
+    fn from_value(value: JValue) -> JResult<Self> {
+        value.j()
+    }
+}
 impl FromValue for String {
     fn signature() -> SignatureComp {
         ClassDecl("Ljava/lang/String;").into()
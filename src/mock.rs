@@ -0,0 +1,262 @@
+/*!
+# In-Memory Mock Store
+
+This module provides a credential store that keeps its credentials in memory,
+so that applications can unit-test their keyring integration on a development
+host, where there's no Android runtime to call through JNI.
+
+The mock store follows the conventions of a [named store](crate::by_store):
+entries can't have modifiers, their user and service names can't contain
+the default divider string, and each secret is kept in the same envelope
+as an encrypted secret, bound to its entry's service and user names.
+Reading a missing secret fails with [NoEntry](keyring_core::Error::NoEntry),
+and reading a malformed or tampered secret fails with
+[BadDataFormat](keyring_core::Error::BadDataFormat) wrapping the same
+[CorruptedData](crate::CorruptedData) error a named store would report.
+
+The raw values can be read and written with [Store::get_raw] and
+[Store::set_raw], so that tests can check how their code handles
+corrupted secrets. Secrets are _not_ encrypted in the mock store,
+so it must never be used to hold real ones.
+ */
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use keyring_core::{
+    Credential, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+    attributes::parse_attributes,
+};
+use regex::{Error as RegexError, Regex};
+
+use crate::by_store::cred::aad;
+use crate::crypto::{mock_open, mock_seal};
+
+const DIVIDER: &str = "\u{FEFF}@\u{FEFF}";
+
+type Values = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+/// An in-memory credential store. See the [module documentation](self).
+#[derive(Debug)]
+pub struct Store {
+    id: String,
+    values: Values,
+}
+
+impl Store {
+    /// Returns a new, empty mock store.
+    pub fn new() -> Arc<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = format!(
+            "In-memory mock storage, Crate version {}, Instance {}",
+            env!("CARGO_PKG_VERSION"),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        );
+        Arc::new(Store {
+            id,
+            values: Arc::new(Mutex::new(BTreeMap::new())),
+        })
+    }
+
+    /// The stored (enveloped) value of the credential with the given
+    /// service and user, if there is one.
+    pub fn get_raw(&self, service: &str, user: &str) -> Option<Vec<u8>> {
+        let values = self
+            .values
+            .lock()
+            .expect("Values lock poisoned: report a bug!");
+        values.get(&credential_id(service, user)).cloned()
+    }
+
+    /// Replace the stored value of the credential with the given service
+    /// and user, bypassing the envelope, so that its reads can be tested.
+    pub fn set_raw(&self, service: &str, user: &str, value: &[u8]) {
+        let mut values = self
+            .values
+            .lock()
+            .expect("Values lock poisoned: report a bug!");
+        values.insert(credential_id(service, user), value.to_vec());
+    }
+}
+
+impl CredentialStoreApi for Store {
+    fn vendor(&self) -> String {
+        "Android native keyring store (in-memory mock), https://github.com/open-source-cooperative/android-native-keyring-store".to_string()
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// See the API documentation for [CredentialStoreApi::build].
+    ///
+    /// As with a named store, no modifiers are allowed, and the user
+    /// and service values can't contain the divider string.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        modifiers: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        if modifiers.is_some_and(|mods| !mods.is_empty()) {
+            return Err(Error::NotSupportedByStore(
+                "The Android native store doesn't allow entry modifiers".to_string(),
+            ));
+        }
+        if service.contains(DIVIDER) {
+            return Err(Error::Invalid(
+                "service".to_string(),
+                "cannot contain the divider".to_string(),
+            ));
+        }
+        if user.contains(DIVIDER) {
+            return Err(Error::Invalid(
+                "user".to_string(),
+                "cannot contain the divider".to_string(),
+            ));
+        }
+        let credential = Cred::new(self.values.clone(), service, user);
+        Ok(Entry::new_with_credential(Arc::new(credential)))
+    }
+
+    /// See the API documentation for [CredentialStoreApi::search].
+    ///
+    /// As with a named store, the allowed specifiers are `id`, `service`,
+    /// and `user`, and their values are regular expressions.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let spec_err = |key: &str, e: RegexError| {
+            let msg = format!("invalid regexp: {}", e);
+            Error::Invalid(key.to_string(), msg)
+        };
+        let spec = parse_attributes(&["id", "service", "user"], Some(spec))?;
+        let id_spec = spec.get("id").cloned().unwrap_or_default();
+        let id_exp = Regex::new(&id_spec).map_err(|e| spec_err("id", e))?;
+        let service_spec = spec.get("service").cloned().unwrap_or_default();
+        let service_exp = Regex::new(&service_spec).map_err(|e| spec_err("service", e))?;
+        let user_spec = spec.get("user").cloned().unwrap_or_default();
+        let user_exp = Regex::new(&user_spec).map_err(|e| spec_err("user", e))?;
+        let values = self
+            .values
+            .lock()
+            .expect("Values lock poisoned: report a bug!");
+        let mut results = Vec::new();
+        for id in values.keys().filter(|id| id_exp.is_match(id)) {
+            let Some((user, service)) = id.split_once(DIVIDER) else {
+                continue;
+            };
+            if user_exp.is_match(user) && service_exp.is_match(service) {
+                let credential = Cred::new(self.values.clone(), service, user);
+                results.push(Entry::new_with_credential(Arc::new(credential)));
+            }
+        }
+        Ok(results)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a mock [Store].
+pub struct Cred {
+    values: Values,
+    id: String,
+    specifiers: (String, String),
+}
+
+impl std::fmt::Debug for Cred {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cred")
+            .field("id", &self.id)
+            .field("specifiers", &self.specifiers)
+            .finish()
+    }
+}
+
+impl Cred {
+    fn new(values: Values, service: &str, user: &str) -> Self {
+        Cred {
+            values,
+            id: credential_id(service, user),
+            specifiers: (service.to_string(), user.to_string()),
+        }
+    }
+
+    fn aad(&self) -> Vec<u8> {
+        let (service, user) = &self.specifiers;
+        aad(service, user)
+    }
+}
+
+impl CredentialApi for Cred {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        static NEXT_IV: AtomicU64 = AtomicU64::new(0);
+        let mut iv = [0u8; 12];
+        iv[4..].copy_from_slice(&NEXT_IV.fetch_add(1, Ordering::SeqCst).to_be_bytes());
+        let value = mock_seal(&iv, &self.aad(), secret);
+        let mut values = self
+            .values
+            .lock()
+            .expect("Values lock poisoned: report a bug!");
+        values.insert(self.id.clone(), value);
+        Ok(())
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        let values = self
+            .values
+            .lock()
+            .expect("Values lock poisoned: report a bug!");
+        let Some(data) = values.get(&self.id) else {
+            return Err(Error::NoEntry);
+        };
+        Ok(mock_open(&self.aad(), data.clone())?)
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        let mut values = self
+            .values
+            .lock()
+            .expect("Values lock poisoned: report a bug!");
+        match values.remove(&self.id) {
+            Some(_) => Ok(()),
+            None => Err(Error::NoEntry),
+        }
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        let values = self
+            .values
+            .lock()
+            .expect("Values lock poisoned: report a bug!");
+        if !values.contains_key(&self.id) {
+            return Err(Error::NoEntry);
+        }
+        Ok(None)
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        Some(self.specifiers.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+fn credential_id(service: &str, user: &str) -> String {
+    format!("{user}{DIVIDER}{service}")
+}
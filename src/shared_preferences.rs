@@ -1,14 +1,79 @@
-use crate::methods::{ClassDecl, FromValue, JResult, Method, NoParam, SignatureComp};
+use crate::change_listener::ChangeListener;
+#[cfg(feature = "encrypted-preferences")]
+use crate::encrypted_preferences::{EncryptedSharedPreferences, MasterKey};
+use crate::error::{AndroidKeyringError, AndroidKeyringResult, CorruptedData};
+use crate::methods::{
+    ClassDecl, Constructible, FromValue, JResult, LoadedClass, Method, NoParam, SignatureComp,
+    StaticMethod, ToValue,
+};
 use base64::{Engine, prelude::BASE64_STANDARD};
-#[cfg(any(feature = "legacy", feature = "compile-tests"))]
-use jni::objects::JObject;
 use jni::{
     JNIEnv,
-    objects::{AutoLocal, GlobalRef, JMap, JString},
+    objects::{AutoLocal, GlobalRef, JMap, JObject, JString, JValueGen},
 };
+use std::collections::BTreeSet;
 use std::marker::PhantomData;
+#[cfg(feature = "compile-tests")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zeroize::{Zeroize, Zeroizing};
 
 pub const MODE_PRIVATE: i32 = 0;
+/// Reload a file that another process has changed whenever it's opened.
+pub const MODE_MULTI_PROCESS: i32 = 4;
+
+/// The number of times an editor has been committed,
+/// so tests can check that changes aren't committed twice.
+#[cfg(feature = "compile-tests")]
+pub(crate) static COMMITS: AtomicUsize = AtomicUsize::new(0);
+
+/// The current time in milliseconds since the Unix epoch,
+/// as given by `System.currentTimeMillis`.
+#[cfg(feature = "legacy")]
+pub fn current_time_millis(env: &mut JNIEnv) -> JResult<i64> {
+    struct ThisMethod;
+    impl StaticMethod for ThisMethod {
+        type Param = NoParam;
+        type Return = i64;
+
+        const NAME: &str = "currentTimeMillis";
+    }
+    ThisMethod::call(ClassDecl("Ljava/lang/System;"), env, NoParam)
+}
+
+/// A `java.util.UUID`.
+struct Uuid {
+    self_: GlobalRef,
+}
+
+impl FromValue for Uuid {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/util/UUID;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+/// A random UUID in its string form, as given by `UUID.randomUUID`.
+pub fn random_uuid(env: &mut JNIEnv) -> JResult<String> {
+    struct RandomUuid;
+    impl StaticMethod for RandomUuid {
+        type Param = NoParam;
+        type Return = Uuid;
+
+        const NAME: &str = "randomUUID";
+    }
+    struct ToString;
+    impl Method for ToString {
+        type Param = NoParam;
+        type Return = String;
+
+        const NAME: &str = "toString";
+    }
+    let uuid = RandomUuid::call(ClassDecl("Ljava/util/UUID;"), env, NoParam)?;
+    ToString::call(&uuid.self_, env, NoParam)
+}
 
 #[derive(Clone)]
 pub struct Context {
@@ -18,6 +83,9 @@ pub struct Context {
 impl Context {
     #[cfg(any(feature = "legacy", feature = "compile-tests"))]
     pub fn new(env: &JNIEnv, obj: JObject) -> JResult<Self> {
+        if obj.is_null() {
+            return Err(jni::errors::Error::NullPtr("Android Context"));
+        }
         Ok(Self {
             self_: env.new_global_ref(obj)?,
         })
@@ -27,6 +95,34 @@ impl Context {
         Self { self_ }
     }
 
+    pub fn into_raw(self) -> GlobalRef {
+        self.self_
+    }
+
+    /// Get the context of the application this context belongs to.
+    ///
+    /// Unlike an Activity, the application context lives as long as the
+    /// process, so it's safe to hold on to. A context that isn't attached
+    /// to an application (such as one made by a test) has no application
+    /// context, in which case this context is returned.
+    pub fn get_application_context(&self, env: &mut JNIEnv) -> JResult<Context> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Option<Context>;
+
+            const NAME: &str = "getApplicationContext";
+        }
+
+        Ok(ThisMethod::call(&self.self_, env, NoParam)?.unwrap_or_else(|| self.clone()))
+    }
+
+    /// Whether this is the same Java object as another context.
+    #[cfg(feature = "compile-tests")]
+    pub fn is_same(&self, env: &JNIEnv, other: &Context) -> JResult<bool> {
+        env.is_same_object(&self.self_, &other.self_)
+    }
+
     pub fn get_shared_preferences(
         &self,
         env: &mut JNIEnv,
@@ -36,12 +132,15 @@ impl Context {
         struct ThisMethod<'a>(PhantomData<&'a ()>);
         impl<'a> Method for ThisMethod<'a> {
             type Param = (&'a str, i32);
-            type Return = SharedPreferences;
+            type Return = Option<SharedPreferences>;
 
             const NAME: &'static str = "getSharedPreferences";
         }
 
-        ThisMethod::call(&self.self_, env, (name, mode))
+        // a misbehaving context can return null rather than throw
+        ThisMethod::call(&self.self_, env, (name, mode))?.ok_or(jni::errors::Error::NullPtr(
+            "SharedPreferences from Context",
+        ))
     }
 
     pub fn delete_shared_preferences(&self, env: &mut JNIEnv, name: &str) -> JResult<bool> {
@@ -56,10 +155,128 @@ impl Context {
         ThisMethod::call(&self.self_, env, name)
     }
 
+    pub fn get_system_service(&self, env: &mut JNIEnv, name: &str) -> JResult<SystemService> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = SystemService;
+
+            const NAME: &'static str = "getSystemService";
+        }
+
+        ThisMethod::call(&self.self_, env, name)
+    }
+
     #[cfg(feature = "legacy")]
     pub fn id(&self) -> usize {
         self.self_.as_raw() as usize
     }
+
+    /// Get the class loader of the application, which can find the classes
+    /// of its dependencies from threads attached by native code.
+    pub fn get_class_loader(&self, env: &mut JNIEnv) -> JResult<ClassLoader> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = ClassLoader;
+
+            const NAME: &str = "getClassLoader";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    /// Get a file whose keys and values are encrypted by AndroidX
+    /// `EncryptedSharedPreferences`, using the default master key.
+    ///
+    /// The application must depend on `androidx.security:security-crypto`.
+    #[cfg(feature = "encrypted-preferences")]
+    pub fn get_encrypted_shared_preferences(
+        &self,
+        env: &mut JNIEnv,
+        name: &str,
+    ) -> JResult<SharedPreferences> {
+        let loader = self.get_class_loader(env)?;
+        let master_key = MasterKey::get_or_create(env, &loader, self)?;
+        EncryptedSharedPreferences::create(env, &loader, self, name, &master_key)
+    }
+}
+
+impl FromValue for Context {
+    fn signature() -> SignatureComp {
+        ClassDecl("Landroid/content/Context;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for Context {
+    fn signature() -> SignatureComp {
+        ClassDecl("Landroid/content/Context;").into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+/// A service returned by `Context.getSystemService`.
+///
+/// The Java method is declared as returning an `Object`, so
+/// callers convert this into the type of the service they requested.
+pub struct SystemService {
+    self_: GlobalRef,
+}
+
+impl FromValue for SystemService {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/lang/Object;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl SystemService {
+    pub fn into_raw(self) -> GlobalRef {
+        self.self_
+    }
+}
+
+pub struct ClassLoader {
+    self_: GlobalRef,
+}
+
+impl FromValue for ClassLoader {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ClassLoader {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/lang/ClassLoader;")
+    }
+
+    /// Load a class given its binary name, such as `java.lang.String`.
+    pub fn load_class(&self, env: &mut JNIEnv, name: &str) -> JResult<LoadedClass> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = LoadedClass;
+
+            const NAME: &'static str = "loadClass";
+        }
+
+        ThisMethod::call(&self.self_, env, name)
+    }
 }
 
 pub struct SharedPreferences {
@@ -92,6 +309,14 @@ impl SharedPreferences {
         ThisMethod::call(&self.self_, env, NoParam)
     }
 
+    /// Get the keys of all the entries in the file.
+    ///
+    /// This includes any bookkeeping entries, so callers
+    /// must skip the keys that aren't credential IDs.
+    pub fn get_all_keys(&self, env: &mut JNIEnv) -> JResult<Vec<String>> {
+        self.get_all(env)?.get_keys(env)
+    }
+
     pub fn contains(&self, env: &mut JNIEnv, key: &str) -> JResult<bool> {
         struct ThisMethod<'a>(PhantomData<&'a ()>);
         impl<'a> Method for ThisMethod<'a> {
@@ -114,19 +339,105 @@ impl SharedPreferences {
         ThisMethod::call(&self.self_, env, (key, None))
     }
 
-    pub fn get_binary(&self, env: &mut JNIEnv, key: &str) -> JResult<Option<Vec<u8>>> {
-        let Some(b64) = self.get_string(env, key)? else {
+    /// Get a long value. Java longs can't be null, so a missing
+    /// entry is detected by checking for the key first.
+    pub fn get_long(&self, env: &mut JNIEnv, key: &str) -> JResult<Option<i64>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, i64);
+            type Return = i64;
+
+            const NAME: &'static str = "getLong";
+        }
+        if !self.contains(env, key)? {
+            return Ok(None);
+        }
+        Ok(Some(ThisMethod::call(&self.self_, env, (key, 0))?))
+    }
+
+    /// Get an int value. See [get_long](SharedPreferences::get_long).
+    pub fn get_int(&self, env: &mut JNIEnv, key: &str) -> JResult<Option<i32>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, i32);
+            type Return = i32;
+
+            const NAME: &'static str = "getInt";
+        }
+        if !self.contains(env, key)? {
+            return Ok(None);
+        }
+        Ok(Some(ThisMethod::call(&self.self_, env, (key, 0))?))
+    }
+
+    /// Get a boolean value. See [get_long](SharedPreferences::get_long).
+    pub fn get_boolean(&self, env: &mut JNIEnv, key: &str) -> JResult<Option<bool>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, bool);
+            type Return = bool;
+
+            const NAME: &'static str = "getBoolean";
+        }
+        if !self.contains(env, key)? {
+            return Ok(None);
+        }
+        Ok(Some(ThisMethod::call(&self.self_, env, (key, false))?))
+    }
+
+    /// Get a string-set value, or `None` if there is no entry with the key.
+    pub fn get_string_set(&self, env: &mut JNIEnv, key: &str) -> JResult<Option<StringSet>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, Option<&'a StringSet>);
+            type Return = Option<StringSet>;
+
+            const NAME: &'static str = "getStringSet";
+        }
+        ThisMethod::call(&self.self_, env, (key, None))
+    }
+
+    /// Get a binary value, which is stored base64-encoded.
+    ///
+    /// A value that isn't valid base64 is reported as corrupted data,
+    /// so that callers can tell a damaged entry from a missing one.
+    pub fn get_binary(&self, env: &mut JNIEnv, key: &str) -> AndroidKeyringResult<Option<Vec<u8>>> {
+        let Some(mut b64) = self.get_string(env, key)? else {
             return Ok(None);
         };
 
-        Ok(match BASE64_STANDARD.decode(&b64) {
-            Ok(data) => Some(data),
+        match BASE64_STANDARD.decode(&b64) {
+            Ok(data) => {
+                // the values of encrypted files are secrets in the clear
+                b64.zeroize();
+                Ok(Some(data))
+            }
             Err(e) => {
-                tracing::error!(%e, "Error decoding base64 data, ignoring value");
+                tracing::error!(%e, "Error decoding base64 data");
                 tracing::debug!(?e, ?b64);
-                None
+                Err(AndroidKeyringError::CorruptedData(
+                    b64.into_bytes(),
+                    CorruptedData::BadBase64,
+                ))
             }
-        })
+        }
+    }
+
+    /// Have the file notify the listener of changes. The file only holds
+    /// a weak reference to the listener, so the caller must keep it.
+    pub fn register_on_shared_preference_change_listener(
+        &self,
+        env: &mut JNIEnv,
+        listener: &ChangeListener,
+    ) -> JResult<()> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a ChangeListener;
+            type Return = ();
+
+            const NAME: &'static str = "registerOnSharedPreferenceChangeListener";
+        }
+        ThisMethod::call(&self.self_, env, listener)
     }
 
     pub fn edit(&self, env: &mut JNIEnv) -> JResult<SharedPreferencesEditor> {
@@ -169,8 +480,52 @@ impl SharedPreferencesEditor {
         ThisMethod::call(&self.self_, env, (key, value))
     }
 
+    pub fn put_long(&self, env: &mut JNIEnv, key: &str, value: i64) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, i64);
+            type Return = SharedPreferencesEditor;
+
+            const NAME: &'static str = "putLong";
+        }
+        ThisMethod::call(&self.self_, env, (key, value))
+    }
+
+    pub fn put_int(&self, env: &mut JNIEnv, key: &str, value: i32) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, i32);
+            type Return = SharedPreferencesEditor;
+
+            const NAME: &'static str = "putInt";
+        }
+        ThisMethod::call(&self.self_, env, (key, value))
+    }
+
+    pub fn put_boolean(&self, env: &mut JNIEnv, key: &str, value: bool) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, bool);
+            type Return = SharedPreferencesEditor;
+
+            const NAME: &'static str = "putBoolean";
+        }
+        ThisMethod::call(&self.self_, env, (key, value))
+    }
+
+    pub fn put_string_set(&self, env: &mut JNIEnv, key: &str, value: &StringSet) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a str, &'a StringSet);
+            type Return = SharedPreferencesEditor;
+
+            const NAME: &'static str = "putStringSet";
+        }
+        ThisMethod::call(&self.self_, env, (key, value))
+    }
+
     pub fn put_binary(&self, env: &mut JNIEnv, key: &str, value: &[u8]) -> JResult<Self> {
-        let value = BASE64_STANDARD.encode(value);
+        let value = Zeroizing::new(BASE64_STANDARD.encode(value));
         self.put_string(env, key, &value)
     }
 
@@ -185,6 +540,35 @@ impl SharedPreferencesEditor {
         ThisMethod::call(&self.self_, env, key)
     }
 
+    /// Remove every entry from the file. When the edits are saved,
+    /// the removal happens before any other edits are made.
+    pub fn clear(&self, env: &mut JNIEnv) -> JResult<Self> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = SharedPreferencesEditor;
+
+            const NAME: &str = "clear";
+        }
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    /// Write the edits to memory at once, and to disk in the background.
+    ///
+    /// Unlike `commit`, this returns before the edits have been written to disk,
+    /// so edits can be lost if the process dies. Reads made after this returns
+    /// see the edits, whether or not they have been written yet.
+    pub fn apply(&self, env: &mut JNIEnv) -> JResult<()> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = ();
+
+            const NAME: &str = "apply";
+        }
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
     pub fn commit(&self, env: &mut JNIEnv) -> JResult<bool> {
         struct ThisMethod;
         impl Method for ThisMethod {
@@ -193,6 +577,8 @@ impl SharedPreferencesEditor {
 
             const NAME: &str = "commit";
         }
+        #[cfg(feature = "compile-tests")]
+        COMMITS.fetch_add(1, Ordering::SeqCst);
         ThisMethod::call(&self.self_, env, NoParam)
     }
 }
@@ -225,3 +611,158 @@ impl SharedPreferencesKeys {
         Ok(result)
     }
 }
+
+/// The strings of a `java.util.Set`, as read by
+/// [get_string_set](SharedPreferences::get_string_set) and written by
+/// [put_string_set](SharedPreferencesEditor::put_string_set).
+///
+/// The strings are kept sorted, so sets read back can be compared.
+/// A set read from a file must not be modified in place, so each
+/// one written is a new `java.util.HashSet`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StringSet(pub BTreeSet<String>);
+
+impl FromValue for StringSet {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/util/Set;").into()
+    }
+
+    fn from_object(self_: GlobalRef, env: &mut JNIEnv) -> JResult<Self> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = SetIterator;
+
+            const NAME: &str = "iterator";
+        }
+
+        let iterator = ThisMethod::call(self_.as_obj(), env, NoParam)?;
+        let mut strings = BTreeSet::new();
+        while iterator.has_next(env)? {
+            let SetElement(string) = iterator.next(env)?;
+            strings.insert(string);
+        }
+        Ok(Self(strings))
+    }
+}
+
+impl ToValue for StringSet {
+    fn signature() -> SignatureComp {
+        <Self as FromValue>::signature()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        let set = HashSet::new(env, self.0.len() as i32)?;
+        for string in &self.0 {
+            set.add(env, &SetElement(string.clone()))?;
+        }
+        Ok(env.new_local_ref(&set.self_)?.into())
+    }
+}
+
+struct SetIterator {
+    self_: GlobalRef,
+}
+
+impl FromValue for SetIterator {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/util/Iterator;").into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl SetIterator {
+    fn has_next(&self, env: &mut JNIEnv) -> JResult<bool> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = bool;
+
+            const NAME: &str = "hasNext";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    fn next(&self, env: &mut JNIEnv) -> JResult<SetElement> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = SetElement;
+
+            const NAME: &str = "next";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+/// The elements of a string set are strings, but the methods
+/// of `Set` and `Iterator` are declared in terms of `Object`.
+struct SetElement(String);
+
+impl FromValue for SetElement {
+    fn signature() -> SignatureComp {
+        ClassDecl("Ljava/lang/Object;").into()
+    }
+
+    fn from_object(value: GlobalRef, env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self(String::from_object(value, env)?))
+    }
+}
+
+impl ToValue for SetElement {
+    fn signature() -> SignatureComp {
+        <Self as FromValue>::signature()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        self.0.as_str().to_value(env)
+    }
+}
+
+/// The `java.util.HashSet` that each written [StringSet] is copied into.
+struct HashSet {
+    self_: GlobalRef,
+}
+
+impl FromValue for HashSet {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl HashSet {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/util/HashSet;")
+    }
+
+    fn new(env: &mut JNIEnv, capacity: i32) -> JResult<Self> {
+        struct ThisMethod;
+        impl Constructible for ThisMethod {
+            type Param = i32;
+            type Return = HashSet;
+        }
+
+        ThisMethod::call_new(Self::class(), env, capacity)
+    }
+
+    fn add(&self, env: &mut JNIEnv, element: &SetElement) -> JResult<bool> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a SetElement;
+            type Return = bool;
+
+            const NAME: &'static str = "add";
+        }
+
+        ThisMethod::call(&self.self_, env, element)
+    }
+}
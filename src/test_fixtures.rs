@@ -0,0 +1,84 @@
+/*!
+# Deterministic Test Fixtures
+
+Under the `test-fixtures` feature flag, tests can import a known AES key
+into the Android Keystore, rather than having one generated at random,
+and encrypt with a known IV, so that the values they write are always
+the same bytes and can be compared with golden files.
+
+A known key and IV give no protection at all, so this feature must
+never be enabled in a build that holds real secrets.
+ */
+use jni::JNIEnv;
+use keyring_core::{Error, Result};
+
+use crate::{
+    crypto::{Transformation, encrypt_with_iv},
+    error::AndroidKeyringResult,
+    keystore::{
+        BLOCK_MODE_GCM, ENCRYPTION_PADDING_NONE, KEY_ALGORITHM_AES, KeyProtectionBuilder, KeyStore,
+        PROVIDER, PURPOSE_DECRYPT, PURPOSE_ENCRYPT, SecretKeySpec,
+    },
+};
+
+/// The AES-256 key imported by [import_fixed_key]: the bytes 0 through 31.
+pub const FIXED_KEY: [u8; 32] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+];
+
+/// The GCM IV used by [encrypt_fixed]: the bytes 0xa0 through 0xab.
+pub const FIXED_IV: [u8; 12] = [
+    0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xab,
+];
+
+/// Import [FIXED_KEY] under the given alias, replacing any key there.
+///
+/// Unlike the keys this crate generates, the imported key
+/// doesn't require randomized encryption, so it accepts a given IV.
+pub fn import_fixed_key(env: &mut JNIEnv, alias: &str) -> Result<()> {
+    let import = |env: &mut JNIEnv| -> AndroidKeyringResult<()> {
+        let purposes = PURPOSE_DECRYPT | PURPOSE_ENCRYPT;
+        let protection = KeyProtectionBuilder::new(env, KEY_ALGORITHM_AES, purposes)?
+            .set_block_modes(env, &[BLOCK_MODE_GCM])?
+            .set_encryption_paddings(env, &[ENCRYPTION_PADDING_NONE])?
+            .set_randomized_encryption_required(env, false)?
+            .build(env)?;
+        let key = SecretKeySpec::new(env, &FIXED_KEY, KEY_ALGORITHM_AES)?.into();
+        let keystore = KeyStore::get_instance(env, PROVIDER)?;
+        keystore.load(env)?;
+        keystore.set_entry(env, alias, &key, protection)?;
+        Ok(())
+    };
+    Ok(import(env)?)
+}
+
+/// Encrypt data with AES-GCM under the key at the given alias and [FIXED_IV],
+/// in the versioned envelope that a store writes, bound to the given
+/// additional data (if any).
+///
+/// The key must have been imported by [import_fixed_key], because
+/// generated keys don't accept a given IV.
+pub fn encrypt_fixed(
+    env: &mut JNIEnv,
+    alias: &str,
+    aad: Option<&[u8]>,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let encrypt = |env: &mut JNIEnv| -> AndroidKeyringResult<Option<Vec<u8>>> {
+        let keystore = KeyStore::get_instance(env, PROVIDER)?;
+        keystore.load(env)?;
+        let Some(key) = keystore.get_key(env, alias)? else {
+            return Ok(None);
+        };
+        let value = encrypt_with_iv(env, key, Transformation::AesGcm, aad, &FIXED_IV, data)?;
+        Ok(Some(value))
+    };
+    match encrypt(env)? {
+        Some(value) => Ok(value),
+        None => Err(Error::Invalid(
+            "alias".to_string(),
+            "has no key; import one with import_fixed_key".to_string(),
+        )),
+    }
+}
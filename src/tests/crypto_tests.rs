@@ -1,85 +1,113 @@
 use std::collections::HashMap;
-use std::ffi::CString;
-use std::panic::catch_unwind;
+use std::sync::Arc;
 
-use android_log_sys::{__android_log_write, LogPriority};
-use jni::{JNIEnv, JavaVM};
+use jni::{JNIEnv, JavaVM, objects::JObject};
 
-use keyring_core::{Entry, get_default_store};
+use keyring_core::{Entry, api::CredentialStoreApi, get_default_store};
 
 use crate::{
-    error::CorruptedData,
+    change_listener::ChangeListener,
+    cipher::Cipher,
+    crypto::{MAGIC, Transformation, VERSION, decrypt, encrypt},
+    error::{AndroidKeyringError, CorruptedData, take_pending_exception},
+    keyguard::KeyguardManager,
+    keystore::{
+        BLOCK_MODE_GCM, ENCRYPTION_PADDING_NONE, KEY_ALGORITHM_AES, KeyGenParameterSpecBuilder,
+        KeyGenerator, KeyProtectionBuilder, KeyStore, PROVIDER, PURPOSE_DECRYPT, PURPOSE_ENCRYPT,
+        SecretKeySpec,
+    },
     shared_preferences::{Context, MODE_PRIVATE},
 };
 
-pub fn run_tests(env: JNIEnv, context: Context) -> (usize, usize) {
-    let testing = [
+use super::test_suite::Suite;
+
+pub fn suite(env: &JNIEnv, context: Context) -> Suite {
+    let tests = [
         (
             "setup",
             setup as fn(JavaVM, Context) -> keyring_core::Result<()>,
         ),
         ("missing_iv_len", missing_iv_len),
+        ("empty_secret", empty_secret),
         ("data_too_small", data_too_small),
         ("invalid_iv", invalid_iv),
+        ("bad_base64", bad_base64),
         ("decryption_failure", decryption_failure),
+        ("legacy_envelope", legacy_envelope),
+        ("v03_envelope", v03_envelope),
+        ("cipher_parameters", cipher_parameters),
+        ("versioned_envelope", versioned_envelope),
+        ("describe_service", describe_service),
+        ("dump_raw", dump_raw),
+        ("on_corrupt", on_corrupt),
+        ("aliases", aliases),
+        ("key_size", key_size),
+        (
+            "randomized_encryption_not_required",
+            randomized_encryption_not_required,
+        ),
+        ("keyguard", keyguard),
+        ("authentication_required", authentication_required),
+        ("sdk_int", sdk_int),
+        #[cfg(feature = "legacy")]
+        ("auth_policy", auth_policy),
+        #[cfg(feature = "legacy")]
+        ("auth_type_combination", auth_type_combination),
+        ("exception_details", exception_details),
+        ("error_mappings", error_mappings),
+        ("provider_unavailable", provider_unavailable),
+        #[cfg(all(feature = "biometric", feature = "legacy"))]
+        ("auth_canceled", auth_canceled),
+        ("strongbox", strongbox),
+        (
+            "invalidated_by_biometric_enrollment",
+            invalidated_by_biometric_enrollment,
+        ),
+        ("key_expired", key_expired),
+        ("key_security_info", key_security_info),
+        ("purposes", purposes),
+        ("attestation_chain", attestation_chain),
+        ("signing_key", signing_key),
+        ("hmac", hmac),
+        #[cfg(feature = "content-provider")]
+        ("content_provider_query", content_provider_query),
+        #[cfg(feature = "content-provider")]
+        ("content_provider_insert", content_provider_insert),
+        #[cfg(feature = "content-provider")]
+        ("content_provider_delete", content_provider_delete),
+        #[cfg(feature = "iv-check")]
+        ("iv_reuse", iv_reuse),
+        ("unlocked_device_required", unlocked_device_required),
+        ("import_key", import_key),
+        ("application_context", application_context),
+        ("multi_process", multi_process),
+        #[cfg(feature = "test-fixtures")]
+        ("golden_ciphertext", golden_ciphertext),
+        ("cbc_round_trip", cbc_round_trip),
+        ("cbor_round_trip", cbor_round_trip),
+        ("transformation_option", transformation_option),
+        ("swapped_blobs", swapped_blobs),
+        ("large_secret", large_secret),
+        ("max_secret_size", max_secret_size),
+        ("output_size", output_size),
+        ("shared_preferences_remove", shared_preferences_remove),
+        (
+            "shared_preferences_get_all_keys",
+            shared_preferences_get_all_keys,
+        ),
+        ("change_listener", change_listener),
+        ("shared_preferences_long", shared_preferences_long),
+        ("shared_preferences_int", shared_preferences_int),
+        ("shared_preferences_boolean", shared_preferences_boolean),
         ("teardown", teardown),
-    ]
-    .iter()
-    .map(|(name, entry)| {
+    ];
+    let mut suite = Suite::new("Crypto");
+    for (name, test) in tests {
         let java_vm = env.get_java_vm().unwrap();
         let context = context.clone();
-        (name, move || -> keyring_core::Result<()> {
-            catch_unwind(move || entry(java_vm, context))
-                .map_err(|e| {
-                    log::error!("Test {name} panicked: {e:?}");
-                    bad_result(name, "panicked")
-                })
-                .unwrap_or_else(|e| e)
-        })
-    })
-    .collect::<Vec<_>>();
-
-    let msg = c"Running shared crypto tests...";
-    let tag = c"unit-test";
-    let level = LogPriority::INFO as i32;
-    unsafe {
-        __android_log_write(level, tag.as_ptr(), msg.as_ptr());
-    }
-    let mut successes = 0;
-    let mut failures = 0;
-    for (name, testing) in testing {
-        let level;
-        let msg;
-        match testing() {
-            Ok(()) => {
-                level = LogPriority::INFO as i32;
-                msg = format!("{name} success");
-                successes += 1;
-            }
-            Err(e) => {
-                level = LogPriority::ERROR as i32;
-                msg = format!("{name} error: {e:?}");
-                failures += 1;
-            }
-        }
-
-        let msg = CString::new(msg).unwrap();
-        let tag = c"unit-test";
-        unsafe {
-            __android_log_write(level, tag.as_ptr(), msg.as_ptr());
-        }
-    }
-    let msg = CString::new(format!(
-        "Crypto: {} successes, {} failures",
-        successes, failures
-    ))
-    .unwrap();
-    let tag = c"unit-test";
-    let level = LogPriority::INFO as i32;
-    unsafe {
-        __android_log_write(level, tag.as_ptr(), msg.as_ptr());
+        suite.add(name, move || test(java_vm, context));
     }
-    (successes, failures)
+    suite
 }
 
 fn bad_result(op: &str, msg: &str) -> keyring_core::Result<()> {
@@ -95,6 +123,51 @@ const STORE_CONFIG: [(&str, &str); 3] = [
     ("divider", "@"),
 ];
 
+const STRONGBOX_STORE_CONFIG: [(&str, &str); 2] =
+    [("name", "strongbox-test"), ("strongbox", "true")];
+const EXPIRED_STORE_CONFIG: [(&str, &str); 2] =
+    [("name", "expired-test"), ("key_validity_end", "1000")];
+const KEY_SIZE_STORE_CONFIG: [(&str, &str); 2] = [("name", "key-size-test"), ("key_size", "256")];
+const IMPORT_STORE_CONFIG: [(&str, &str); 1] = [("name", "import-test")];
+const CBC_STORE_CONFIG: [(&str, &str); 2] = [
+    ("name", "cbc-test"),
+    ("transformation", "AES/CBC/PKCS7Padding"),
+];
+const LARGE_STORE_CONFIG: [(&str, &str); 2] =
+    [("name", "large-test"), ("max_secret_size", "4194304")];
+const NO_ENTRY_STORE_CONFIG: [(&str, &str); 4] = [
+    ("name", "no-entry-test"),
+    ("filename", "no-entry-test"),
+    ("divider", "@"),
+    ("on_corrupt", "no_entry"),
+];
+
+const LEGACY_LAYOUT_STORE_CONFIG: [(&str, &str); 4] = [
+    ("name", "legacy-layout-test"),
+    ("filename", "legacy-layout-test"),
+    ("divider", "@"),
+    ("upgrade_envelopes", "false"),
+];
+
+const CBOR_STORE_CONFIG: [(&str, &str); 4] = [
+    ("name", "cbor-test"),
+    ("filename", "cbor-test"),
+    ("divider", "@"),
+    ("envelope_format", "cbor"),
+];
+
+const MULTI_PROCESS_STORE_CONFIG: [(&str, &str); 4] = [
+    ("name", "multi-process-test"),
+    ("filename", "multi-process-test"),
+    ("divider", "@"),
+    ("multi_process", "true"),
+];
+
+const GIVEN_CONTEXT_STORE_CONFIG: [(&str, &str); 2] = [
+    ("name", "given-context-test"),
+    ("application_context", "false"),
+];
+
 fn setup(_vm: JavaVM, _context: Context) -> keyring_core::Result<()> {
     cleanup()?;
     let store_config = HashMap::from(STORE_CONFIG);
@@ -118,6 +191,50 @@ pub fn cleanup() -> keyring_core::Result<()> {
     } else {
         log::info!("No crypto-test store found to delete");
     }
+    let store_config = HashMap::from(KEY_SIZE_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("key-size-test store successfully deleted");
+    }
+    let store_config = HashMap::from(STRONGBOX_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("strongbox-test store successfully deleted");
+    }
+    let store_config = HashMap::from(EXPIRED_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("expired-test store successfully deleted");
+    }
+    let store_config = HashMap::from(IMPORT_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("import-test store successfully deleted");
+    }
+    let store_config = HashMap::from(CBC_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("cbc-test store successfully deleted");
+    }
+    let store_config = HashMap::from(LARGE_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("large-test store successfully deleted");
+    }
+    let store_config = HashMap::from(NO_ENTRY_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("no-entry-test store successfully deleted");
+    }
+    let store_config = HashMap::from(LEGACY_LAYOUT_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("legacy-layout-test store successfully deleted");
+    }
+    let store_config = HashMap::from(CBOR_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("cbor-test store successfully deleted");
+    }
+    let store_config = HashMap::from(MULTI_PROCESS_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("multi-process-test store successfully deleted");
+    }
+    let store_config = HashMap::from(GIVEN_CONTEXT_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("given-context-test store successfully deleted");
+    }
     Ok(())
 }
 
@@ -148,34 +265,60 @@ fn missing_iv_len(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
     Ok(())
 }
 
-fn data_too_small(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
-    let entry1 = Entry::new("iv-too-big", "user")?;
-    entry1.set_password("test")?;
-    // Force setting entry to empty data
+fn empty_secret(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    // an empty secret is stored as a full envelope, unlike an empty value
+    let entry1 = Entry::new("empty-secret", "user")?;
+    entry1.set_secret(&[])?;
     {
-        let mut env = vm.attach_current_thread().expect("attach_current_thread");
+        let mut env = vm.attach_current_thread().unwrap();
         let shared = ctx
             .get_shared_preferences(&mut env, "crypto-test", MODE_PRIVATE)
             .unwrap();
-        let mut original = shared
-            .get_binary(&mut env, "user@iv-too-big")
-            .unwrap()
-            .unwrap();
-        original.truncate(13);
-        let editor = shared.edit(&mut env).unwrap();
-        editor
-            .put_binary(&mut env, "user@iv-too-big", &original)
-            .unwrap();
-        editor.commit(&mut env).unwrap();
+        let value = shared.get_binary(&mut env, "user@empty-secret").unwrap();
+        // the header, the IV length, the IV, and the GCM tag
+        match value {
+            Some(value) if value.len() == 4 + 1 + 12 + 16 => {}
+            x => return bad_result("set_secret", &format!("a 33-byte value, got {x:?}")),
+        }
     }
-    match entry1.get_password() {
-        Err(keyring_core::Error::BadDataFormat(_, error)) => {
-            match error.downcast::<CorruptedData>().as_deref() {
-                Ok(&CorruptedData::DataTooSmall(12)) => (),
-                x => return bad_result("data_too_small", &format!("CorruptedData, got {x:?}")),
+    match entry1.get_secret() {
+        Ok(secret) if secret.is_empty() => {}
+        x => return bad_result("get_secret", &format!("an empty secret, got {x:?}")),
+    }
+    // CBC pads an empty secret to a whole block
+    let store = crate::Store::new_with_configuration(&HashMap::from(CBC_STORE_CONFIG))?;
+    let entry2 = store.build("empty-secret", "user", None)?;
+    entry2.set_secret(&[])?;
+    match entry2.get_secret() {
+        Ok(secret) if secret.is_empty() => {}
+        x => return bad_result("get_secret", &format!("an empty secret, got {x:?}")),
+    }
+    entry1.delete_credential()?;
+    entry2.delete_credential()?;
+    Ok(())
+}
+
+fn data_too_small(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let entry1 = Entry::new("iv-too-big", "user")?;
+    // keep the 4-byte header, the IV length, 12 bytes of IV,
+    // and then either nothing or one byte less than the GCM tag
+    for (len, ciphertext_len) in [(17, 0), (32, 15)] {
+        entry1.set_password("test")?;
+        rewrite_secret(&vm, &ctx, "user@iv-too-big", |mut original| {
+            original.truncate(len);
+            original
+        });
+        match entry1.get_password() {
+            Err(keyring_core::Error::BadDataFormat(_, error)) => {
+                match error.downcast::<CorruptedData>().as_deref() {
+                    Ok(&CorruptedData::DataTooSmall(n)) if n == ciphertext_len => (),
+                    x => {
+                        return bad_result("data_too_small", &format!("CorruptedData, got {x:?}"));
+                    }
+                }
             }
+            x => return bad_result("data_too_small", &format!("CorruptedData, got {x:?}")),
         }
-        x => return bad_result("data_too_small", &format!("CorruptedData, got {x:?}")),
     }
     entry1.delete_credential()?;
     Ok(())
@@ -195,7 +338,8 @@ fn invalid_iv(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
             .get_binary(&mut env, "user@invalid-iv")
             .unwrap()
             .unwrap();
-        original[0] = (CIPHERTEXT_LEN - 1) as u8;
+        // the IV length follows the 4-byte header
+        original[4] = (CIPHERTEXT_LEN - 1) as u8;
         let editor = shared.edit(&mut env).unwrap();
         editor
             .put_binary(&mut env, "user@invalid-iv", &original)
@@ -218,6 +362,37 @@ fn invalid_iv(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
     Ok(())
 }
 
+fn bad_base64(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let entry1 = Entry::new("bad-base64", "user")?;
+    entry1.set_password("test")?;
+    // Force setting entry to a value that isn't base64
+    {
+        let mut env = vm.attach_current_thread().unwrap();
+        let shared = ctx
+            .get_shared_preferences(&mut env, "crypto-test", MODE_PRIVATE)
+            .unwrap();
+        let editor = shared.edit(&mut env).unwrap();
+        editor
+            .put_string(&mut env, "user@bad-base64", "not base64!")
+            .unwrap();
+        editor.commit(&mut env).unwrap();
+    }
+    match entry1.get_password() {
+        Err(keyring_core::Error::BadDataFormat(data, error)) => {
+            if data != b"not base64!" {
+                return bad_result("bad_base64", &format!("the bad value, got {data:?}"));
+            }
+            match error.downcast::<CorruptedData>().as_deref() {
+                Ok(&CorruptedData::BadBase64) => {}
+                x => return bad_result("bad_base64", &format!("BadBase64, got {x:?}")),
+            }
+        }
+        x => return bad_result("bad_base64", &format!("CorruptedData, got {x:?}")),
+    };
+    entry1.delete_credential()?;
+    Ok(())
+}
+
 fn decryption_failure(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
     // create secret on entry with existing key
     let entry1 = Entry::new("corrupted", "my-user")?;
@@ -248,3 +423,1731 @@ fn decryption_failure(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
     entry1.delete_credential()?;
     Ok(())
 }
+
+/// Truncate the stored secret of a credential so that it can't be decrypted.
+fn rewrite_secret(vm: &JavaVM, ctx: &Context, id: &str, f: impl FnOnce(Vec<u8>) -> Vec<u8>) {
+    let mut env = vm.attach_current_thread().unwrap();
+    let shared = ctx
+        .get_shared_preferences(&mut env, "crypto-test", MODE_PRIVATE)
+        .unwrap();
+    let original = shared.get_binary(&mut env, id).unwrap().unwrap();
+    let editor = shared.edit(&mut env).unwrap();
+    editor.put_binary(&mut env, id, &f(original)).unwrap();
+    editor.commit(&mut env).unwrap();
+}
+
+fn legacy_envelope(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let entry1 = Entry::new("legacy-envelope", "user")?;
+    entry1.set_password("test")?;
+    // Rewrite the value in the layout used before the header was added,
+    // where the AAD flag is the high bit of the IV length
+    rewrite_secret(&vm, &ctx, "user@legacy-envelope", |original| {
+        if !original.starts_with(&MAGIC) || original[2] != VERSION || original[3] != 0x80 {
+            panic!("Stored value doesn't have a bound v1 header: {original:?}");
+        }
+        let mut legacy = vec![original[4] | 0x80];
+        legacy.extend_from_slice(&original[5..]);
+        legacy
+    });
+    match entry1.get_password() {
+        Ok(password) if password == "test" => {}
+        x => return bad_result("legacy_envelope", &format!("test, got {x:?}")),
+    }
+    // Values are rewritten in the current layout when they are set
+    entry1.set_password("test 2")?;
+    rewrite_secret(&vm, &ctx, "user@legacy-envelope", |original| {
+        if !original.starts_with(&MAGIC) {
+            panic!("Stored value doesn't have a header: {original:?}");
+        }
+        original
+    });
+    match entry1.get_password() {
+        Ok(password) if password == "test 2" => {}
+        x => return bad_result("legacy_envelope", &format!("test 2, got {x:?}")),
+    }
+    entry1.delete_credential()?;
+    Ok(())
+}
+
+/// Encrypt data with the key of the given alias, in the exact layout written
+/// by version 0.3 of this crate: the IV length, the IV, and the ciphertext.
+fn v03_value(env: &mut JNIEnv, alias: &str, data: &[u8]) -> Vec<u8> {
+    const ENCRYPT_MODE: i32 = 1;
+    let keystore = KeyStore::get_instance(env, PROVIDER).unwrap();
+    keystore.load(env).unwrap();
+    let key = keystore.get_key(env, alias).unwrap().unwrap();
+    let cipher = Cipher::get_instance(env, "AES/GCM/NoPadding").unwrap();
+    cipher.init(env, ENCRYPT_MODE, &key).unwrap();
+    let iv = cipher.get_iv(env).unwrap().unwrap();
+    let ciphertext = cipher.do_final(env, data).unwrap();
+    [&[iv.len() as u8], iv.as_slice(), ciphertext.as_slice()].concat()
+}
+
+fn cipher_parameters(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    const ENCRYPT_MODE: i32 = 1;
+    // make sure the store's key exists
+    let entry = Entry::new("cipher-parameters", "user")?;
+    entry.set_password("test")?;
+    {
+        let mut env = vm.attach_current_thread().unwrap();
+        let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+        keystore.load(&mut env).unwrap();
+        let key = keystore.get_key(&mut env, "crypto-test").unwrap().unwrap();
+        let cipher = Cipher::get_instance(&mut env, "AES/GCM/NoPadding").unwrap();
+        cipher.init(&mut env, ENCRYPT_MODE, &key).unwrap();
+        let iv = cipher.get_iv(&mut env).unwrap();
+        let parameters = cipher.get_parameters(&mut env).unwrap().unwrap();
+        let spec_class = "javax/crypto/spec/GCMParameterSpec";
+        let from_parameters = parameters.get_iv(&mut env, spec_class).unwrap();
+        if from_parameters.len() != 12 || iv.as_ref() != Some(&from_parameters) {
+            let msg = format!("the IV {iv:?}, got {from_parameters:?}");
+            return bad_result("get_parameters", &msg);
+        }
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn v03_envelope(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let layout = |filename: &str, id: &str| {
+        let mut env = vm.attach_current_thread().unwrap();
+        let shared = ctx
+            .get_shared_preferences(&mut env, filename, MODE_PRIVATE)
+            .unwrap();
+        let value = shared.get_binary(&mut env, id).unwrap().unwrap();
+        if value.starts_with(&MAGIC) {
+            Some(value[2])
+        } else {
+            // an unbound legacy value has the bare IV length
+            assert_eq!(value[0], 12, "legacy value is bound: {value:?}");
+            None
+        }
+    };
+    let write_v03 = |filename: &str, id: &str, data: &[u8]| {
+        let mut env = vm.attach_current_thread().unwrap();
+        let value = v03_value(&mut env, filename, data);
+        let shared = ctx
+            .get_shared_preferences(&mut env, filename, MODE_PRIVATE)
+            .unwrap();
+        let editor = shared.edit(&mut env).unwrap();
+        editor.put_binary(&mut env, id, &value).unwrap();
+        editor.commit(&mut env).unwrap();
+    };
+    // a v0.3 value is read transparently, and upgraded when it's next written
+    let entry = Entry::new("v03-envelope", "user")?;
+    write_v03("crypto-test", "user@v03-envelope", b"v0.3 secret");
+    match entry.get_password() {
+        Ok(password) if password == "v0.3 secret" => {}
+        x => return bad_result("get_password", &format!("v0.3 secret, got {x:?}")),
+    }
+    entry.set_password("upgraded")?;
+    match layout("crypto-test", "user@v03-envelope") {
+        Some(VERSION) => {}
+        x => return bad_result("set_password", &format!("version {VERSION}, got {x:?}")),
+    }
+    match entry.get_password() {
+        Ok(password) if password == "upgraded" => {}
+        x => return bad_result("get_password", &format!("upgraded, got {x:?}")),
+    }
+    entry.delete_credential()?;
+    // a store that doesn't upgrade envelopes keeps the v0.3 layout
+    let store = crate::Store::new_with_configuration(&HashMap::from(LEGACY_LAYOUT_STORE_CONFIG))?;
+    let entry = store.build("v03-envelope", "user", None)?;
+    entry.set_password("new")?;
+    match layout("legacy-layout-test", "user@v03-envelope") {
+        Some(VERSION) => {}
+        x => return bad_result("set_password", &format!("version {VERSION}, got {x:?}")),
+    }
+    write_v03("legacy-layout-test", "user@v03-envelope", b"v0.3 secret");
+    entry.set_password("not upgraded")?;
+    match layout("legacy-layout-test", "user@v03-envelope") {
+        None => {}
+        x => return bad_result("set_password", &format!("the v0.3 layout, got {x:?}")),
+    }
+    match entry.get_password() {
+        Ok(password) if password == "not upgraded" => {}
+        x => return bad_result("get_password", &format!("not upgraded, got {x:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn versioned_envelope(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let entry1 = Entry::new("versioned-envelope", "user")?;
+    entry1.set_password("test")?;
+    rewrite_secret(&vm, &ctx, "user@versioned-envelope", |original| {
+        if !original.starts_with(&MAGIC) || original[2] != VERSION {
+            panic!("Stored value doesn't have a v1 header: {original:?}");
+        }
+        original
+    });
+    match entry1.get_password() {
+        Ok(password) if password == "test" => {}
+        x => return bad_result("versioned_envelope", &format!("test, got {x:?}")),
+    }
+    // A value with unknown flags can't be read
+    rewrite_secret(&vm, &ctx, "user@versioned-envelope", |mut original| {
+        original[3] |= 0x01;
+        original
+    });
+    match entry1.get_password() {
+        Err(keyring_core::Error::BadDataFormat(_, error)) => {
+            match error.downcast::<CorruptedData>().as_deref() {
+                Ok(&CorruptedData::UnknownFlags(0x81)) => (),
+                x => return bad_result("unknown_flags", &format!("UnknownFlags, got {x:?}")),
+            }
+        }
+        x => return bad_result("unknown_flags", &format!("BadDataFormat, got {x:?}")),
+    }
+    // Nor can a value written by a later version
+    rewrite_secret(&vm, &ctx, "user@versioned-envelope", |mut original| {
+        original[2] = VERSION + 1;
+        original
+    });
+    match entry1.get_password() {
+        Err(keyring_core::Error::BadDataFormat(_, error)) => {
+            match error.downcast::<CorruptedData>().as_deref() {
+                Ok(&CorruptedData::UnsupportedVersion(v)) if v == VERSION + 1 => (),
+                x => {
+                    return bad_result(
+                        "unsupported_version",
+                        &format!("UnsupportedVersion, got {x:?}"),
+                    );
+                }
+            }
+        }
+        x => return bad_result("unsupported_version", &format!("BadDataFormat, got {x:?}")),
+    }
+    entry1.delete_credential()?;
+    Ok(())
+}
+
+fn truncate_secret(vm: &JavaVM, ctx: &Context, filename: &str, id: &str) {
+    let mut env = vm.attach_current_thread().unwrap();
+    let shared = ctx
+        .get_shared_preferences(&mut env, filename, MODE_PRIVATE)
+        .unwrap();
+    let mut original = shared.get_binary(&mut env, id).unwrap().unwrap();
+    original.truncate(13);
+    let editor = shared.edit(&mut env).unwrap();
+    editor.put_binary(&mut env, id, &original).unwrap();
+    editor.commit(&mut env).unwrap();
+}
+
+fn dump_raw(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let entry = Entry::new("dump-raw", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    if let Some(dump) = cred.dump_raw()? {
+        return bad_result("dump_raw", &format!("nothing, got {dump:?}"));
+    }
+    entry.set_password("test")?;
+    let stored = {
+        let mut env = vm.attach_current_thread().unwrap();
+        let shared = ctx
+            .get_shared_preferences(&mut env, "crypto-test", MODE_PRIVATE)
+            .unwrap();
+        shared
+            .get_binary(&mut env, "user@dump-raw")
+            .unwrap()
+            .unwrap()
+    };
+    let Some(dump) = cred.dump_raw()? else {
+        return bad_result("dump_raw", "the stored value, got nothing");
+    };
+    if dump.bytes() != stored.as_slice()
+        || dump.version != Some(VERSION)
+        || dump.iv_len != Some(12)
+        || dump.error.is_some()
+    {
+        return bad_result("dump_raw", &format!("{stored:?}, got {dump:?}"));
+    }
+    // only the summary is shown when a dump is logged
+    let shown = format!("{dump:?}");
+    if shown
+        != format!(
+            "RawValue {{ len: {}, version: Some(1), iv_len: Some(12), error: None }}",
+            stored.len()
+        )
+    {
+        return bad_result("dump_raw", &format!("a summary, got {shown}"));
+    }
+    rewrite_secret(&vm, &ctx, "user@dump-raw", |mut original| {
+        original.truncate(17);
+        original
+    });
+    match cred.dump_raw()? {
+        Some(dump) if dump.len() == 17 && dump.error == Some("DataTooSmall") => {}
+        r => return bad_result("dump_raw", &format!("a truncated value, got {r:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn describe_service(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let service = "describe-service";
+    Entry::new(service, "user1")?.set_password("test")?;
+    let entry2 = Entry::new(service, "user2")?;
+    entry2.set_password("test")?;
+    // cut the second value short, so its ciphertext is too small for a tag
+    rewrite_secret(&vm, &ctx, "user2@describe-service", |mut original| {
+        original.truncate(original.len() - 17);
+        original
+    });
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let description = store.describe_service(service)?;
+    let actual: serde_json::Value = serde_json::from_str(&description)
+        .map_err(|e| keyring_core::Error::Invalid("describe_service".to_string(), e.to_string()))?;
+    let expected = serde_json::json!({
+        "service": service,
+        "credentials": [
+            {"user": "user1", "version": VERSION, "iv_len": 12, "ciphertext_len": 20},
+            {"user": "user2", "error": "DataTooSmall"},
+        ],
+    });
+    if actual != expected {
+        return bad_result("describe_service", &format!("{expected}, got {actual}"));
+    }
+    Entry::new(service, "user1")?.delete_credential()?;
+    entry2.delete_credential()?;
+    Ok(())
+}
+
+fn on_corrupt(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    // by default, a corrupted secret is an error
+    let entry1 = Entry::new("on-corrupt", "user")?;
+    entry1.set_password("test")?;
+    truncate_secret(&vm, &ctx, "crypto-test", "user@on-corrupt");
+    match entry1.get_password() {
+        Err(keyring_core::Error::BadDataFormat(_, _)) => {}
+        x => return bad_result("get_password", &format!("BadDataFormat, got {x:?}")),
+    }
+    let cred1 = entry1.as_any().downcast_ref::<crate::Cred>().unwrap();
+    if !cred1.exists()? {
+        return bad_result("exists", "true for a corrupted secret");
+    }
+    entry1.delete_credential()?;
+    // but it can be configured to be a missing entry
+    let store = crate::Store::new_with_configuration(&HashMap::from(NO_ENTRY_STORE_CONFIG))?;
+    let entry2 = store.build("on-corrupt", "user", None)?;
+    entry2.set_password("test")?;
+    truncate_secret(&vm, &ctx, "no-entry-test", "user@on-corrupt");
+    match entry2.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        x => return bad_result("get_password", &format!("NoEntry, got {x:?}")),
+    }
+    let cred2 = entry2.as_any().downcast_ref::<crate::Cred>().unwrap();
+    if cred2.exists()? {
+        return bad_result("exists", "false for a corrupted secret");
+    }
+    // as is one that isn't base64
+    entry2.set_password("test")?;
+    {
+        let mut env = vm.attach_current_thread().unwrap();
+        let shared = ctx
+            .get_shared_preferences(&mut env, "no-entry-test", MODE_PRIVATE)
+            .unwrap();
+        let editor = shared.edit(&mut env).unwrap();
+        editor
+            .put_string(&mut env, "user@on-corrupt", "not base64!")
+            .unwrap();
+        editor.commit(&mut env).unwrap();
+    }
+    match entry2.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        x => {
+            return bad_result(
+                "get_password",
+                &format!("NoEntry for bad base64, got {x:?}"),
+            );
+        }
+    }
+    // and writing the secret replaces the corrupted one
+    entry2.set_password("reset")?;
+    match entry2.get_password() {
+        Ok(p) if p.eq("reset") => {}
+        x => return bad_result("get_password", &format!("'reset', got {x:?}")),
+    }
+    entry2.delete_credential()?;
+    // other values are rejected
+    let config = HashMap::from([("name", "no-entry-test"), ("on_corrupt", "ignore")]);
+    match crate::Store::new_with_configuration(&config) {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "on_corrupt" => Ok(()),
+        x => bad_result("new_with_configuration", &format!("Invalid, got {x:?}")),
+    }
+}
+
+fn generate_test_key(env: &mut jni::JNIEnv, alias: &str) -> keyring_core::Result<()> {
+    let spec = KeyGenParameterSpecBuilder::new(
+        env,
+        KEY_ALGORITHM_AES,
+        alias,
+        PURPOSE_DECRYPT | PURPOSE_ENCRYPT,
+    )
+    .unwrap()
+    .set_block_modes(env, &[BLOCK_MODE_GCM])
+    .unwrap()
+    .set_encryption_paddings(env, &[ENCRYPTION_PADDING_NONE])
+    .unwrap()
+    .build(env)
+    .unwrap();
+    let generator = KeyGenerator::get_instance(env, KEY_ALGORITHM_AES, PROVIDER).unwrap();
+    generator.init(env, spec.into()).unwrap();
+    generator.generate_key(env).unwrap();
+    Ok(())
+}
+
+fn aliases(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let names = ["aliases-test-1", "aliases-test-2"];
+    for name in names {
+        generate_test_key(&mut env, name)?;
+    }
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    let aliases = keystore.aliases(&mut env).unwrap();
+    for name in names {
+        keystore.delete_entry(&mut env, name).unwrap();
+    }
+    for name in names {
+        if !aliases.iter().any(|alias| alias == name) {
+            return bad_result("aliases", &format!("{name} in {aliases:?}"));
+        }
+    }
+    Ok(())
+}
+
+fn key_size(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let store_config = HashMap::from(KEY_SIZE_STORE_CONFIG);
+    let store = crate::Store::new_with_configuration(&store_config)?;
+    let entry = store.build("key-size", "user", None)?;
+    entry.set_password("test")?;
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        Ok(p) => return bad_result("get_password", &format!("'test', got '{p}'")),
+        Err(e) => return bad_result("get_password", &format!("'test', got {e:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+/// Generate (and then delete) a key whose spec has been customized,
+/// failing if the key can't be generated.
+fn try_generate_key<F>(vm: JavaVM, alias: &str, customize: F) -> keyring_core::Result<()>
+where
+    F: FnOnce(
+        &mut jni::JNIEnv,
+        KeyGenParameterSpecBuilder,
+    ) -> crate::methods::JResult<KeyGenParameterSpecBuilder>,
+{
+    let mut env = vm.attach_current_thread().unwrap();
+    let builder = KeyGenParameterSpecBuilder::new(
+        &mut env,
+        KEY_ALGORITHM_AES,
+        alias,
+        PURPOSE_DECRYPT | PURPOSE_ENCRYPT,
+    )
+    .unwrap()
+    .set_block_modes(&mut env, &[BLOCK_MODE_GCM])
+    .unwrap()
+    .set_encryption_paddings(&mut env, &[ENCRYPTION_PADDING_NONE])
+    .unwrap();
+    let spec = match customize(&mut env, builder) {
+        Ok(builder) => builder.build(&mut env).unwrap(),
+        Err(e) => return bad_result("customize", &format!("a builder, got {e:?}")),
+    };
+    let generator = KeyGenerator::get_instance(&mut env, KEY_ALGORITHM_AES, PROVIDER).unwrap();
+    generator.init(&mut env, spec.into()).unwrap();
+    let result = generator.generate_key(&mut env);
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    keystore.delete_entry(&mut env, alias).unwrap();
+    if let Err(e) = result {
+        return bad_result("generate_key", &format!("a key, got {e:?}"));
+    }
+    Ok(())
+}
+
+fn randomized_encryption_not_required(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    try_generate_key(vm, "randomized-encryption-test", |env, builder| {
+        builder.set_randomized_encryption_required(env, false)
+    })
+}
+
+fn invalidated_by_biometric_enrollment(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    try_generate_key(vm, "biometric-enrollment-test", |env, builder| {
+        builder.set_invalidated_by_biometric_enrollment(env, true)
+    })
+}
+
+fn unlocked_device_required(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    try_generate_key(vm, "unlocked-device-required-test", |env, builder| {
+        builder.set_unlocked_device_required(env, true)
+    })
+}
+
+fn keyguard(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let keyguard = match KeyguardManager::from_context(&mut env, &ctx) {
+        Ok(keyguard) => keyguard,
+        Err(e) => return bad_result("from_context", &format!("a KeyguardManager, got {e:?}")),
+    };
+    match keyguard.is_device_secure(&mut env) {
+        Ok(secure) => log::info!("Device is secure: {secure}"),
+        Err(e) => return bad_result("is_device_secure", &format!("a boolean, got {e:?}")),
+    }
+    match keyguard.is_device_locked(&mut env) {
+        Ok(locked) => log::info!("Device is locked: {locked}"),
+        Err(e) => return bad_result("is_device_locked", &format!("a boolean, got {e:?}")),
+    }
+    Ok(())
+}
+
+fn authentication_required(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let keyguard = KeyguardManager::from_context(&mut env, &ctx).unwrap();
+    if !keyguard.is_device_secure(&mut env).unwrap() {
+        // keys that require authentication can't be generated without a secure lock screen
+        log::info!("Skipping authentication_required: device has no secure lock screen");
+        return Ok(());
+    }
+    let alias = "authentication-required-test";
+    let spec = KeyGenParameterSpecBuilder::new(
+        &mut env,
+        KEY_ALGORITHM_AES,
+        alias,
+        PURPOSE_DECRYPT | PURPOSE_ENCRYPT,
+    )
+    .unwrap()
+    .set_block_modes(&mut env, &[BLOCK_MODE_GCM])
+    .unwrap()
+    .set_encryption_paddings(&mut env, &[ENCRYPTION_PADDING_NONE])
+    .unwrap()
+    .set_user_authentication_required(&mut env, true)
+    .unwrap()
+    .set_user_authentication_validity_duration_seconds(&mut env, 1)
+    .unwrap()
+    .build(&mut env)
+    .unwrap();
+    let generator = KeyGenerator::get_instance(&mut env, KEY_ALGORITHM_AES, PROVIDER).unwrap();
+    generator.init(&mut env, spec.into()).unwrap();
+    let key = generator.generate_key(&mut env).unwrap();
+    // let any recent authentication lapse
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    let result = encrypt(
+        &mut env,
+        key.into(),
+        Transformation::default(),
+        None,
+        b"test",
+    );
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    keystore.delete_entry(&mut env, alias).unwrap();
+    match result {
+        Err(AndroidKeyringError::AuthenticationRequired(_)) => Ok(()),
+        r => bad_result("encrypt", &format!("AuthenticationRequired, got {r:?}")),
+    }
+}
+
+fn sdk_int(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let sdk_int = crate::sdk_int(&mut env).unwrap();
+    // the KeyStore features this crate uses need API level 23 (Marshmallow)
+    if !(23..100).contains(&sdk_int) {
+        return bad_result("sdk_int", &format!("a plausible API level, got {sdk_int}"));
+    }
+    // later reads come from the cache
+    let again = crate::sdk_int(&mut env).unwrap();
+    if again != sdk_int {
+        return bad_result("sdk_int", &format!("{sdk_int} again, got {again}"));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "legacy")]
+fn auth_policy(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    use crate::by_service::{AuthPolicy, AuthType, Cred};
+
+    let mut env = vm.attach_current_thread().unwrap();
+    let keyguard = KeyguardManager::from_context(&mut env, &ctx).unwrap();
+    let secure = keyguard.is_device_secure(&mut env).unwrap();
+    let alias = "auth-policy-test";
+    let policies = [
+        // a time window, as protected keys have by default
+        (AuthPolicy::default(), 30),
+        // authentication for every use
+        (
+            AuthPolicy {
+                validity_seconds: 0,
+                auth_type: AuthType::Biometric,
+            },
+            0,
+        ),
+    ];
+    for (policy, expected) in policies {
+        let spec = match Cred::key_spec(&mut env, &ctx, alias, Some(policy), None) {
+            Ok(spec) => spec,
+            Err(AndroidKeyringError::KeyringError(keyring_core::Error::NotSupportedByStore(e))) => {
+                // the emulator may have no biometric enrolled
+                log::info!("Skipping {policy:?}: {e}");
+                continue;
+            }
+            Err(e) => return bad_result("key_spec", &format!("a spec for {policy:?}, got {e:?}")),
+        };
+        if !secure {
+            // keys that require authentication can't be generated without a secure lock screen
+            continue;
+        }
+        let generator = KeyGenerator::get_instance(&mut env, KEY_ALGORITHM_AES, PROVIDER).unwrap();
+        generator.init(&mut env, spec.into()).unwrap();
+        let key: crate::keystore::Key = generator.generate_key(&mut env).unwrap().into();
+        let key_info = key.get_key_info(&mut env).unwrap();
+        let seconds = key_info
+            .get_user_authentication_validity_duration_seconds(&mut env)
+            .unwrap();
+        let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+        keystore.load(&mut env).unwrap();
+        keystore.delete_entry(&mut env, alias).unwrap();
+        // keys for every use report 0 or -1, depending on the API level
+        if seconds.max(0) != expected {
+            return bad_result(
+                "validity duration",
+                &format!("{expected} for {policy:?}, got {seconds}"),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "legacy")]
+fn auth_type_combination(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    use crate::by_service::{AuthPolicy, AuthType, Cred};
+
+    match "biometric_strong|device_credential".parse::<AuthType>() {
+        Ok(AuthType::Any) => {}
+        r => return bad_result("parse", &format!("Any, got {r:?}")),
+    }
+    if "biometric_weak|device_credential"
+        .parse::<AuthType>()
+        .is_ok()
+    {
+        return bad_result("parse", "an error for a weak biometric");
+    }
+    let mut env = vm.attach_current_thread().unwrap();
+    let keyguard = KeyguardManager::from_context(&mut env, &ctx).unwrap();
+    if !keyguard.is_device_secure(&mut env).unwrap() {
+        // without a secure lock screen, the user can't authenticate at all
+        log::info!("Skipping auth_type_combination: device has no secure lock screen");
+        return Ok(());
+    }
+    // with the device credential as a fallback, a secure lock screen is enough
+    let alias = "auth-type-combination-test";
+    let policy = AuthPolicy {
+        validity_seconds: 30,
+        auth_type: AuthType::Any,
+    };
+    let spec = match Cred::key_spec(&mut env, &ctx, alias, Some(policy), None) {
+        Ok(spec) => spec,
+        Err(e) => return bad_result("key_spec", &format!("a spec, got {e:?}")),
+    };
+    let generator = KeyGenerator::get_instance(&mut env, KEY_ALGORITHM_AES, PROVIDER).unwrap();
+    generator.init(&mut env, spec.into()).unwrap();
+    let generated = generator.generate_key(&mut env).map(|_| ());
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    keystore.delete_entry(&mut env, alias).unwrap();
+    match generated {
+        Ok(()) => Ok(()),
+        Err(e) => bad_result("generate_key", &format!("a key, got {e:?}")),
+    }
+}
+
+fn exception_details(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    if Cipher::get_instance(&mut env, "NoSuch/Cipher/Transformation").is_ok() {
+        return bad_result("get_instance", "an error");
+    }
+    let thrown = match take_pending_exception(&mut env) {
+        Ok(thrown) => thrown,
+        Err(e) => return bad_result("take_pending_exception", &format!("an error, got {e:?}")),
+    };
+    if env.exception_check().unwrap() {
+        return bad_result("take_pending_exception", "no pending exception");
+    }
+    match thrown {
+        AndroidKeyringError::JavaExceptionThrow { class, .. }
+            if class == "java.security.NoSuchAlgorithmException" =>
+        {
+            Ok(())
+        }
+        e => bad_result(
+            "take_pending_exception",
+            &format!("a NoSuchAlgorithmException, got {e:?}"),
+        ),
+    }
+}
+
+fn error_mappings(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    use keyring_core::Error;
+    type Check = fn(&Error) -> bool;
+    // both stores report their failures through this one conversion
+    let cases: [(AndroidKeyringError, Check); 11] = [
+        (
+            AndroidKeyringError::JniError(jni::errors::Error::JavaException),
+            |e| matches!(e, Error::PlatformFailure(_)),
+        ),
+        (
+            AndroidKeyringError::JavaExceptionThrow {
+                class: "java.lang.IllegalStateException".to_string(),
+                message: None,
+            },
+            |e| matches!(e, Error::PlatformFailure(_)),
+        ),
+        (
+            AndroidKeyringError::CorruptedData(vec![1, 2], CorruptedData::BadBase64),
+            |e| matches!(e, Error::BadDataFormat(data, _) if data == &[1, 2]),
+        ),
+        (AndroidKeyringError::KeyringError(Error::NoEntry), |e| {
+            matches!(e, Error::NoEntry)
+        }),
+        (AndroidKeyringError::DeviceLocked, |e| {
+            matches!(e, Error::NoStorageAccess(_))
+        }),
+        (AndroidKeyringError::KeyExpired, |e| {
+            matches!(e, Error::NoStorageAccess(_))
+        }),
+        (AndroidKeyringError::KeyNotYetValid, |e| {
+            matches!(e, Error::NoStorageAccess(_))
+        }),
+        (AndroidKeyringError::AuthenticationRequired(None), |e| {
+            matches!(e, Error::NoStorageAccess(_))
+        }),
+        (AndroidKeyringError::KeyInvalidated, |e| {
+            matches!(e, Error::NoStorageAccess(_))
+        }),
+        (AndroidKeyringError::AuthCanceled, |e| {
+            matches!(e, Error::NoStorageAccess(_))
+        }),
+        (AndroidKeyringError::ProviderUnavailable(None), |e| {
+            matches!(e, Error::NotSupportedByStore(_))
+        }),
+    ];
+    for (error, expected) in cases {
+        let description = format!("{error:?}");
+        let mapped = Error::from(error);
+        if !expected(&mapped) {
+            return bad_result(
+                "from",
+                &format!("the mapping for {description}, got {mapped:?}"),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn purposes(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    use crate::keystore::{
+        KEY_ALGORITHM_EC, KEY_ALGORITHM_HMAC_SHA256, KEY_ALGORITHM_RSA, KeyProtectionBuilder,
+        PURPOSE_AGREE_KEY, PURPOSE_ATTEST_KEY, PURPOSE_SIGN, PURPOSE_VERIFY, PURPOSE_WRAP_KEY,
+        check_purposes,
+    };
+    let valid = [
+        (KEY_ALGORITHM_AES, PURPOSE_ENCRYPT | PURPOSE_DECRYPT),
+        (KEY_ALGORITHM_EC, PURPOSE_SIGN | PURPOSE_VERIFY),
+        (KEY_ALGORITHM_EC, PURPOSE_ATTEST_KEY),
+        (KEY_ALGORITHM_EC, PURPOSE_AGREE_KEY),
+        (KEY_ALGORITHM_HMAC_SHA256, PURPOSE_SIGN | PURPOSE_VERIFY),
+        (KEY_ALGORITHM_RSA, PURPOSE_WRAP_KEY),
+    ];
+    for (algorithm, purposes) in valid {
+        if let Err(e) = check_purposes(algorithm, purposes) {
+            return bad_result(
+                "check_purposes",
+                &format!("Ok for {algorithm} {purposes}, got {e:?}"),
+            );
+        }
+    }
+    let invalid = [
+        (KEY_ALGORITHM_AES, PURPOSE_SIGN),
+        (KEY_ALGORITHM_AES, PURPOSE_ENCRYPT | PURPOSE_VERIFY),
+        (KEY_ALGORITHM_EC, PURPOSE_ENCRYPT),
+        (KEY_ALGORITHM_HMAC_SHA256, PURPOSE_DECRYPT),
+        (KEY_ALGORITHM_HMAC_SHA256, PURPOSE_AGREE_KEY),
+        (KEY_ALGORITHM_AES, 0),
+        (KEY_ALGORITHM_AES, 1 << 20),
+        (KEY_ALGORITHM_AES, PURPOSE_WRAP_KEY),
+        (KEY_ALGORITHM_RSA, PURPOSE_AGREE_KEY),
+        ("DES", PURPOSE_ENCRYPT),
+    ];
+    for (algorithm, purposes) in invalid {
+        match check_purposes(algorithm, purposes) {
+            Err(keyring_core::Error::Invalid(attr, _)) if attr == "purposes" => {}
+            r => {
+                return bad_result(
+                    "check_purposes",
+                    &format!("Invalid for {algorithm} {purposes}, got {r:?}"),
+                );
+            }
+        }
+    }
+    // the spec builders refuse the purposes before creating anything
+    let mut env = vm.attach_current_thread().unwrap();
+    match KeyGenParameterSpecBuilder::new(&mut env, KEY_ALGORITHM_AES, "unused", PURPOSE_SIGN) {
+        Err(AndroidKeyringError::KeyringError(keyring_core::Error::Invalid(attr, _)))
+            if attr == "purposes" => {}
+        r => return bad_result("KeyGenParameterSpecBuilder::new", &format!("{:?}", r.err())),
+    }
+    match KeyProtectionBuilder::new(&mut env, KEY_ALGORITHM_AES, PURPOSE_WRAP_KEY) {
+        Err(AndroidKeyringError::KeyringError(keyring_core::Error::Invalid(attr, _)))
+            if attr == "purposes" => {}
+        r => return bad_result("KeyProtectionBuilder::new", &format!("{:?}", r.err())),
+    }
+    Ok(())
+}
+
+fn provider_unavailable(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    // a device without the provider fails the way a missing provider or type does
+    let mut env = vm.attach_current_thread().unwrap();
+    match KeyGenerator::get_instance(&mut env, KEY_ALGORITHM_AES, "NoSuchKeyStore") {
+        Err(AndroidKeyringError::ProviderUnavailable(_)) => {}
+        r => {
+            let r = r.map(|_| "a generator");
+            return bad_result("get_instance", &format!("ProviderUnavailable, got {r:?}"));
+        }
+    }
+    match KeyStore::get_instance(&mut env, "NoSuchKeyStore") {
+        Err(AndroidKeyringError::ProviderUnavailable(_)) => {}
+        r => {
+            let r = r.map(|_| "a keystore");
+            return bad_result("get_instance", &format!("ProviderUnavailable, got {r:?}"));
+        }
+    }
+    if env.exception_check().unwrap() {
+        return bad_result("get_instance", "the exception to be cleared");
+    }
+    // the same exceptions thrown anywhere else aren't mistaken for it
+    env.throw_new(
+        "java/security/NoSuchProviderException",
+        "not a keystore call",
+    )
+    .unwrap();
+    match take_pending_exception(&mut env).unwrap() {
+        AndroidKeyringError::JavaExceptionThrow { .. } => {}
+        r => {
+            return bad_result(
+                "take_pending_exception",
+                &format!("JavaExceptionThrow, got {r:?}"),
+            );
+        }
+    }
+    // and applications see that the store can't be used
+    let error = keyring_core::Error::from(AndroidKeyringError::ProviderUnavailable(None));
+    match error {
+        keyring_core::Error::NotSupportedByStore(msg) if msg.contains("AndroidKeyStore") => Ok(()),
+        r => bad_result("from", &format!("NotSupportedByStore, got {r:?}")),
+    }
+}
+
+#[cfg(all(feature = "biometric", feature = "legacy"))]
+fn auth_canceled(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    use crate::biometric::{
+        Java_io_crates_keyring_KeyringBiometricPrompt_onResult as on_result, receive_result,
+        wait_for_result,
+    };
+    use jni::objects::JObject;
+
+    let mut env = vm.attach_current_thread().unwrap();
+    // the prompt's error codes: canceled, user canceled, negative button, and lockout
+    let cases = [(5, true), (10, true), (13, true), (7, false)];
+    for (code, canceled) in cases {
+        let (handle, receiver) = wait_for_result();
+        // deliver the result as the Kotlin shim would
+        let message = env.new_string("Authentication failed").unwrap();
+        on_result(
+            unsafe { env.unsafe_clone() },
+            JObject::null(),
+            handle,
+            JObject::null(),
+            code,
+            message,
+        );
+        match receive_result(&mut env, handle, &receiver) {
+            Err(AndroidKeyringError::AuthCanceled) if canceled => {}
+            Err(AndroidKeyringError::AuthenticationRequired(_)) if !canceled => {}
+            r => {
+                return bad_result(
+                    "receive_result",
+                    &format!(
+                        "canceled = {canceled} for error {code}, got {:?}",
+                        r.as_ref().map(Option::is_some)
+                    ),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn strongbox(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    // Emulators don't have StrongBox, so there this exercises the fallback to the TEE.
+    let store_config = HashMap::from(STRONGBOX_STORE_CONFIG);
+    let store = crate::Store::new_with_configuration(&store_config)?;
+    let entry = store.build("strongbox", "user", None)?;
+    entry.set_password("test")?;
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        Ok(p) => return bad_result("get_password", &format!("'test', got '{p}'")),
+        Err(e) => return bad_result("get_password", &format!("'test', got {e:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn key_expired(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let store_config = HashMap::from(EXPIRED_STORE_CONFIG);
+    let store = crate::Store::new_with_configuration(&store_config)?;
+    let entry = store.build("expired", "user", None)?;
+    match entry.set_password("test") {
+        Err(keyring_core::Error::NoStorageAccess(err)) => {
+            match err.downcast_ref::<AndroidKeyringError>() {
+                Some(AndroidKeyringError::KeyExpired) => {}
+                x => return bad_result("set_password", &format!("KeyExpired, got {x:?}")),
+            }
+        }
+        r => return bad_result("set_password", &format!("KeyExpired, got {r:?}")),
+    }
+    Ok(())
+}
+
+fn key_security_info(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    // Emulators vary in what they report, so this only checks that the calls succeed.
+    let mut env = vm.attach_current_thread().unwrap();
+    let alias = "key-security-info-test";
+    generate_test_key(&mut env, alias)?;
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    let key = keystore.get_key(&mut env, alias).unwrap().unwrap();
+    let key_info = key.get_key_info(&mut env);
+    let result = key_info.and_then(|info| {
+        let hardware = info.is_inside_secure_hardware(&mut env)?;
+        let level = info.get_security_level(&mut env)?;
+        Ok((hardware, level))
+    });
+    keystore.delete_entry(&mut env, alias).unwrap();
+    match result {
+        Ok((hardware, level)) => log::info!("Key in hardware: {hardware}, level: {level:?}"),
+        Err(e) => return bad_result("get_key_info", &format!("success, got {e:?}")),
+    }
+    let entry = Entry::new("key-security-info", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    match cred.key_security_info() {
+        Ok(info) => log::info!("Store key security info: {info:?}"),
+        Err(e) => return bad_result("key_security_info", &format!("success, got {e:?}")),
+    }
+    Ok(())
+}
+
+fn attestation_chain(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let chain = store.attestation_chain(b"attestation-test-challenge")?;
+    if chain.is_empty() || chain.iter().any(|cert| cert.is_empty()) {
+        return bad_result("attestation_chain", &format!("certificates, got {chain:?}"));
+    }
+    // each DER-encoded certificate is an ASN.1 SEQUENCE
+    if chain.iter().any(|cert| cert[0] != 0x30) {
+        return bad_result("attestation_chain", "DER-encoded certificates");
+    }
+    log::info!("Attestation chain has {} certificates", chain.len());
+    Ok(())
+}
+
+fn signing_key(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    match store.generate_signing_key("") {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "alias" => {}
+        r => return bad_result("generate_signing_key", &format!("Invalid, got {r:?}")),
+    }
+    let public_key = store.generate_signing_key("signing-test")?;
+    // an X.509 SubjectPublicKeyInfo is an ASN.1 SEQUENCE
+    if public_key.first() != Some(&0x30) {
+        return bad_result("generate_signing_key", &format!("DER, got {public_key:?}"));
+    }
+    let mut env = vm.attach_current_thread().unwrap();
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    if !keystore
+        .contains_alias(&mut env, "crypto-test/signing/signing-test")
+        .unwrap()
+    {
+        return bad_result("contains_alias", "the signing key to be in the KeyStore");
+    }
+    // generating it again replaces it
+    let replaced = store.generate_signing_key("signing-test")?;
+    if replaced == public_key {
+        return bad_result("generate_signing_key", "a new key");
+    }
+    Ok(())
+}
+
+fn hmac(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    match store.hmac("", b"data") {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "alias" => {}
+        r => return bad_result("hmac", &format!("Invalid, got {r:?}")),
+    }
+    let mac = store.hmac("hmac-test", b"fixed input")?;
+    if mac.len() != 32 {
+        return bad_result("hmac", &format!("32 bytes, got {}", mac.len()));
+    }
+    let mut env = vm.attach_current_thread().unwrap();
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    if !keystore
+        .contains_alias(&mut env, "crypto-test/hmac/hmac-test")
+        .unwrap()
+    {
+        return bad_result("contains_alias", "the HMAC key to be in the KeyStore");
+    }
+    // the key is reused, so the MAC is stable
+    if store.hmac("hmac-test", b"fixed input")? != mac {
+        return bad_result("hmac", "the same MAC for the same input");
+    }
+    if store.hmac("hmac-test", b"other input")? == mac {
+        return bad_result("hmac", "a different MAC for different input");
+    }
+    if store.hmac("other-hmac-test", b"fixed input")? == mac {
+        return bad_result("hmac", "a different MAC for a different key");
+    }
+    Ok(())
+}
+
+const IMPORTED_KEY: [u8; 32] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+];
+
+#[cfg(feature = "content-provider")]
+fn content_provider_query(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    use crate::content_provider::{
+        Java_io_crates_keyring_KeyringContentProvider_query as query, MatrixCursor, Uri, share,
+        unshare,
+    };
+    use jni::objects::JObject;
+
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let service = "content-provider";
+    store.build(service, "user1", None)?.set_secret(b"first")?;
+    store.build(service, "user2", None)?.set_secret(b"second")?;
+    let mut env = vm.attach_current_thread().unwrap();
+    // query as the Kotlin shim would, returning the rows of the cursor, if any
+    let mut rows = |path: &str| {
+        let uri = Uri::parse(&mut env, &format!("content://io.crates.keyring.test{path}")).unwrap();
+        let uri = env.new_local_ref(uri.as_obj()).unwrap();
+        let cursor = query(unsafe { env.unsafe_clone() }, JObject::null(), uri);
+        let cursor = MatrixCursor::from_raw(&mut env, cursor).unwrap()?;
+        let mut rows = Vec::new();
+        while cursor.move_to_next(&mut env).unwrap() {
+            let service = cursor.get_string(&mut env, 0).unwrap();
+            let user = cursor.get_string(&mut env, 1).unwrap();
+            let secret = cursor.get_blob(&mut env, 2).unwrap();
+            rows.push((service, user, secret));
+        }
+        rows.sort();
+        Some(rows)
+    };
+    let row = |user: &str, secret: &[u8]| (service.to_string(), user.to_string(), secret.to_vec());
+    // nothing is exposed until the service is shared
+    if let Some(r) = rows("/content-provider") {
+        return bad_result("query", &format!("null for an unshared service, got {r:?}"));
+    }
+    share(store.clone(), service, true);
+    match rows("/content-provider") {
+        Some(r) if r == [row("user1", b"first"), row("user2", b"second")] => {}
+        r => return bad_result("query", &format!("both credentials, got {r:?}")),
+    }
+    match rows("/content-provider/user2") {
+        Some(r) if r == [row("user2", b"second")] => {}
+        r => return bad_result("query", &format!("user2's credential, got {r:?}")),
+    }
+    match rows("/content-provider/missing") {
+        Some(r) if r.is_empty() => {}
+        r => return bad_result("query", &format!("no credentials, got {r:?}")),
+    }
+    if let Some(r) = rows("/content-provider/user2/extra") {
+        return bad_result("query", &format!("null for a malformed path, got {r:?}"));
+    }
+    // by default, the envelopes are shared rather than the secrets
+    share(store.clone(), service, false);
+    let entry = store.build(service, "user2", None)?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    let envelope = cred.dump_raw()?.unwrap();
+    match rows("/content-provider/user2") {
+        Some(r) if r == [row("user2", envelope.bytes())] => {}
+        r => return bad_result("query", &format!("user2's envelope, got {r:?}")),
+    }
+    if !unshare(service) {
+        return bad_result("unshare", "true for a shared service");
+    }
+    if let Some(r) = rows("/content-provider") {
+        return bad_result("query", &format!("null once unshared, got {r:?}"));
+    }
+    store.build(service, "user1", None)?.delete_credential()?;
+    store.build(service, "user2", None)?.delete_credential()?;
+    Ok(())
+}
+
+#[cfg(feature = "content-provider")]
+fn content_provider_insert(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    use crate::content_provider::{
+        Java_io_crates_keyring_KeyringContentProvider_insert as insert, Uri, share, unshare,
+    };
+    use jni::{objects::JObject, sys::JNI_TRUE};
+
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let service = "content-provider-insert";
+    let mut env = vm.attach_current_thread().unwrap();
+    // insert as the Kotlin shim would, returning whether it succeeded
+    let mut inserted = |path: &str, secret: &[u8]| {
+        let uri = format!("content://io.crates.keyring.test/{service}{path}");
+        let uri = Uri::parse(&mut env, &uri).unwrap();
+        let uri = env.new_local_ref(uri.as_obj()).unwrap();
+        let secret = env.byte_array_from_slice(secret).unwrap();
+        insert(unsafe { env.unsafe_clone() }, JObject::null(), uri, secret) == JNI_TRUE
+    };
+    let entry = store.build(service, "user1", None)?;
+    // nothing is exposed until the service is shared
+    if inserted("/user1", b"unshared") {
+        return bad_result("insert", "false for an unshared service");
+    }
+    if !matches!(entry.get_secret(), Err(keyring_core::Error::NoEntry)) {
+        return bad_result("insert", "nothing stored for an unshared service");
+    }
+    share(store.clone(), service, false);
+    if !inserted("/user1", b"first") {
+        return bad_result("insert", "true for a named user");
+    }
+    match entry.get_secret() {
+        Ok(secret) if secret == b"first" => {}
+        r => return bad_result("insert", &format!("the inserted secret, got {r:?}")),
+    }
+    if !inserted("/user1", b"second") {
+        return bad_result("insert", "true for an existing credential");
+    }
+    match entry.get_secret() {
+        Ok(secret) if secret == b"second" => {}
+        r => return bad_result("insert", &format!("the replaced secret, got {r:?}")),
+    }
+    // an insert must name a user
+    if inserted("", b"everyone") {
+        return bad_result("insert", "false for a path without a user");
+    }
+    if inserted("/user1/extra", b"malformed") {
+        return bad_result("insert", "false for a malformed path");
+    }
+    match entry.get_secret() {
+        Ok(secret) if secret == b"second" => {}
+        r => return bad_result("insert", &format!("the secret unchanged, got {r:?}")),
+    }
+    unshare(service);
+    entry.delete_credential()?;
+    Ok(())
+}
+
+#[cfg(feature = "content-provider")]
+fn content_provider_delete(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    use crate::content_provider::{
+        Java_io_crates_keyring_KeyringContentProvider_delete as delete, Uri, share, unshare,
+    };
+    use jni::objects::JObject;
+
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let service = "content-provider-delete";
+    let users = ["user1", "user2", "user3"];
+    for user in users {
+        store
+            .build(service, user, None)?
+            .set_secret(user.as_bytes())?;
+    }
+    let mut env = vm.attach_current_thread().unwrap();
+    // delete as the Kotlin shim would, returning the count
+    let mut deleted = |path: &str| {
+        let uri = format!("content://io.crates.keyring.test/{service}{path}");
+        let uri = Uri::parse(&mut env, &uri).unwrap();
+        let uri = env.new_local_ref(uri.as_obj()).unwrap();
+        delete(unsafe { env.unsafe_clone() }, JObject::null(), uri)
+    };
+    // nothing is exposed until the service is shared
+    if deleted("") != 0 {
+        return bad_result("delete", "0 for an unshared service");
+    }
+    share(store.clone(), service, false);
+    match deleted("/user1") {
+        1 => {}
+        n => return bad_result("delete", &format!("1 for user1, got {n}")),
+    }
+    match deleted("/user1") {
+        0 => {}
+        n => return bad_result("delete", &format!("0 for a deleted user, got {n}")),
+    }
+    match deleted("/user2/extra") {
+        0 => {}
+        n => return bad_result("delete", &format!("0 for a malformed path, got {n}")),
+    }
+    match deleted("") {
+        2 => {}
+        n => return bad_result("delete", &format!("2 for the service, got {n}")),
+    }
+    for user in users {
+        if !matches!(
+            store.build(service, user, None)?.get_secret(),
+            Err(keyring_core::Error::NoEntry)
+        ) {
+            return bad_result("delete", &format!("no credential for {user}"));
+        }
+    }
+    match deleted("") {
+        0 => {}
+        n => return bad_result("delete", &format!("0 for an empty service, got {n}")),
+    }
+    unshare(service);
+    Ok(())
+}
+
+#[cfg(feature = "iv-check")]
+fn iv_reuse(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    // ordinary writes get fresh IVs
+    let entry = store.build("iv-reuse", "user", None)?;
+    entry.set_secret(b"first")?;
+    entry.set_secret(b"second")?;
+    entry.delete_credential()?;
+    let mut env = vm.attach_current_thread().unwrap();
+    let key = SecretKeySpec::new(&mut env, &IMPORTED_KEY, KEY_ALGORITHM_AES).unwrap();
+    let value = encrypt(
+        &mut env,
+        key.into(),
+        Transformation::AesGcm,
+        None,
+        b"iv-reuse",
+    )?;
+    crate::crypto::check_iv_unique("iv-reuse-test", &value)?;
+    // checking the same value again injects a duplicate IV
+    match crate::crypto::check_iv_unique("iv-reuse-test", &value) {
+        Err(AndroidKeyringError::KeyringError(keyring_core::Error::PlatformFailure(_))) => {}
+        r => return bad_result("check_iv_unique", &format!("PlatformFailure, got {r:?}")),
+    }
+    // IVs are only tracked per key
+    crate::crypto::check_iv_unique("other-iv-reuse-test", &value)?;
+    Ok(())
+}
+
+fn import_key(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let alias = "import-key-test";
+    let protection =
+        KeyProtectionBuilder::new(env, KEY_ALGORITHM_AES, PURPOSE_DECRYPT | PURPOSE_ENCRYPT)
+            .unwrap()
+            .set_block_modes(env, &[BLOCK_MODE_GCM])
+            .unwrap()
+            .set_encryption_paddings(env, &[ENCRYPTION_PADDING_NONE])
+            .unwrap()
+            .build(env)
+            .unwrap();
+    let key = SecretKeySpec::new(env, &IMPORTED_KEY, KEY_ALGORITHM_AES)
+        .unwrap()
+        .into();
+    let keystore = KeyStore::get_instance(env, PROVIDER).unwrap();
+    keystore.load(env).unwrap();
+    keystore.set_entry(env, alias, &key, protection).unwrap();
+    let result = (|| {
+        let key = keystore.get_key(env, alias).unwrap().unwrap();
+        let ciphertext = encrypt(env, key, Transformation::default(), None, b"imported")?;
+        let key = keystore.get_key(env, alias).unwrap().unwrap();
+        decrypt(env, key, None, ciphertext)
+    })();
+    keystore.delete_entry(env, alias).unwrap();
+    match result {
+        Ok(plaintext) if plaintext == b"imported" => {}
+        r => return bad_result("decrypt", &format!("'imported', got {r:?}")),
+    }
+    // a store can have its key replaced until it holds credentials
+    let store = crate::Store::new_with_configuration(&HashMap::from(IMPORT_STORE_CONFIG))?;
+    store.import_key(&IMPORTED_KEY)?;
+    let entry = store.build("import", "user", None)?;
+    entry.set_password("test")?;
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    match store.import_key(&IMPORTED_KEY) {
+        Err(keyring_core::Error::Invalid(_, _)) => {}
+        r => return bad_result("import_key", &format!("Invalid, got {r:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+/// The values of "golden" encrypted by [golden_ciphertext], unbound and
+/// bound to "aad", as computed off-device with an independent AES-GCM.
+#[cfg(feature = "test-fixtures")]
+const GOLDEN_VALUES: [[u8; 39]; 2] = [
+    [
+        0x41, 0x4b, 0x01, 0x00, 0x0c, 0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9,
+        0xaa, 0xab, 0x81, 0x77, 0x10, 0x49, 0x20, 0xa5, 0x99, 0xb5, 0x6c, 0xb5, 0xf5, 0xeb, 0xf0,
+        0x11, 0xe0, 0x08, 0xee, 0x2e, 0xe7, 0x53, 0x3e, 0xd1,
+    ],
+    [
+        0x41, 0x4b, 0x01, 0x80, 0x0c, 0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9,
+        0xaa, 0xab, 0x81, 0x77, 0x10, 0x49, 0x20, 0xa5, 0x5d, 0x03, 0x47, 0xf1, 0x58, 0x11, 0x2c,
+        0x29, 0x47, 0x69, 0x7f, 0x2f, 0xb6, 0x8a, 0x0a, 0x3a,
+    ],
+];
+
+#[cfg(feature = "test-fixtures")]
+fn golden_ciphertext(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    use crate::test_fixtures::{encrypt_fixed, import_fixed_key};
+
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let alias = "golden-test";
+    import_fixed_key(env, alias)?;
+    let keystore = KeyStore::get_instance(env, PROVIDER).unwrap();
+    keystore.load(env).unwrap();
+    let aad = Some(b"aad".as_slice());
+    let result = (|| {
+        let unbound = encrypt_fixed(env, alias, None, b"golden")?;
+        let bound = encrypt_fixed(env, alias, aad, b"golden")?;
+        // the golden values can be read like any others
+        let key = keystore.get_key(env, alias).unwrap().unwrap();
+        let plaintext = decrypt(env, key, aad, GOLDEN_VALUES[1].to_vec())?;
+        Ok::<_, keyring_core::Error>((unbound, bound, plaintext))
+    })();
+    keystore.delete_entry(env, alias).unwrap();
+    let (unbound, bound, plaintext) = result?;
+    if unbound != GOLDEN_VALUES[0] || bound != GOLDEN_VALUES[1] {
+        let msg = format!("the golden values, got {unbound:?} and {bound:?}");
+        return bad_result("encrypt_fixed", &msg);
+    }
+    if plaintext != b"golden" {
+        return bad_result("decrypt", &format!("'golden', got {plaintext:?}"));
+    }
+    Ok(())
+}
+
+fn application_context(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let given = {
+        let ctx = ndk_context::android_context();
+        let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+        Context::new(&env, context).unwrap()
+    };
+    let application = given.get_application_context(&mut env).unwrap();
+    // stores keep the application context by default
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    if !store.context().is_same(&env, &application).unwrap() {
+        return bad_result("new_with_configuration", "the application context");
+    }
+    // unless they are configured to keep the given one
+    let store = crate::Store::new_with_configuration(&HashMap::from(GIVEN_CONTEXT_STORE_CONFIG))?;
+    if !store.context().is_same(&env, &given).unwrap() {
+        return bad_result("new_with_configuration", "the given context");
+    }
+    Ok(())
+}
+
+fn multi_process(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(MULTI_PROCESS_STORE_CONFIG))?;
+    let entry = store.build("multi-process", "user", None)?;
+    let path = {
+        let mut env = vm.attach_current_thread().unwrap();
+        let context = ctx.clone().into_raw();
+        // where `getSharedPreferences` keeps the file
+        let dir = env
+            .call_method(&context, "getDataDir", "()Ljava/io/File;", &[])
+            .unwrap()
+            .l()
+            .unwrap();
+        let dir = env
+            .call_method(&dir, "getPath", "()Ljava/lang/String;", &[])
+            .unwrap()
+            .l()
+            .unwrap();
+        let dir: String = env.get_string(&dir.into()).unwrap().into();
+        format!("{dir}/shared_prefs/multi-process-test.xml")
+    };
+    entry.set_password("from the other process")?;
+    let other = std::fs::read(&path).unwrap();
+    entry.set_password("from this process")?;
+    let mut env = vm.attach_current_thread().unwrap();
+    let shared = ctx
+        .get_shared_preferences(&mut env, "multi-process-test", MODE_PRIVATE)
+        .unwrap();
+    let mine = shared.get_binary(&mut env, "user@multi-process").unwrap();
+    // another process commits its change by rewriting the file
+    std::fs::write(&path, other).unwrap();
+    // a handle opened in the default mode still has the stale value
+    let stale = shared.get_binary(&mut env, "user@multi-process").unwrap();
+    if stale != mine {
+        return bad_result("get_binary", &format!("{mine:?}, got {stale:?}"));
+    }
+    // but the store re-reads the file
+    match entry.get_password() {
+        Ok(p) if p.eq("from the other process") => {}
+        r => {
+            return bad_result(
+                "get_password",
+                &format!("'from the other process', got {r:?}"),
+            );
+        }
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn cbc_round_trip(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(CBC_STORE_CONFIG))?;
+    let entry = store.build("cbc", "user", None)?;
+    entry.set_password("test")?;
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    let info = cred.protection_info()?;
+    if info.aead {
+        return bad_result("protection_info", &format!("no AEAD, got {info:?}"));
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn cbor_round_trip(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(CBOR_STORE_CONFIG))?;
+    let entry = store.build("cbor", "user", None)?;
+    entry.set_password("test")?;
+    {
+        let mut env = vm.attach_current_thread().unwrap();
+        let shared = ctx
+            .get_shared_preferences(&mut env, "cbor-test", MODE_PRIVATE)
+            .unwrap();
+        let value = shared.get_binary(&mut env, "user@cbor").unwrap().unwrap();
+        // a map of six fields
+        if value.first() != Some(&0xa6) {
+            return bad_result("set_password", &format!("a CBOR map, got {value:?}"));
+        }
+    }
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    // CBOR values are described like compact ones
+    let description = store.describe_service("cbor")?;
+    if !description.contains(r#""version":1,"iv_len":12,"ciphertext_len":20"#) {
+        return bad_result(
+            "describe_service",
+            &format!("a GCM value, got {description}"),
+        );
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn transformation_option(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    // the default store encrypts with GCM
+    let entry = Entry::new("transformation", "user")?;
+    entry.set_password("test")?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    let info = cred.protection_info()?;
+    if !info.aead {
+        return bad_result("protection_info", &format!("AEAD, got {info:?}"));
+    }
+    entry.delete_credential()?;
+    // unknown transformations are rejected before a store is created
+    let mut config = HashMap::from(CBC_STORE_CONFIG);
+    config.insert("transformation", "AES/ECB/NoPadding");
+    match crate::Store::new_with_configuration(&config) {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "transformation" => {}
+        r => return bad_result("new_with_configuration", &format!("Invalid, got {r:?}")),
+    }
+    Ok(())
+}
+
+fn swapped_blobs(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let entry1 = Entry::new("swapped-blobs", "user1")?;
+    let entry2 = Entry::new("swapped-blobs", "user2")?;
+    entry1.set_password("secret 1")?;
+    entry2.set_password("secret 2")?;
+    // Swap the encrypted secrets of the two entries
+    {
+        let mut env = vm.attach_current_thread().unwrap();
+        let shared = ctx
+            .get_shared_preferences(&mut env, "crypto-test", MODE_PRIVATE)
+            .unwrap();
+        let blob1 = shared
+            .get_binary(&mut env, "user1@swapped-blobs")
+            .unwrap()
+            .unwrap();
+        let blob2 = shared
+            .get_binary(&mut env, "user2@swapped-blobs")
+            .unwrap()
+            .unwrap();
+        let editor = shared.edit(&mut env).unwrap();
+        editor
+            .put_binary(&mut env, "user1@swapped-blobs", &blob2)
+            .unwrap();
+        editor
+            .put_binary(&mut env, "user2@swapped-blobs", &blob1)
+            .unwrap();
+        editor.commit(&mut env).unwrap();
+    }
+    for entry in [&entry1, &entry2] {
+        match entry.get_password() {
+            Err(keyring_core::Error::BadDataFormat(_, error)) => {
+                match error.downcast::<CorruptedData>().as_deref() {
+                    Ok(&CorruptedData::DecryptionFailure) => (),
+                    x => {
+                        return bad_result(
+                            "swapped_blobs",
+                            &format!("DecryptionFailure, got {x:?}"),
+                        );
+                    }
+                }
+            }
+            x => return bad_result("swapped_blobs", &format!("BadDataFormat, got {x:?}")),
+        }
+    }
+    entry1.delete_credential()?;
+    entry2.delete_credential()?;
+    Ok(())
+}
+
+fn large_secret(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    // large enough to be streamed through the cipher in chunks,
+    // which is over the default size limit
+    let secret: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let store = crate::Store::new_with_configuration(&HashMap::from(LARGE_STORE_CONFIG))?;
+    let entry = store.build("large-secret", "user", None)?;
+    entry.set_secret(&secret)?;
+    let result = entry.get_secret();
+    entry.delete_credential()?;
+    match result {
+        Ok(s) if s == secret => Ok(()),
+        Ok(s) => bad_result(
+            "get_secret",
+            &format!("the 2 MiB secret, got {} bytes", s.len()),
+        ),
+        Err(e) => bad_result("get_secret", &format!("the 2 MiB secret, got {e:?}")),
+    }
+}
+
+fn max_secret_size(_vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    let entry = Entry::new("max-secret-size", "user")?;
+    entry.set_secret(&vec![7; 64 * 1024])?;
+    match entry.get_secret() {
+        Ok(s) if s.len() == 64 * 1024 => {}
+        r => {
+            let r = r.map(|s| s.len());
+            return bad_result("get_secret", &format!("64 KiB, got {r:?} bytes"));
+        }
+    }
+    match entry.set_secret(&vec![8; 64 * 1024 + 1]) {
+        Err(keyring_core::Error::TooLong(name, 65536)) if name == "secret" => {}
+        r => return bad_result("set_secret", &format!("TooLong, got {r:?}")),
+    }
+    // the secret that was too large wasn't stored
+    match entry.get_secret() {
+        Ok(s) if s == vec![7; 64 * 1024] => {}
+        r => {
+            let r = r.map(|s| s.len());
+            return bad_result("get_secret", &format!("the old secret, got {r:?} bytes"));
+        }
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn output_size(vm: JavaVM, _ctx: Context) -> keyring_core::Result<()> {
+    const ENCRYPT_MODE: i32 = 1;
+    let mut env = vm.attach_current_thread().unwrap();
+    let alias = "output-size-test";
+    generate_test_key(&mut env, alias)?;
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    let key = keystore.get_key(&mut env, alias).unwrap().unwrap();
+    let cipher = Cipher::get_instance(&mut env, "AES/GCM/NoPadding").unwrap();
+    cipher.init(&mut env, ENCRYPT_MODE, &key).unwrap();
+    let size = cipher.get_output_size(&mut env, 100);
+    keystore.delete_entry(&mut env, alias).unwrap();
+    // GCM output is the input plus a 16-byte tag
+    match size {
+        Ok(116) => Ok(()),
+        r => bad_result("get_output_size", &format!("116, got {r:?}")),
+    }
+}
+
+fn shared_preferences_remove(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let shared = ctx
+        .get_shared_preferences(env, "crypto-test", MODE_PRIVATE)
+        .unwrap();
+    let key = "shared-preferences-remove";
+    let editor = shared.edit(env).unwrap();
+    editor.put_string(env, key, "value").unwrap();
+    editor.commit(env).unwrap();
+    match shared.get_string(env, key) {
+        Ok(Some(v)) if v == "value" => {}
+        r => return bad_result("get_string", &format!("'value', got {r:?}")),
+    }
+    let editor = shared.edit(env).unwrap();
+    editor.remove(env, key).unwrap().commit(env).unwrap();
+    match shared.get_string(env, key) {
+        Ok(None) => Ok(()),
+        r => bad_result("get_string", &format!("None, got {r:?}")),
+    }
+}
+
+fn shared_preferences_get_all_keys(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let shared = ctx
+        .get_shared_preferences(env, "get-all-keys-test", MODE_PRIVATE)
+        .unwrap();
+    let users = ["user1", "user2", "user3"];
+    let editor = shared.edit(env).unwrap();
+    for user in users {
+        editor.put_string(env, user, "value").unwrap();
+    }
+    editor.commit(env).unwrap();
+    let keys = shared.get_all_keys(env);
+    ctx.delete_shared_preferences(env, "get-all-keys-test")
+        .unwrap();
+    match keys {
+        Ok(mut keys) => {
+            keys.sort();
+            if keys != users {
+                return bad_result("get_all_keys", &format!("{users:?}, got {keys:?}"));
+            }
+        }
+        Err(e) => return bad_result("get_all_keys", &format!("{users:?}, got {e:?}")),
+    }
+    Ok(())
+}
+
+fn change_listener(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let callback = Arc::new(move |key: Option<&str>| {
+        let _ = sender.send(key.map(str::to_string));
+    });
+    let listener = ChangeListener::new(env, &ctx, callback).unwrap();
+    let watched = ctx
+        .get_shared_preferences(env, "change-listener-test", MODE_PRIVATE)
+        .unwrap();
+    watched
+        .register_on_shared_preference_change_listener(env, &listener)
+        .unwrap();
+    // write through a separate handle on the same file
+    let writer = ctx
+        .get_shared_preferences(env, "change-listener-test", MODE_PRIVATE)
+        .unwrap();
+    let editor = writer.edit(env).unwrap();
+    editor.put_string(env, "changed-key", "value").unwrap();
+    editor.commit(env).unwrap();
+    // notifications are posted to the main thread unless we are on it
+    let result = receiver.recv_timeout(std::time::Duration::from_secs(5));
+    drop(listener);
+    ctx.delete_shared_preferences(env, "change-listener-test")
+        .unwrap();
+    match result {
+        Ok(Some(key)) if key == "changed-key" => Ok(()),
+        r => bad_result("change_listener", &format!("changed-key, got {r:?}")),
+    }
+}
+
+fn shared_preferences_long(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let shared = ctx
+        .get_shared_preferences(env, "crypto-test", MODE_PRIVATE)
+        .unwrap();
+    let key = "shared-preferences-long";
+    match shared.get_long(env, key) {
+        Ok(None) => {}
+        r => return bad_result("get_long", &format!("None, got {r:?}")),
+    }
+    let editor = shared.edit(env).unwrap();
+    editor.put_long(env, key, i64::MAX).unwrap();
+    editor.commit(env).unwrap();
+    let result = shared.get_long(env, key);
+    shared
+        .edit(env)
+        .unwrap()
+        .remove(env, key)
+        .unwrap()
+        .commit(env)
+        .unwrap();
+    match result {
+        Ok(Some(i64::MAX)) => Ok(()),
+        r => bad_result("get_long", &format!("{}, got {r:?}", i64::MAX)),
+    }
+}
+
+fn shared_preferences_int(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let shared = ctx
+        .get_shared_preferences(env, "crypto-test", MODE_PRIVATE)
+        .unwrap();
+    let key = "shared-preferences-int";
+    match shared.get_int(env, key) {
+        Ok(None) => {}
+        r => return bad_result("get_int", &format!("None, got {r:?}")),
+    }
+    let editor = shared.edit(env).unwrap();
+    editor.put_int(env, key, -42).unwrap();
+    editor.commit(env).unwrap();
+    let result = shared.get_int(env, key);
+    shared
+        .edit(env)
+        .unwrap()
+        .remove(env, key)
+        .unwrap()
+        .commit(env)
+        .unwrap();
+    match result {
+        Ok(Some(-42)) => Ok(()),
+        r => bad_result("get_int", &format!("-42, got {r:?}")),
+    }
+}
+
+fn shared_preferences_boolean(vm: JavaVM, ctx: Context) -> keyring_core::Result<()> {
+    let mut env = vm.attach_current_thread().unwrap();
+    let env = &mut env;
+    let shared = ctx
+        .get_shared_preferences(env, "crypto-test", MODE_PRIVATE)
+        .unwrap();
+    let key = "shared-preferences-boolean";
+    match shared.get_boolean(env, key) {
+        Ok(None) => {}
+        r => return bad_result("get_boolean", &format!("None, got {r:?}")),
+    }
+    // false is also the default that's returned for a missing key
+    let editor = shared.edit(env).unwrap();
+    editor.put_boolean(env, key, false).unwrap();
+    editor.commit(env).unwrap();
+    let result = shared.get_boolean(env, key);
+    shared
+        .edit(env)
+        .unwrap()
+        .remove(env, key)
+        .unwrap()
+        .commit(env)
+        .unwrap();
+    match result {
+        Ok(Some(false)) => Ok(()),
+        r => bad_result("get_boolean", &format!("false, got {r:?}")),
+    }
+}
@@ -1,72 +1,62 @@
-use std::ffi::CString;
-use std::panic::catch_unwind;
+use std::collections::HashMap;
 
-use android_log_sys::{__android_log_write, LogPriority};
+use jni::{JavaVM, objects::JObject};
 
-use keyring_core::Entry;
+use keyring_core::{Entry, api::CredentialStoreApi};
 
-pub fn run_tests() -> (usize, usize) {
-    let testing = [
+use crate::shared_preferences::{Context, MODE_PRIVATE};
+
+use super::test_suite::Suite;
+
+pub fn suite() -> Suite {
+    let tests = [
         ("setup", setup as fn() -> keyring_core::Result<()>),
         ("golden_path", golden_path),
         ("delete_credential", delete_credential),
         ("concurrent_access", concurrent_access),
+        ("concurrent_users", concurrent_users),
+        ("purge_service", purge_service),
+        ("delete_service", delete_service),
+        ("search_by_service", search_by_service),
+        ("search_by_user", search_by_user),
+        ("search_empty", search_empty),
+        ("attributes", attributes),
+        ("rotate_key", rotate_key),
+        ("rotate_protected", rotate_protected),
+        ("key_cache", key_cache),
+        ("require_auth", require_auth),
+        ("exists", exists),
+        ("file_names", file_names),
+        ("set_many", set_many),
+        ("shared_file", shared_file),
+        ("shared_file_service", shared_file_service),
+        ("timestamps", timestamps),
+        ("explicit_context", explicit_context),
+        ("null_context", null_context),
+        ("application_context", application_context),
+        ("read_retries", read_retries),
+        ("requires_authentication", requires_authentication),
+        ("on_key_invalidated", on_key_invalidated),
+        #[cfg(feature = "biometric")]
+        ("prompt_on_main_thread", prompt_on_main_thread),
+        #[cfg(feature = "biometric")]
+        ("crypto_object", crypto_object),
+        ("capabilities", capabilities),
+        ("compat_aliases", compat_aliases),
+        ("empty_secret", empty_secret),
+        ("user_index", user_index),
+        ("swapped_secrets", swapped_secrets),
+        ("upgrade_envelopes", upgrade_envelopes),
+        ("migrate_legacy", migrate_legacy),
+        ("key_size", key_size),
+        ("key_generation_backoff", key_generation_backoff),
         ("teardown", teardown),
-    ]
-    .iter()
-    .map(|(name, entry)| {
-        (name, move || -> keyring_core::Result<()> {
-            catch_unwind(entry)
-                .map_err(|e| {
-                    log::error!("Test {name} panicked: {e:?}");
-                    bad_result(name, "panicked")
-                })
-                .unwrap_or_else(|e| e)
-        })
-    })
-    .collect::<Vec<_>>();
-
-    let msg = c"Running LegacyStore tests...";
-    let tag = c"unit-test";
-    let level = LogPriority::INFO as i32;
-    unsafe {
-        __android_log_write(level, tag.as_ptr(), msg.as_ptr());
-    }
-    let mut successes = 0;
-    let mut failures = 0;
-    for (name, testing) in testing {
-        let level;
-        let msg;
-        match testing() {
-            Ok(()) => {
-                level = LogPriority::INFO as i32;
-                msg = format!("{name} success");
-                successes += 1;
-            }
-            Err(e) => {
-                level = LogPriority::ERROR as i32;
-                msg = format!("{name} error: {e:?}");
-                failures += 1;
-            }
-        }
-
-        let msg = CString::new(msg).unwrap();
-        let tag = c"unit-test";
-        unsafe {
-            __android_log_write(level, tag.as_ptr(), msg.as_ptr());
-        }
-    }
-    let msg = CString::new(format!(
-        "Legacy: {} successes, {} failures",
-        successes, failures
-    ))
-    .unwrap();
-    let tag = c"unit-test";
-    let level = LogPriority::INFO as i32;
-    unsafe {
-        __android_log_write(level, tag.as_ptr(), msg.as_ptr());
+    ];
+    let mut suite = Suite::new("Legacy");
+    for (name, test) in tests {
+        suite.add(name, test);
     }
-    (successes, failures)
+    suite
 }
 
 fn bad_result(op: &str, msg: &str) -> keyring_core::Result<()> {
@@ -104,6 +94,77 @@ fn cleanup() -> keyring_core::Result<()> {
     // concurrent_access:
     let entry1 = Entry::new("concurrent", "user")?;
     _ = entry1.delete_credential();
+    // concurrent_users:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("concurrent-users-service");
+    // purge_service:
+    for user in ["user1", "user2"] {
+        _ = Entry::new("purge-service", user)?.delete_credential();
+    }
+    // search:
+    for user in ["search-user1", "search-user2", "other-user"] {
+        _ = Entry::new("search-service", user)?.delete_credential();
+    }
+    // attributes:
+    _ = Entry::new("attributes-service", "user")?.delete_credential();
+    // explicit_context:
+    _ = Entry::new("explicit-context-service", "user")?.delete_credential();
+    // timestamps:
+    _ = Entry::new("timestamp-service", "user")?.delete_credential();
+    // shared_file:
+    let store = crate::LegacyStore::from_ndk_context()?;
+    for (service, user) in SHARED_FILE_CREDENTIALS {
+        let modifiers = HashMap::from([("file_name", "shared-file-test")]);
+        _ = store
+            .build(service, user, Some(&modifiers))?
+            .delete_credential();
+    }
+    // shared_file_service:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("shared-rotate-service");
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("shared-other-service");
+    // set_many:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("batch-service");
+    // file_names:
+    for service in FILE_NAME_SERVICES {
+        _ = crate::LegacyStore::from_ndk_context()?.delete_service(service);
+    }
+    // exists:
+    _ = Entry::new("exists-service", "user")?.delete_credential();
+    // require_auth:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("auth-service");
+    // key_cache:
+    _ = Entry::new("key-cache-service", "user")?.delete_credential();
+    // rotate_protected:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("rotate-protected-service");
+    // rotate_key:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("rotate-service");
+    // delete_service:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("delete-service");
+    // requires_authentication:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("requires-auth-service");
+    // on_key_invalidated:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("invalidated-service");
+    // user_index:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("index-service");
+    // empty_secret:
+    _ = Entry::new("empty-secret-service", "user")?.delete_credential();
+    // swapped_secrets:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("swapped-service");
+    // upgrade_envelopes:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("upgrade-service");
+    // migrate_legacy:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("migrate-service");
+    // key_size:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("key-size-service");
+    // key_generation_backoff:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("backoff-service");
+    // compat_aliases:
+    _ = crate::LegacyStore::from_ndk_context()?.delete_service("compat-service");
+    // prompt_on_main_thread:
+    #[cfg(feature = "biometric")]
+    let _ = crate::LegacyStore::from_ndk_context()?.delete_service("prompt-service");
+    // crypto_object:
+    #[cfg(feature = "biometric")]
+    let _ = crate::LegacyStore::from_ndk_context()?.delete_service("crypto-object-service");
     Ok(())
 }
 
@@ -143,6 +204,9 @@ fn golden_path() -> keyring_core::Result<()> {
 }
 
 fn delete_credential() -> keyring_core::Result<()> {
+    use crate::shared_preferences::COMMITS;
+    use std::sync::atomic::Ordering;
+
     let entry1 = Entry::new("my-service", "delete-test")?;
     entry1.set_password("test")?;
     match entry1.get_password() {
@@ -150,11 +214,32 @@ fn delete_credential() -> keyring_core::Result<()> {
         Ok(p) => return bad_result("get_password", &format!("'test', got '{p}'")),
         Err(e) => return bad_result("get_password", &format!("'test', got {e:?}")),
     }
+    let commits = COMMITS.load(Ordering::SeqCst);
     entry1.delete_credential()?;
+    let committed = COMMITS.load(Ordering::SeqCst) - commits;
+    if committed != 1 {
+        return bad_result("delete_credential", &format!("one commit, got {committed}"));
+    }
+    match entry1.get_password() {
+        Ok(_) => return bad_result("get_credential", "NoEntry, but got password"),
+        Err(keyring_core::Error::NoEntry) => {}
+        Err(e) => return bad_result("get_credential", &format!("NoEntry, but got {e:?}")),
+    }
+    // deleting an absent credential fails
+    match entry1.delete_credential() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("delete_credential", &format!("NoEntry, got {r:?}")),
+    }
+    // unless it's built to be lenient
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let mods = HashMap::from([("idempotent_delete", "true")]);
+    let entry2 = store.build("my-service", "delete-test", Some(&mods))?;
+    entry2.delete_credential()?;
+    entry2.set_password("test")?;
+    entry2.delete_credential()?;
     match entry1.get_password() {
-        Ok(_) => bad_result("get_credential", "NoEntry, but got password"),
         Err(keyring_core::Error::NoEntry) => Ok(()),
-        Err(e) => bad_result("get_credential", &format!("NoEntry, but got {e:?}")),
+        r => bad_result("get_password", &format!("NoEntry, got {r:?}")),
     }
 }
 
@@ -196,3 +281,1280 @@ fn concurrent_access() -> keyring_core::Result<()> {
     }
     Ok(())
 }
+
+fn concurrent_users() -> keyring_core::Result<()> {
+    // writes of different users' secrets edit the same file
+    let all = (0..64)
+        .map(|i| {
+            std::thread::spawn(move || {
+                let entry = Entry::new("concurrent-users-service", &format!("user{i}")).unwrap();
+                entry.set_password(&i.to_string()).unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in all {
+        t.join()
+            .map_err(|_| keyring_core::Error::Invalid("join".to_string(), "failed".to_string()))?;
+    }
+    for i in 0..64 {
+        let entry = Entry::new("concurrent-users-service", &format!("user{i}"))?;
+        match entry.get_password() {
+            Ok(s) if s == i.to_string() => {}
+            r => return bad_result("get_password", &format!("'{i}', got {r:?}")),
+        }
+    }
+    // and none of them were lost from the index
+    let found = search_users(&[("service", "concurrent-users-service")])?;
+    if found.len() != 64 {
+        return bad_result("search", &format!("64 users, got {}", found.len()));
+    }
+    crate::LegacyStore::from_ndk_context()?.delete_service("concurrent-users-service")?;
+    Ok(())
+}
+
+fn purge_service() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let entries = [
+        Entry::new("purge-service", "user1")?,
+        Entry::new("purge-service", "user2")?,
+    ];
+    for entry in &entries {
+        entry.set_password("test")?;
+    }
+    let other = Entry::new("my-service", "purge-test")?;
+    other.set_password("test")?;
+    store.purge_service("purge-service")?;
+    for entry in &entries {
+        match entry.get_password() {
+            Err(keyring_core::Error::NoEntry) => {}
+            r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+        }
+    }
+    // other services are unaffected
+    match other.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    other.delete_credential()?;
+    Ok(())
+}
+
+fn delete_service() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let entries = [
+        Entry::new("delete-service", "user1")?,
+        Entry::new("delete-service", "user2")?,
+    ];
+    for entry in &entries {
+        entry.set_password("test")?;
+    }
+    store.delete_service("delete-service")?;
+    for entry in &entries {
+        match entry.get_password() {
+            Err(keyring_core::Error::NoEntry) => {}
+            r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+        }
+    }
+    // reading the entries above created a new key, so delete it too
+    store.delete_service("delete-service")?;
+    match store.delete_service("delete-service") {
+        Err(keyring_core::Error::NoEntry) => Ok(()),
+        r => bad_result("delete_service", &format!("NoEntry, got {r:?}")),
+    }
+}
+
+fn search_users(spec: &[(&str, &str)]) -> keyring_core::Result<Vec<(String, String)>> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let entries = store.search(&HashMap::from_iter(spec.iter().cloned()))?;
+    let mut found: Vec<_> = entries
+        .iter()
+        .filter_map(|entry| entry.get_specifiers())
+        .collect();
+    found.sort();
+    Ok(found)
+}
+
+fn search_by_service() -> keyring_core::Result<()> {
+    let users = ["search-user1", "search-user2", "other-user"];
+    for user in users {
+        Entry::new("search-service", user)?.set_password("test")?;
+    }
+    let found = search_users(&[("service", "search-service")])?;
+    let mut expected: Vec<_> = users
+        .iter()
+        .map(|user| ("search-service".to_string(), user.to_string()))
+        .collect();
+    expected.sort();
+    if found != expected {
+        return bad_result("search", &format!("{expected:?}, got {found:?}"));
+    }
+    // services must match exactly
+    let found = search_users(&[("service", "search-serv")])?;
+    if !found.is_empty() {
+        return bad_result("search", &format!("no entries, got {found:?}"));
+    }
+    Ok(())
+}
+
+fn search_by_user() -> keyring_core::Result<()> {
+    let found = search_users(&[("user", "search-user")])?;
+    let expected = vec![
+        ("search-service".to_string(), "search-user1".to_string()),
+        ("search-service".to_string(), "search-user2".to_string()),
+    ];
+    if found != expected {
+        return bad_result("search", &format!("{expected:?}, got {found:?}"));
+    }
+    Ok(())
+}
+
+fn search_empty() -> keyring_core::Result<()> {
+    let found = search_users(&[("service", "search-service"), ("user", "nobody")])?;
+    if !found.is_empty() {
+        return bad_result("search", &format!("no entries, got {found:?}"));
+    }
+    for user in ["search-user1", "search-user2", "other-user"] {
+        Entry::new("search-service", user)?.delete_credential()?;
+    }
+    Ok(())
+}
+
+fn attributes() -> keyring_core::Result<()> {
+    let entry = Entry::new("attributes-service", "user")?;
+    match entry.update_attributes(&HashMap::from([("label", "test")])) {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("update_attributes", &format!("NoEntry, got {r:?}")),
+    }
+    entry.set_password("test")?;
+    if !entry.get_attributes()?.is_empty() {
+        return bad_result("get_attributes", "no attributes");
+    }
+    entry.update_attributes(&HashMap::from([("label", "test"), ("created", "1")]))?;
+    entry.update_attributes(&HashMap::from([("label", "updated")]))?;
+    // attributes survive a change of secret
+    entry.set_password("test2")?;
+    let expected = HashMap::from([
+        ("label".to_string(), "updated".to_string()),
+        ("created".to_string(), "1".to_string()),
+    ]);
+    match entry.get_attributes() {
+        Ok(attributes) if attributes == expected => {}
+        r => return bad_result("get_attributes", &format!("{expected:?}, got {r:?}")),
+    }
+    // the attributes aren't mistaken for a credential
+    let found = search_users(&[("service", "attributes-service")])?;
+    if found.len() != 1 {
+        return bad_result("search", &format!("one entry, got {found:?}"));
+    }
+    entry.delete_credential()?;
+    match entry.get_attributes() {
+        Err(keyring_core::Error::NoEntry) => Ok(()),
+        r => bad_result("get_attributes", &format!("NoEntry, got {r:?}")),
+    }
+}
+
+fn rotate_key() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let entries = [
+        Entry::new("rotate-service", "user1")?,
+        Entry::new("rotate-service", "user2")?,
+        Entry::new("rotate-service", "user3")?,
+    ];
+    for (i, entry) in entries.iter().enumerate() {
+        entry.set_password(&format!("test{i}"))?;
+    }
+    entries[0].update_attributes(&HashMap::from([("label", "first")]))?;
+    store.rotate_key("rotate-service")?;
+    for (i, entry) in entries.iter().enumerate() {
+        match entry.get_password() {
+            Ok(p) if p == format!("test{i}") => {}
+            r => return bad_result("get_password", &format!("'test{i}', got {r:?}")),
+        }
+    }
+    // attributes aren't encrypted, so they are left as they were
+    match entries[0].get_attributes() {
+        Ok(attrs) if attrs.get("label").is_some_and(|v| v == "first") => {}
+        r => return bad_result("get_attributes", &format!("label=first, got {r:?}")),
+    }
+    // entries written after the rotation use the new key
+    entries[1].set_password("updated")?;
+    match entries[1].get_password() {
+        Ok(p) if p == "updated" => {}
+        r => return bad_result("get_password", &format!("'updated', got {r:?}")),
+    }
+    store.delete_service("rotate-service")?;
+    Ok(())
+}
+
+fn rotate_protected() -> keyring_core::Result<()> {
+    use crate::{
+        by_service::cred::{USERS_KEY, protected_alias},
+        by_store::cred::aad,
+        crypto::{Transformation, encrypt},
+        keystore::{KEY_ALGORITHM_AES, KeyGenerator, PROVIDER},
+    };
+
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let entry = Entry::new("rotate-protected-service", "user")?;
+    entry.set_password("test")?;
+    // a protected credential's key needs a secure lock screen, so write
+    // a secret with a key under its alias that doesn't require one
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let context = Context::new(&env, context).unwrap();
+    let alias = protected_alias("rotate-protected-service");
+    let spec = crate::LegacyCred::key_spec(&mut env, &context, &alias, None, None).unwrap();
+    let generator = KeyGenerator::get_instance(&mut env, KEY_ALGORITHM_AES, PROVIDER).unwrap();
+    generator.init(&mut env, spec.into()).unwrap();
+    let key = generator.generate_key(&mut env).unwrap().into();
+    let aad = aad("rotate-protected-service", "protected");
+    let value = encrypt(
+        &mut env,
+        key,
+        Transformation::default(),
+        Some(&aad),
+        b"protected",
+    )
+    .unwrap();
+    let file = context
+        .get_shared_preferences(&mut env, "rotate-protected-service", MODE_PRIVATE)
+        .unwrap();
+    let mut users = file.get_string_set(&mut env, USERS_KEY).unwrap().unwrap();
+    users.0.insert("protected".to_string());
+    let editor = file.edit(&mut env).unwrap();
+    editor.put_binary(&mut env, "protected", &value).unwrap();
+    editor.put_string_set(&mut env, USERS_KEY, &users).unwrap();
+    editor.commit(&mut env).unwrap();
+    // the service can't be rotated, and is left as it was
+    match store.rotate_key("rotate-protected-service") {
+        Err(keyring_core::Error::NotSupportedByStore(_)) => {}
+        r => return bad_result("rotate_key", &format!("NotSupportedByStore, got {r:?}")),
+    }
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    if file.get_binary(&mut env, "protected").unwrap() != Some(value) {
+        return bad_result("rotate_key", "the protected secret left as it was");
+    }
+    store.delete_service("rotate-protected-service")?;
+    Ok(())
+}
+
+fn key_cache() -> keyring_core::Result<()> {
+    use crate::by_service::cred::test_hooks::KEY_LOOKUPS;
+    use std::sync::atomic::Ordering;
+
+    let entry = Entry::new("key-cache-service", "user")?;
+    let before = KEY_LOOKUPS.load(Ordering::SeqCst);
+    entry.set_password("test")?;
+    for _ in 0..20 {
+        match entry.get_password() {
+            Ok(p) if p.eq("test") => {}
+            r => return bad_result("get_password", &format!("'test', got {r:?}")),
+        }
+    }
+    let lookups = KEY_LOOKUPS.load(Ordering::SeqCst) - before;
+    if lookups != 1 {
+        return bad_result("get_password", &format!("1 key lookup, got {lookups}"));
+    }
+    // a cached key that's been replaced is looked up again
+    let store = crate::LegacyStore::from_ndk_context()?;
+    store.rotate_key("key-cache-service")?;
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn require_auth() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let protected = store.build(
+        "auth-service",
+        "protected",
+        Some(&HashMap::from([("require_auth", "true")])),
+    )?;
+    let unprotected = store.build("auth-service", "unprotected", None)?;
+    let alias = |entry: &Entry| {
+        let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+        cred.key_alias()
+    };
+    if alias(&unprotected) != "auth-service" {
+        return bad_result(
+            "key_alias",
+            &format!("the service, got {}", alias(&unprotected)),
+        );
+    }
+    if alias(&protected) == alias(&unprotected) {
+        return bad_result("key_alias", "different aliases for protected credentials");
+    }
+    // an explicit false is the same as no modifier
+    let explicit = store.build(
+        "auth-service",
+        "explicit",
+        Some(&HashMap::from([("require_auth", "false")])),
+    )?;
+    if alias(&explicit) != alias(&unprotected) {
+        return bad_result(
+            "key_alias",
+            &format!("the service, got {}", alias(&explicit)),
+        );
+    }
+    // other modifiers are rejected
+    match store.build(
+        "auth-service",
+        "user",
+        Some(&HashMap::from([("other", "x")])),
+    ) {
+        Err(keyring_core::Error::Invalid(_, _)) => {}
+        r => return bad_result("build", &format!("Invalid, got {r:?}")),
+    }
+    unprotected.set_password("test")?;
+    match unprotected.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    store.delete_service("auth-service")?;
+    Ok(())
+}
+
+/// Overwrite a credential's stored secret with a value that isn't base64.
+fn corrupt_secret(service: &str, user: &str) {
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let context = Context::new(&env, context).unwrap();
+    let file = context
+        .get_shared_preferences(&mut env, service, MODE_PRIVATE)
+        .unwrap();
+    let editor = file.edit(&mut env).unwrap();
+    editor.put_string(&mut env, user, "not base64!").unwrap();
+    editor.commit(&mut env).unwrap();
+}
+
+fn exists() -> keyring_core::Result<()> {
+    let entry = Entry::new("exists-service", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+    if cred.exists()? {
+        return bad_result("exists", "false before the secret is set");
+    }
+    entry.set_password("test")?;
+    if !cred.exists()? {
+        return bad_result("exists", "true after the secret is set");
+    }
+    // a corrupted secret still exists, even though it can't be read
+    corrupt_secret("exists-service", "user");
+    if !cred.exists()? {
+        return bad_result("exists", "true for a corrupted secret");
+    }
+    match entry.get_password() {
+        Err(keyring_core::Error::BadDataFormat(_, _)) => {}
+        r => return bad_result("get_password", &format!("BadDataFormat, got {r:?}")),
+    }
+    entry.delete_credential()?;
+    if cred.exists()? {
+        return bad_result("exists", "false after the credential is deleted");
+    }
+    Ok(())
+}
+
+// the second is the encoding of the first, so they'd collide if '%' weren't encoded
+const FILE_NAME_SERVICES: [&str; 4] = [
+    "slash/service",
+    "slash%2Fservice",
+    "ünïcødé-服务",
+    "https://example.com",
+];
+
+fn file_names() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    for service in FILE_NAME_SERVICES {
+        Entry::new(service, "user")?.set_password(service)?;
+    }
+    for service in FILE_NAME_SERVICES {
+        match Entry::new(service, "user")?.get_password() {
+            Ok(p) if p == service => {}
+            r => return bad_result("get_password", &format!("{service:?}, got {r:?}")),
+        }
+        let found = search_users(&[("service", service)])?;
+        if found != [(service.to_string(), "user".to_string())] {
+            return bad_result(
+                "search",
+                &format!("one entry for {service:?}, got {found:?}"),
+            );
+        }
+    }
+    // services can't end with the suffixes of reserved key aliases
+    for service in ["my-service/rotation", "my-service/auth"] {
+        match Entry::new(service, "user") {
+            Err(keyring_core::Error::Invalid(_, _)) => {}
+            r => return bad_result("build", &format!("Invalid for {service:?}, got {r:?}")),
+        }
+    }
+    for service in FILE_NAME_SERVICES {
+        store.delete_service(service)?;
+    }
+    Ok(())
+}
+
+fn set_many() -> keyring_core::Result<()> {
+    use crate::shared_preferences::COMMITS;
+    use std::sync::atomic::Ordering;
+
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let users: Vec<String> = (0..50).map(|i| format!("user{i}")).collect();
+    let passwords: Vec<String> = (0..50).map(|i| format!("password{i}")).collect();
+    let secrets: Vec<(&str, &[u8])> = users
+        .iter()
+        .zip(&passwords)
+        .map(|(user, password)| (user.as_str(), password.as_bytes()))
+        .collect();
+    let commits = COMMITS.load(Ordering::SeqCst);
+    store.set_many("batch-service", &secrets)?;
+    let committed = COMMITS.load(Ordering::SeqCst) - commits;
+    if committed != 1 {
+        return bad_result("set_many", &format!("one commit, got {committed}"));
+    }
+    for (user, password) in users.iter().zip(&passwords) {
+        match Entry::new("batch-service", user)?.get_password() {
+            Ok(p) if &p == password => {}
+            r => return bad_result("get_password", &format!("{password:?}, got {r:?}")),
+        }
+    }
+    store.delete_service("batch-service")?;
+    Ok(())
+}
+
+const SHARED_FILE_CREDENTIALS: [(&str, &str); 3] = [
+    ("shared-service-a", "user1"),
+    ("shared-service-a", "user2"),
+    ("shared-service-b", "user1"),
+];
+
+fn shared_file() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let modifiers = HashMap::from([("file_name", "shared-file-test")]);
+    for (service, user) in SHARED_FILE_CREDENTIALS {
+        let entry = store.build(service, user, Some(&modifiers))?;
+        entry.set_password(&format!("{service}/{user}"))?;
+    }
+    for (service, user) in SHARED_FILE_CREDENTIALS {
+        let entry = store.build(service, user, Some(&modifiers))?;
+        match entry.get_password() {
+            Ok(p) if p == format!("{service}/{user}") => {}
+            r => return bad_result("get_password", &format!("{service}/{user}, got {r:?}")),
+        }
+        // the service's own file doesn't have it
+        if Entry::new(service, user)?.get_password().is_ok() {
+            return bad_result("get_password", "no secret in the service's file");
+        }
+    }
+    // each key is prefixed by its service
+    {
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+        let mut env = vm.attach_current_thread().unwrap();
+        let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+        let context = Context::new(&env, context).unwrap();
+        let file = context
+            .get_shared_preferences(&mut env, "shared-file-test", MODE_PRIVATE)
+            .unwrap();
+        if !file.contains(&mut env, "shared-service-a\0user1").unwrap() {
+            return bad_result("set_password", "a key of shared-service-a\\0user1");
+        }
+    }
+    // searches of the file filter by service
+    let search = |spec: &[(&str, &str)]| -> keyring_core::Result<Vec<(String, String)>> {
+        let mut spec = spec.to_vec();
+        spec.push(("file_name", "shared-file-test"));
+        search_users(&spec)
+    };
+    let found = search(&[("service", "shared-service-a")])?;
+    let expected: Vec<_> = SHARED_FILE_CREDENTIALS[..2]
+        .iter()
+        .map(|(s, u)| (s.to_string(), u.to_string()))
+        .collect();
+    if found != expected {
+        return bad_result("search", &format!("{expected:?}, got {found:?}"));
+    }
+    let found = search(&[("user", "user1")])?;
+    if found.len() != 2 {
+        return bad_result("search", &format!("two user1 credentials, got {found:?}"));
+    }
+    let found = search(&[])?;
+    if found.len() != 3 {
+        return bad_result("search", &format!("three credentials, got {found:?}"));
+    }
+    // but searches of the service's own file don't see them
+    let found = search_users(&[("service", "shared-service-a")])?;
+    if !found.is_empty() {
+        return bad_result("search", &format!("no credentials, got {found:?}"));
+    }
+    // found credentials are in the shared file
+    let spec = HashMap::from([
+        ("service", "shared-service-b"),
+        ("file_name", "shared-file-test"),
+    ]);
+    let entries = store.search(&spec)?;
+    match entries.first().map(|entry| entry.get_password()) {
+        Some(Ok(p)) if p == "shared-service-b/user1" => {}
+        r => {
+            return bad_result(
+                "get_password",
+                &format!("shared-service-b/user1, got {r:?}"),
+            );
+        }
+    }
+    // services in shared files can't contain the separator
+    match store.build("bad\0service", "user", Some(&modifiers)) {
+        Err(keyring_core::Error::Invalid(attr, _)) if attr == "service" => {}
+        r => return bad_result("build", &format!("an invalid service, got {r:?}")),
+    }
+    for (service, user) in SHARED_FILE_CREDENTIALS {
+        store
+            .build(service, user, Some(&modifiers))?
+            .delete_credential()?;
+    }
+    let found = search(&[])?;
+    if !found.is_empty() {
+        return bad_result("search", &format!("no credentials, got {found:?}"));
+    }
+    Ok(())
+}
+
+fn shared_file_service() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let modifiers = HashMap::from([("file_name", "shared-service-test")]);
+    let own = Entry::new("shared-rotate-service", "user")?;
+    let shared = store.build("shared-rotate-service", "user", Some(&modifiers))?;
+    let other = store.build("shared-other-service", "user", Some(&modifiers))?;
+    own.set_password("own")?;
+    shared.set_password("shared")?;
+    other.set_password("other")?;
+    // rotating the service's key re-encrypts its secrets in the shared file too
+    store.rotate_key("shared-rotate-service")?;
+    for (entry, expected) in [(&own, "own"), (&shared, "shared"), (&other, "other")] {
+        match entry.get_password() {
+            Ok(p) if p == expected => {}
+            r => return bad_result("get_password", &format!("{expected:?}, got {r:?}")),
+        }
+    }
+    // purging the service removes them, but not those of the file's other services
+    store.purge_service("shared-rotate-service")?;
+    for entry in [&own, &shared] {
+        match entry.get_password() {
+            Err(keyring_core::Error::NoEntry) => {}
+            r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+        }
+    }
+    match other.get_password() {
+        Ok(p) if p == "other" => {}
+        r => return bad_result("get_password", &format!("'other', got {r:?}")),
+    }
+    // and so does deleting it
+    shared.set_password("shared")?;
+    store.delete_service("shared-rotate-service")?;
+    match shared.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+    }
+    let spec = HashMap::from([("file_name", "shared-service-test")]);
+    let found = store.search(&spec)?;
+    if found.len() != 1 {
+        return bad_result("search", &format!("only the other service, got {found:?}"));
+    }
+    // the user name under which the shared files are listed is reserved
+    match Entry::new("shared-rotate-service", ".files") {
+        Err(keyring_core::Error::Invalid(attr, _)) if attr == "user" => {}
+        r => return bad_result("new", &format!("an invalid user, got {r:?}")),
+    }
+    // reading the entries above created a new key, so delete it too
+    store.delete_service("shared-rotate-service")?;
+    store.delete_service("shared-other-service")?;
+    Ok(())
+}
+
+fn timestamps() -> keyring_core::Result<()> {
+    let entry = Entry::new("timestamp-service", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+    match (cred.created_at()?, cred.modified_at()?) {
+        (None, None) => {}
+        r => return bad_result("created_at", &format!("no timestamps, got {r:?}")),
+    }
+    entry.set_password("test")?;
+    let (Some(created), Some(modified)) = (cred.created_at()?, cred.modified_at()?) else {
+        return bad_result("created_at", "timestamps after set_password");
+    };
+    if created != modified {
+        return bad_result("modified_at", &format!("{created}, got {modified}"));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    entry.set_password("test2")?;
+    match cred.created_at()? {
+        Some(t) if t == created => {}
+        r => return bad_result("created_at", &format!("{created}, got {r:?}")),
+    }
+    match cred.modified_at()? {
+        Some(t) if t > modified => {}
+        r => return bad_result("modified_at", &format!("after {modified}, got {r:?}")),
+    }
+    // the timestamps aren't credentials
+    let found = search_users(&[("service", "timestamp-service")])?;
+    let expected = vec![("timestamp-service".to_string(), "user".to_string())];
+    if found != expected {
+        return bad_result("search", &format!("{expected:?}, got {found:?}"));
+    }
+    entry.delete_credential()?;
+    match (cred.created_at()?, cred.modified_at()?) {
+        (None, None) => Ok(()),
+        r => bad_result("created_at", &format!("no timestamps, got {r:?}")),
+    }
+}
+
+fn explicit_context() -> keyring_core::Result<()> {
+    let store = {
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+        let env = vm.attach_current_thread().unwrap();
+        let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+        crate::LegacyStore::new(&env, context)?
+    };
+    let entry = store.build("explicit-context-service", "user", None)?;
+    entry.set_password("test")?;
+    // it shares credentials with stores built from the NDK context
+    match Entry::new("explicit-context-service", "user")?.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn null_context() -> keyring_core::Result<()> {
+    let result = {
+        let ctx = ndk_context::android_context();
+        let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+        let env = vm.attach_current_thread().unwrap();
+        crate::LegacyStore::new(&env, JObject::null())
+    };
+    match result.map_err(keyring_core::Error::from) {
+        Err(keyring_core::Error::PlatformFailure(e)) if e.to_string().contains("Context") => Ok(()),
+        r => bad_result(
+            "new",
+            &format!("PlatformFailure naming the Context, got {r:?}"),
+        ),
+    }
+}
+
+fn application_context() -> keyring_core::Result<()> {
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let given = Context::new(&env, context).unwrap();
+    let application = given.get_application_context(&mut env).unwrap();
+    // stores keep the application context by default
+    let store = crate::LegacyStore::from_ndk_context()?;
+    if !store.context().is_same(&env, &application).unwrap() {
+        return bad_result("from_ndk_context", "a store with the application context");
+    }
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let store = crate::LegacyStore::new(&env, context)?;
+    if !store.context().is_same(&env, &application).unwrap() {
+        return bad_result("new", "a store with the application context");
+    }
+    // unless they are asked to keep the given one
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let store = crate::LegacyStore::new_keeping_context(&env, context)?;
+    if !store.context().is_same(&env, &given).unwrap() {
+        return bad_result("new_keeping_context", "a store with the given context");
+    }
+    Ok(())
+}
+
+fn read_retries() -> keyring_core::Result<()> {
+    use crate::by_service::HasJavaVm;
+    use crate::error::AndroidKeyringError;
+    use jni::errors::{Error, JniError};
+
+    let store = crate::LegacyStore::from_ndk_context()?;
+    // the first attempt fails the way a thread attach race does
+    let mut attempts = 0;
+    let result = store.check_for_exception_retrying(|_env| {
+        attempts += 1;
+        if attempts == 1 {
+            return Err(Error::JniCall(JniError::ThreadDetached).into());
+        }
+        Ok(attempts)
+    });
+    match result {
+        Ok(2) => {}
+        r => return bad_result("check_for_exception_retrying", &format!("Ok(2), got {r:?}")),
+    }
+    // failures that threw a Java exception are never retried
+    let mut attempts = 0;
+    let result = store.check_for_exception_retrying(|env| {
+        attempts += 1;
+        env.throw_new("java/lang/IllegalStateException", "not retriable")?;
+        Ok(())
+    });
+    match result {
+        Err(AndroidKeyringError::JavaExceptionThrow { class, .. })
+            if class == "java.lang.IllegalStateException" && attempts == 1 => {}
+        r => {
+            return bad_result(
+                "check_for_exception_retrying",
+                &format!("one IllegalStateException, got {r:?} after {attempts} attempts"),
+            );
+        }
+    }
+    // the modifier must be a count
+    let modifiers = HashMap::from([("read_retries", "twice")]);
+    match store.build("retry-service", "user", Some(&modifiers)) {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "read_retries" => Ok(()),
+        r => bad_result("build", &format!("Invalid, got {r:?}")),
+    }
+}
+
+fn requires_authentication() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let unprotected = store.build("requires-auth-service", "unprotected", None)?;
+    let protected = store.build(
+        "requires-auth-service",
+        "protected",
+        Some(&HashMap::from([("require_auth", "true")])),
+    )?;
+    let required = |entry: &Entry| {
+        let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+        cred.requires_authentication()
+    };
+    // there's no key until a secret is set
+    match required(&unprotected) {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("requires_authentication", &format!("NoEntry, got {r:?}")),
+    }
+    unprotected.set_password("test")?;
+    match required(&unprotected) {
+        Ok(false) => {}
+        r => return bad_result("requires_authentication", &format!("false, got {r:?}")),
+    }
+    // this generates the protected key, but the user hasn't authenticated
+    // recently (and without a secure lock screen, it can't be generated)
+    _ = protected.set_password("test");
+    match required(&protected) {
+        Ok(true) => Ok(()),
+        Err(keyring_core::Error::NoEntry) => {
+            log::info!("Skipping protected key check: device has no secure lock screen");
+            Ok(())
+        }
+        r => bad_result("requires_authentication", &format!("true, got {r:?}")),
+    }
+}
+
+fn on_key_invalidated() -> keyring_core::Result<()> {
+    use crate::by_service::cred::test_hooks::simulate_key_invalidated;
+
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let deleting = store.build(
+        "invalidated-service",
+        "deleting",
+        Some(&HashMap::from([("on_key_invalidated", "delete")])),
+    )?;
+    let failing = store.build("invalidated-service", "failing", None)?;
+    deleting.set_password("test")?;
+    failing.set_password("test")?;
+    simulate_key_invalidated("invalidated-service");
+    // by default, the secret is kept and the error is reported
+    match failing.get_password() {
+        Err(keyring_core::Error::NoStorageAccess(_)) => {}
+        r => return bad_result("get_password", &format!("NoStorageAccess, got {r:?}")),
+    }
+    match failing.set_password("test2") {
+        Err(keyring_core::Error::NoStorageAccess(_)) => {}
+        r => return bad_result("set_password", &format!("NoStorageAccess, got {r:?}")),
+    }
+    let exists = |entry: &Entry| {
+        let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+        cred.exists()
+    };
+    if !exists(&failing)? {
+        return bad_result("exists", "true for the failing credential");
+    }
+    // with the policy, the dead secret is deleted
+    match deleting.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+    }
+    if exists(&deleting)? {
+        return bad_result("exists", "false for the deleting credential");
+    }
+    // and setting the secret replaces the key
+    deleting.set_password("test2")?;
+    match deleting.get_password() {
+        Ok(p) if p == "test2" => {}
+        r => return bad_result("get_password", &format!("'test2', got {r:?}")),
+    }
+    // the other credential's secret was encrypted with the old key
+    match failing.get_password() {
+        Err(keyring_core::Error::BadDataFormat(_, _)) => Ok(()),
+        r => bad_result("get_password", &format!("BadDataFormat, got {r:?}")),
+    }
+}
+
+#[cfg(feature = "biometric")]
+fn prompt_on_main_thread() -> keyring_core::Result<()> {
+    use crate::biometric::PromptInfo;
+
+    let entry = Entry::new("prompt-service", "user")?;
+    entry.set_password("test")?;
+    let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+    let info = PromptInfo {
+        title: "Unlock".to_string(),
+        subtitle: None,
+        negative_button: "Cancel".to_string(),
+    };
+    // the tests run on the main thread, where a prompt can't be waited for,
+    // so this fails before the (missing) activity is used
+    let result = cred.get_secret_with_prompt(&JObject::null(), &info);
+    entry.delete_credential()?;
+    match result {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "thread" => Ok(()),
+        r => bad_result("get_secret_with_prompt", &format!("Invalid, got {r:?}")),
+    }
+}
+
+#[cfg(feature = "biometric")]
+fn crypto_object() -> keyring_core::Result<()> {
+    let entry = Entry::new("crypto-object-service", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+    match cred.crypto_object() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("crypto_object", &format!("NoEntry, got {:?}", r.err())),
+    }
+    entry.set_password("test")?;
+    // this key doesn't require authentication, so the cipher
+    // works without the prompt an application would show
+    let crypto_object = cred.crypto_object()?;
+    let result = cred.get_secret_with_crypto_object(&crypto_object);
+    entry.delete_credential()?;
+    match result {
+        Ok(secret) if secret == b"test" => Ok(()),
+        r => bad_result(
+            "get_secret_with_crypto_object",
+            &format!("'test', got {r:?}"),
+        ),
+    }
+}
+
+fn capabilities() -> keyring_core::Result<()> {
+    let capabilities = crate::LegacyStore::from_ndk_context()?.capabilities();
+    let expected = crate::Capabilities {
+        search: true,
+        attributes: true,
+        persistent: true,
+        hardware_backed: None,
+    };
+    if capabilities != expected {
+        return bad_result(
+            "capabilities",
+            &format!("{expected:?}, got {capabilities:?}"),
+        );
+    }
+    Ok(())
+}
+
+#[allow(deprecated)]
+fn compat_aliases() -> keyring_core::Result<()> {
+    use crate::by_service::{AndroidCredential, AndroidStore};
+    // the aliases of earlier versions name the same types
+    let store = AndroidStore::from_ndk_context()?;
+    let legacy_store = crate::LegacyStore::from_ndk_context()?;
+    let entry = store.build("compat-service", "user", None)?;
+    entry.set_password("test")?;
+    if entry.as_any().downcast_ref::<AndroidCredential>().is_none() {
+        return bad_result("build", "an AndroidCredential");
+    }
+    let legacy_entry = legacy_store.build("compat-service", "user", None)?;
+    match legacy_entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    legacy_entry.delete_credential()?;
+    match entry.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+    }
+    Ok(())
+}
+
+fn empty_secret() -> keyring_core::Result<()> {
+    let entry = Entry::new("empty-secret-service", "user")?;
+    entry.set_secret(&[])?;
+    match entry.get_secret() {
+        Ok(secret) if secret.is_empty() => {}
+        r => return bad_result("get_secret", &format!("an empty secret, got {r:?}")),
+    }
+    entry.set_password("")?;
+    match entry.get_password() {
+        Ok(p) if p.is_empty() => {}
+        r => return bad_result("get_password", &format!("an empty password, got {r:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+/// The users in a service's index, and those with a secret in its file.
+fn index_and_keys(service: &str) -> (Option<Vec<String>>, Vec<String>) {
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let context = Context::new(&env, context).unwrap();
+    let file = context
+        .get_shared_preferences(&mut env, service, MODE_PRIVATE)
+        .unwrap();
+    let index = file.get_string_set(&mut env, ".users").unwrap();
+    let mut keys: Vec<String> = file
+        .get_all_keys(&mut env)
+        .unwrap()
+        .into_iter()
+        // the index and the attributes and timestamps all have a dot
+        .filter(|key| !key.contains('.'))
+        .collect();
+    keys.sort();
+    (index.map(|users| users.0.into_iter().collect()), keys)
+}
+
+fn user_index() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    for user in ["user1", "user2", "user3"] {
+        Entry::new("index-service", user)?.set_password("test")?;
+    }
+    store.set_many(
+        "index-service",
+        &[("user4", b"test".as_slice()), ("user5", b"test".as_slice())],
+    )?;
+    Entry::new("index-service", "user2")?.delete_credential()?;
+    // overwriting a secret doesn't add its user twice
+    Entry::new("index-service", "user3")?.set_password("test2")?;
+    let expected = ["user1", "user3", "user4", "user5"];
+    match index_and_keys("index-service") {
+        (Some(index), keys) if index == expected && keys == expected => {}
+        r => return bad_result("index", &format!("{expected:?} twice, got {r:?}")),
+    }
+    let found = search_users(&[("service", "index-service")])?;
+    let users: Vec<_> = found.iter().map(|(_, user)| user.as_str()).collect();
+    if users != expected {
+        return bad_result("search", &format!("{expected:?}, got {users:?}"));
+    }
+    // the names of the index and the attribute and timestamp keys are reserved
+    for user in [".users", "user1.attrs", "user1.ctime", "user1.mtime"] {
+        match Entry::new("index-service", user) {
+            Err(keyring_core::Error::Invalid(_, _)) => {}
+            r => return bad_result("build", &format!("Invalid for {user}, got {r:?}")),
+        }
+    }
+    match store.set_many("index-service", &[("user6.attrs", b"test".as_slice())]) {
+        Err(keyring_core::Error::Invalid(_, _)) => {}
+        r => return bad_result("set_many", &format!("Invalid for user6.attrs, got {r:?}")),
+    }
+    for user in expected {
+        Entry::new("index-service", user)?.delete_credential()?;
+    }
+    match index_and_keys("index-service") {
+        (Some(index), keys) if index.is_empty() && keys.is_empty() => {}
+        r => return bad_result("index", &format!("an empty index, got {r:?}")),
+    }
+    store.delete_service("index-service")?;
+    Ok(())
+}
+
+fn swapped_secrets() -> keyring_core::Result<()> {
+    use crate::{
+        crypto::{Transformation, encrypt_legacy},
+        keystore::{KeyStore, PROVIDER},
+    };
+
+    let entry1 = Entry::new("swapped-service", "user1")?;
+    let entry2 = Entry::new("swapped-service", "user2")?;
+    entry1.set_password("secret 1")?;
+    entry2.set_password("secret 2")?;
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let context = Context::new(&env, context).unwrap();
+    let file = context
+        .get_shared_preferences(&mut env, "swapped-service", MODE_PRIVATE)
+        .unwrap();
+    let blob1 = file.get_binary(&mut env, "user1").unwrap().unwrap();
+    let blob2 = file.get_binary(&mut env, "user2").unwrap().unwrap();
+    let editor = file.edit(&mut env).unwrap();
+    editor.put_binary(&mut env, "user1", &blob2).unwrap();
+    editor.put_binary(&mut env, "user2", &blob1).unwrap();
+    editor.commit(&mut env).unwrap();
+    // each secret is bound to its service and user
+    for entry in [&entry1, &entry2] {
+        match entry.get_password() {
+            Err(keyring_core::Error::BadDataFormat(_, _)) => {}
+            r => return bad_result("get_password", &format!("BadDataFormat, got {r:?}")),
+        }
+    }
+    // but values written by earlier versions, which aren't bound, are still read
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    let key = keystore
+        .get_key(&mut env, "swapped-service")
+        .unwrap()
+        .unwrap();
+    let value = encrypt_legacy(&mut env, key, Transformation::AesGcm, None, b"unbound").unwrap();
+    let editor = file.edit(&mut env).unwrap();
+    editor.put_binary(&mut env, "user1", &value).unwrap();
+    editor.commit(&mut env).unwrap();
+    match entry1.get_password() {
+        Ok(password) if password == "unbound" => {}
+        r => return bad_result("get_password", &format!("'unbound', got {r:?}")),
+    }
+    crate::LegacyStore::from_ndk_context()?.delete_service("swapped-service")?;
+    Ok(())
+}
+
+fn upgrade_envelopes() -> keyring_core::Result<()> {
+    use crate::{
+        crypto::{MAGIC, Transformation, encrypt_legacy},
+        keystore::{KeyStore, PROVIDER},
+    };
+
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let entry = Entry::new("upgrade-service", "user")?;
+    entry.set_password("test")?;
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let context = Context::new(&env, context).unwrap();
+    let file = context
+        .get_shared_preferences(&mut env, "upgrade-service", MODE_PRIVATE)
+        .unwrap();
+    // write the secret as an earlier version would have
+    let write_legacy = |env: &mut jni::JNIEnv| {
+        let keystore = KeyStore::get_instance(env, PROVIDER).unwrap();
+        keystore.load(env).unwrap();
+        let key = keystore.get_key(env, "upgrade-service").unwrap().unwrap();
+        let value = encrypt_legacy(env, key, Transformation::AesGcm, None, b"old").unwrap();
+        let editor = file.edit(env).unwrap();
+        editor.put_binary(env, "user", &value).unwrap();
+        editor.commit(env).unwrap();
+    };
+    let has_header = |env: &mut jni::JNIEnv| {
+        let value = file.get_binary(env, "user").unwrap().unwrap();
+        value.starts_with(&MAGIC)
+    };
+    // by default, rewriting it adds the header
+    write_legacy(&mut env);
+    entry.set_password("upgraded")?;
+    if !has_header(&mut env) {
+        return bad_result("set_password", "a value with a header");
+    }
+    // but it can be kept in the legacy layout
+    let modifiers = HashMap::from([("upgrade_envelopes", "false")]);
+    let entry = store.build("upgrade-service", "user", Some(&modifiers))?;
+    write_legacy(&mut env);
+    entry.set_password("not upgraded")?;
+    if has_header(&mut env) {
+        return bad_result("set_password", "a value without a header");
+    }
+    match entry.get_password() {
+        Ok(password) if password == "not upgraded" => {}
+        r => return bad_result("get_password", &format!("'not upgraded', got {r:?}")),
+    }
+    // the same goes for the service-wide operations, following the store
+    store.set_upgrade_envelopes(false);
+    write_legacy(&mut env);
+    store.set_many("upgrade-service", &[("user", b"batch".as_slice())])?;
+    if has_header(&mut env) {
+        return bad_result("set_many", "a value without a header");
+    }
+    store.rotate_key("upgrade-service")?;
+    if has_header(&mut env) {
+        return bad_result("rotate_key", "a value without a header");
+    }
+    match entry.get_password() {
+        Ok(password) if password == "batch" => {}
+        r => return bad_result("get_password", &format!("'batch', got {r:?}")),
+    }
+    store.set_upgrade_envelopes(true);
+    store.set_many("upgrade-service", &[("user", b"batch".as_slice())])?;
+    if !has_header(&mut env) {
+        return bad_result("set_many", "a value with a header");
+    }
+    // while new secrets still get the header
+    entry.delete_credential()?;
+    entry.set_password("new")?;
+    if !has_header(&mut env) {
+        return bad_result("set_password", "a new value with a header");
+    }
+    store.delete_service("upgrade-service")?;
+    Ok(())
+}
+
+fn migrate_legacy() -> keyring_core::Result<()> {
+    use crate::{
+        by_store::cred::aad,
+        crypto::{MAGIC, Transformation, decrypt, encrypt_legacy},
+        keystore::{KeyStore, PROVIDER},
+    };
+
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let users = ["unbound", "bound", "current"];
+    for user in users {
+        Entry::new("migrate-service", user)?.set_password("placeholder")?;
+    }
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let context = unsafe { JObject::from_raw(ctx.context() as jni::sys::jobject) };
+    let context = Context::new(&env, context).unwrap();
+    let file = context
+        .get_shared_preferences(&mut env, "migrate-service", MODE_PRIVATE)
+        .unwrap();
+    // write two of the secrets as earlier versions would have, with and without binding
+    let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+    keystore.load(&mut env).unwrap();
+    let key = keystore
+        .get_key(&mut env, "migrate-service")
+        .unwrap()
+        .unwrap();
+    let bound_aad = aad("migrate-service", "bound");
+    let editor = file.edit(&mut env).unwrap();
+    for (user, aad) in [("unbound", None), ("bound", Some(bound_aad.as_slice()))] {
+        let value = encrypt_legacy(
+            &mut env,
+            key.clone(),
+            Transformation::AesGcm,
+            aad,
+            user.as_bytes(),
+        )
+        .unwrap();
+        editor.put_binary(&mut env, user, &value).unwrap();
+    }
+    editor.commit(&mut env).unwrap();
+    let created = Entry::new("migrate-service", "unbound")?
+        .as_any()
+        .downcast_ref::<crate::LegacyCred>()
+        .unwrap()
+        .created_at()?;
+    // only the secrets in the legacy layout are rewritten
+    match store.migrate_legacy("migrate-service") {
+        Ok(2) => {}
+        r => return bad_result("migrate_legacy", &format!("2, got {r:?}")),
+    }
+    for user in users {
+        let value = file.get_binary(&mut env, user).unwrap().unwrap();
+        if !value.starts_with(&MAGIC) {
+            return bad_result("migrate_legacy", &format!("a header for {user}"));
+        }
+    }
+    // with the same key, and without changing their secrets or timestamps
+    let value = file.get_binary(&mut env, "bound").unwrap().unwrap();
+    match decrypt(&mut env, key, Some(&bound_aad), value) {
+        Ok(secret) if secret == b"bound" => {}
+        r => return bad_result("decrypt", &format!("'bound' with the old key, got {r:?}")),
+    }
+    for (user, expected) in [
+        ("unbound", "unbound"),
+        ("bound", "bound"),
+        ("current", "placeholder"),
+    ] {
+        match Entry::new("migrate-service", user)?.get_password() {
+            Ok(p) if p == expected => {}
+            r => return bad_result("get_password", &format!("{expected:?}, got {r:?}")),
+        }
+    }
+    let entry = Entry::new("migrate-service", "unbound")?;
+    let cred = entry.as_any().downcast_ref::<crate::LegacyCred>().unwrap();
+    if cred.created_at()? != created {
+        return bad_result("created_at", &format!("{created:?}"));
+    }
+    // so there's nothing left to migrate
+    match store.migrate_legacy("migrate-service") {
+        Ok(0) => {}
+        r => return bad_result("migrate_legacy", &format!("0, got {r:?}")),
+    }
+    store.delete_service("migrate-service")?;
+    Ok(())
+}
+
+fn key_size() -> keyring_core::Result<()> {
+    use crate::keystore::{KeyStore, PROVIDER};
+
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let modifiers = HashMap::from([("key_size", "256")]);
+    let entry = store.build("key-size-service", "user", Some(&modifiers))?;
+    entry.set_password("test")?;
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast()) }.unwrap();
+    let mut env = vm.attach_current_thread().unwrap();
+    let mut key_size = || {
+        let keystore = KeyStore::get_instance(&mut env, PROVIDER).unwrap();
+        keystore.load(&mut env).unwrap();
+        let key = keystore
+            .get_key(&mut env, "key-size-service")
+            .unwrap()
+            .unwrap();
+        let info = key.get_key_info(&mut env).unwrap();
+        info.get_key_size(&mut env).unwrap()
+    };
+    if key_size() != 256 {
+        return bad_result("get_key_size", "256");
+    }
+    // a rotated key keeps its size
+    store.rotate_key("key-size-service")?;
+    if key_size() != 256 {
+        return bad_result("get_key_size", "256 after rotation");
+    }
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    // only AES key sizes are accepted
+    let modifiers = HashMap::from([("key_size", "512")]);
+    match store.build("key-size-service", "user", Some(&modifiers)) {
+        Err(keyring_core::Error::Invalid(attr, _)) if attr == "key_size" => {}
+        r => return bad_result("build", &format!("an invalid key_size, got {r:?}")),
+    }
+    store.delete_service("key-size-service")?;
+    Ok(())
+}
+
+fn key_generation_backoff() -> keyring_core::Result<()> {
+    let store = crate::LegacyStore::from_ndk_context()?;
+    let modifiers = HashMap::from([
+        ("key_generation_attempts", "5"),
+        ("key_generation_delay_ms", "10"),
+    ]);
+    let entry = store.build("backoff-service", "user", Some(&modifiers))?;
+    entry.set_password("test")?;
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        r => return bad_result("get_password", &format!("'test', got {r:?}")),
+    }
+    // there must be at least one attempt
+    let modifiers = HashMap::from([("key_generation_attempts", "0")]);
+    match store.build("backoff-service", "user", Some(&modifiers)) {
+        Err(keyring_core::Error::Invalid(attr, _)) if attr == "key_generation_attempts" => {}
+        r => return bad_result("build", &format!("an invalid attempt count, got {r:?}")),
+    }
+    let modifiers = HashMap::from([("key_generation_delay_ms", "-1")]);
+    match store.build("backoff-service", "user", Some(&modifiers)) {
+        Err(keyring_core::Error::Invalid(attr, _)) if attr == "key_generation_delay_ms" => {}
+        r => return bad_result("build", &format!("an invalid delay, got {r:?}")),
+    }
+    store.delete_service("backoff-service")?;
+    Ok(())
+}
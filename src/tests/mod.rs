@@ -1,15 +1,19 @@
-use android_log_sys::{__android_log_write, LogPriority};
+use android_log_sys::LogPriority;
 use jni::{JNIEnv, objects::JObject};
-use std::ffi::CString;
 
 use keyring_core::Result;
 
-use crate::{by_store::clear_vault_list, shared_preferences::Context};
+use crate::by_store::clear_vault_list;
 
 mod crypto_tests;
 #[cfg(feature = "legacy")]
 pub mod legacy_tests;
 pub mod store_tests;
+pub mod test_suite;
+#[cfg(feature = "transfer")]
+pub mod transfer_tests;
+
+use test_suite::{log_line, suites, tally};
 
 // package io.crates.keyring
 // import android.content.Context
@@ -25,39 +29,19 @@ pub extern "system" fn Java_io_crates_keyring_KeyringTests_00024Companion_runAll
     _class: JObject,
     context: JObject,
 ) {
-    let context = Context::new(&env, context).unwrap();
-    #[cfg(feature = "legacy")]
-    let (ls, lf) = legacy_tests::run_tests();
-    let (ss, sf) = store_tests::run_tests();
-    let (cs, cf) = crypto_tests::run_tests(env, context);
-    #[cfg(feature = "legacy")]
-    let successes = ls + ss + cs;
-    #[cfg(not(feature = "legacy"))]
-    let successes = ss + cs;
-    #[cfg(feature = "legacy")]
-    let failures = lf + sf + cf;
-    #[cfg(not(feature = "legacy"))]
-    let failures = sf + cf;
-    let msg = CString::new(format!(
-        "Overall: {} successes, {} failures",
-        successes, failures
-    ))
-    .unwrap();
-    let tag = c"unit-test";
-    let level = LogPriority::INFO as i32;
-    unsafe {
-        __android_log_write(level, tag.as_ptr(), msg.as_ptr());
+    let mut results = Vec::new();
+    for suite in suites(&env, context) {
+        results.extend(suite.run());
     }
+    let (successes, failures) = tally(&results);
+    let msg = format!("Overall: {successes} successes, {failures} failures");
+    log_line(LogPriority::INFO, &msg);
     match cleanup() {
         Ok(()) => log::info!("Successfully cleaned up tests"),
-        Err(e) => {
-            let msg = CString::new(format!("Failed to clean up tests: {e}")).unwrap();
-            let tag = c"unit-test";
-            let level = LogPriority::ERROR as i32;
-            unsafe {
-                __android_log_write(level, tag.as_ptr(), msg.as_ptr());
-            }
-        }
+        Err(e) => log_line(
+            LogPriority::ERROR,
+            &format!("Failed to clean up tests: {e}"),
+        ),
     }
 }
 
@@ -69,5 +53,7 @@ pub fn cleanup() -> Result<()> {
     clear_vault_list();
     store_tests::cleanup()?;
     crypto_tests::cleanup()?;
+    #[cfg(feature = "transfer")]
+    transfer_tests::cleanup()?;
     Ok(())
 }
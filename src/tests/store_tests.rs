@@ -1,74 +1,54 @@
 use std::collections::HashMap;
-use std::ffi::CString;
-use std::panic::catch_unwind;
 
-use android_log_sys::{__android_log_write, LogPriority};
+use std::sync::Arc;
 
-use keyring_core::Entry;
+use keyring_core::{Entry, api::CredentialStoreApi};
 
-pub fn run_tests() -> (usize, usize) {
-    let testing = [
+use crate::{error::AndroidKeyringError, keyguard::simulate_device_locked};
+
+use super::test_suite::{Suite, tally};
+
+pub fn suite() -> Suite {
+    let tests = [
         ("setup", setup as fn() -> keyring_core::Result<()>),
         ("golden_path", golden_path),
         ("delete_credential", delete_credential),
         ("concurrent_access", concurrent_access),
+        ("many_operations", many_operations),
         ("search", search),
+        ("readonly_if_locked", readonly_if_locked),
+        ("reprovision", reprovision),
+        ("store_lock", store_lock),
+        ("protection_info", protection_info),
+        ("asynchronous_writes", asynchronous_writes),
+        ("exists", exists),
+        ("update_secret", update_secret),
+        ("get_secret_zeroizing", get_secret_zeroizing),
+        ("named_stores", named_stores),
+        ("capabilities", capabilities),
+        ("persistent_id", persistent_id),
+        #[cfg(feature = "biometric")]
+        ("can_authenticate", can_authenticate),
+        #[cfg(feature = "encrypted-preferences")]
+        ("encrypted_preferences", encrypted_preferences),
+        #[cfg(feature = "backup")]
+        ("export_service", export_service),
+        #[cfg(feature = "backup")]
+        ("import_service_failures", import_service_failures),
+        #[cfg(feature = "android-log")]
+        ("log_tag", log_tag),
+        #[cfg(feature = "android-log")]
+        ("log_lines", log_lines),
+        #[cfg(feature = "android-log")]
+        ("log_reload", log_reload),
+        ("suite_runner", suite_runner),
         ("teardown", teardown),
-    ]
-    .iter()
-    .map(|(name, entry)| {
-        (name, move || -> keyring_core::Result<()> {
-            catch_unwind(entry)
-                .map_err(|e| {
-                    log::error!("Test {name} panicked: {e:?}");
-                    bad_result(name, "panicked")
-                })
-                .unwrap_or_else(|e| e)
-        })
-    })
-    .collect::<Vec<_>>();
-
-    let msg = c"Running Store tests...";
-    let tag = c"unit-test";
-    let level = LogPriority::INFO as i32;
-    unsafe {
-        __android_log_write(level, tag.as_ptr(), msg.as_ptr());
-    }
-    let mut successes = 0;
-    let mut failures = 0;
-    for (name, testing) in testing {
-        let level;
-        let msg;
-        match testing() {
-            Ok(()) => {
-                level = LogPriority::INFO as i32;
-                msg = format!("{name} success");
-                successes += 1;
-            }
-            Err(e) => {
-                level = LogPriority::ERROR as i32;
-                msg = format!("{name} error: {e:?}");
-                failures += 1;
-            }
-        }
-
-        let msg = CString::new(msg).unwrap();
-        let tag = c"unit-test";
-        unsafe {
-            __android_log_write(level, tag.as_ptr(), msg.as_ptr());
-        }
-    }
-    let msg = CString::new(format!(
-        "Store: {} successes, {} failures",
-        successes, failures
-    ))
-    .unwrap();
-    let tag = c"unit-test";
-    let level = LogPriority::INFO as i32;
-    unsafe {
-        __android_log_write(level, tag.as_ptr(), msg.as_ptr());
+    ];
+    let mut suite = Suite::new("Store");
+    for (name, test) in tests {
+        suite.add(name, test);
     }
-    (successes, failures)
+    suite
 }
 
 fn bad_result(op: &str, msg: &str) -> keyring_core::Result<()> {
@@ -79,6 +59,21 @@ fn bad_result(op: &str, msg: &str) -> keyring_core::Result<()> {
 }
 
 const STORE_CONFIG: [(&str, &str); 2] = [("name", "test"), ("divider", "@")];
+const REPROVISION_STORE_CONFIG: [(&str, &str); 1] = [("name", "reprovision-test")];
+const ASYNC_STORE_CONFIG: [(&str, &str); 2] =
+    [("name", "async-test"), ("synchronous_writes", "false")];
+const LOCKED_STORE_CONFIG: [(&str, &str); 2] =
+    [("name", "locked-test"), ("readonly_if_locked", "true")];
+const PERSISTENT_ID_STORE_CONFIG: [(&str, &str); 2] =
+    [("name", "persistent-id-test"), ("persistent_id", "true")];
+const NAMED_STORES: [&str; 2] = ["protected-test", "unprotected-test"];
+#[cfg(feature = "backup")]
+const BACKUP_STORE_CONFIG: [(&str, &str); 1] = [("name", "backup-test")];
+#[cfg(feature = "encrypted-preferences")]
+const ENCRYPTED_STORE_CONFIG: [(&str, &str); 2] = [
+    ("name", "encrypted-test"),
+    ("encrypted_preferences", "true"),
+];
 
 fn setup() -> keyring_core::Result<()> {
     cleanup()?;
@@ -89,6 +84,118 @@ fn setup() -> keyring_core::Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "backup")]
+fn export_service() -> keyring_core::Result<()> {
+    let service = "backup-service";
+    let passphrase = "correct horse battery staple";
+    Entry::new(service, "backup-user-1")?.set_password("first")?;
+    Entry::new(service, "backup-user-2")?.set_secret(&[0, 1, 2, 255])?;
+    // only exact matches of the service are exported
+    Entry::new("backup-service-too", "backup-user-1")?.set_password("other")?;
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    match store.export_service(service, "") {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "passphrase" => {}
+        r => return bad_result("export_service", &format!("Invalid, got {r:?}")),
+    }
+    let blob = store.export_service(service, passphrase)?;
+    let backup = crate::backup::open(&blob, passphrase)?;
+    if backup.service != service {
+        return bad_result("open", &format!("{service:?}, got {:?}", backup.service));
+    }
+    // import the backup into a fresh store
+    let fresh = crate::Store::new_with_configuration(&HashMap::from(BACKUP_STORE_CONFIG))?;
+    match fresh.import_service(service, passphrase, &blob) {
+        Ok(2) => {}
+        r => return bad_result("import_service", &format!("2, got {r:?}")),
+    }
+    let imported = fresh.search(&HashMap::new())?;
+    if imported.len() != 2 {
+        return bad_result("search", &format!("2 imported, got {}", imported.len()));
+    }
+    match fresh.build(service, "backup-user-1", None)?.get_password() {
+        Ok(p) if p == "first" => {}
+        r => return bad_result("get_password", &format!("'first', got {r:?}")),
+    }
+    match fresh.build(service, "backup-user-2", None)?.get_secret() {
+        Ok(s) if s == [0, 1, 2, 255] => {}
+        r => return bad_result("get_secret", &format!("[0, 1, 2, 255], got {r:?}")),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "backup")]
+fn import_service_failures() -> keyring_core::Result<()> {
+    let service = "backup-failures-service";
+    let passphrase = "correct horse battery staple";
+    Entry::new(service, "backup-user")?.set_password("secret")?;
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let blob = store.export_service(service, passphrase)?;
+    let fresh = crate::Store::new_with_configuration(&HashMap::from(BACKUP_STORE_CONFIG))?;
+    match fresh.import_service(service, "wrong passphrase", &blob) {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "passphrase" => {}
+        r => return bad_result("import_service", &format!("Invalid passphrase, got {r:?}")),
+    }
+    for truncated in [&blob[..blob.len() - 1], &blob[..20]] {
+        match fresh.import_service(service, passphrase, truncated) {
+            Err(keyring_core::Error::Invalid(key, _)) if key == "blob" => {}
+            r => return bad_result("import_service", &format!("Invalid blob, got {r:?}")),
+        }
+    }
+    let mut future = blob.clone();
+    future[4] = 2;
+    match fresh.import_service(service, passphrase, &future) {
+        Err(keyring_core::Error::Invalid(key, msg)) if key == "blob" && msg.contains("version") => {
+        }
+        r => return bad_result("import_service", &format!("Invalid version, got {r:?}")),
+    }
+    match fresh.build(service, "backup-user", None)?.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+    }
+    Ok(())
+}
+
+fn suite_runner() -> keyring_core::Result<()> {
+    let mut suite = Suite::new("Nested");
+    suite.add("setup", || Ok(()));
+    suite.add("passes", golden_path);
+    suite.add("fails", || bad_result("fails", "an error"));
+    suite.add("panics", || panic!("on purpose"));
+    suite.add("skipped", || bad_result("skipped", "not to be run"));
+    let suite = suite.select(&["passes", "fails", "panics"]);
+    let names = suite.test_names();
+    if names != ["setup", "passes", "fails", "panics"] {
+        return bad_result(
+            "select",
+            &format!("setup and the selected tests, got {names:?}"),
+        );
+    }
+    let results = suite.run();
+    let outcomes: Vec<(&str, bool)> = results
+        .iter()
+        .map(|r| (r.name.as_str(), r.passed))
+        .collect();
+    let expected = [
+        ("setup", true),
+        ("passes", true),
+        ("fails", false),
+        ("panics", false),
+    ];
+    if outcomes != expected {
+        return bad_result("run", &format!("{expected:?}, got {outcomes:?}"));
+    }
+    if results
+        .iter()
+        .any(|r| r.suite != "Nested" || r.passed != r.message.is_none())
+    {
+        return bad_result("run", &format!("consistent results, got {results:?}"));
+    }
+    if tally(&results) != (2, 2) {
+        return bad_result("tally", "2 passes and 2 failures");
+    }
+    Ok(())
+}
+
 fn teardown() -> keyring_core::Result<()> {
     keyring_core::unset_default_store();
     let store_config = HashMap::from(STORE_CONFIG);
@@ -107,6 +214,41 @@ pub fn cleanup() -> keyring_core::Result<()> {
     } else {
         log::info!("No test store found to delete");
     }
+    let store_config = HashMap::from(LOCKED_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("Locked test store successfully deleted");
+    }
+    let store_config = HashMap::from(REPROVISION_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("Reprovision test store successfully deleted");
+    }
+    let store_config = HashMap::from(ASYNC_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("Async test store successfully deleted");
+    }
+    let store_config = HashMap::from(PERSISTENT_ID_STORE_CONFIG);
+    if crate::Store::delete(&store_config)? {
+        log::info!("Persistent id test store successfully deleted");
+    }
+    for name in NAMED_STORES {
+        if crate::Store::delete(&HashMap::from([("name", name)]))? {
+            log::info!("Named test store {name} successfully deleted");
+        }
+    }
+    #[cfg(feature = "encrypted-preferences")]
+    {
+        let store_config = HashMap::from(ENCRYPTED_STORE_CONFIG);
+        if crate::Store::delete(&store_config)? {
+            log::info!("Encrypted test store successfully deleted");
+        }
+    }
+    #[cfg(feature = "backup")]
+    {
+        let store_config = HashMap::from(BACKUP_STORE_CONFIG);
+        if crate::Store::delete(&store_config)? {
+            log::info!("Backup test store successfully deleted");
+        }
+    }
     Ok(())
 }
 
@@ -200,6 +342,28 @@ fn concurrent_access() -> keyring_core::Result<()> {
     Ok(())
 }
 
+fn many_operations() -> keyring_core::Result<()> {
+    // a fresh native thread is attached once, and the local references
+    // made by each operation don't pile up on it
+    let worker = std::thread::spawn(|| -> keyring_core::Result<()> {
+        let entry = Entry::new("many-operations", "user")?;
+        for i in 0..1000 {
+            let password = format!("test{i}");
+            entry.set_password(&password)?;
+            for _ in 0..9 {
+                match entry.get_password() {
+                    Ok(p) if p == password => {}
+                    r => return bad_result("get_password", &format!("{password:?}, got {r:?}")),
+                }
+            }
+        }
+        entry.delete_credential()
+    });
+    worker
+        .join()
+        .map_err(|_| keyring_core::Error::Invalid("join".to_string(), "failed".to_string()))?
+}
+
 fn search() -> keyring_core::Result<()> {
     Entry::new("search-service-only", "search-user-as-well")?.set_password("p")?;
     Entry::new("search-service-as-well", "search-user-only")?.set_password("p")?;
@@ -231,3 +395,420 @@ fn search() -> keyring_core::Result<()> {
     }
     Ok(())
 }
+
+fn is_device_locked_error(result: &keyring_core::Result<impl std::fmt::Debug>) -> bool {
+    match result {
+        Err(keyring_core::Error::NoStorageAccess(err)) => matches!(
+            err.downcast_ref::<AndroidKeyringError>(),
+            Some(AndroidKeyringError::DeviceLocked)
+        ),
+        _ => false,
+    }
+}
+
+fn readonly_if_locked() -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(LOCKED_STORE_CONFIG))?;
+    let entry = store.build("locked-service", "user", None)?;
+    simulate_device_locked(Some(false));
+    entry.set_password("test")?;
+    simulate_device_locked(Some(true));
+    let get = entry.get_password();
+    let set = entry.set_password("changed");
+    let exists = entry.get_credential();
+    simulate_device_locked(None);
+    if !is_device_locked_error(&get) {
+        return bad_result("get_password", &format!("DeviceLocked, got {get:?}"));
+    }
+    if !is_device_locked_error(&set) {
+        return bad_result("set_password", &format!("DeviceLocked, got {set:?}"));
+    }
+    if let Err(e) = exists {
+        return bad_result("get_credential", &format!("Ok, got {e:?}"));
+    }
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        Ok(p) => return bad_result("get_password", &format!("'test', got '{p}'")),
+        Err(e) => return bad_result("get_password", &format!("'test', got {e:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn reprovision() -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(REPROVISION_STORE_CONFIG))?;
+    let entry = store.build("reprovision-service", "user", None)?;
+    entry.set_password("stale")?;
+    // without a reprovisioner, an unreadable secret is an error
+    store.change_key()?;
+    match entry.get_password() {
+        Err(keyring_core::Error::BadDataFormat(_, _)) => {}
+        r => return bad_result("get_password", &format!("BadDataFormat, got {r:?}")),
+    }
+    // with one, the secret is replaced by a fresh one
+    store.set_reprovisioner(Some(Arc::new(|service: &str, user: &str| {
+        Ok(format!("fresh for {user}@{service}").into_bytes())
+    })));
+    let result = entry.get_password();
+    store.set_reprovisioner(None);
+    match result {
+        Ok(p) if p.eq("fresh for user@reprovision-service") => {}
+        r => return bad_result("get_password", &format!("a fresh password, got {r:?}")),
+    }
+    // and the fresh secret was stored
+    match entry.get_password() {
+        Ok(p) if p.eq("fresh for user@reprovision-service") => {}
+        r => return bad_result("get_password", &format!("a fresh password, got {r:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn store_lock() -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let entry = store.build("lock-service", "user", None)?;
+    entry.set_password("test")?;
+    store.set_locked(true);
+    if !store.is_locked() {
+        return bad_result("is_locked", "true");
+    }
+    let get = entry.get_password();
+    let set = entry.set_password("changed");
+    let delete = entry.delete_credential();
+    store.set_locked(false);
+    match get {
+        Err(keyring_core::Error::NotSupportedByStore(_)) => {}
+        r => return bad_result("get_password", &format!("NotSupportedByStore, got {r:?}")),
+    }
+    match set {
+        Err(keyring_core::Error::NotSupportedByStore(_)) => {}
+        r => return bad_result("set_password", &format!("NotSupportedByStore, got {r:?}")),
+    }
+    match delete {
+        Err(keyring_core::Error::NotSupportedByStore(_)) => {}
+        r => {
+            let err = format!("NotSupportedByStore, got {r:?}");
+            return bad_result("delete_credential", &err);
+        }
+    }
+    match entry.get_password() {
+        Ok(p) if p.eq("test") => {}
+        Ok(p) => return bad_result("get_password", &format!("'test', got '{p}'")),
+        Err(e) => return bad_result("get_password", &format!("'test', got {e:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn protection_info() -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let entry = store.build("protection-service", "user", None)?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    match cred.protection_info() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("protection_info", &format!("NoEntry, got {r:?}")),
+    }
+    entry.set_password("test")?;
+    let info = cred.protection_info()?;
+    log::info!("Protection info: {info:?}");
+    if info.backend != "AndroidKeyStore" {
+        return bad_result("protection_info", &format!("AndroidKeyStore, got {info:?}"));
+    }
+    if !info.aead {
+        return bad_result("protection_info", &format!("an AEAD cipher, got {info:?}"));
+    }
+    if info.auth_required {
+        return bad_result(
+            "protection_info",
+            &format!("no auth required, got {info:?}"),
+        );
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn asynchronous_writes() -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(ASYNC_STORE_CONFIG))?;
+    let entries = (0..10)
+        .map(|i| store.build("async-service", &format!("user{i}"), None))
+        .collect::<keyring_core::Result<Vec<_>>>()?;
+    for (i, entry) in entries.iter().enumerate() {
+        entry.set_password(&format!("password{i}"))?;
+    }
+    // the writes may not be on disk yet, but they must be visible
+    for (i, entry) in entries.iter().enumerate() {
+        match entry.get_password() {
+            Ok(p) if p == format!("password{i}") => {}
+            r => return bad_result("get_password", &format!("password{i}, got {r:?}")),
+        }
+    }
+    for entry in &entries {
+        entry.delete_credential()?;
+    }
+    for entry in &entries {
+        match entry.get_password() {
+            Err(keyring_core::Error::NoEntry) => {}
+            r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+        }
+    }
+    Ok(())
+}
+
+fn exists() -> keyring_core::Result<()> {
+    let entry = Entry::new("exists-service", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    if cred.exists()? {
+        return bad_result("exists", "false before the secret is set");
+    }
+    entry.set_password("test")?;
+    if !cred.exists()? {
+        return bad_result("exists", "true after the secret is set");
+    }
+    entry.delete_credential()?;
+    if cred.exists()? {
+        return bad_result("exists", "false after the credential is deleted");
+    }
+    Ok(())
+}
+
+fn update_secret() -> keyring_core::Result<()> {
+    let entry = Entry::new("update-service", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    // a credential without a secret isn't given one
+    match cred.update_secret(b"new") {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("update_secret", &format!("NoEntry, got {r:?}")),
+    }
+    match entry.get_secret() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_secret", &format!("NoEntry, got {r:?}")),
+    }
+    // but an existing secret is replaced
+    entry.set_password("old")?;
+    cred.update_secret(b"new")?;
+    match entry.get_password() {
+        Ok(p) if p.eq("new") => {}
+        r => return bad_result("get_password", &format!("'new', got {r:?}")),
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn get_secret_zeroizing() -> keyring_core::Result<()> {
+    let entry = Entry::new("zeroizing-service", "user")?;
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    match cred.get_secret_zeroizing() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_secret_zeroizing", &format!("NoEntry, got {r:?}")),
+    }
+    entry.set_secret(&[0, 1, 2, 255])?;
+    match cred.get_secret_zeroizing() {
+        Ok(s) if *s == [0, 1, 2, 255] => {}
+        r => {
+            return bad_result(
+                "get_secret_zeroizing",
+                &format!("[0, 1, 2, 255], got {r:?}"),
+            );
+        }
+    }
+    entry.delete_credential()?;
+    Ok(())
+}
+
+fn named_stores() -> keyring_core::Result<()> {
+    let protected = crate::Store::new_named(NAMED_STORES[0])?;
+    let unprotected = crate::Store::new_named(NAMED_STORES[1])?;
+    let entry1 = protected.build("named-service", "user", None)?;
+    let entry2 = unprotected.build("named-service", "user", None)?;
+    entry1.set_password("protected")?;
+    match entry2.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+    }
+    entry2.set_password("unprotected")?;
+    match entry1.get_password() {
+        Ok(s) if s == "protected" => {}
+        r => return bad_result("get_password", &format!("protected, got {r:?}")),
+    }
+    match entry2.get_password() {
+        Ok(s) if s == "unprotected" => {}
+        r => return bad_result("get_password", &format!("unprotected, got {r:?}")),
+    }
+    let found = unprotected.search(&HashMap::from([("service", "^named-service$")]))?;
+    if found.len() != 1 {
+        return bad_result("search", &format!("one entry, got {}", found.len()));
+    }
+    entry1.delete_credential()?;
+    match entry2.get_password() {
+        Ok(s) if s == "unprotected" => {}
+        r => return bad_result("get_password", &format!("unprotected, got {r:?}")),
+    }
+    entry2.delete_credential()?;
+    Ok(())
+}
+
+#[cfg(feature = "android-log")]
+fn log_tag() -> keyring_core::Result<()> {
+    use crate::android_log::AndroidLogCat;
+
+    let target = "android_native_keyring_store::by_store::vault";
+    let tag = AndroidLogCat::with_tag("keyring").tag(target);
+    if tag.as_c_str() != c"keyring" {
+        return bad_result("tag", &format!("keyring, got {tag:?}"));
+    }
+    // without a tag, or with one logcat can't take, messages are tagged with their target
+    for layer in [
+        AndroidLogCat::default(),
+        AndroidLogCat::with_tag("bad\0tag"),
+    ] {
+        let tag = layer.tag(target);
+        if tag.to_str() != Ok(target) {
+            return bad_result("tag", &format!("{target}, got {tag:?}"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "android-log")]
+fn log_lines() -> keyring_core::Result<()> {
+    use crate::android_log::{CONTINUATION_MARKER, MAX_LINE_LEN, log_lines};
+
+    // multi-byte characters make some line ends fall inside a character
+    let message = "0123456789é".repeat(1000);
+    let lines = log_lines(&message);
+    if lines.len() < 3 {
+        return bad_result(
+            "log_lines",
+            &format!("3 or more lines, got {}", lines.len()),
+        );
+    }
+    let mut joined = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.to_str().unwrap();
+        if line.len() > MAX_LINE_LEN {
+            return bad_result("log_lines", &format!("short lines, got {}", line.len()));
+        }
+        match line.strip_suffix(CONTINUATION_MARKER) {
+            Some(chunk) if i < lines.len() - 1 => joined.push_str(chunk),
+            None if i == lines.len() - 1 => joined.push_str(line),
+            _ => return bad_result("log_lines", "markers on all but the last line"),
+        }
+    }
+    if joined != message {
+        return bad_result("log_lines", "lines that make up the message");
+    }
+    // NULs don't end a line early
+    match log_lines("before\0after").as_slice() {
+        [line] if line.as_c_str() == c"before\\0after" => Ok(()),
+        lines => bad_result("log_lines", &format!("one escaped line, got {lines:?}")),
+    }
+}
+
+#[cfg(feature = "android-log")]
+fn log_reload() -> keyring_core::Result<()> {
+    use crate::android_log::{FILTER, init};
+    use tracing::{Level, enabled};
+
+    // the test app may have started logging, so its filter is put back afterwards
+    let previous = FILTER
+        .get()
+        .and_then(|h| h.with_current(|f| f.to_string()).ok());
+    let set = |filter: &str| init(filter).map_err(keyring_core::Error::PlatformFailure);
+    set("error")?;
+    let before = enabled!(target: "log_reload", Level::DEBUG);
+    set("debug")?;
+    let after = enabled!(target: "log_reload", Level::DEBUG);
+    tracing::debug!(target: "log_reload", "Logging initialized from Rust");
+    set(previous.as_deref().unwrap_or("off"))?;
+    match (before, after) {
+        (false, true) => Ok(()),
+        r => bad_result("init", &format!("(false, true), got {r:?}")),
+    }
+}
+
+fn capabilities() -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    let capabilities = store.capabilities()?;
+    if !capabilities.search || capabilities.attributes || !capabilities.persistent {
+        return bad_result(
+            "capabilities",
+            &format!("search only, got {capabilities:?}"),
+        );
+    }
+    if capabilities.hardware_backed.is_none() {
+        return bad_result("capabilities", "hardware_backed to be reported");
+    }
+    Ok(())
+}
+
+fn persistent_id() -> keyring_core::Result<()> {
+    let config = HashMap::from(PERSISTENT_ID_STORE_CONFIG);
+    let store1 = crate::Store::new_with_configuration(&config)?;
+    // the id is read back from the device for every new store object
+    let store2 = crate::Store::new_with_configuration(&config)?;
+    if store1.id() != store2.id() {
+        return bad_result("id", &format!("{:?}, got {:?}", store1.id(), store2.id()));
+    }
+    // without the option, every store has its own id
+    let store3 = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    if store3.id() == store1.id() {
+        return bad_result("id", &format!("an id other than {:?}", store1.id()));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "biometric")]
+fn can_authenticate() -> keyring_core::Result<()> {
+    use crate::biometric::{AuthenticationStatus, BIOMETRIC_STRONG, DEVICE_CREDENTIAL};
+
+    let store = crate::Store::new_with_configuration(&HashMap::from(STORE_CONFIG))?;
+    // emulators differ in what they have enrolled, but every
+    // answer should be one of the documented result codes
+    for authenticators in [BIOMETRIC_STRONG, BIOMETRIC_STRONG | DEVICE_CREDENTIAL] {
+        match store.can_authenticate(authenticators)? {
+            AuthenticationStatus::Other(code) => {
+                return bad_result(
+                    "can_authenticate",
+                    &format!("a known status, got code {code}"),
+                );
+            }
+            status => log::info!("Authenticators {authenticators:#x}: {status:?}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "encrypted-preferences")]
+fn encrypted_preferences() -> keyring_core::Result<()> {
+    let store = crate::Store::new_with_configuration(&HashMap::from(ENCRYPTED_STORE_CONFIG))?;
+    let entry = store.build("encrypted-service", "user", None)?;
+    entry.set_secret(&[0, 1, 2, 255])?;
+    match entry.get_secret() {
+        Ok(s) if s == [0, 1, 2, 255] => {}
+        r => return bad_result("get_secret", &format!("[0, 1, 2, 255], got {r:?}")),
+    }
+    let cred = entry.as_any().downcast_ref::<crate::Cred>().unwrap();
+    match cred.protection_info() {
+        Ok(info) if info.backend == "EncryptedSharedPreferences" && info.aead => {}
+        r => {
+            let err = format!("EncryptedSharedPreferences, got {r:?}");
+            return bad_result("protection_info", &err);
+        }
+    }
+    let found = store.search(&HashMap::from([("service", "^encrypted-service$")]))?;
+    if found.len() != 1 {
+        return bad_result("search", &format!("one entry, got {}", found.len()));
+    }
+    // the same file can't be reopened as a plain one
+    let mut plain_config = HashMap::from(ENCRYPTED_STORE_CONFIG);
+    plain_config.remove("encrypted_preferences");
+    match crate::Store::new_with_configuration(&plain_config) {
+        Err(keyring_core::Error::Invalid(key, _)) if key == "encrypted_preferences" => {}
+        r => return bad_result("new_with_configuration", &format!("Invalid, got {r:?}")),
+    }
+    entry.delete_credential()?;
+    match entry.get_secret() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_secret", &format!("NoEntry, got {r:?}")),
+    }
+    Ok(())
+}
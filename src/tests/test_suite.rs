@@ -0,0 +1,146 @@
+/*!
+# Reusable Test Suites
+
+The on-device tests are grouped into [Suite]s of named test closures, so that
+an application can run all of them, a subset of them, or its own tests
+alongside them, and get back a [TestResult] for each test rather than only
+the lines logged to logcat.
+
+Each suite starts with a `setup` test and most end with a `teardown` test,
+which [Suite::select] keeps whichever other tests are selected.
+After running suites, call [cleanup](super::cleanup) to remove the
+stores and files the tests leave behind.
+ */
+use std::ffi::CString;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use android_log_sys::{__android_log_write, LogPriority};
+use jni::{JNIEnv, objects::JObject};
+
+use crate::shared_preferences::Context;
+
+type TestFn = Box<dyn FnOnce() -> keyring_core::Result<()>>;
+
+/// A named sequence of tests, run in order.
+pub struct Suite {
+    name: String,
+    tests: Vec<(String, TestFn)>,
+}
+
+/// The outcome of running one test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    /// The name of the suite the test belongs to.
+    pub suite: String,
+    /// The name of the test.
+    pub name: String,
+    /// Whether the test passed.
+    pub passed: bool,
+    /// Why the test failed, if it did.
+    pub message: Option<String>,
+}
+
+impl Suite {
+    /// Returns an empty suite with the given name.
+    pub fn new(name: &str) -> Self {
+        Suite {
+            name: name.to_string(),
+            tests: Vec::new(),
+        }
+    }
+
+    /// Add a test to the end of this suite.
+    ///
+    /// The test fails if it returns an error or panics.
+    pub fn add<F>(&mut self, name: &str, test: F)
+    where
+        F: FnOnce() -> keyring_core::Result<()> + 'static,
+    {
+        self.tests.push((name.to_string(), Box::new(test)));
+    }
+
+    /// The name of this suite.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The names of this suite's tests, in the order they run.
+    pub fn test_names(&self) -> Vec<&str> {
+        self.tests.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Keep only the named tests, along with the `setup` and `teardown` tests
+    /// that the others depend on.
+    pub fn select(mut self, names: &[&str]) -> Self {
+        self.tests.retain(|(name, _)| {
+            name == "setup" || name == "teardown" || names.contains(&name.as_str())
+        });
+        self
+    }
+
+    /// Run this suite's tests in order, logging each outcome, and return their results.
+    pub fn run(self) -> Vec<TestResult> {
+        log_line(
+            LogPriority::INFO,
+            &format!("Running {} tests...", self.name),
+        );
+        let mut results = Vec::with_capacity(self.tests.len());
+        for (name, test) in self.tests {
+            let outcome = catch_unwind(AssertUnwindSafe(test)).unwrap_or_else(|e| {
+                log::error!("Test {name} panicked: {e:?}");
+                Err(keyring_core::Error::Invalid(
+                    name.clone(),
+                    "should not have panicked".to_string(),
+                ))
+            });
+            let message = match outcome {
+                Ok(()) => {
+                    log_line(LogPriority::INFO, &format!("{name} success"));
+                    None
+                }
+                Err(e) => {
+                    log_line(LogPriority::ERROR, &format!("{name} error: {e:?}"));
+                    Some(format!("{e:?}"))
+                }
+            };
+            results.push(TestResult {
+                suite: self.name.clone(),
+                name,
+                passed: message.is_none(),
+                message,
+            });
+        }
+        let (successes, failures) = tally(&results);
+        let summary = format!("{}: {successes} successes, {failures} failures", self.name);
+        log_line(LogPriority::INFO, &summary);
+        results
+    }
+}
+
+/// All of this crate's test suites, in the order they should be run,
+/// given the application's `android.content.Context`.
+pub fn suites(env: &JNIEnv, context: JObject) -> Vec<Suite> {
+    let context = Context::new(env, context).unwrap();
+    vec![
+        #[cfg(feature = "legacy")]
+        super::legacy_tests::suite(),
+        super::store_tests::suite(),
+        super::crypto_tests::suite(env, context),
+        #[cfg(feature = "transfer")]
+        super::transfer_tests::suite(),
+    ]
+}
+
+/// The numbers of passed and failed tests among the given results.
+pub fn tally(results: &[TestResult]) -> (usize, usize) {
+    let passed = results.iter().filter(|r| r.passed).count();
+    (passed, results.len() - passed)
+}
+
+pub(crate) fn log_line(priority: LogPriority, msg: &str) {
+    let msg = CString::new(msg).unwrap();
+    let tag = c"unit-test";
+    unsafe {
+        __android_log_write(priority as i32, tag.as_ptr(), msg.as_ptr());
+    }
+}
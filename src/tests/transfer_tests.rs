@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use keyring_core::{Entry, api::CredentialStoreApi};
+
+use crate::{Cred, transfer::TransportKey};
+
+use super::test_suite::Suite;
+
+pub fn suite() -> Suite {
+    let tests = [
+        ("setup", setup as fn() -> keyring_core::Result<()>),
+        ("round_trip", round_trip),
+        ("wrong_transport_key", wrong_transport_key),
+        ("teardown", teardown),
+    ];
+    let mut suite = Suite::new("Transfer");
+    for (name, test) in tests {
+        suite.add(name, test);
+    }
+    suite
+}
+
+fn bad_result(op: &str, msg: &str) -> keyring_core::Result<()> {
+    Err(keyring_core::Error::Invalid(
+        op.to_string(),
+        format!("should have returned {msg}"),
+    ))
+}
+
+const SOURCE_CONFIG: [(&str, &str); 1] = [("name", "transfer-source")];
+const DESTINATION_CONFIG: [(&str, &str); 1] = [("name", "transfer-destination")];
+
+fn setup() -> keyring_core::Result<()> {
+    cleanup()
+}
+
+fn teardown() -> keyring_core::Result<()> {
+    Ok(())
+}
+
+pub fn cleanup() -> keyring_core::Result<()> {
+    for config in [SOURCE_CONFIG, DESTINATION_CONFIG] {
+        let store_config = HashMap::from(config);
+        if crate::Store::delete(&store_config)? {
+            log::info!("Store {store_config:?} successfully deleted");
+        }
+    }
+    Ok(())
+}
+
+fn entries() -> keyring_core::Result<(Entry, Entry)> {
+    let source = crate::Store::new_with_configuration(&HashMap::from(SOURCE_CONFIG))?;
+    let destination = crate::Store::new_with_configuration(&HashMap::from(DESTINATION_CONFIG))?;
+    Ok((
+        source.build("transfer", "user", None)?,
+        destination.build("transfer", "user", None)?,
+    ))
+}
+
+fn as_cred(entry: &Entry) -> &Cred {
+    entry.as_any().downcast_ref::<Cred>().unwrap()
+}
+
+fn round_trip() -> keyring_core::Result<()> {
+    let (source, destination) = entries()?;
+    let secret = b"transfer-secret".to_vec();
+    source.set_secret(&secret)?;
+    // the destination device makes a transport key and sends its public half
+    let transport_key = TransportKey::generate()?;
+    let public_key = transport_key.public_key()?;
+    // the source device seals the secret to it
+    let blob = as_cred(&source).export_blob(&public_key)?;
+    if blob.windows(secret.len()).any(|w| w == secret.as_slice()) {
+        return bad_result("export_blob", "a blob without the plaintext");
+    }
+    // the destination device opens the blob and stores the secret
+    as_cred(&destination).import_blob(&transport_key, &blob)?;
+    match destination.get_secret() {
+        Ok(s) if s == secret => {}
+        Ok(s) => return bad_result("get_secret", &format!("{secret:?}, got {s:?}")),
+        Err(e) => return bad_result("get_secret", &format!("{secret:?}, got {e:?}")),
+    }
+    // the source credential is untouched
+    match source.get_secret() {
+        Ok(s) if s == secret => {}
+        Ok(s) => return bad_result("get_secret", &format!("{secret:?}, got {s:?}")),
+        Err(e) => return bad_result("get_secret", &format!("{secret:?}, got {e:?}")),
+    }
+    source.delete_credential()?;
+    destination.delete_credential()?;
+    Ok(())
+}
+
+fn wrong_transport_key() -> keyring_core::Result<()> {
+    let (source, destination) = entries()?;
+    source.set_password("transfer-secret")?;
+    let intended = TransportKey::generate()?;
+    let interloper = TransportKey::generate()?;
+    let blob = as_cred(&source).export_blob(&intended.public_key()?)?;
+    match as_cred(&destination).import_blob(&interloper, &blob) {
+        Err(keyring_core::Error::BadDataFormat(_, _)) => {}
+        r => return bad_result("import_blob", &format!("BadDataFormat, got {r:?}")),
+    }
+    match destination.get_password() {
+        Err(keyring_core::Error::NoEntry) => {}
+        r => return bad_result("get_password", &format!("NoEntry, got {r:?}")),
+    }
+    source.delete_credential()?;
+    Ok(())
+}
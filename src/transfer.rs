@@ -0,0 +1,549 @@
+/*!
+# Device-to-Device Credential Transfer
+
+This module lets a credential's secret be moved from one device to another
+without the secret ever being written to storage in the clear.
+
+The destination device generates a [TransportKey], an ephemeral EC key pair
+that lives only in memory, and sends its public half to the source device.
+The source device uses [Cred::export_blob](crate::Cred::export_blob) to seal
+the credential's secret to that public key, producing an opaque blob. The
+destination device then uses [Cred::import_blob](crate::Cred::import_blob)
+to open the blob with its transport key and store the secret under its own
+device key.
+
+## Blob Format
+
+Sealing uses ECDH over P-256 with an ephemeral sender key pair. The AES-256
+key that encrypts the secret is the SHA-256 digest of the shared secret
+followed by the sender's and recipient's X.509-encoded public keys. A blob
+is a version byte, the two-byte big-endian length of the sender's encoded
+public key, the key itself, and then the secret encrypted with AES/GCM in
+the same IV-prefixed form used for credentials at rest.
+ */
+use std::{marker::PhantomData, sync::Arc};
+
+use jni::{
+    JNIEnv, JavaVM,
+    objects::{GlobalRef, JObject, JValueGen},
+};
+use keyring_core::{Error, Result};
+
+use crate::{
+    crypto::{Transformation, decrypt, encrypt},
+    error::{AndroidKeyringError, AndroidKeyringResult, take_pending_exception},
+    keystore::{KEY_ALGORITHM_AES, Key, SecretKeySpec},
+    methods::{
+        ClassDecl, Constructible, FromValue, JResult, LOCAL_FRAME_CAPACITY, Method, NoParam,
+        SignatureComp, StaticMethod, ToValue, attach,
+    },
+};
+
+const BLOB_VERSION: u8 = 1;
+const EC_ALGORITHM: &str = "EC";
+const EC_KEY_SIZE: i32 = 256;
+const KEY_AGREEMENT_ALGORITHM: &str = "ECDH";
+const DIGEST_ALGORITHM: &str = "SHA-256";
+
+/// An ephemeral key pair used to receive credentials from another device.
+///
+/// The private half never leaves memory, so a transport key is only
+/// good for as long as the process that generated it is running.
+pub struct TransportKey {
+    vm: Arc<JavaVM>,
+    key_pair: KeyPair,
+}
+
+impl std::fmt::Debug for TransportKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransportKey").finish_non_exhaustive()
+    }
+}
+
+impl TransportKey {
+    /// Generate a new transport key.
+    pub fn generate() -> Result<Self> {
+        let vm = get_java_vm()?;
+        let key_pair = with_env(&vm, generate_key_pair)?;
+        Ok(Self { vm, key_pair })
+    }
+
+    /// The X.509 encoding of the public half of this key,
+    /// to be sent to the device that is exporting credentials.
+    pub fn public_key(&self) -> Result<Vec<u8>> {
+        Ok(with_env(&self.vm, |env| {
+            let public = self.key_pair.get_public(env)?;
+            Ok(public.get_encoded(env)?)
+        })?)
+    }
+
+    pub(crate) fn open(&self, env: &mut JNIEnv, blob: &[u8]) -> AndroidKeyringResult<Vec<u8>> {
+        open(env, &self.key_pair, blob)
+    }
+}
+
+/// Encrypt a secret so that only the holder of the transport
+/// key with the given public key can decrypt it.
+pub(crate) fn seal(
+    env: &mut JNIEnv,
+    recipient_public: &[u8],
+    secret: &[u8],
+) -> AndroidKeyringResult<Vec<u8>> {
+    let recipient = decode_public_key(env, recipient_public)?;
+    let sender = generate_key_pair(env)?;
+    let sender_public = sender.get_public(env)?.get_encoded(env)?;
+    let Ok(sender_public_len) = u16::try_from(sender_public.len()) else {
+        let msg = "public key is too long".to_string();
+        return Err(Error::Invalid("transport key".to_string(), msg).into());
+    };
+    let key = derive_key(env, &sender, &recipient, &sender_public, recipient_public)?;
+    let ciphertext = encrypt(env, key, Transformation::AesGcm, None, secret)?;
+    let mut blob = vec![BLOB_VERSION];
+    blob.extend_from_slice(&sender_public_len.to_be_bytes());
+    blob.extend_from_slice(&sender_public);
+    blob.extend_from_slice(&ciphertext);
+    Ok(blob)
+}
+
+fn open(env: &mut JNIEnv, recipient: &KeyPair, blob: &[u8]) -> AndroidKeyringResult<Vec<u8>> {
+    let malformed = || Error::Invalid("blob".to_string(), "malformed transfer blob".to_string());
+    let [version, len_hi, len_lo, rest @ ..] = blob else {
+        return Err(malformed().into());
+    };
+    if *version != BLOB_VERSION {
+        let msg = format!("unsupported transfer blob version {version}");
+        return Err(Error::Invalid("blob".to_string(), msg).into());
+    }
+    let sender_public_len = u16::from_be_bytes([*len_hi, *len_lo]) as usize;
+    if rest.len() < sender_public_len {
+        return Err(malformed().into());
+    }
+    let (sender_public, ciphertext) = rest.split_at(sender_public_len);
+    let sender = decode_public_key(env, sender_public)?;
+    let recipient_public = recipient.get_public(env)?.get_encoded(env)?;
+    let key = derive_key(env, recipient, &sender, sender_public, &recipient_public)?;
+    decrypt(env, key, None, ciphertext.to_vec())
+}
+
+fn generate_key_pair(env: &mut JNIEnv) -> AndroidKeyringResult<KeyPair> {
+    let generator = KeyPairGenerator::get_instance(env, EC_ALGORITHM)?;
+    generator.initialize(env, EC_KEY_SIZE)?;
+    Ok(generator.generate_key_pair(env)?)
+}
+
+fn decode_public_key(env: &mut JNIEnv, encoded: &[u8]) -> AndroidKeyringResult<PublicKey> {
+    let factory = KeyFactory::get_instance(env, EC_ALGORITHM)?;
+    let spec = X509EncodedKeySpec::new(env, encoded)?;
+    factory.generate_public(env, spec).map_err(|_| {
+        if env.exception_check().unwrap_or(false) {
+            _ = env.exception_clear();
+        }
+        Error::Invalid(
+            "transport key".to_string(),
+            "not an encoded EC public key".to_string(),
+        )
+        .into()
+    })
+}
+
+fn derive_key(
+    env: &mut JNIEnv,
+    own: &KeyPair,
+    peer: &PublicKey,
+    sender_public: &[u8],
+    recipient_public: &[u8],
+) -> AndroidKeyringResult<Key> {
+    let agreement = KeyAgreement::get_instance(env, KEY_AGREEMENT_ALGORITHM)?;
+    let private = own.get_private(env)?.as_key(env)?;
+    agreement.init(env, &private)?;
+    let peer = peer.as_key(env)?;
+    agreement.do_phase(env, &peer)?;
+    let mut material = agreement.generate_secret(env)?;
+    material.extend_from_slice(sender_public);
+    material.extend_from_slice(recipient_public);
+    let digest = MessageDigest::get_instance(env, DIGEST_ALGORITHM)?;
+    let key_bytes = digest.digest(env, &material)?;
+    let spec = SecretKeySpec::new(env, &key_bytes, KEY_ALGORITHM_AES)?;
+    Ok(spec.into())
+}
+
+fn get_java_vm() -> AndroidKeyringResult<Arc<JavaVM>> {
+    let ctx = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(ctx.vm().cast())? };
+    let env = vm.attach_current_thread()?;
+    Ok(Arc::new(env.get_java_vm()?))
+}
+
+fn with_env<T, F>(vm: &JavaVM, f: F) -> AndroidKeyringResult<T>
+where
+    F: FnOnce(&mut JNIEnv) -> AndroidKeyringResult<T>,
+{
+    let mut env = attach(vm)?;
+    let result = env.with_local_frame(LOCAL_FRAME_CAPACITY, f);
+    if env.exception_check()? {
+        let thrown = take_pending_exception(&mut env)?;
+        log::error!("Exception during credential transfer: {thrown}");
+        if let Err(AndroidKeyringError::JniError(jni::errors::Error::JavaException)) = result {
+            return Err(thrown);
+        }
+    }
+    result
+}
+
+struct KeyPairGenerator {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyPairGenerator {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyPairGenerator {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/KeyPairGenerator;")
+    }
+
+    fn get_instance(env: &mut JNIEnv, algorithm: &str) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = KeyPairGenerator;
+
+            const NAME: &'static str = "getInstance";
+        }
+
+        ThisMethod::call(Self::class(), env, algorithm)
+    }
+
+    fn initialize(&self, env: &mut JNIEnv, key_size: i32) -> JResult<()> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = i32;
+            type Return = ();
+
+            const NAME: &str = "initialize";
+        }
+
+        ThisMethod::call(&self.self_, env, key_size)
+    }
+
+    fn generate_key_pair(&self, env: &mut JNIEnv) -> JResult<KeyPair> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = KeyPair;
+
+            const NAME: &str = "generateKeyPair";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+struct KeyPair {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyPair {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyPair {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/KeyPair;")
+    }
+
+    fn get_public(&self, env: &mut JNIEnv) -> JResult<PublicKey> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = PublicKey;
+
+            const NAME: &str = "getPublic";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    fn get_private(&self, env: &mut JNIEnv) -> JResult<PrivateKey> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = PrivateKey;
+
+            const NAME: &str = "getPrivate";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+struct PublicKey {
+    self_: GlobalRef,
+}
+
+impl FromValue for PublicKey {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl PublicKey {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/PublicKey;")
+    }
+
+    fn get_encoded(&self, env: &mut JNIEnv) -> JResult<Vec<u8>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Vec<u8>;
+
+            const NAME: &str = "getEncoded";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+
+    fn as_key(&self, env: &mut JNIEnv) -> JResult<Key> {
+        Key::from_object(self.self_.clone(), env)
+    }
+}
+
+struct PrivateKey {
+    self_: GlobalRef,
+}
+
+impl FromValue for PrivateKey {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl PrivateKey {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/PrivateKey;")
+    }
+
+    fn as_key(&self, env: &mut JNIEnv) -> JResult<Key> {
+        Key::from_object(self.self_.clone(), env)
+    }
+}
+
+struct KeyFactory {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyFactory {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyFactory {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/KeyFactory;")
+    }
+
+    fn get_instance(env: &mut JNIEnv, algorithm: &str) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = KeyFactory;
+
+            const NAME: &'static str = "getInstance";
+        }
+
+        ThisMethod::call(Self::class(), env, algorithm)
+    }
+
+    fn generate_public(&self, env: &mut JNIEnv, spec: X509EncodedKeySpec) -> JResult<PublicKey> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = X509EncodedKeySpec;
+            type Return = PublicKey;
+
+            const NAME: &str = "generatePublic";
+        }
+
+        ThisMethod::call(&self.self_, env, spec)
+    }
+}
+
+struct X509EncodedKeySpec {
+    self_: GlobalRef,
+}
+
+impl FromValue for X509EncodedKeySpec {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl ToValue for X509EncodedKeySpec {
+    fn signature() -> SignatureComp {
+        // `KeyFactory.generatePublic` is declared to take any `KeySpec`
+        ClassDecl("Ljava/security/spec/KeySpec;").into()
+    }
+
+    fn to_value<'a>(&self, env: &mut JNIEnv<'a>) -> JResult<JValueGen<JObject<'a>>> {
+        Ok(env.new_local_ref(&self.self_)?.into())
+    }
+}
+
+impl X509EncodedKeySpec {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/spec/X509EncodedKeySpec;")
+    }
+
+    fn new(env: &mut JNIEnv, encoded: &[u8]) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Constructible for ThisMethod<'a> {
+            type Param = &'a [u8];
+            type Return = X509EncodedKeySpec;
+        }
+
+        ThisMethod::call_new(Self::class(), env, encoded)
+    }
+}
+
+struct KeyAgreement {
+    self_: GlobalRef,
+}
+
+impl FromValue for KeyAgreement {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl KeyAgreement {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljavax/crypto/KeyAgreement;")
+    }
+
+    fn get_instance(env: &mut JNIEnv, algorithm: &str) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = KeyAgreement;
+
+            const NAME: &'static str = "getInstance";
+        }
+
+        ThisMethod::call(Self::class(), env, algorithm)
+    }
+
+    fn init(&self, env: &mut JNIEnv, key: &Key) -> JResult<()> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a Key;
+            type Return = ();
+
+            const NAME: &'static str = "init";
+        }
+
+        ThisMethod::call(&self.self_, env, key)
+    }
+
+    fn do_phase(&self, env: &mut JNIEnv, key: &Key) -> JResult<()> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = (&'a Key, bool);
+            type Return = Option<Key>;
+
+            const NAME: &'static str = "doPhase";
+        }
+
+        ThisMethod::call(&self.self_, env, (key, true))?;
+        Ok(())
+    }
+
+    fn generate_secret(&self, env: &mut JNIEnv) -> JResult<Vec<u8>> {
+        struct ThisMethod;
+        impl Method for ThisMethod {
+            type Param = NoParam;
+            type Return = Vec<u8>;
+
+            const NAME: &str = "generateSecret";
+        }
+
+        ThisMethod::call(&self.self_, env, NoParam)
+    }
+}
+
+struct MessageDigest {
+    self_: GlobalRef,
+}
+
+impl FromValue for MessageDigest {
+    fn signature() -> SignatureComp {
+        Self::class().into()
+    }
+
+    fn from_object(self_: GlobalRef, _env: &mut JNIEnv) -> JResult<Self> {
+        Ok(Self { self_ })
+    }
+}
+
+impl MessageDigest {
+    fn class() -> ClassDecl {
+        ClassDecl("Ljava/security/MessageDigest;")
+    }
+
+    fn get_instance(env: &mut JNIEnv, algorithm: &str) -> JResult<Self> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> StaticMethod for ThisMethod<'a> {
+            type Param = &'a str;
+            type Return = MessageDigest;
+
+            const NAME: &'static str = "getInstance";
+        }
+
+        ThisMethod::call(Self::class(), env, algorithm)
+    }
+
+    fn digest(&self, env: &mut JNIEnv, input: &[u8]) -> JResult<Vec<u8>> {
+        struct ThisMethod<'a>(PhantomData<&'a ()>);
+        impl<'a> Method for ThisMethod<'a> {
+            type Param = &'a [u8];
+            type Return = Vec<u8>;
+
+            const NAME: &'static str = "digest";
+        }
+
+        ThisMethod::call(&self.self_, env, input)
+    }
+}
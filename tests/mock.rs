@@ -0,0 +1,185 @@
+//! Host-runnable tests of the in-memory mock store.
+
+use std::collections::HashMap;
+
+use android_native_keyring_store::{CorruptedData, mock::Store};
+use keyring_core::{Error, api::CredentialStoreApi};
+
+fn corrupted_data(err: Error) -> CorruptedData {
+    match err {
+        Error::BadDataFormat(_, inner) => match inner.downcast::<CorruptedData>() {
+            Ok(inner) => *inner,
+            Err(inner) => panic!("expected CorruptedData, got {inner:?}"),
+        },
+        err => panic!("expected BadDataFormat, got {err:?}"),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let store = Store::new();
+    let entry = store.build("service", "user", None).unwrap();
+    entry.set_secret(b"secret").unwrap();
+    assert_eq!(entry.get_secret().unwrap(), b"secret");
+    entry.get_credential().unwrap();
+    assert_eq!(
+        entry.get_specifiers(),
+        Some(("service".to_string(), "user".to_string()))
+    );
+    entry.set_password("password").unwrap();
+    assert_eq!(entry.get_password().unwrap(), "password");
+    entry.delete_credential().unwrap();
+}
+
+#[test]
+fn missing_entry() {
+    let store = Store::new();
+    let entry = store.build("service", "missing", None).unwrap();
+    assert!(matches!(entry.get_secret(), Err(Error::NoEntry)));
+    assert!(matches!(entry.get_credential(), Err(Error::NoEntry)));
+    assert!(matches!(entry.delete_credential(), Err(Error::NoEntry)));
+    entry.set_secret(b"secret").unwrap();
+    entry.delete_credential().unwrap();
+    assert!(matches!(entry.get_secret(), Err(Error::NoEntry)));
+}
+
+#[test]
+fn stores_are_independent() {
+    let store1 = Store::new();
+    let store2 = Store::new();
+    assert_ne!(store1.id(), store2.id());
+    let entry1 = store1.build("service", "user", None).unwrap();
+    entry1.set_secret(b"secret").unwrap();
+    let entry2 = store2.build("service", "user", None).unwrap();
+    assert!(matches!(entry2.get_secret(), Err(Error::NoEntry)));
+}
+
+#[test]
+fn invalid_entries() {
+    let store = Store::new();
+    let modifiers = HashMap::from([("key", "value")]);
+    assert!(matches!(
+        store.build("service", "user", Some(&modifiers)),
+        Err(Error::NotSupportedByStore(_))
+    ));
+    assert!(matches!(
+        store.build("service\u{FEFF}@\u{FEFF}", "user", None),
+        Err(Error::Invalid(key, _)) if key == "service"
+    ));
+    assert!(matches!(
+        store.build("service", "\u{FEFF}@\u{FEFF}user", None),
+        Err(Error::Invalid(key, _)) if key == "user"
+    ));
+}
+
+#[test]
+fn values_use_the_envelope() {
+    let store = Store::new();
+    let entry = store.build("service", "user", None).unwrap();
+    entry.set_secret(b"first").unwrap();
+    let first = store.get_raw("service", "user").unwrap();
+    assert!(first.starts_with(b"AK\x01"));
+    // every write gets a fresh IV
+    entry.set_secret(b"first").unwrap();
+    assert_ne!(store.get_raw("service", "user").unwrap(), first);
+}
+
+#[test]
+fn empty_secret() {
+    let store = Store::new();
+    let entry = store.build("service", "user", None).unwrap();
+    entry.set_secret(b"").unwrap();
+    // the header, the IV length, the IV, and the tag
+    assert_eq!(
+        store.get_raw("service", "user").unwrap().len(),
+        4 + 1 + 12 + 16
+    );
+    assert_eq!(entry.get_secret().unwrap(), b"");
+}
+
+#[test]
+fn malformed_values() {
+    let store = Store::new();
+    let entry = store.build("service", "user", None).unwrap();
+    store.set_raw("service", "user", b"");
+    let err = entry.get_secret().unwrap_err();
+    assert!(matches!(corrupted_data(err), CorruptedData::MissingIvLen));
+    store.set_raw("service", "user", b"AK\x09\x00");
+    let err = entry.get_secret().unwrap_err();
+    assert!(matches!(
+        corrupted_data(err),
+        CorruptedData::UnsupportedVersion(9)
+    ));
+    store.set_raw("service", "user", b"AK\x01\x40\x0c");
+    let err = entry.get_secret().unwrap_err();
+    assert!(matches!(
+        corrupted_data(err),
+        CorruptedData::UnknownFlags(0x40)
+    ));
+    store.set_raw("service", "user", b"AK\x01\x80\x07");
+    let err = entry.get_secret().unwrap_err();
+    assert!(matches!(
+        corrupted_data(err),
+        CorruptedData::InvalidIvLen { actual: 7, .. }
+    ));
+    store.set_raw("service", "user", b"AK\x01\x80\x0c0123456789ab");
+    let err = entry.get_secret().unwrap_err();
+    assert!(matches!(
+        corrupted_data(err),
+        CorruptedData::DataTooSmall(0)
+    ));
+}
+
+#[test]
+fn tampered_values() {
+    let store = Store::new();
+    let entry = store.build("service", "user", None).unwrap();
+    entry.set_secret(b"secret").unwrap();
+    let mut value = store.get_raw("service", "user").unwrap();
+    let last = value.len() - 1;
+    value[last] ^= 1;
+    store.set_raw("service", "user", &value);
+    let err = entry.get_secret().unwrap_err();
+    assert!(matches!(
+        corrupted_data(err),
+        CorruptedData::DecryptionFailure
+    ));
+}
+
+#[test]
+fn values_are_bound_to_their_entry() {
+    let store = Store::new();
+    let entry1 = store.build("service", "user1", None).unwrap();
+    entry1.set_secret(b"secret").unwrap();
+    let value = store.get_raw("service", "user1").unwrap();
+    store.set_raw("service", "user2", &value);
+    let entry2 = store.build("service", "user2", None).unwrap();
+    let err = entry2.get_secret().unwrap_err();
+    assert!(matches!(
+        corrupted_data(err),
+        CorruptedData::DecryptionFailure
+    ));
+}
+
+#[test]
+fn search() {
+    let store = Store::new();
+    for (service, user) in [("s1", "u1"), ("s1", "u2"), ("s2", "u1")] {
+        let entry = store.build(service, user, None).unwrap();
+        entry.set_secret(b"secret").unwrap();
+    }
+    let found = store.search(&HashMap::from([("service", "^s1$")])).unwrap();
+    assert_eq!(found.len(), 2);
+    let found = store
+        .search(&HashMap::from([("service", "^s2$"), ("user", "^u1$")]))
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(
+        found[0].get_specifiers(),
+        Some(("s2".to_string(), "u1".to_string()))
+    );
+    assert!(matches!(
+        store.search(&HashMap::from([("user", "(")])),
+        Err(Error::Invalid(key, _)) if key == "user"
+    ));
+}